@@ -0,0 +1,119 @@
+//! Benchmarks of what every query goes through: parsing the query, finding its record and writing
+//! the response. The app is a binary, so the modules (which only depend on the prelude) are built
+//! here on their own.
+//!
+//! Run with `cargo bench` (or `just bench`), compare with a baseline with
+//! `cargo bench -- --save-baseline main` and `cargo bench -- --baseline main`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::hint::black_box;
+
+mod prelude {
+    pub use anyhow::{anyhow, Result};
+    pub use std::collections::HashMap;
+    pub use std::net::{Ipv4Addr, Ipv6Addr};
+    pub use tracing::warn;
+}
+
+#[allow(dead_code)]
+#[path = "../src/dns/matching.rs"]
+mod matching;
+#[allow(dead_code)]
+#[path = "../src/dns/protocol.rs"]
+mod protocol;
+
+use matching::find_record;
+use prelude::*;
+use protocol::*;
+
+const NAME: &str = "api.my-service.loc";
+
+fn query(name: &str) -> BytePacketBuffer {
+    let mut packet = DnsPacket::new();
+    packet.header.id = 7;
+    packet.header.recursion_desired = true;
+    packet
+        .questions
+        .push(DnsQuestion::new(name.to_owned(), QueryType::A));
+    let mut buffer = BytePacketBuffer::new();
+    packet.write(&mut buffer).unwrap();
+    buffer.pos = 0;
+    buffer
+}
+
+fn response(request: &DnsPacket) -> DnsPacket {
+    let mut response = DnsPacket::new();
+    response.header.id = request.header.id;
+    response.header.response = true;
+    let question = request.questions[0].clone();
+    response.answers.push(DnsRecord::A {
+        domain: question.name.clone(),
+        addr: Ipv4Addr::LOCALHOST,
+        ttl: 0,
+    });
+    response.questions.push(question);
+    response
+}
+
+fn protocol(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let query = query(NAME);
+    c.bench_function("parse query", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let mut buffer = BytePacketBuffer::new();
+            buffer.buf = query.buf;
+            black_box(DnsPacket::from_buffer(&mut buffer).await.unwrap())
+        });
+    });
+    let mut buffer = BytePacketBuffer::new();
+    buffer.buf = query.buf;
+    let request = runtime
+        .block_on(DnsPacket::from_buffer(&mut buffer))
+        .unwrap();
+    let mut packet = response(&request);
+    c.bench_function("write response", |b| {
+        b.iter(|| {
+            let mut buffer = BytePacketBuffer::new();
+            packet.write(&mut buffer).unwrap();
+            black_box(buffer.pos())
+        });
+    });
+}
+
+/// `count` names under the top level domain, each with `depth - 1` nested subdomains that are also
+/// records (e.g. `s1.loc`, `a.s1.loc` and `a.a.s1.loc` for a depth of 3).
+fn records(count: usize, depth: usize) -> HashMap<String, Ipv4Addr> {
+    (0..count)
+        .flat_map(|i| (0..depth).map(move |level| format!("{}s{i}.loc", "a.".repeat(level))))
+        .map(|name| (name, Ipv4Addr::LOCALHOST))
+        .collect()
+}
+
+fn lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find record");
+    let databases = [
+        ("small", records(10, 1)),
+        ("large", records(10_000, 1)),
+        // most names are subdomains of others, queries are answered by the longest one
+        ("subdomains", records(1_000, 4)),
+    ];
+    for (db, records) in &databases {
+        let name = records.keys().max_by_key(|name| name.len()).unwrap();
+        group.bench_with_input(BenchmarkId::new("exact", db), name, |b, name| {
+            b.iter(|| find_record(black_box(name), records));
+        });
+        let subdomain = format!("www.{name}");
+        group.bench_with_input(BenchmarkId::new("subdomain", db), &subdomain, |b, name| {
+            b.iter(|| find_record(black_box(name), records));
+        });
+        group.bench_with_input(BenchmarkId::new("no match", db), NAME, |b, name| {
+            b.iter(|| find_record(black_box(name), records));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, protocol, lookup);
+criterion_main!(benches);
@@ -1,7 +1,9 @@
 fn main() {
-    {
+    // the icon and the performance counters are Windows resources
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("windows") {
         let mut res = winres::WindowsResource::new();
         res.set_icon("resources/Icon.ico"); // Path to your .ico file
+        res.append_rc_content(include_str!("resources/performance-counters.rc"));
         res.compile().unwrap();
     }
 }
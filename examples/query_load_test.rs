@@ -7,10 +7,15 @@ use hickory_resolver::Resolver;
 use rand::Rng;
 use rand_regex::Regex;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
 
-/// Send multiple concurrent A record queries for generated hosts within the provided domain.
+/// Send multiple concurrent A record queries for generated hosts within the provided domain and
+/// report the rate, latency percentiles and errors.
 ///
-/// Fails on the first error!
+/// Fails on the first error, unless a failure budget is set!
 #[derive(Parser)]
 struct Args {
     /// The top-level domain to generate hosts for
@@ -19,6 +24,20 @@ struct Args {
     /// Number of requests to send
     #[arg(long, short, default_value = "1000")]
     count: usize,
+    /// Number of concurrent workers sending the requests
+    #[arg(long, short = 'j', default_value = "4")]
+    concurrency: usize,
+    /// Number of failed requests to tolerate before aborting
+    #[arg(long, default_value = "0")]
+    max_errors: usize,
+}
+
+/// What a worker measured.
+#[derive(Default)]
+struct Measurements {
+    /// The latency of the successful requests.
+    latencies: Vec<Duration>,
+    errors: usize,
 }
 
 #[tokio::main]
@@ -29,41 +48,88 @@ async fn main() -> Result<(), Error> {
 
 async fn run(args: Args) -> Result<(), Error> {
     let domains = generate_hostname(&args.domain, args.count);
-    let chunks = split_vec_into_parts(&domains, 4);
-    // let sizes = chunks.iter().map(|c| c.len()).collect::<Vec<usize>>();
-    // dbg!(sizes);
     let config = mk_resolver_config();
     let resolver =
         Resolver::builder_with_config(config, TokioConnectionProvider::default()).build();
-    tokio::try_join!(
-        mk_resolver_worker(chunks[0], &resolver),
-        mk_resolver_worker(chunks[1], &resolver),
-        mk_resolver_worker(chunks[2], &resolver),
-        mk_resolver_worker(chunks[3], &resolver),
-    )
-    .map(|_| ())
+    let errors = Arc::new(AtomicUsize::new(0));
+    let mut workers = JoinSet::new();
+    let started = Instant::now();
+    for hosts in split_vec_into_parts(&domains, args.concurrency.max(1)) {
+        workers.spawn(mk_resolver_worker(
+            hosts,
+            resolver.clone(),
+            errors.clone(),
+            args.max_errors,
+        ));
+    }
+    let mut measurements = Measurements::default();
+    while let Some(worker) = workers.join_next().await {
+        let worker = worker??;
+        measurements.latencies.extend(worker.latencies);
+        measurements.errors += worker.errors;
+    }
+    report(&mut measurements, started.elapsed());
+    Ok(())
 }
 
 async fn mk_resolver_worker(
-    hosts: &[String],
-    resolver: &Resolver<GenericConnector<TokioRuntimeProvider>>,
-) -> Result<(), Error> {
-    let resolver = resolver.clone();
+    hosts: Vec<String>,
+    resolver: Resolver<GenericConnector<TokioRuntimeProvider>>,
+    errors: Arc<AtomicUsize>,
+    max_errors: usize,
+) -> Result<Measurements, Error> {
+    let mut measurements = Measurements::default();
     for host in hosts {
+        let started = Instant::now();
         // Because of some race conditions we might get a None here
-        let ips = resolver.lookup_ip(host).await?;
-        if ips.iter().count() > 0 {
-            print!(".");
-        } else {
-            return Err(anyhow!("no ip found for {host}"));
+        let error = match resolver.lookup_ip(&host).await {
+            Ok(ips) if ips.iter().count() > 0 => {
+                measurements.latencies.push(started.elapsed());
+                continue;
+            }
+            Ok(_) => anyhow!("no ip found for {host}"),
+            Err(e) => e.into(),
+        };
+        measurements.errors += 1;
+        // the errors of all the workers count against the budget
+        if errors.fetch_add(1, Ordering::Relaxed) + 1 > max_errors {
+            return Err(error.context(format!("more than {max_errors} errors")));
         }
+        eprintln!("Error: {error}");
     }
-    Ok(())
+    Ok(measurements)
+}
+
+fn report(measurements: &mut Measurements, elapsed: Duration) {
+    let latencies = &mut measurements.latencies;
+    latencies.sort_unstable();
+    let total = latencies.len() + measurements.errors;
+    println!(
+        "{total} requests in {:.2}s ({:.0} requests/s), {} errors",
+        elapsed.as_secs_f64(),
+        total as f64 / elapsed.as_secs_f64(),
+        measurements.errors
+    );
+    if latencies.is_empty() {
+        return;
+    }
+    let percentile = |p: f64| {
+        // nearest rank
+        let rank = (p / 100.0 * latencies.len() as f64).ceil() as usize;
+        latencies[rank.clamp(1, latencies.len()) - 1].as_secs_f64() * 1000.0
+    };
+    println!(
+        "latency: p50 {:.2}ms, p95 {:.2}ms, p99 {:.2}ms, max {:.2}ms",
+        percentile(50.0),
+        percentile(95.0),
+        percentile(99.0),
+        percentile(100.0)
+    );
 }
 
-fn split_vec_into_parts<T>(vec: &[T], num_parts: usize) -> Vec<&[T]> {
-    let chunk_size = vec.len().div_ceil(num_parts); // Ceiling division
-    vec.chunks(chunk_size).collect()
+fn split_vec_into_parts<T: Clone>(vec: &[T], num_parts: usize) -> Vec<Vec<T>> {
+    let chunk_size = vec.len().div_ceil(num_parts).max(1); // Ceiling division
+    vec.chunks(chunk_size).map(<[T]>::to_vec).collect()
 }
 
 fn generate_hostname(domain: &str, samples: usize) -> Vec<String> {
@@ -0,0 +1,274 @@
+//! A soak test of the running app (`cargo run`): queries at a steady rate for hours, mixed with
+//! reloads, merges, record changes and pauses through the control pipe, sampling the memory
+//! (working set) of the app and the latency of the queries to catch leaks and degradation.
+//!
+//! It changes the records of the app: the `soak*` records it adds are removed when it ends.
+
+#[path = "../src/control/client.rs"]
+#[allow(dead_code)] // watching the queries isn't used
+mod client;
+#[path = "../src/control/protocol.rs"]
+#[allow(dead_code)] // the server side of the protocol is used by the app
+mod protocol;
+#[path = "../src/control/token.rs"]
+#[allow(dead_code)] // generating and checking the token is done by the app
+mod token;
+
+use anyhow::{Context, Error};
+use clap::Parser;
+use client::Client;
+use hickory_resolver::config::{NameServerConfig, ResolverConfig, ResolverOpts};
+use hickory_resolver::name_server::{GenericConnector, TokioConnectionProvider};
+use hickory_resolver::proto::runtime::TokioRuntimeProvider;
+use hickory_resolver::proto::xfer::Protocol;
+use hickory_resolver::Resolver;
+use protocol::{MergeInfo, Method};
+use rand::Rng;
+use rand_regex::Regex;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tokio::time::{interval, sleep, MissedTickBehavior};
+
+/// The records added by the merges (and removed at the end).
+const MERGED_RECORDS: u8 = 10;
+
+/// Query the running app for hours while changing its records, reporting its memory and the
+/// query latency.
+#[derive(Parser)]
+struct Args {
+    /// The top-level domain to query
+    #[arg(long, default_value = "loc")]
+    domain: String,
+    /// The port the app listens on
+    #[arg(long, default_value = "2053")]
+    port: u16,
+    /// How long to run
+    #[arg(long, default_value = "4")]
+    hours: f64,
+    /// Queries per second
+    #[arg(long, default_value = "50")]
+    rate: u32,
+    /// Seconds between the changes (reload, merge, add, pause and remove, in turn)
+    #[arg(long, default_value = "10")]
+    change_interval: u64,
+    /// Seconds between the samples
+    #[arg(long, default_value = "60")]
+    sample_interval: u64,
+    /// The process name of the app
+    #[arg(long, default_value = "dot-local-dns")]
+    process: String,
+}
+
+/// The queries since the previous sample.
+#[derive(Default)]
+struct Window {
+    latencies: Vec<Duration>,
+    errors: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let args = Args::parse();
+    let window = Arc::new(Mutex::new(Window::default()));
+    // queries are refused while paused, they aren't sent meanwhile
+    let paused = Arc::new(AtomicBool::new(false));
+    let resolver = mk_resolver(args.port);
+    let queries = tokio::spawn(send_queries(
+        resolver,
+        args.domain.clone(),
+        args.rate.max(1),
+        window.clone(),
+        paused.clone(),
+    ));
+    let changes = tokio::spawn(make_changes(
+        args.domain.clone(),
+        Duration::from_secs(args.change_interval),
+        paused,
+    ));
+    let started = Instant::now();
+    let duration = Duration::from_secs_f64(args.hours * 3600.0);
+    let mut samples = interval(Duration::from_secs(args.sample_interval));
+    samples.tick().await;
+    let first = working_set(&args.process).await?;
+    println!("working set at start: {:.1} MB", megabytes(first));
+    let mut last = first;
+    while started.elapsed() < duration {
+        samples.tick().await;
+        let sample = std::mem::take(&mut *window.lock().unwrap());
+        last = working_set(&args.process).await?;
+        report(started.elapsed(), sample, last);
+    }
+    queries.abort();
+    changes.abort();
+    println!(
+        "working set grew by {:.1} MB in {:.1} hours",
+        megabytes(last) - megabytes(first),
+        args.hours
+    );
+    remove_records(&args.domain).await
+}
+
+async fn send_queries(
+    resolver: Resolver<GenericConnector<TokioRuntimeProvider>>,
+    domain: String,
+    rate: u32,
+    window: Arc<Mutex<Window>>,
+    paused: Arc<AtomicBool>,
+) {
+    // the merged records, subdomains of them and unknown names
+    let pattern = format!(
+        "([a-z0-9]{{3,10}}\\.){{0,2}}(soak-[0-9]|[a-z]{{3,10}})\\.{}",
+        regex::escape(&domain)
+    );
+    let names = Regex::compile(&pattern, 100).expect("Invalid regex pattern");
+    let mut ticks = interval(Duration::from_secs(1) / rate);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        if paused.load(Ordering::Relaxed) {
+            continue;
+        }
+        let name: String = rand::rng().sample(&names);
+        let resolver = resolver.clone();
+        let window = window.clone();
+        tokio::spawn(async move {
+            let started = Instant::now();
+            let result = resolver.ipv4_lookup(name.as_str()).await;
+            let mut window = window.lock().unwrap();
+            match result {
+                Ok(_) => window.latencies.push(started.elapsed()),
+                Err(e) => {
+                    window.errors += 1;
+                    eprintln!("Error querying {name}: {e}");
+                }
+            }
+        });
+    }
+}
+
+/// Reload, merge, add a record, pause and remove the record, in turn.
+async fn make_changes(domain: String, period: Duration, paused: Arc<AtomicBool>) {
+    let mut client = match Client::connect(None).await {
+        Ok(client) => client,
+        Err(e) => return eprintln!("Not changing the records: {e:#}"),
+    };
+    let merged = tempfile::NamedTempFile::new().expect("creating the merged file");
+    let records: String = (0..MERGED_RECORDS)
+        .map(|i| format!("soak-{i}.{domain}:10.0.0.{i}\n"))
+        .collect();
+    std::fs::write(merged.path(), records).expect("writing the merged file");
+    let name = format!("soak.{domain}");
+    let mut ticks = interval(period);
+    ticks.tick().await;
+    for change in (0..5).cycle() {
+        ticks.tick().await;
+        let result = match change {
+            0 => client.call::<()>(Method::Reload).await,
+            1 => client
+                .call::<MergeInfo>(Method::Merge {
+                    path: merged.path().to_owned(),
+                })
+                .await
+                .map(|_| ()),
+            2 => {
+                let addr = Ipv4Addr::new(10, 1, 0, rand::rng().random());
+                let name = name.clone();
+                client.call::<()>(Method::AddRecord { name, addr }).await
+            }
+            3 => pause(&mut client, &paused).await,
+            _ => {
+                let name = name.clone();
+                client.call::<()>(Method::RemoveRecord { name }).await
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("Error changing the records: {e:#}");
+        }
+    }
+}
+
+async fn pause(client: &mut Client, paused: &AtomicBool) -> anyhow::Result<()> {
+    paused.store(true, Ordering::Relaxed);
+    let result = match client.call::<()>(Method::Pause).await {
+        Ok(()) => {
+            sleep(Duration::from_secs(1)).await;
+            client.call::<()>(Method::Resume).await
+        }
+        Err(e) => Err(e),
+    };
+    paused.store(false, Ordering::Relaxed);
+    result
+}
+
+async fn remove_records(domain: &str) -> Result<(), Error> {
+    let mut client = Client::connect(None).await?;
+    let names = (0..MERGED_RECORDS)
+        .map(|i| format!("soak-{i}.{domain}"))
+        .chain([format!("soak.{domain}")]);
+    for name in names {
+        // the record may already be removed
+        let _ = client.call::<()>(Method::RemoveRecord { name }).await;
+    }
+    Ok(())
+}
+
+/// The working set of the app process, in bytes.
+async fn working_set(process: &str) -> Result<u64, Error> {
+    let output = Command::new("powershell.exe")
+        .args([
+            "-NoLogo",
+            "-NoProfile",
+            "-Command",
+            &format!("(Get-Process -Name '{process}' | Select-Object -First 1).WorkingSet64"),
+        ])
+        .output()
+        .await?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout
+        .trim()
+        .parse()
+        .with_context(|| format!("reading the working set of {process} (is the app running?)"))
+}
+
+fn report(elapsed: Duration, mut window: Window, working_set: u64) {
+    window.latencies.sort_unstable();
+    let latencies = &window.latencies;
+    let percentile = |p: f64| {
+        // nearest rank
+        let rank = (p / 100.0 * latencies.len() as f64).ceil() as usize;
+        latencies
+            .get(rank.clamp(1, latencies.len().max(1)) - 1)
+            .map_or(0.0, |latency| latency.as_secs_f64() * 1000.0)
+    };
+    let minutes = elapsed.as_secs() / 60;
+    println!(
+        "[{:02}:{:02}] {} queries, {} errors, p50 {:.2}ms, p99 {:.2}ms, max {:.2}ms, working set {:.1} MB",
+        minutes / 60,
+        minutes % 60,
+        latencies.len() + window.errors,
+        window.errors,
+        percentile(50.0),
+        percentile(99.0),
+        percentile(100.0),
+        megabytes(working_set)
+    );
+}
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+fn mk_resolver(port: u16) -> Resolver<GenericConnector<TokioRuntimeProvider>> {
+    let name_server =
+        NameServerConfig::new(SocketAddr::from((Ipv4Addr::LOCALHOST, port)), Protocol::Udp);
+    let config = ResolverConfig::from_parts(None, vec![], vec![name_server]);
+    // every query goes to the app
+    let mut options = ResolverOpts::default();
+    options.cache_size = 0;
+    Resolver::builder_with_config(config, TokioConnectionProvider::default())
+        .with_options(options)
+        .build()
+}
@@ -0,0 +1,11 @@
+//! Any datagram is parsed or rejected, without panicking or hanging (e.g. on compression pointer
+//! loops or truncated labels).
+
+#![no_main]
+
+use dot_local_dns_fuzz::parse;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse(data);
+});
@@ -0,0 +1,22 @@
+//! The responses are written from parsed packets: whatever is parsed and written is parsed back,
+//! and written again to the same datagram.
+
+#![no_main]
+
+use dot_local_dns_fuzz::{parse, write};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(mut packet) = parse(data) else {
+        return;
+    };
+    // e.g. names with labels that are too long once lowercased
+    let Ok(written) = write(&mut packet) else {
+        return;
+    };
+    let mut parsed = parse(&written).expect("parsing a written packet");
+    assert_eq!(
+        write(&mut parsed).expect("writing a parsed packet"),
+        written
+    );
+});
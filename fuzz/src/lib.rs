@@ -0,0 +1,48 @@
+//! The packet parsing and writing of the server for the fuzz targets. The app is a binary, so the
+//! protocol module (which only depends on the prelude) is built here on its own.
+//!
+//! Run with `cargo +nightly fuzz run parse_packet` (or `round_trip`) from the repository root, or
+//! `just fuzz parse_packet`.
+
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+mod prelude {
+    pub use anyhow::{anyhow, Result};
+    pub use std::net::{Ipv4Addr, Ipv6Addr};
+    pub use tracing::warn;
+}
+
+#[allow(dead_code, clippy::new_without_default)]
+#[path = "../../src/dns/protocol.rs"]
+mod protocol;
+
+use prelude::*;
+pub use protocol::DnsPacket;
+use protocol::BytePacketBuffer;
+
+/// Parse the received datagram `data`.
+pub fn parse(data: &[u8]) -> Result<DnsPacket> {
+    let mut buffer = BytePacketBuffer::new();
+    let len = data.len().min(buffer.buf.len());
+    buffer.buf[..len].copy_from_slice(&data[..len]);
+    buffer.set_len(len);
+    block_on(DnsPacket::from_buffer(&mut buffer))
+}
+
+/// Write `packet` as a datagram.
+pub fn write(packet: &mut DnsPacket) -> Result<Vec<u8>> {
+    let mut buffer = BytePacketBuffer::new();
+    packet.write(&mut buffer)?;
+    Ok(buffer.buf[..buffer.pos()].to_vec())
+}
+
+/// Parsing doesn't wait on anything, a single poll completes it.
+fn block_on<T>(future: impl Future<Output = T>) -> T {
+    let mut future = pin!(future);
+    match future.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => output,
+        Poll::Pending => unreachable!("parsing is never pending"),
+    }
+}
@@ -0,0 +1,227 @@
+//! Point the DNS of the active network adapter at the server while the app runs (opt-in with
+//! `adapter_dns = true`). The server quietly refuses the queries outside the top level domain (see
+//! [`DnsServer::set_refuse_other_domains`](crate::dns::DnsServer::set_refuse_other_domains)), so
+//! the original servers stay configured after it and Windows asks them next.
+//!
+//! The original configuration is saved in `adapter-dns-backup.json` (in the configuration directory)
+//! before changing it and restored on exit. If it wasn't restored (the app crashed, or was stopped
+//! by a logoff or shutdown, when it can't ask for administrator rights) the backup is still there
+//! and the next start restores it (or keeps using it as the original when the mode is still
+//! enabled).
+
+use crate::elevation::{run_powershell, run_powershell_as_admin, ElevationDeclined};
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const BACKUP_FILE_NAME: &str = "adapter-dns-backup.json";
+const SERVER: Ipv4Addr = Ipv4Addr::LOCALHOST;
+
+/// The DNS configuration of an adapter.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct AdapterDnsConfig {
+    interface_index: u32,
+    alias: String,
+    /// The servers in use (from DHCP unless `static_servers`).
+    servers: Vec<Ipv4Addr>,
+    /// Were the servers configured manually (rather than by DHCP)?
+    static_servers: bool,
+}
+
+/// Restores the adapter configuration (see [`AdapterDns::restore`]).
+pub struct AdapterDns {
+    backup_path: PathBuf,
+}
+
+impl AdapterDns {
+    /// Point the active adapter at the server when `enabled` (and the server listens on
+    /// `listen_address`, `None` when it follows an adapter, and `port` where Windows can query it),
+    /// otherwise restore the configuration left behind by a previous run. Failures are reported
+    /// but don't stop the app.
+    pub async fn start(
+        enabled: bool,
+        config_dir: &Path,
+        listen_address: Option<Ipv4Addr>,
+        port: u16,
+    ) -> Self {
+        let adapter_dns = Self {
+            backup_path: config_dir.join(BACKUP_FILE_NAME),
+        };
+        let result = match enabled.then(|| check_listener(listen_address, port)) {
+            Some(Ok(())) => adapter_dns.configure().await,
+            Some(Err(e)) => adapter_dns.restore_backup().await.and(Err(e)),
+            None => adapter_dns.restore_backup().await,
+        };
+        if let Err(e) = result {
+            report_error("Error configuring the adapter DNS", &e);
+        }
+        adapter_dns
+    }
+
+    /// Restore the configuration left behind by a crashed run, if any (e.g. when uninstalling).
+    /// Returns whether there was one.
+    pub async fn restore_leftover(config_dir: &Path) -> Result<bool> {
+        let adapter_dns = Self {
+            backup_path: config_dir.join(BACKUP_FILE_NAME),
+        };
+        if !adapter_dns.backup_path.exists() {
+            return Ok(false);
+        }
+        adapter_dns.restore_backup().await?;
+        Ok(true)
+    }
+
+    /// Restore the original configuration (if it was changed).
+    pub async fn restore(&self) {
+        if let Err(e) = self.restore_backup().await {
+            report_error("Error restoring the adapter DNS", &e);
+        }
+    }
+
+    async fn configure(&self) -> Result<()> {
+        if self.backup_path.exists() {
+            info!(
+                "Adapter DNS backup found (the app didn't exit cleanly), keeping the adapter DNS"
+            );
+            return Ok(());
+        }
+        let original = active_adapter_dns().await?;
+        if original.servers.contains(&SERVER) {
+            info!(
+                "{} already uses {SERVER}, not changing its DNS",
+                original.alias
+            );
+            return Ok(());
+        }
+        fs::write(&self.backup_path, serde_json::to_string_pretty(&original)?)
+            .with_context(|| format!("writing {}", self.backup_path.display()))?;
+        let servers: Vec<Ipv4Addr> = [SERVER]
+            .into_iter()
+            .chain(original.servers.iter().copied())
+            .collect();
+        let action = format!("Pointing the DNS of {} at {APP_NAME}", original.alias);
+        if let Err(e) = run_powershell_as_admin(
+            &action,
+            set_servers_script(original.interface_index, &servers),
+        )
+        .await
+        {
+            // nothing changed, so there's nothing to restore
+            fs::remove_file(&self.backup_path)?;
+            return Err(e);
+        }
+        info!("Set the DNS servers of {} to {servers:?}", original.alias);
+        Ok(())
+    }
+
+    async fn restore_backup(&self) -> Result<()> {
+        let contents = match fs::read_to_string(&self.backup_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).context(format!("reading {}", self.backup_path.display())),
+        };
+        let original: AdapterDnsConfig = serde_json::from_str(&contents)
+            .with_context(|| format!("parsing {}", self.backup_path.display()))?;
+        let action = format!("Restoring the DNS of {}", original.alias);
+        run_powershell_as_admin(&action, restore_script(&original)).await?;
+        fs::remove_file(&self.backup_path)?;
+        info!("Restored the DNS servers of {}", original.alias);
+        Ok(())
+    }
+}
+
+/// Windows only queries port 53 of its DNS servers, and the adapter is pointed at [`SERVER`].
+fn check_listener(listen_address: Option<Ipv4Addr>, port: u16) -> Result<()> {
+    if port != 53 {
+        return Err(anyhow!(
+            "Windows only queries port 53, but the server listens on port {port}"
+        ));
+    }
+    match listen_address {
+        Some(SERVER) => Ok(()),
+        Some(address) => Err(anyhow!(
+            "The adapter is pointed at {SERVER}, but the server listens on {address}"
+        )),
+        None => Err(anyhow!(
+            "The adapter is pointed at {SERVER}, but the server listens on the address of an adapter"
+        )),
+    }
+}
+
+fn report_error(message: &str, e: &Error) {
+    if e.is::<ElevationDeclined>() {
+        warn!("{message}: {e}");
+    } else {
+        notify_error!("{message}: {e:#}");
+    }
+}
+
+/// The DNS configuration of the adapter with the default route.
+async fn active_adapter_dns() -> Result<AdapterDnsConfig> {
+    let output = run_powershell(ACTIVE_ADAPTER_SCRIPT.to_owned())
+        .await
+        .context("finding the active network adapter")?;
+    serde_json::from_str(output.trim())
+        .with_context(|| format!("parsing the adapter configuration: {}", output.trim()))
+}
+
+/// Prints the [`AdapterDnsConfig`] of the adapter with the default route as JSON. Manually set
+/// servers are in the `NameServer` registry value of the interface (empty when using DHCP).
+const ACTIVE_ADAPTER_SCRIPT: &str = "$ErrorActionPreference = 'Stop'; \
+$route = Get-NetRoute -DestinationPrefix '0.0.0.0/0' | Sort-Object { $_.RouteMetric + $_.InterfaceMetric } | Select-Object -First 1; \
+$dns = Get-DnsClientServerAddress -InterfaceIndex $route.InterfaceIndex -AddressFamily IPv4; \
+$guid = (Get-NetAdapter -InterfaceIndex $route.InterfaceIndex).InterfaceGuid; \
+$static = (Get-ItemProperty ('HKLM:\\SYSTEM\\CurrentControlSet\\Services\\Tcpip\\Parameters\\Interfaces\\' + $guid)).NameServer; \
+[pscustomobject]@{ interface_index = $route.InterfaceIndex; alias = $dns.InterfaceAlias; servers = @($dns.ServerAddresses); static_servers = [bool]$static } | ConvertTo-Json -Compress";
+
+fn set_servers_script(interface_index: u32, servers: &[Ipv4Addr]) -> String {
+    let servers = servers
+        .iter()
+        .map(|server| format!("'{server}'"))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "Set-DnsClientServerAddress -InterfaceIndex {interface_index} -ServerAddresses ({servers}) -ErrorAction Stop; Clear-DnsClientCache"
+    )
+}
+
+fn restore_script(original: &AdapterDnsConfig) -> String {
+    if original.static_servers {
+        set_servers_script(original.interface_index, &original.servers)
+    } else {
+        format!(
+            "Set-DnsClientServerAddress -InterfaceIndex {} -ResetServerAddresses -ErrorAction Stop; Clear-DnsClientCache",
+            original.interface_index
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_server_must_listen_on_the_loopback_port_53() {
+        assert!(check_listener(Some(SERVER), 53).is_ok());
+        assert!(check_listener(Some(SERVER), 5353).is_err());
+        assert!(check_listener(Some(Ipv4Addr::UNSPECIFIED), 53).is_err());
+        assert!(check_listener(Some(Ipv4Addr::new(192, 168, 1, 2)), 53).is_err());
+        assert!(check_listener(None, 53).is_err());
+    }
+
+    #[test]
+    fn adapter_scripts() {
+        let mut original: AdapterDnsConfig = serde_json::from_str(
+            r#"{"interface_index":12,"alias":"Wi-Fi","servers":["192.168.1.1","1.1.1.1"],"static_servers":false}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            restore_script(&original),
+            "Set-DnsClientServerAddress -InterfaceIndex 12 -ResetServerAddresses -ErrorAction Stop; Clear-DnsClientCache"
+        );
+        original.static_servers = true;
+        assert_eq!(
+            restore_script(&original),
+            "Set-DnsClientServerAddress -InterfaceIndex 12 -ServerAddresses ('192.168.1.1','1.1.1.1') -ErrorAction Stop; Clear-DnsClientCache"
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! Record values naming a network adapter instead of an address (e.g. `laptop.loc:@tailscale` in
+//! the records file), for addresses that change: the Tailscale address, or the host side of the
+//! Hyper-V (default switch) and `VirtualBox` (host-only) networks, which change across reboots.
+//! The adapters are polled and the server is told when their addresses change, so it reloads the
+//! records. On macOS the adapters are the network interfaces (e.g. `en0`, `utun3`).
+
+use crate::prelude::*;
+#[cfg(target_os = "windows")]
+use std::ffi::c_void;
+#[cfg(target_os = "windows")]
+use std::ptr::null;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::sleep;
+#[cfg(target_os = "windows")]
+use windows_strings::PWSTR;
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Foundation::{ERROR_BUFFER_OVERFLOW, NO_ERROR};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::NetworkManagement::IpHelper::{
+    GetAdaptersAddresses, GAA_FLAG_SKIP_ANYCAST, GAA_FLAG_SKIP_DNS_SERVER, GAA_FLAG_SKIP_MULTICAST,
+    IP_ADAPTER_ADDRESSES_LH,
+};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::NetworkManagement::Ndis::IfOperStatusUp;
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Networking::WinSock::{AF_INET, SOCKADDR_IN};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The current addresses of the aliases, by alias (e.g. `@tailscale`).
+pub type AliasAddresses = HashMap<String, Ipv4Addr>;
+
+/// A record value resolving to the address of an adapter.
+struct Alias {
+    value: &'static str,
+    /// Part of the adapter name or description (of the interface name on macOS).
+    adapter: &'static str,
+    /// Does the address belong to the network of the alias?
+    accepts: fn(Ipv4Addr) -> bool,
+}
+
+const ALIASES: [Alias; 3] = [
+    Alias {
+        value: "@tailscale",
+        adapter: if cfg!(target_os = "macos") {
+            "utun"
+        } else {
+            "Tailscale"
+        },
+        accepts: is_tailnet_address,
+    },
+    Alias {
+        value: "@hyperv-default-switch",
+        adapter: "vEthernet (Default Switch)",
+        accepts: is_assigned_address,
+    },
+    Alias {
+        value: "@vbox-hostonly",
+        adapter: if cfg!(target_os = "macos") {
+            "vboxnet"
+        } else {
+            "VirtualBox Host-Only"
+        },
+        accepts: is_assigned_address,
+    },
+];
+
+/// Is `value` (e.g. `@tailscale`) a known alias?
+pub fn is_alias(value: &str) -> bool {
+    ALIASES.iter().any(|alias| alias.value == value)
+}
+
+/// The known aliases, for error messages.
+pub fn alias_names() -> String {
+    ALIASES.map(|alias| alias.value).join(", ")
+}
+
+/// The addresses of the aliases whose adapters are connected.
+pub fn alias_addresses() -> AliasAddresses {
+    match adapter_addresses() {
+        Ok(addresses) => resolve_aliases(&addresses),
+        Err(e) => {
+            warn!("Error listing the network adapters: {e:#}");
+            HashMap::new()
+        }
+    }
+}
+
+/// Is a network adapter connected with an assigned address (at login the network may not be up
+/// yet)?
+pub fn network_connected() -> bool {
+    adapter_addresses().is_ok_and(|addresses| {
+        addresses
+            .iter()
+            .any(|(_, ip)| !ip.is_loopback() && is_assigned_address(*ip))
+    })
+}
+
+/// The assigned address of the connected adapter whose name or description contains `adapter`
+/// (case-insensitive).
+pub fn adapter_address(adapter: &str) -> Option<Ipv4Addr> {
+    match adapter_addresses() {
+        Ok(addresses) => find_adapter_address(&addresses, adapter),
+        Err(e) => {
+            warn!("Error listing the network adapters: {e:#}");
+            None
+        }
+    }
+}
+
+fn find_adapter_address(addresses: &[(String, Ipv4Addr)], adapter: &str) -> Option<Ipv4Addr> {
+    let adapter = adapter.to_lowercase();
+    addresses
+        .iter()
+        .find(|(name, ip)| {
+            name.to_lowercase().contains(&adapter) && !ip.is_loopback() && is_assigned_address(*ip)
+        })
+        .map(|(_, ip)| *ip)
+}
+
+/// The names (friendly name and description) of the connected adapters.
+pub fn connected_adapters() -> Result<Vec<String>> {
+    let mut names: Vec<String> = adapter_addresses()?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    names.dedup();
+    Ok(names)
+}
+
+/// Tell the server whenever the addresses of the aliases change, until the server stops.
+pub async fn watch_alias_addresses(notify_tx: Sender<Notification>) {
+    let mut addresses = alias_addresses();
+    loop {
+        sleep(POLL_INTERVAL).await;
+        let current = alias_addresses();
+        if current == addresses {
+            continue;
+        }
+        addresses = current;
+        if notify_tx
+            .send(AliasesChanged(addresses.clone()))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Tell the server whenever the listen adapter has another address than the one the server
+/// listens on (also when binding it failed, so it's retried), until the server stops.
+pub async fn watch_listen_adapter(
+    adapter: String,
+    status_rx: watch::Receiver<ServerStatus>,
+    notify_tx: Sender<Notification>,
+) {
+    loop {
+        sleep(POLL_INTERVAL).await;
+        let listening = match &*status_rx.borrow() {
+            ServerStatus::Listening(addr) | ServerStatus::Paused(addr) => addr.ip(),
+            _ => continue,
+        };
+        let Some(address) = adapter_address(&adapter) else {
+            continue;
+        };
+        if listening == address {
+            continue;
+        }
+        if notify_tx.send(ListenAdapterMoved(address)).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn resolve_aliases(addresses: &[(String, Ipv4Addr)]) -> AliasAddresses {
+    ALIASES
+        .iter()
+        .filter_map(|alias| {
+            addresses
+                .iter()
+                .find(|(name, ip)| name.contains(alias.adapter) && (alias.accepts)(*ip))
+                .map(|(_, ip)| (alias.value.to_owned(), *ip))
+        })
+        .collect()
+}
+
+/// Tailscale assigns addresses from the shared address space (100.64.0.0/10).
+fn is_tailnet_address(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    first == 100 && second & 0b1100_0000 == 64
+}
+
+/// Not the link-local address Windows uses until the adapter gets an address.
+fn is_assigned_address(ip: Ipv4Addr) -> bool {
+    !ip.is_link_local()
+}
+
+/// The IPv4 addresses of the connected adapters with the adapter names (friendly name and
+/// description).
+#[cfg(target_os = "windows")]
+fn adapter_addresses() -> Result<Vec<(String, Ipv4Addr)>> {
+    let flags = GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST | GAA_FLAG_SKIP_DNS_SERVER;
+    let mut size = 16 * 1024;
+    let mut buffer: Vec<u64> = vec![];
+    loop {
+        buffer.resize((size as usize).div_ceil(8), 0);
+        let result = unsafe {
+            GetAdaptersAddresses(
+                u32::from(AF_INET),
+                flags,
+                null::<c_void>(),
+                buffer.as_mut_ptr().cast(),
+                &raw mut size,
+            )
+        };
+        match result {
+            NO_ERROR => break,
+            ERROR_BUFFER_OVERFLOW => (),
+            code => {
+                return Err(std::io::Error::from_raw_os_error(
+                    i32::try_from(code).unwrap_or(i32::MAX),
+                ))
+                .context("GetAdaptersAddresses");
+            }
+        }
+    }
+    let mut addresses = vec![];
+    let mut adapter = buffer.as_ptr().cast::<IP_ADAPTER_ADDRESSES_LH>();
+    while let Some(current) = unsafe { adapter.as_ref() } {
+        if current.OperStatus == IfOperStatusUp {
+            let name = format!(
+                "{} {}",
+                wide_string(current.FriendlyName),
+                wide_string(current.Description)
+            );
+            let mut unicast = current.FirstUnicastAddress;
+            while let Some(address) = unsafe { unicast.as_ref() } {
+                let sockaddr = address.Address.lpSockaddr;
+                if !sockaddr.is_null() && unsafe { (*sockaddr).sa_family } == AF_INET {
+                    let sockaddr = unsafe { sockaddr.cast::<SOCKADDR_IN>().read_unaligned() };
+                    let ip = Ipv4Addr::from(u32::from_be(unsafe { sockaddr.sin_addr.S_un.S_addr }));
+                    addresses.push((name.clone(), ip));
+                }
+                unicast = address.Next;
+            }
+        }
+        adapter = current.Next.cast_const();
+    }
+    Ok(addresses)
+}
+
+#[cfg(target_os = "windows")]
+fn wide_string(s: *mut u16) -> String {
+    let s = PWSTR::from_raw(s);
+    if s.is_null() {
+        return String::new();
+    }
+    String::from_utf16_lossy(unsafe { s.as_wide() })
+}
+
+/// The IPv4 addresses of the connected (up and running) interfaces with the interface names.
+#[cfg(target_os = "macos")]
+fn adapter_addresses() -> Result<Vec<(String, Ipv4Addr)>> {
+    let output = std::process::Command::new("ifconfig")
+        .output()
+        .context("running ifconfig")?;
+    if !output.status.success() {
+        return Err(anyhow!("ifconfig failed ({})", output.status));
+    }
+    Ok(ifconfig_addresses(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The addresses of the running interfaces in `ifconfig` output: an unindented line with the name
+/// and the flags of every interface, then indented lines with its addresses (`inet <address> …`).
+#[cfg(target_os = "macos")]
+fn ifconfig_addresses(output: &str) -> Vec<(String, Ipv4Addr)> {
+    let mut addresses = vec![];
+    let mut interface: Option<&str> = None;
+    for line in output.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            interface = line.split_once(": flags=").and_then(|(name, flags)| {
+                let flags = flags.split(['<', '>']).nth(1).unwrap_or_default();
+                let flags: Vec<&str> = flags.split(',').collect();
+                (flags.contains(&"UP") && flags.contains(&"RUNNING")).then_some(name)
+            });
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        if let (Some(name), Some("inet"), Some(ip)) = (interface, words.next(), words.next()) {
+            if let Ok(ip) = ip.parse() {
+                addresses.push((name.to_owned(), ip));
+            }
+        }
+    }
+    addresses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn aliases_resolve_to_their_adapter_addresses() {
+        let addresses = [
+            (
+                "Ethernet Intel(R) Ethernet Connection".to_owned(),
+                Ipv4Addr::new(192, 168, 1, 10),
+            ),
+            (
+                "vEthernet (Default Switch) Hyper-V Virtual Ethernet Adapter".to_owned(),
+                Ipv4Addr::new(172, 20, 96, 1),
+            ),
+            (
+                "Ethernet 3 VirtualBox Host-Only Ethernet Adapter".to_owned(),
+                Ipv4Addr::new(169, 254, 10, 1),
+            ),
+            (
+                "Tailscale Tailscale Tunnel".to_owned(),
+                Ipv4Addr::new(100, 90, 1, 2),
+            ),
+        ];
+        let aliases = resolve_aliases(&addresses);
+        assert_eq!(
+            aliases.get("@hyperv-default-switch"),
+            Some(&Ipv4Addr::new(172, 20, 96, 1))
+        );
+        assert_eq!(
+            aliases.get("@tailscale"),
+            Some(&Ipv4Addr::new(100, 90, 1, 2))
+        );
+        assert_eq!(aliases.get("@vbox-hostonly"), None);
+        assert!(is_alias("@vbox-hostonly"));
+        assert!(!is_alias("@wifi"));
+    }
+
+    #[test]
+    fn adapters_are_found_by_part_of_their_name() {
+        let addresses = [
+            (
+                "vEthernet (WSL) Hyper-V Virtual Ethernet Adapter".to_owned(),
+                Ipv4Addr::new(169, 254, 3, 4),
+            ),
+            (
+                "vEthernet (WSL) Hyper-V Virtual Ethernet Adapter".to_owned(),
+                Ipv4Addr::new(172, 28, 80, 1),
+            ),
+            (
+                "Ethernet Intel(R) Ethernet Connection".to_owned(),
+                Ipv4Addr::new(192, 168, 1, 10),
+            ),
+        ];
+        assert_eq!(
+            find_adapter_address(&addresses, "vethernet (wsl)"),
+            Some(Ipv4Addr::new(172, 28, 80, 1))
+        );
+        assert_eq!(
+            find_adapter_address(&addresses, "Intel"),
+            Some(Ipv4Addr::new(192, 168, 1, 10))
+        );
+        assert_eq!(find_adapter_address(&addresses, "Wi-Fi"), None);
+    }
+
+    #[test]
+    fn tailnet_addresses() {
+        assert!(is_tailnet_address(Ipv4Addr::new(100, 64, 0, 1)));
+        assert!(is_tailnet_address(Ipv4Addr::new(100, 127, 255, 254)));
+        assert!(!is_tailnet_address(Ipv4Addr::new(100, 128, 0, 1)));
+        assert!(!is_tailnet_address(Ipv4Addr::new(192, 168, 1, 10)));
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn aliases_resolve_to_their_interface_addresses() {
+        let output = "lo0: flags=8049<UP,LOOPBACK,RUNNING,MULTICAST> mtu 16384
+\tinet 127.0.0.1 netmask 0xff000000
+en0: flags=8863<UP,BROADCAST,SMART,RUNNING,SIMPLEX,MULTICAST> mtu 1500
+\tether 3c:22:fb:00:00:01
+\tinet6 fe80::1%en0 prefixlen 64 secured scopeid 0x6
+\tinet 192.168.1.10 netmask 0xffffff00 broadcast 192.168.1.255
+\tstatus: active
+en1: flags=8822<BROADCAST,SMART,SIMPLEX,MULTICAST> mtu 1500
+\tinet 10.0.0.5 netmask 0xffffff00 broadcast 10.0.0.255
+utun0: flags=8051<UP,POINTOPOINT,RUNNING,MULTICAST> mtu 1380
+\tinet6 fe80::2%utun0 prefixlen 64 scopeid 0xf
+utun3: flags=8051<UP,POINTOPOINT,RUNNING,MULTICAST> mtu 1280
+\tinet 100.90.1.2 --> 100.90.1.2 netmask 0xffffffff
+";
+        let addresses = ifconfig_addresses(output);
+        assert_eq!(
+            addresses,
+            [
+                ("lo0".to_owned(), Ipv4Addr::LOCALHOST),
+                ("en0".to_owned(), Ipv4Addr::new(192, 168, 1, 10)),
+                ("utun3".to_owned(), Ipv4Addr::new(100, 90, 1, 2)),
+            ]
+        );
+        let aliases = resolve_aliases(&addresses);
+        assert_eq!(
+            aliases.get("@tailscale"),
+            Some(&Ipv4Addr::new(100, 90, 1, 2))
+        );
+        assert_eq!(aliases.get("@vbox-hostonly"), None);
+        assert_eq!(
+            find_adapter_address(&addresses, "en0"),
+            Some(Ipv4Addr::new(192, 168, 1, 10))
+        );
+    }
+}
@@ -1,20 +1,169 @@
+use crate::audit;
 use crate::prelude::*;
 #[cfg_attr(test, allow(unused_imports))]
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
+const CONFIG_FILE_NAME: &str = "application.toml";
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
 pub struct AppConfig {
     pub top_level_domain: String,
     pub port: u16,
+    /// The address the DNS server listens on. A LAN address (or `0.0.0.0`) also serves the other
+    /// devices on the network.
+    #[serde(default = "default_listen_address")]
+    pub listen_address: Ipv4Addr,
+    /// Listen on the address of this network adapter (part of its name or description) instead,
+    /// following it when it changes (e.g. a virtual switch). `listen_address` is used while the
+    /// adapter isn't connected.
+    #[serde(default)]
+    pub listen_adapter: Option<String>,
     pub log_level: String,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    #[serde(default)]
+    pub log_rotation: LogRotationConfig,
     pub logging_dir: PathBuf,
     pub records_file: PathBuf,
     pub start_at_login: bool,
+    /// How the app is started at login.
+    #[serde(default)]
+    pub start_at_login_method: StartAtLoginMethod,
+    /// Start with DNS serving paused (e.g. when starting at login but only needed on demand).
+    #[serde(default)]
+    pub start_paused: bool,
+    /// Keep a `.local` top level domain without offering to switch when an mDNS responder runs
+    /// (set when the offer is declined).
+    #[serde(default)]
+    pub keep_mdns_tld: bool,
+    /// Flush the Windows DNS client cache after reloading the records.
+    #[serde(default)]
+    pub flush_dns_cache_on_reload: bool,
+    /// Write every answered query to `queries.log` in the logs directory.
+    #[serde(default)]
+    pub query_log: bool,
+    /// Point the DNS of the active network adapter at the server while the app runs.
+    #[serde(default)]
+    pub adapter_dns: bool,
+    /// Answer every name pointing at 127.0.0.1 with its own loopback address.
+    #[serde(default)]
+    pub loopback_aliases: bool,
+    /// Publish the query rate, error rate and latency as Windows performance counters.
+    #[serde(default)]
+    pub performance_counters: bool,
+    /// Answer `localhost` and its subdomains (e.g. `app.localhost`) with the loopback addresses.
+    #[serde(default = "default_localhost_zone")]
+    pub localhost_zone: bool,
+    /// Log the queries taking longer than this many milliseconds to answer (0 disables it).
+    #[serde(default = "default_slow_query_ms")]
+    pub slow_query_ms: u64,
+    /// Answer the queries not answered within this many milliseconds with SERVFAIL (0, the
+    /// default, disables it).
+    #[serde(default)]
+    pub query_deadline_ms: u64,
+    /// Log a summary of the traffic every this many minutes (0 disables the summaries).
+    #[serde(default = "default_stats_summary_minutes")]
+    pub stats_summary_minutes: u64,
+    /// Check that the system sends the queries of the top level domain to the server every this
+    /// many minutes (0, the default, disables the check).
+    #[serde(default)]
+    pub resolver_check_minutes: u64,
     pub config_revision: ConfigRevision,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub updates: UpdatesConfig,
+    #[serde(default)]
+    pub admin_api: AdminApiConfig,
+    #[serde(default)]
+    pub dnstap: DnstapConfig,
+    #[serde(default)]
+    pub docker: DockerSyncConfig,
+    #[serde(default)]
+    pub kubernetes: KubernetesSyncConfig,
+    #[serde(default)]
+    pub proxies: ProxiesSyncConfig,
+    #[serde(default)]
+    pub machine: MachineSyncConfig,
+    #[serde(default)]
+    pub login_start: LoginStartConfig,
+    #[serde(default)]
+    pub auto_pause: AutoPauseConfig,
     #[serde(skip)]
     pub config_path: PathBuf,
 }
 
+fn default_listen_address() -> Ipv4Addr {
+    Ipv4Addr::LOCALHOST
+}
+
+fn default_localhost_zone() -> bool {
+    true
+}
+
+fn default_slow_query_ms() -> u64 {
+    100
+}
+
+fn default_stats_summary_minutes() -> u64 {
+    60
+}
+
+/// The format of the log lines. JSON lines can be shipped to log collectors (e.g. Loki or Seq)
+/// without parsing the text format.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// How the log files (and the query log) are rotated.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct LogRotationConfig {
+    /// `size` rotates when the file reaches `max_size_mb`, `daily` and `hourly` also rotate at the
+    /// start of every day (hour).
+    pub criterion: RotationCriterion,
+    pub max_size_mb: u64,
+    /// The rotated files are numbered (`application_r00001.log`) or timestamped
+    /// (`application_r2025-01-01_10-00-00.log`).
+    pub naming: RotationNaming,
+    /// The number of rotated files to keep, older ones are deleted.
+    pub keep_files: usize,
+    /// Gzip the rotated files (`.log.gz`).
+    pub compress: bool,
+}
+
+impl Default for LogRotationConfig {
+    fn default() -> Self {
+        Self {
+            criterion: RotationCriterion::Size,
+            max_size_mb: 10,
+            naming: RotationNaming::Numbers,
+            keep_files: 7,
+            compress: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum RotationCriterion {
+    Size,
+    Daily,
+    Hourly,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum RotationNaming {
+    Numbers,
+    Timestamps,
+}
+
 /// These are variable that are changed between OS, runtime environment, etc...
 #[derive(Clone)]
 struct DynamicValues {
@@ -24,17 +173,255 @@ struct DynamicValues {
     log_level: String,
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
 pub struct ConfigRevision {
     revision: u8,
 }
 
+/// Controls which events pop up a notification. Disabled events are still logged.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct NotificationsConfig {
+    /// Suppress all notifications (fatal error dialogs are still shown).
+    pub mute: bool,
+    /// How notifications and dialogs are shown.
+    pub backend: NotificationBackend,
+    pub reload_success: bool,
+    pub reload_failure: bool,
+    pub merge: bool,
+    /// Errors while handling DNS requests (usually malformed packets).
+    pub server_errors: bool,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        Self {
+            mute: false,
+            backend: NotificationBackend::default(),
+            reload_success: true,
+            reload_failure: true,
+            merge: true,
+            server_errors: false,
+        }
+    }
+}
+
+/// How the app is started at login.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StartAtLoginMethod {
+    /// The startup entry in the registry (`Run` key), started unelevated.
+    #[default]
+    Registry,
+    /// A scheduled task started with the highest privileges, so the app can bind port 53 or change
+    /// the NRPT rules without a UAC prompt. Enabling and disabling it asks for administrator rights.
+    ScheduledTask,
+}
+
+/// How notifications and dialogs are shown (they're only logged when headless regardless).
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationBackend {
+    /// Toast notifications, message boxes for errors.
+    #[default]
+    Toast,
+    /// Message boxes, when toasts are disabled (e.g. by a group policy).
+    MessageBox,
+    /// Only logged.
+    Log,
+}
+
+/// Automatic updates are opt-in. The "Check for Updates" menu item works regardless.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct UpdatesConfig {
+    /// Periodically check for new releases, download and verify the installer.
+    pub automatic: bool,
+    pub check_interval_hours: u64,
+}
+
+impl Default for UpdatesConfig {
+    fn default() -> Self {
+        Self {
+            automatic: false,
+            check_interval_hours: 24,
+        }
+    }
+}
+
+/// The HTTP admin API is opt-in and only listens on the loopback interface. Requests require the
+/// per-install API token (see `control::token`).
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct AdminApiConfig {
+    pub enabled: bool,
+    pub port: u16,
+}
+
+impl Default for AdminApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 5380,
+        }
+    }
+}
+
+/// The dnstap output is opt-in. It's written to `file` or streamed to a Frame Streams collector
+/// listening on `address` (e.g. `fstrm_capture`), `dnstap.fstrm` in the logs directory by default.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Default)]
+#[serde(default)]
+pub struct DnstapConfig {
+    pub enabled: bool,
+    pub file: Option<PathBuf>,
+    pub address: Option<SocketAddr>,
+}
+
+/// Registering the docker compose services is opt-in. Running services get a
+/// `service.project<tld>` record pointing at `address` (their published ports are on localhost
+/// with Docker Desktop), unless their labels say otherwise.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct DockerSyncConfig {
+    pub enabled: bool,
+    pub address: Ipv4Addr,
+}
+
+impl Default for DockerSyncConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: Ipv4Addr::LOCALHOST,
+        }
+    }
+}
+
+/// Mirroring the Ingress and `HTTPRoute` hostnames of a local cluster is opt-in. The cluster is the
+/// current `kubectl` context unless `context` (and `kubeconfig`) are set. The records point at the
+/// published address of the ingress controller (or gateway), or `address` when set.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct KubernetesSyncConfig {
+    pub enabled: bool,
+    pub context: Option<String>,
+    pub kubeconfig: Option<PathBuf>,
+    pub address: Option<Ipv4Addr>,
+    pub interval_secs: u64,
+}
+
+impl Default for KubernetesSyncConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            context: None,
+            kubeconfig: None,
+            address: None,
+            interval_secs: 30,
+        }
+    }
+}
+
+/// Registering the hostnames routed by a local Traefik or Caddy is opt-in. Their admin APIs are
+/// polled at the default addresses unless configured otherwise, and the names point at `address`.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct ProxiesSyncConfig {
+    pub enabled: bool,
+    pub traefik_api: String,
+    pub caddy_api: String,
+    pub address: Ipv4Addr,
+    pub interval_secs: u64,
+}
+
+impl Default for ProxiesSyncConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            traefik_api: "http://127.0.0.1:8080".to_owned(),
+            caddy_api: "http://127.0.0.1:2019".to_owned(),
+            address: Ipv4Addr::LOCALHOST,
+            interval_secs: 30,
+        }
+    }
+}
+
+/// The records of the machine (`<computer name><tld>`) and of the running WSL distributions are
+/// registered unless disabled. WSL addresses are polled every `interval_secs`.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct MachineSyncConfig {
+    pub computer_name: bool,
+    pub wsl: bool,
+    pub interval_secs: u64,
+}
+
+impl Default for MachineSyncConfig {
+    fn default() -> Self {
+        Self {
+            computer_name: true,
+            wsl: true,
+            interval_secs: 60,
+        }
+    }
+}
+
+/// Pause serving while one of the `adapters` (part of the adapter name or description, e.g. of the
+/// corporate VPN) is connected, resume when they disconnect.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Default)]
+#[serde(default)]
+pub struct AutoPauseConfig {
+    pub adapters: Vec<String>,
+}
+
+/// When started at login the network (and the mapped drive of a records file) may not be ready yet,
+/// serving can start after a delay and wait (up to `network_timeout_secs`) for the network.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct LoginStartConfig {
+    pub delay_secs: u64,
+    /// Wait for a network adapter to be connected and the records file to be reachable.
+    pub wait_for_network: bool,
+    pub network_timeout_secs: u64,
+    /// Extra arguments of the app started at login (e.g. `--paused`).
+    pub args: Vec<String>,
+}
+
+impl Default for LoginStartConfig {
+    fn default() -> Self {
+        Self {
+            delay_secs: 0,
+            wait_for_network: false,
+            network_timeout_secs: 60,
+            args: vec![],
+        }
+    }
+}
+
+impl NotificationsConfig {
+    pub fn allows(&self, event: NotificationEvent) -> bool {
+        if self.mute {
+            return false;
+        }
+        match event {
+            NotificationEvent::ReloadSuccess => self.reload_success,
+            NotificationEvent::ReloadFailure => self.reload_failure,
+            NotificationEvent::Merge => self.merge,
+            NotificationEvent::ServerError => self.server_errors,
+        }
+    }
+}
+
 impl AppConfig {
     pub fn new() -> Result<Self> {
         let values = DynamicValues::get()?;
-        let config_path = values.config_dir.join("application.toml");
+        let config_path = values.config_dir.join(CONFIG_FILE_NAME);
         if config_path.exists() {
-            Self::from_file(config_path)
+            let config = Self::from_file(config_path)?;
+            audit::detect_file_edit(&config).unwrap_or_else(|e| {
+                warn!("Error checking configuration history: {e}");
+            });
+            Ok(config)
         } else {
             let config = Self::generate(config_path, values);
             config.init_files()?;
@@ -42,53 +429,209 @@ impl AppConfig {
         }
     }
 
-    pub fn set_start_at_login(&mut self, start: bool) -> Result<()> {
+    /// Whether the configuration file was created (i.e. this isn't the first run).
+    pub fn exists() -> Result<bool> {
+        Ok(DynamicValues::get()?
+            .config_dir
+            .join(CONFIG_FILE_NAME)
+            .exists())
+    }
+
+    /// The local address the firewall rule allows the queries to: any with a listen adapter, as
+    /// its address changes.
+    #[cfg(target_os = "windows")]
+    pub fn firewall_address(&self) -> Ipv4Addr {
+        if self.listen_adapter.is_some() {
+            Ipv4Addr::UNSPECIFIED
+        } else {
+            self.listen_address
+        }
+    }
+
+    pub fn config_dir(&self) -> &Path {
+        self.config_path.parent().unwrap_or_else(|| Path::new(""))
+    }
+
+    pub fn set_start_at_login(&mut self, start: bool, source: ChangeSource) -> Result<()> {
         if self.start_at_login == start {
             debug!("Start at login is already: {start}");
             Ok(())
         } else {
             debug!("Setting start at login to: {start}");
-            self.start_at_login = start;
-            self.save_with_comments()
+            self.update(source, |config| config.start_at_login = start)
+        }
+    }
+
+    /// The extra arguments of the app started at login (checked by the caller).
+    #[cfg(target_os = "windows")]
+    pub fn set_login_args(&mut self, args: Vec<String>, source: ChangeSource) -> Result<()> {
+        debug!("Setting the start at login arguments to: {args:?}");
+        self.update(source, |config| config.login_start.args = args)
+    }
+
+    #[cfg(target_os = "windows")]
+    pub fn set_flush_dns_cache_on_reload(
+        &mut self,
+        flush: bool,
+        source: ChangeSource,
+    ) -> Result<()> {
+        debug!("Setting flush DNS cache on reload to: {flush}");
+        self.update(source, |config| config.flush_dns_cache_on_reload = flush)
+    }
+
+    pub fn set_keep_mdns_tld(&mut self, keep: bool, source: ChangeSource) -> Result<()> {
+        debug!("Setting keep the mDNS top level domain to: {keep}");
+        self.update(source, |config| config.keep_mdns_tld = keep)
+    }
+
+    pub fn set_top_level_domain(&mut self, tld: &str, source: ChangeSource) -> Result<()> {
+        validate_top_level_domain(tld)?;
+        debug!("Setting the top level domain to: {tld}");
+        self.update(source, |config| {
+            tld.clone_into(&mut config.top_level_domain);
+        })
+    }
+
+    /// Import the portable settings from the contents of another configuration file. Machine
+    /// specific values (paths, start at login) are kept.
+    #[cfg(any(target_os = "windows", test))]
+    pub fn import_settings(&mut self, contents: &str, source: ChangeSource) -> Result<()> {
+        let imported: Self = toml::from_str(contents).context("parsing imported configuration")?;
+        validate_top_level_domain(&imported.top_level_domain)?;
+        self.update(source, |config| {
+            config.top_level_domain = imported.top_level_domain;
+            config.port = imported.port;
+            config.log_level = imported.log_level;
+            config.log_format = imported.log_format;
+            config.log_rotation = imported.log_rotation;
+            config.notifications = imported.notifications;
+            config.updates = imported.updates;
+            config.flush_dns_cache_on_reload = imported.flush_dns_cache_on_reload;
+            config.query_log = imported.query_log;
+            config.localhost_zone = imported.localhost_zone;
+            config.slow_query_ms = imported.slow_query_ms;
+            config.query_deadline_ms = imported.query_deadline_ms;
+            config.stats_summary_minutes = imported.stats_summary_minutes;
+            config.resolver_check_minutes = imported.resolver_check_minutes;
+        })
+    }
+
+    /// Apply provisioning settings: any subset of the configuration file's settings (sections are
+    /// merged key by key). Unlike [`Self::import_settings`] every setting may be seeded.
+    pub fn apply_seed(&mut self, seed: toml::Table, source: ChangeSource) -> Result<()> {
+        let mut merged = toml::Table::try_from(&*self)?;
+        merge_tables(&mut merged, seed);
+        let seeded: Self = merged.try_into().context("parsing provisioning settings")?;
+        validate_top_level_domain(&seeded.top_level_domain)?;
+        self.update(source, |config| {
+            *config = AppConfig {
+                config_path: config.config_path.clone(),
+                config_revision: config.config_revision.clone(),
+                ..seeded
+            };
+        })
+    }
+
+    /// Open the configuration file in the default editor, recreating it first if it was deleted.
+    #[cfg(target_os = "windows")]
+    pub fn safe_open_config_file(&self) -> Result<()> {
+        self.ensure_config_file()?;
+        open_path(&self.config_path)
+    }
+
+    #[cfg(any(target_os = "windows", test))]
+    fn ensure_config_file(&self) -> Result<()> {
+        if self.config_path.exists() {
+            return Ok(());
         }
+        self.save_with_comments()?;
+        audit::record_change(
+            self.config_dir(),
+            ChangeSource::Application,
+            "recreated missing configuration file",
+        )
+    }
+
+    /// Apply a change, save the configuration and record the change in the configuration history.
+    fn update(&mut self, source: ChangeSource, change: impl FnOnce(&mut Self)) -> Result<()> {
+        let previous = self.clone();
+        change(self);
+        self.save_with_comments()?;
+        let description = audit::describe_changes(&previous, self)?;
+        audit::record_change(self.config_dir(), source, &description)
     }
 
     fn from_file(path: PathBuf) -> Result<Self> {
-        fs::read_to_string(&path)
+        let config: Self = fs::read_to_string(&path)
             .map_err(anyhow::Error::from)
             .and_then(|s| toml::from_str(&s).map_err(anyhow::Error::from))
             .map(|config| AppConfig {
                 config_path: path,
                 ..config
-            })
+            })?;
+        validate_top_level_domain(&config.top_level_domain)
+            .with_context(|| format!("Invalid configuration ({})", config.config_path.display()))?;
+        Ok(config)
     }
 
     fn generate(config_path: PathBuf, values: DynamicValues) -> Self {
         AppConfig {
             top_level_domain: DEFAULT_TOP_LEVEL_DOMAIN.to_string(),
             port: values.port,
+            listen_address: default_listen_address(),
+            listen_adapter: None,
             log_level: values.log_level,
+            log_format: LogFormat::Text,
+            log_rotation: LogRotationConfig::default(),
             logging_dir: values.config_dir.join(LOGS_DIR_NAME),
             records_file: values.records_file,
             start_at_login: false,
+            start_at_login_method: StartAtLoginMethod::default(),
+            start_paused: false,
+            keep_mdns_tld: false,
+            flush_dns_cache_on_reload: false,
+            query_log: false,
+            adapter_dns: false,
+            loopback_aliases: false,
+            localhost_zone: default_localhost_zone(),
+            performance_counters: false,
+            slow_query_ms: default_slow_query_ms(),
+            query_deadline_ms: 0,
+            stats_summary_minutes: default_stats_summary_minutes(),
+            resolver_check_minutes: 0,
             config_revision: ConfigRevision { revision: 0 },
+            notifications: NotificationsConfig::default(),
+            updates: UpdatesConfig::default(),
+            admin_api: AdminApiConfig::default(),
+            dnstap: DnstapConfig::default(),
+            docker: DockerSyncConfig::default(),
+            kubernetes: KubernetesSyncConfig::default(),
+            proxies: ProxiesSyncConfig::default(),
+            machine: MachineSyncConfig::default(),
+            login_start: LoginStartConfig::default(),
+            auto_pause: AutoPauseConfig::default(),
             config_path,
         }
     }
 
     fn init_files(&self) -> Result<()> {
         fs::create_dir_all(&self.logging_dir)?;
-        self.save_with_comments()
+        self.save_with_comments()?;
+        audit::record_change(
+            self.config_dir(),
+            ChangeSource::Application,
+            "created default configuration",
+        )
     }
 
-    fn save_with_comments(&self) -> Result<()> {
+    pub(crate) fn save_with_comments(&self) -> Result<()> {
         let config_str = toml::to_string_pretty(&self)?;
         let with_comments = format!(
             "{}{}\n\n{}\n{}\n{}",
             "# Application Configuration for ",
             APP_NAME,
             "# It is HIGHLY recommended that you DO NOT edit this file!",
-            "# The only field that is somewhat safe to edit is the log_level which accepts one of (error, warn, info, debug, trace)",
+            "# The only fields that are somewhat safe to edit are the log_level which accepts one of (error, warn, info, debug, trace),\n# optionally with per-module levels (e.g. \"info, dot_local_dns::dns=trace\"), log_format (text or json), listen_address,\n# listen_adapter, adapter_dns, loopback_aliases, localhost_zone, performance_counters, query_log, slow_query_ms, query_deadline_ms, stats_summary_minutes, resolver_check_minutes, start_paused, keep_mdns_tld and the [log_rotation], [notifications], [updates], [admin_api],\n# [dnstap], [docker], [kubernetes], [proxies], [machine], [login_start] and [auto_pause] sections",
             config_str,
         );
         let mut file = File::create(&self.config_path)?;
@@ -97,10 +640,26 @@ impl AppConfig {
     }
 }
 
+#[cfg(test)]
+impl AppConfig {
+    /// Initialized configuration with all the files inside the supplied directory.
+    pub(crate) fn with_dir(dir: &Path) -> Self {
+        let values = DynamicValues {
+            records_file: dir.join(DEFAULT_RECORDS_FILE_NAME),
+            config_dir: dir.to_owned(),
+            port: 0,
+            log_level: "info".to_string(),
+        };
+        let config = Self::generate(dir.join(CONFIG_FILE_NAME), values);
+        config.init_files().unwrap();
+        config
+    }
+}
+
 impl DynamicValues {
     #[cfg(debug_assertions)]
     fn get() -> Result<Self> {
-        let app_name = format!("{APP_NAME}-dev");
+        let app_name = format!("{}-dev", instance_app_name());
         let config_dir = app_config_dir()?.join(&app_name);
         let records_file = config_dir.join(DEFAULT_RECORDS_FILE_NAME);
         Ok(Self {
@@ -113,7 +672,7 @@ impl DynamicValues {
 
     #[cfg(not(debug_assertions))]
     fn get() -> Result<Self> {
-        let config_dir = app_config_dir()?.join(APP_NAME);
+        let config_dir = app_config_dir()?.join(instance_app_name());
         let records_file = config_dir.join(DEFAULT_RECORDS_FILE_NAME);
         Ok(Self {
             config_dir,
@@ -124,6 +683,59 @@ impl DynamicValues {
     }
 }
 
+/// The top level domain must start with a dot and consist of valid lowercase hostname labels
+/// (e.g. `.loc` or `.dev.test`).
+pub fn validate_top_level_domain(tld: &str) -> Result<()> {
+    let labels = tld
+        .strip_prefix('.')
+        .ok_or_else(|| anyhow!("Top level domain must start with a dot: '{tld}'"))?;
+    for label in labels.split('.') {
+        if !valid_label(label) {
+            return Err(anyhow!(
+                "Invalid label '{label}' in top level domain '{tld}' (only lowercase letters, digits and '-' are allowed)"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Validate a (lowercase) host name in the top level domain.
+pub fn validate_host_name(host: &str, tld: &str) -> Result<()> {
+    let name = host
+        .strip_suffix(tld)
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| anyhow!("'{host}' is not a host in the {tld} domain"))?;
+    match name.split('.').find(|label| !valid_label(label)) {
+        Some(label) => Err(anyhow!(
+            "Invalid label '{label}' in '{host}' (only lowercase letters, digits and '-' are allowed)"
+        )),
+        None => Ok(()),
+    }
+}
+
+fn valid_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.len() <= 63
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+fn merge_tables(target: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (target.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(table)) => {
+                merge_tables(existing, table);
+            }
+            (_, value) => {
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
 pub fn app_config_dir() -> Result<PathBuf> {
     dirs::config_dir().with_context(|| "Could not find config directory")
 }
@@ -172,6 +784,148 @@ mod tests {
         assert_eq!(parsed, config);
     }
 
+    #[test]
+    fn config_without_notifications_section_uses_defaults() {
+        let dv = dynamic_values();
+        let temp_dir = tempdir().unwrap();
+        let config_file = temp_dir.path().join("config.toml");
+        let config = AppConfig::generate(config_file.clone(), dv);
+        let saved = toml::to_string_pretty(&config).unwrap();
+        let without_section = saved.split("[notifications]").next().unwrap();
+        fs::write(&config_file, without_section).unwrap();
+        let parsed = AppConfig::from_file(config_file).unwrap();
+        assert_eq!(parsed.notifications, NotificationsConfig::default());
+    }
+
+    #[test]
+    fn partial_log_rotation_uses_defaults() {
+        let rotation: LogRotationConfig =
+            toml::from_str("criterion = \"daily\"\nkeep_files = 3").unwrap();
+        assert_eq!(
+            rotation,
+            LogRotationConfig {
+                criterion: RotationCriterion::Daily,
+                keep_files: 3,
+                ..LogRotationConfig::default()
+            }
+        );
+        assert!(toml::from_str::<LogRotationConfig>("naming = \"dates\"").is_err());
+    }
+
+    #[test]
+    fn mute_disables_all_notifications() {
+        let config = NotificationsConfig {
+            mute: true,
+            ..NotificationsConfig::default()
+        };
+        assert!(!config.allows(NotificationEvent::ReloadFailure));
+        assert!(NotificationsConfig::default().allows(NotificationEvent::ReloadFailure));
+    }
+
+    #[test]
+    fn validate_top_level_domain_test() {
+        assert!(validate_top_level_domain(".loc").is_ok());
+        assert!(validate_top_level_domain(".dev-1.test").is_ok());
+        assert!(validate_top_level_domain("loc").is_err());
+        assert!(validate_top_level_domain(".").is_err());
+        assert!(validate_top_level_domain(".Loc").is_err());
+        assert!(validate_top_level_domain(".loc.").is_err());
+        assert!(validate_top_level_domain(".-loc").is_err());
+    }
+
+    #[test]
+    fn validate_host_name_test() {
+        assert!(validate_host_name("nas.loc", ".loc").is_ok());
+        assert!(validate_host_name("a.my-nas.loc", ".loc").is_ok());
+        assert!(validate_host_name(".loc", ".loc").is_err());
+        assert!(validate_host_name("nas.com", ".loc").is_err());
+        assert!(validate_host_name("my_nas.loc", ".loc").is_err());
+        assert!(validate_host_name("a..loc", ".loc").is_err());
+    }
+
+    #[test]
+    fn import_settings_keeps_machine_specific_values() {
+        let temp_dir = tempdir().unwrap();
+        let mut config = AppConfig::with_dir(temp_dir.path());
+        let imported = r#"
+            top_level_domain = ".test"
+            port = 5353
+            listen_address = "192.168.1.5"
+            log_level = "debug"
+            logging_dir = "C:\\elsewhere\\logs"
+            records_file = "C:\\elsewhere\\records.txt"
+            start_at_login = true
+
+            [config_revision]
+            revision = 0
+        "#;
+        config
+            .import_settings(imported, ChangeSource::Tray)
+            .unwrap();
+        assert_eq!(config.top_level_domain, ".test");
+        assert_eq!(config.port, 5353);
+        assert_eq!(config.log_level, "debug");
+        assert_eq!(config.records_file, temp_dir.path().join("records.txt"));
+        assert!(!config.start_at_login);
+        assert_eq!(config.listen_address, Ipv4Addr::LOCALHOST);
+        let saved = AppConfig::from_file(config.config_path.clone()).unwrap();
+        assert_eq!(saved, config);
+    }
+
+    #[test]
+    fn seeds_override_only_the_settings_they_contain() {
+        let temp_dir = tempdir().unwrap();
+        let mut config = AppConfig::with_dir(temp_dir.path());
+        let seed = toml::from_str(
+            r#"
+            top_level_domain = ".corp"
+            adapter_dns = true
+
+            [machine]
+            wsl = false
+        "#,
+        )
+        .unwrap();
+        config.apply_seed(seed, ChangeSource::Provisioning).unwrap();
+        assert_eq!(config.top_level_domain, ".corp");
+        assert!(config.adapter_dns);
+        assert!(!config.machine.wsl);
+        assert!(config.machine.computer_name);
+        assert_eq!(config.records_file, temp_dir.path().join("records.txt"));
+        let saved = AppConfig::from_file(config.config_path.clone()).unwrap();
+        assert_eq!(saved, config);
+        let bad_seed = toml::from_str(r#"top_level_domain = "corp""#).unwrap();
+        assert!(config
+            .apply_seed(bad_seed, ChangeSource::Provisioning)
+            .is_err());
+    }
+
+    #[test]
+    fn config_changes_are_recorded_in_history() {
+        let temp_dir = tempdir().unwrap();
+        let mut config = AppConfig::with_dir(temp_dir.path());
+        config.set_start_at_login(true, ChangeSource::Tray).unwrap();
+        let history = fs::read_to_string(audit::history_path(temp_dir.path())).unwrap();
+        let lines: Vec<&str> = history.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("[application] created default configuration"));
+        assert!(lines[1].ends_with("[tray] start_at_login: false -> true"));
+    }
+
+    #[test]
+    fn missing_config_file_is_recreated_with_comments() {
+        let temp_dir = tempdir().unwrap();
+        let config = AppConfig::with_dir(temp_dir.path());
+        fs::remove_file(&config.config_path).unwrap();
+        config.ensure_config_file().unwrap();
+        let contents = fs::read_to_string(&config.config_path).unwrap();
+        assert!(contents.starts_with("# Application Configuration for "));
+        assert_eq!(
+            AppConfig::from_file(config.config_path.clone()).unwrap(),
+            config
+        );
+    }
+
     fn dynamic_values() -> DynamicValues {
         DynamicValues {
             records_file: FilePath().fake(),
@@ -0,0 +1,89 @@
+use crate::prelude::*;
+use std::fmt;
+use std::fs::OpenOptions;
+
+const HISTORY_FILE_NAME: &str = "config-history.log";
+
+/// Who performed a configuration change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeSource {
+    Application,
+    Tray,
+    FileEdit,
+    Provisioning,
+}
+
+impl fmt::Display for ChangeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ChangeSource::Application => "application",
+            ChangeSource::Tray => "tray",
+            ChangeSource::FileEdit => "file-edit",
+            ChangeSource::Provisioning => "provisioning",
+        };
+        f.write_str(name)
+    }
+}
+
+pub fn history_path(config_dir: &Path) -> PathBuf {
+    config_dir.join(HISTORY_FILE_NAME)
+}
+
+/// Append an entry to the (append only) configuration history log in the config directory.
+pub fn record_change(config_dir: &Path, source: ChangeSource, change: &str) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(history_path(config_dir))?;
+    let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%z");
+    writeln!(file, "{now} [{source}] {change}")?;
+    Ok(())
+}
+
+/// Record a change if the configuration file was modified after the last recorded change (i.e.
+/// it was edited by hand).
+pub fn detect_file_edit(config: &AppConfig) -> Result<()> {
+    let config_dir = config.config_dir();
+    let config_modified = fs::metadata(&config.config_path)?.modified()?;
+    match fs::metadata(history_path(config_dir)).and_then(|m| m.modified()) {
+        Ok(recorded) if recorded >= config_modified => Ok(()),
+        Ok(_) => {
+            let values = toml::Table::try_from(config)?;
+            let summary = values
+                .iter()
+                .filter(|(_, v)| !v.is_table())
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            record_change(
+                config_dir,
+                ChangeSource::FileEdit,
+                &format!("configuration file modified outside the application ({summary})"),
+            )
+        }
+        Err(_) => record_change(
+            config_dir,
+            ChangeSource::Application,
+            "started recording configuration history",
+        ),
+    }
+}
+
+/// Describe the differences between two configurations as `key: old -> new` entries.
+pub fn describe_changes(previous: &AppConfig, current: &AppConfig) -> Result<String> {
+    let previous = toml::Table::try_from(previous)?;
+    let current = toml::Table::try_from(current)?;
+    let changes = current
+        .iter()
+        .filter(|&(key, value)| previous.get(key) != Some(value))
+        .map(|(key, value)| match previous.get(key) {
+            Some(old) => format!("{key}: {old} -> {value}"),
+            None => format!("{key}: {value}"),
+        })
+        .collect::<Vec<_>>();
+    if changes.is_empty() {
+        Ok("no changes".to_owned())
+    } else {
+        Ok(changes.join(", "))
+    }
+}
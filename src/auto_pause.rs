@@ -0,0 +1,158 @@
+//! Pause serving while a network adapter of the `[auto_pause]` configuration is connected (e.g. the
+//! corporate VPN, whose DNS should answer while it's up) and resume when it disconnects. The
+//! adapters are polled, like the [address aliases](crate::address_aliases).
+//!
+//! Only a pause of its own is resumed: serving paused by the user (or `--paused`) when the adapter
+//! connected stays paused, and resuming it from the tray while the adapter is connected is left
+//! alone until the adapter connects again.
+
+use crate::address_aliases::connected_adapters;
+use crate::prelude::*;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Whether serving is paused because of a connected adapter.
+#[derive(Debug, Default)]
+struct AutoPause {
+    /// The connected adapter (name and description) matching the configuration.
+    adapter: Option<String>,
+    paused: bool,
+}
+
+impl AutoPause {
+    /// Track the `adapter` matching the configuration, returns whether serving should be paused or
+    /// resumed.
+    fn update(&mut self, adapter: Option<String>, server_paused: bool) -> Option<bool> {
+        let change = match (&self.adapter, &adapter) {
+            (None, Some(_)) if !server_paused => {
+                self.paused = true;
+                Some(true)
+            }
+            (Some(_), None) if self.paused && server_paused => {
+                self.paused = false;
+                Some(false)
+            }
+            _ => {
+                // resumed by the user meanwhile
+                self.paused &= server_paused;
+                None
+            }
+        };
+        if adapter.is_none() {
+            self.paused = false;
+        }
+        self.adapter = adapter;
+        change
+    }
+}
+
+/// The first connected adapter whose name or description contains one of the `adapters`
+/// (case-insensitive).
+fn matching_adapter(adapters: &[String], connected: &[String]) -> Option<String> {
+    connected
+        .iter()
+        .find(|name| {
+            let name = name.to_lowercase();
+            adapters
+                .iter()
+                .any(|adapter| name.contains(&adapter.to_lowercase()))
+        })
+        .cloned()
+}
+
+/// Pause and resume serving as the `adapters` connect and disconnect, until the server stops.
+pub async fn auto_pause(
+    adapters: Vec<String>,
+    notify_tx: Sender<Notification>,
+    status_rx: watch::Receiver<ServerStatus>,
+) {
+    info!("Pausing serving while any of these adapters is connected: {adapters:?}");
+    let mut state = AutoPause::default();
+    loop {
+        let server_paused = match &*status_rx.borrow() {
+            ServerStatus::Listening(_) => Some(false),
+            ServerStatus::Paused(_) => Some(true),
+            ServerStatus::Stopped => return,
+            // starting or failed, whether it's paused isn't known yet
+            _ => None,
+        };
+        let Some(server_paused) = server_paused else {
+            sleep(POLL_INTERVAL).await;
+            continue;
+        };
+        let connected = connected_adapters().unwrap_or_else(|e| {
+            warn!("Error listing the network adapters: {e:#}");
+            vec![]
+        });
+        let adapter = matching_adapter(&adapters, &connected);
+        let described = adapter.clone().or_else(|| state.adapter.clone());
+        if let Some(paused) = state.update(adapter, server_paused) {
+            let adapter = described.unwrap_or_default();
+            let (summary, body) = if paused {
+                ("Paused DNS Serving", format!("{adapter} connected"))
+            } else {
+                ("Resumed DNS Serving", format!("{adapter} disconnected"))
+            };
+            info!("{summary}: {body}");
+            send_notification(summary, &body);
+            if notify_tx.send(SetPaused(paused)).await.is_err() {
+                return;
+            }
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adapters_match_by_part_of_their_name() {
+        let connected = [
+            "Ethernet Intel(R) Ethernet Connection".to_owned(),
+            "Ethernet 2 PANGP Virtual Ethernet Adapter".to_owned(),
+        ];
+        assert_eq!(
+            matching_adapter(&["pangp".to_owned()], &connected).as_deref(),
+            Some("Ethernet 2 PANGP Virtual Ethernet Adapter")
+        );
+        assert_eq!(
+            matching_adapter(&["AnyConnect".to_owned()], &connected),
+            None
+        );
+        assert_eq!(matching_adapter(&[], &connected), None);
+    }
+
+    #[test]
+    fn serving_is_paused_while_the_adapter_is_connected() {
+        let vpn = || Some("VPN".to_owned());
+        let mut state = AutoPause::default();
+        assert_eq!(state.update(None, false), None);
+        assert_eq!(state.update(vpn(), false), Some(true));
+        assert_eq!(state.update(vpn(), true), None);
+        assert_eq!(state.update(None, true), Some(false));
+        assert_eq!(state.update(None, false), None);
+    }
+
+    #[test]
+    fn pauses_of_the_user_are_left_alone() {
+        let vpn = || Some("VPN".to_owned());
+        // paused by the user before the adapter connected
+        let mut state = AutoPause::default();
+        assert_eq!(state.update(vpn(), true), None);
+        assert_eq!(state.update(None, true), None);
+
+        // resumed by the user while the adapter is connected
+        let mut state = AutoPause::default();
+        assert_eq!(state.update(vpn(), false), Some(true));
+        assert_eq!(state.update(vpn(), false), None);
+        assert_eq!(state.update(vpn(), true), None);
+        assert_eq!(state.update(None, true), None);
+        // and paused again the next time it connects
+        assert_eq!(state.update(vpn(), false), Some(true));
+    }
+}
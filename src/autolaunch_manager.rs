@@ -1,45 +1,663 @@
+use crate::app_config::{app_config_dir, StartAtLoginMethod};
+use crate::cli::Cli;
+#[cfg(target_os = "windows")]
+use crate::elevation::run_powershell_blocking;
 use crate::prelude::*;
 use auto_launch::{AutoLaunch, AutoLaunchBuilder};
+use clap::Parser;
+use std::iter::once;
+#[cfg(target_os = "windows")]
+use std::ptr::null_mut;
+#[cfg(target_os = "windows")]
+use windows_strings::HSTRING;
+#[cfg(target_os = "windows")]
+use windows_sys::core::w;
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Foundation::{ERROR_FILE_NOT_FOUND, ERROR_SUCCESS};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_SZ};
 
-pub trait AutoLaunchManager {
+/// Passed to the app started at login, so it can wait for the network.
+const AT_LOGIN_ARG: &str = "--at-login";
+/// Where debug builds record the startup entry, in the (`-dev`) configuration directory.
+const DEV_STARTUP_FILE_NAME: &str = "dev-startup-entry";
+
+pub trait AutoLaunchManager: Send + Sync {
     fn enable(&self) -> Result<()>;
     fn disable(&self) -> Result<()>;
     fn is_enabled(&self) -> Result<bool>;
+    /// The executable started at login, `None` when it isn't registered.
+    fn registered_exe(&self) -> Result<Option<PathBuf>>;
+
+    /// Whether the app starts at login and the executable it starts. An error reading the
+    /// executable is logged, it's then unknown.
+    fn registration(&self) -> Result<Registration> {
+        let enabled = self.is_enabled()?;
+        let exe = if enabled {
+            self.registered_exe().unwrap_or_else(|e| {
+                warn!("Error reading the startup entry: {e:#}");
+                None
+            })
+        } else {
+            None
+        };
+        Ok(Registration { enabled, exe })
+    }
+}
+
+/// The startup entry as the system has it.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Registration {
+    pub enabled: bool,
+    /// The executable it starts, `None` when unknown.
+    pub exe: Option<PathBuf>,
+}
+
+/// Whether the app starts at login, once reconciled with the system.
+#[derive(Debug, PartialEq, Eq)]
+pub enum StartupEntry {
+    Disabled,
+    Enabled,
+    /// The startup entry starts another executable (e.g. the app moved to another folder).
+    Stale(PathBuf),
 }
 
 impl AutoLaunchManager for AutoLaunch {
-    #[cfg(not(debug_assertions))]
     fn enable(&self) -> Result<()> {
         self.enable().map_err(Error::from)
     }
 
-    #[cfg(debug_assertions)]
+    fn disable(&self) -> Result<()> {
+        self.disable().map_err(Error::from)
+    }
+
+    fn is_enabled(&self) -> Result<bool> {
+        self.is_enabled().map_err(Error::from)
+    }
+
+    #[cfg(target_os = "windows")]
+    fn registered_exe(&self) -> Result<Option<PathBuf>> {
+        let mut buffer = [0u16; 1024];
+        let mut size = u32::try_from(size_of_val(&buffer)).unwrap_or_default();
+        let result = unsafe {
+            RegGetValueW(
+                HKEY_CURRENT_USER,
+                w!("SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Run"),
+                HSTRING::from(instance_app_name()).as_ptr(),
+                RRF_RT_REG_SZ,
+                null_mut(),
+                buffer.as_mut_ptr().cast(),
+                &raw mut size,
+            )
+        };
+        match result {
+            ERROR_SUCCESS => {
+                let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+                let command_line = String::from_utf16_lossy(&buffer[..len]);
+                Ok(Some(exe_of_command_line(&command_line)))
+            }
+            ERROR_FILE_NOT_FOUND => Ok(None),
+            error => Err(anyhow!("Error reading the startup entry: {error}")),
+        }
+    }
+
+    /// The first program argument of the launch agent (`~/Library/LaunchAgents/<name>.plist`).
+    #[cfg(target_os = "macos")]
+    fn registered_exe(&self) -> Result<Option<PathBuf>> {
+        let path = dirs::home_dir()
+            .context("finding the home directory")?
+            .join("Library/LaunchAgents")
+            .join(format!("{}.plist", self.get_app_name()));
+        match fs::read_to_string(&path) {
+            Ok(plist) => Ok(exe_of_launch_agent(&plist)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+        }
+    }
+}
+
+/// A scheduled task starting the app at logon with the highest privileges, so it can bind port 53
+/// or change the NRPT rules without a UAC prompt at every login. Registering and removing it asks
+/// for administrator rights.
+#[cfg(target_os = "windows")]
+pub struct ScheduledTask {
+    exe_path: PathBuf,
+    args: Vec<String>,
+    /// The user the task starts the app for (`DOMAIN\user`).
+    user: String,
+}
+
+#[cfg(target_os = "windows")]
+impl AutoLaunchManager for ScheduledTask {
     fn enable(&self) -> Result<()> {
-        Err(anyhow!("Not supported in DEBUG mode!"))
+        let script = register_task_script(&self.exe_path, &self.args, &self.user);
+        crate::elevation::run_powershell_elevated_blocking(&script)
+            .context("registering the scheduled task")
     }
 
-    #[cfg(not(debug_assertions))]
     fn disable(&self) -> Result<()> {
-        self.disable().map_err(Error::from)
+        crate::elevation::run_powershell_elevated_blocking(&unregister_task_script())
+            .context("removing the scheduled task")
+    }
+
+    fn is_enabled(&self) -> Result<bool> {
+        Ok(self.registration()?.enabled)
+    }
+
+    fn registered_exe(&self) -> Result<Option<PathBuf>> {
+        Ok(self.registration()?.exe)
+    }
+
+    /// Both from a single `Get-ScheduledTask` (`PowerShell` takes a while to start).
+    fn registration(&self) -> Result<Registration> {
+        let output =
+            run_powershell_blocking(&query_task_script()).context("running Get-ScheduledTask")?;
+        Ok(task_registration(&output))
+    }
+}
+
+/// Remove the startup entry of the other method than `method` (e.g. the registry entry once the
+/// scheduled task starts the app), so the app isn't started twice at login. Removing the scheduled
+/// task asks for administrator rights, so it's only removed when it exists.
+#[cfg(target_os = "windows")]
+pub fn remove_other_startup_entry(method: StartAtLoginMethod, extra_args: &[String]) -> Result<()> {
+    if cfg!(debug_assertions) {
+        // both methods record the same development entry
+        return Ok(());
+    }
+    let other = match method {
+        StartAtLoginMethod::Registry => StartAtLoginMethod::ScheduledTask,
+        StartAtLoginMethod::ScheduledTask => StartAtLoginMethod::Registry,
+    };
+    let entry = mk_auto_launch(other, extra_args)?;
+    if entry.is_enabled()? {
+        info!("Removing the {other:?} startup entry");
+        entry.disable()?;
+    }
+    Ok(())
+}
+
+/// The startup entry of debug builds: a file with the command line that would be started at login,
+/// so starting the development build at login can be toggled (and the mismatch with the
+/// configuration tried, by deleting or creating the file) without registering it.
+pub struct DevStartupEntry {
+    path: PathBuf,
+    command_line: String,
+}
+
+impl DevStartupEntry {
+    fn new(path: PathBuf, exe_path: &Path, args: &[String]) -> Self {
+        let command_line = once(exe_path.display().to_string())
+            .chain(args.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Self { path, command_line }
+    }
+}
+
+impl AutoLaunchManager for DevStartupEntry {
+    fn enable(&self) -> Result<()> {
+        fs::write(&self.path, &self.command_line)
+            .with_context(|| format!("writing {}", self.path.display()))?;
+        info!(
+            "Debug build: recorded the startup entry in {}",
+            self.path.display()
+        );
+        Ok(())
     }
 
-    #[cfg(debug_assertions)]
     fn disable(&self) -> Result<()> {
-        Err(anyhow!("Not supported in DEBUG mode!"))
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("removing {}", self.path.display()))
+            }
+            _ => Ok(()),
+        }
     }
 
     fn is_enabled(&self) -> Result<bool> {
-        self.is_enabled().map_err(Error::from)
+        Ok(self.path.exists())
+    }
+
+    fn registered_exe(&self) -> Result<Option<PathBuf>> {
+        match fs::read_to_string(&self.path) {
+            Ok(command_line) => Ok(Some(exe_of_command_line(&command_line))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("reading {}", self.path.display())),
+        }
+    }
+}
+
+/// The executable of a startup command line. The startup entries don't quote the path, so it ends
+/// at `.exe` rather than at the first space.
+fn exe_of_command_line(command_line: &str) -> PathBuf {
+    let command_line = command_line.trim();
+    if let Some(quoted) = command_line.strip_prefix('"') {
+        return PathBuf::from(quoted.split('"').next().unwrap_or_default());
+    }
+    let end = command_line.to_ascii_lowercase().find(".exe").map_or_else(
+        || command_line.find(' ').unwrap_or(command_line.len()),
+        |i| i + ".exe".len(),
+    );
+    PathBuf::from(&command_line[..end])
+}
+
+/// The executable of a launch agent: the first of its program arguments.
+#[cfg(target_os = "macos")]
+fn exe_of_launch_agent(plist: &str) -> Option<PathBuf> {
+    let (_, arguments) = plist.split_once("<key>ProgramArguments</key>")?;
+    let (_, exe) = arguments.split_once("<string>")?;
+    let (exe, _) = exe.split_once("</string>")?;
+    Some(PathBuf::from(exe.trim()))
+}
+
+/// Whether the paths are the same executable (Windows paths are case-insensitive).
+fn same_exe(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a
+            .to_string_lossy()
+            .eq_ignore_ascii_case(&b.to_string_lossy()),
     }
 }
 
-pub fn mk_auto_launch() -> Result<AutoLaunch> {
+/// `extra_args` are passed to the app started at login (e.g. `--paused`).
+pub fn mk_auto_launch(
+    method: StartAtLoginMethod,
+    extra_args: &[String],
+) -> Result<Box<dyn AutoLaunchManager>> {
     let exe_path =
         std::env::current_exe().map_err(|e| anyhow!("Error identifying application path: {e}"))?;
+    mk_auto_launch_for(&exe_path, method, extra_args)
+}
+
+/// Launch `exe_path` at login (e.g. the installed copy of the app), with `--at-login` so it can wait
+/// for the network and the `extra_args`. Invalid `extra_args` are reported and left out, rather than
+/// keeping the app from starting.
+pub fn mk_auto_launch_for(
+    exe_path: &Path,
+    method: StartAtLoginMethod,
+    extra_args: &[String],
+) -> Result<Box<dyn AutoLaunchManager>> {
+    let args = login_args(extra_args).unwrap_or_else(|e| {
+        notify_error!("{e:#}, starting at login without them");
+        base_login_args().collect()
+    });
+    if cfg!(debug_assertions) {
+        let path = app_config_dir()?
+            .join(format!("{}-dev", instance_app_name()))
+            .join(DEV_STARTUP_FILE_NAME);
+        return Ok(Box::new(DevStartupEntry::new(path, exe_path, &args)));
+    }
+    mk_system_auto_launch(exe_path, method, &args)
+}
+
+#[cfg(target_os = "windows")]
+fn mk_system_auto_launch(
+    exe_path: &Path,
+    method: StartAtLoginMethod,
+    args: &[String],
+) -> Result<Box<dyn AutoLaunchManager>> {
+    match method {
+        StartAtLoginMethod::Registry => {
+            let exe_str = exe_path.to_str().ok_or(anyhow!("Invalid exe path"))?;
+            let auto_launch = AutoLaunchBuilder::new()
+                .set_app_name(&instance_app_name())
+                .set_app_path(exe_str)
+                .set_args(args)
+                .build()?;
+            Ok(Box::new(auto_launch))
+        }
+        StartAtLoginMethod::ScheduledTask => {
+            let domain = std::env::var("USERDOMAIN").context("finding the user domain")?;
+            let user = std::env::var("USERNAME").context("finding the user name")?;
+            Ok(Box::new(ScheduledTask {
+                exe_path: exe_path.to_owned(),
+                args: args.to_vec(),
+                user: format!("{domain}\\{user}"),
+            }))
+        }
+    }
+}
+
+/// A launch agent (`~/Library/LaunchAgents`), the method only applies to Windows.
+#[cfg(target_os = "macos")]
+fn mk_system_auto_launch(
+    exe_path: &Path,
+    _method: StartAtLoginMethod,
+    args: &[String],
+) -> Result<Box<dyn AutoLaunchManager>> {
     let exe_str = exe_path.to_str().ok_or(anyhow!("Invalid exe path"))?;
-    AutoLaunchBuilder::new()
-        .set_app_name(APP_NAME)
+    let auto_launch = AutoLaunchBuilder::new()
+        .set_app_name(&instance_app_name())
         .set_app_path(exe_str)
-        .build()
-        .map_err(Error::from)
+        .set_use_launch_agent(true)
+        .set_args(args)
+        .build()?;
+    Ok(Box::new(auto_launch))
+}
+
+/// Check that `extra_args` can be passed to the app started at login (see [`login_args`]).
+#[cfg(target_os = "windows")]
+pub fn check_login_args(extra_args: &[String]) -> Result<()> {
+    login_args(extra_args).map(|_| ())
+}
+
+fn base_login_args() -> impl Iterator<Item = String> {
+    once(AT_LOGIN_ARG.to_owned()).chain(instance_args())
+}
+
+/// `--at-login`, the instance and the `extra_args`, checked to be options of the app: they're joined
+/// with spaces (unquoted) in the startup entry.
+fn login_args(extra_args: &[String]) -> Result<Vec<String>> {
+    if let Some(arg) = extra_args
+        .iter()
+        .find(|arg| arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"'))
+    {
+        return Err(anyhow!(
+            "Invalid start at login argument {arg:?}: arguments can't be empty or contain spaces or quotes"
+        ));
+    }
+    let args: Vec<String> = base_login_args()
+        .chain(extra_args.iter().cloned())
+        .collect();
+    let cli = Cli::try_parse_from(once(APP_NAME).chain(args.iter().map(String::as_str))).map_err(
+        |e| {
+            let error = e.to_string();
+            let reason = error.lines().next().unwrap_or_default();
+            anyhow!(
+                "Invalid start at login arguments ({}): {reason}",
+                extra_args.join(" ")
+            )
+        },
+    )?;
+    if cli.command.is_some() || cli.install || cli.uninstall_cleanup || cli.init_config.is_some() {
+        return Err(anyhow!(
+            "Invalid start at login arguments ({}): only options of the running app are allowed",
+            extra_args.join(" ")
+        ));
+    }
+    Ok(args)
+}
+
+/// Match the configuration to whether the system starts the app at login (the user may have removed
+/// the startup entry, e.g. in the Task Manager), telling the user when it changed. An entry starting
+/// another executable than `exe` (the running app) is [`StartupEntry::Stale`]: it's left for the user
+/// to replace, as it may be another copy of the app they want started. The `registration` is read
+/// beforehand (see [`AutoLaunchManager::registration`]), off the event loop.
+pub fn reconcile_start_at_login(
+    app_config: &mut AppConfig,
+    registration: Registration,
+    auto_launch: &dyn AutoLaunchManager,
+    exe: &Path,
+) -> Result<StartupEntry> {
+    let start_flag = app_config.start_at_login;
+    if start_flag != registration.enabled {
+        notify_user_about_mismatch_auto_launch(start_flag, !start_flag);
+        app_config.set_start_at_login(!start_flag, ChangeSource::Application)?;
+    }
+    if !app_config.start_at_login {
+        return Ok(StartupEntry::Disabled);
+    }
+    if let Some(registered) = registration
+        .exe
+        .filter(|registered| !same_exe(registered, exe))
+    {
+        warn!(
+            "The startup entry starts {} rather than {}",
+            registered.display(),
+            exe.display()
+        );
+        return Ok(StartupEntry::Stale(registered));
+    }
+    if app_config.start_at_login_method == StartAtLoginMethod::Registry {
+        // refresh the arguments of the startup entry (entries created before `--at-login` was
+        // passed don't wait for the network). The scheduled task isn't: it would ask for
+        // administrator rights at every start.
+        auto_launch
+            .enable()
+            .unwrap_or_else(|e| debug!("Not refreshing the startup entry: {e:#}"));
+    }
+    Ok(StartupEntry::Enabled)
+}
+
+fn notify_user_about_mismatch_auto_launch(app: bool, system: bool) {
+    let tr = |b: bool| {
+        if b {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    };
+    let in_app = tr(app);
+    let in_system = tr(system);
+    let msg = format!(
+        concat!(
+            "There is a mismatch in configured starting at login between the application ",
+            r#"({}) and the system ({})!"#,
+            "\n\nWe've set the application to match the system settings ({}). You can set it to",
+            "your liking using the menu in the system tray."
+        ),
+        in_app, in_system, in_system
+    );
+
+    send_warning("Start at Login Changed", &msg);
+}
+
+#[cfg(target_os = "windows")]
+fn register_task_script(exe_path: &Path, args: &[String], user: &str) -> String {
+    let quote = |s: &str| s.replace('\'', "''");
+    let exe_path = quote(&exe_path.display().to_string());
+    let args = quote(&args.join(" "));
+    let user = quote(user);
+    let task = instance_app_name();
+    format!(
+        "$action = New-ScheduledTaskAction -Execute '{exe_path}' -Argument '{args}'; \
+$trigger = New-ScheduledTaskTrigger -AtLogOn -User '{user}'; \
+$principal = New-ScheduledTaskPrincipal -UserId '{user}' -LogonType Interactive -RunLevel Highest; \
+$settings = New-ScheduledTaskSettingsSet -ExecutionTimeLimit 0 -AllowStartIfOnBatteries -DontStopIfGoingOnBatteries; \
+Register-ScheduledTask -TaskName '{task}' -Action $action -Trigger $trigger -Principal $principal -Settings $settings -Force -ErrorAction Stop | Out-Null"
+    )
+}
+
+/// Prints `task:` and the executable if the task exists.
+#[cfg(target_os = "windows")]
+fn query_task_script() -> String {
+    format!(
+        "$task = Get-ScheduledTask -TaskName '{}' -ErrorAction SilentlyContinue; \
+if ($task) {{ 'task:' + $task.Actions[0].Execute }}",
+        instance_app_name()
+    )
+}
+
+#[cfg(target_os = "windows")]
+fn task_registration(output: &str) -> Registration {
+    match output.trim().strip_prefix("task:") {
+        Some(exe) => Registration {
+            enabled: true,
+            exe: (!exe.is_empty()).then(|| PathBuf::from(exe)),
+        },
+        None => Registration::default(),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn unregister_task_script() -> String {
+    format!(
+        "Unregister-ScheduledTask -TaskName '{}' -Confirm:$false -ErrorAction Stop",
+        instance_app_name()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::recorded_notifications;
+    use tempfile::tempdir;
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn task_scripts() {
+        let args = login_args(&["--paused".to_owned()]).unwrap();
+        let script = register_task_script(
+            Path::new(r"C:\Users\O'Brien\dot-local-dns.exe"),
+            &args,
+            r"PC\O'Brien",
+        );
+        assert!(script.contains(
+            r"New-ScheduledTaskAction -Execute 'C:\Users\O''Brien\dot-local-dns.exe' -Argument '--at-login --paused'"
+        ));
+        assert!(script.contains(r"-AtLogOn -User 'PC\O''Brien';"));
+        assert!(script.contains(" -RunLevel Highest;"));
+        assert!(script.contains("Register-ScheduledTask -TaskName 'DotLocal-DNS' "));
+        assert!(!script.contains('"'));
+        assert!(unregister_task_script().contains("-TaskName 'DotLocal-DNS'"));
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn the_scheduled_task_is_read_at_once() {
+        assert!(query_task_script().contains("-TaskName 'DotLocal-DNS'"));
+        assert!(!query_task_script().contains('"'));
+        assert_eq!(task_registration("\r\n"), Registration::default());
+        assert_eq!(
+            task_registration("task:C:\\App\\dot-local-dns.exe\r\n"),
+            Registration {
+                enabled: true,
+                exe: Some(PathBuf::from(r"C:\App\dot-local-dns.exe")),
+            }
+        );
+        assert_eq!(
+            task_registration("task:"),
+            Registration {
+                enabled: true,
+                exe: None,
+            }
+        );
+    }
+
+    #[test]
+    fn login_args_must_be_options_of_the_app() {
+        let args =
+            |args: &[&str]| login_args(&args.iter().map(|&arg| arg.to_owned()).collect::<Vec<_>>());
+        assert_eq!(args(&[]).unwrap(), ["--at-login"]);
+        assert_eq!(
+            args(&["--paused", "--console"]).unwrap(),
+            ["--at-login", "--paused", "--console"]
+        );
+        let error = args(&["--profile", "office"]).unwrap_err().to_string();
+        assert!(error.starts_with("Invalid start at login arguments (--profile office): "));
+        assert!(error.contains("--profile"));
+        assert!(args(&["reload"]).is_err());
+        assert!(args(&["--install"]).is_err());
+        assert!(args(&["--paused --console"]).is_err());
+        assert!(args(&[""]).is_err());
+    }
+
+    #[test]
+    fn the_configuration_follows_the_startup_entry() {
+        let dir = tempdir().unwrap();
+        let mut app_config = AppConfig::with_dir(dir.path());
+        let args = login_args(&["--paused".to_owned()]).unwrap();
+        let exe = Path::new(r"C:\dot-local-dns.exe");
+        let entry = DevStartupEntry::new(dir.path().join(DEV_STARTUP_FILE_NAME), exe, &args);
+        let reconcile = |app_config: &mut AppConfig| {
+            reconcile_start_at_login(app_config, entry.registration().unwrap(), &entry, exe)
+                .unwrap()
+        };
+        assert_eq!(reconcile(&mut app_config), StartupEntry::Disabled);
+
+        entry.enable().unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join(DEV_STARTUP_FILE_NAME)).unwrap(),
+            r"C:\dot-local-dns.exe --at-login --paused"
+        );
+        assert_eq!(reconcile(&mut app_config), StartupEntry::Enabled);
+        assert!(app_config.start_at_login);
+        assert!(fs::read_to_string(&app_config.config_path)
+            .unwrap()
+            .contains("\nstart_at_login = true\n"));
+        assert!(recorded_notifications()
+            .iter()
+            .any(|(title, body)| title == "Start at Login Changed"
+                && body.contains("application (disabled) and the system (enabled)")));
+
+        // still enabled: the entry is refreshed
+        assert_eq!(reconcile(&mut app_config), StartupEntry::Enabled);
+        assert!(entry.is_enabled().unwrap());
+
+        entry.disable().unwrap();
+        entry.disable().unwrap();
+        assert_eq!(reconcile(&mut app_config), StartupEntry::Disabled);
+        assert!(!app_config.start_at_login);
+    }
+
+    #[test]
+    fn entries_starting_another_executable_are_stale() {
+        let dir = tempdir().unwrap();
+        let mut app_config = AppConfig::with_dir(dir.path());
+        app_config
+            .set_start_at_login(true, ChangeSource::Application)
+            .unwrap();
+        let path = dir.path().join(DEV_STARTUP_FILE_NAME);
+        let moved = Path::new(r"C:\Program Files\DotLocal-DNS 0.2.0\dot-local-dns.exe");
+        DevStartupEntry::new(path.clone(), moved, &[AT_LOGIN_ARG.to_owned()])
+            .enable()
+            .unwrap();
+        let exe = Path::new(r"C:\Program Files\DotLocal-DNS 0.2.1\dot-local-dns.exe");
+        let entry = DevStartupEntry::new(path.clone(), exe, &[AT_LOGIN_ARG.to_owned()]);
+        assert_eq!(
+            reconcile_start_at_login(&mut app_config, entry.registration().unwrap(), &entry, exe)
+                .unwrap(),
+            StartupEntry::Stale(moved.to_owned())
+        );
+        // left for the user to replace
+        assert!(fs::read_to_string(&path).unwrap().contains("0.2.0"));
+        assert_eq!(
+            reconcile_start_at_login(
+                &mut app_config,
+                entry.registration().unwrap(),
+                &entry,
+                Path::new(r"c:\program files\dotlocal-dns 0.2.0\DOT-LOCAL-DNS.EXE")
+            )
+            .unwrap(),
+            StartupEntry::Enabled
+        );
+    }
+
+    #[test]
+    fn the_executable_of_startup_command_lines() {
+        let exe = |command_line| exe_of_command_line(command_line).display().to_string();
+        assert_eq!(
+            exe(r"C:\Users\Jo Doe\dot-local-dns.exe --at-login --paused"),
+            r"C:\Users\Jo Doe\dot-local-dns.exe"
+        );
+        assert_eq!(
+            exe(r"C:\App\DOT-LOCAL-DNS.EXE"),
+            r"C:\App\DOT-LOCAL-DNS.EXE"
+        );
+        assert_eq!(
+            exe(r#""C:\Users\Jo Doe\dot-local-dns.exe" --at-login"#),
+            r"C:\Users\Jo Doe\dot-local-dns.exe"
+        );
+        assert_eq!(
+            exe(r"C:\App\dot-local-dns --at-login"),
+            r"C:\App\dot-local-dns"
+        );
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn the_executable_of_launch_agents() {
+        let plist = "<plist version=\"1.0\">\n  <dict>\n  <key>Label</key>\n  <string>DotLocal-DNS</string>\n  \
+<key>ProgramArguments</key>\n  <array><string>/Applications/DotLocal-DNS.app/Contents/MacOS/dot-local-dns</string>\
+<string>--at-login</string></array>\n  </dict>\n</plist>";
+        assert_eq!(
+            exe_of_launch_agent(plist),
+            Some(PathBuf::from(
+                "/Applications/DotLocal-DNS.app/Contents/MacOS/dot-local-dns"
+            ))
+        );
+        assert_eq!(exe_of_launch_agent("<plist></plist>"), None);
+    }
 }
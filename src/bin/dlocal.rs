@@ -0,0 +1,361 @@
+//! Command line control of the running DotLocal-DNS app.
+
+#[path = "../control/client.rs"]
+mod client;
+#[path = "../control/protocol.rs"]
+#[allow(dead_code)] // the server side of the protocol is used by the app
+mod protocol;
+#[path = "../control/token.rs"]
+#[allow(dead_code)] // generating and checking the token is done by the app
+mod token;
+
+use anyhow::{Context, Error, Result};
+use chrono::Local;
+use clap::{Parser, Subcommand};
+use client::Client;
+use protocol::{
+    ImportFormat, ImportInfo, LookupInfo, MergeInfo, Method, QueryInfo, RecordInfo, RpcError,
+    StatsInfo, StatusInfo, QUERY_NOTIFICATION, RECORDS_FILE_SOURCE,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::Read;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+/// The app rejected the request (e.g. an invalid record).
+const EXIT_REJECTED: u8 = 1;
+/// Couldn't reach the app (or another failure).
+const EXIT_FAILED: u8 = 2;
+/// `status`: the server isn't answering queries. `lookup`: no record matches the name.
+const EXIT_NEGATIVE: u8 = 3;
+
+const EXIT_CODES_HELP: &str =
+    "Exit codes: 0 success, 1 the app rejected the request, 2 couldn't reach the app, \
+3 the server isn't answering queries (status) or no record matches (lookup).";
+
+/// Control the running DotLocal-DNS app.
+#[derive(Parser, Debug)]
+#[command(version, about, after_help = EXIT_CODES_HELP)]
+struct Cli {
+    /// Print the results (and errors) as JSON, `watch` prints a JSON object per line.
+    #[arg(long, global = true)]
+    json: bool,
+    /// Control the app started with `--instance <NAME>` rather than the default one.
+    #[arg(long, global = true, value_name = "NAME")]
+    instance: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Add a record (or change its address) in the records file.
+    Add { name: String, addr: Ipv4Addr },
+    /// Remove a record from the records file.
+    Remove { name: String },
+    /// List the records.
+    List,
+    /// Show the address a hostname resolves to and the record answering it.
+    Lookup { name: String },
+    /// Reload the records file.
+    Reload,
+    /// Show the server status.
+    Status,
+    /// Merge the records from a file (until the next reload).
+    Merge { file: PathBuf },
+    /// Add the records from a file (`-` for stdin) to the records file, all or none of them.
+    ///
+    /// The input is either in the records file format (`name:addr` lines) or a JSON array of
+    /// `{"name", "addr"}` objects (the line numbers in the report are the array positions then).
+    Import {
+        file: PathBuf,
+        /// Import the `address=/name/addr` lines of a dnsmasq configuration file.
+        #[arg(long)]
+        dnsmasq: bool,
+    },
+    /// Stop answering queries until resumed.
+    Pause,
+    /// Resume answering queries.
+    Resume,
+    /// Show the queries answered by the server as they happen (until interrupted).
+    Watch,
+    /// Write the diagnostics (configuration, records, status, stats and recent errors) as JSON to
+    /// the file, to attach to issues.
+    Diagnostics { file: PathBuf },
+}
+
+/// The `status` result, a flat object in JSON.
+#[derive(Serialize)]
+struct StatusOutput {
+    #[serde(flatten)]
+    status: StatusInfo,
+    #[serde(flatten)]
+    stats: StatsInfo,
+}
+
+/// A record of a JSON import.
+#[derive(Deserialize)]
+struct ImportRecord {
+    name: String,
+    addr: Ipv4Addr,
+}
+
+/// Prints the results as text or JSON.
+struct Output {
+    json: bool,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let output = Output { json: cli.json };
+    let result = match Client::connect(cli.instance.as_deref()).await {
+        Ok(mut client) => cli.command.run(&mut client, &output).await,
+        Err(e) => Err(e),
+    };
+    result.unwrap_or_else(|e| output.error(&e))
+}
+
+impl Command {
+    async fn run(self, client: &mut Client, output: &Output) -> Result<ExitCode> {
+        match self {
+            Command::Add { name, addr } => {
+                client
+                    .call::<Value>(Method::AddRecord {
+                        name: name.clone(),
+                        addr,
+                    })
+                    .await?;
+                output.done(&format!("Added {name} → {addr}"));
+            }
+            Command::Remove { name } => {
+                client
+                    .call::<Value>(Method::RemoveRecord { name: name.clone() })
+                    .await?;
+                output.done(&format!("Removed {name}"));
+            }
+            Command::List => {
+                let records: Vec<RecordInfo> = client.call(Method::ListRecords).await?;
+                output.print(&records, |records| {
+                    records
+                        .iter()
+                        .map(|record| {
+                            let note = if record.source != RECORDS_FILE_SOURCE {
+                                format!(" ({})", record.source)
+                            } else if record.enabled {
+                                String::new()
+                            } else {
+                                " (disabled)".to_owned()
+                            };
+                            format!("{} {}{note}", record.name, record.addr)
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                })?;
+            }
+            Command::Lookup { name } => {
+                let result: LookupInfo = client.call(Method::Lookup { name }).await?;
+                output.print(&result, |result| {
+                    format!("{} → {}\n{}", result.name, result.addr, result.description)
+                })?;
+                if result.record.is_none() {
+                    return Ok(ExitCode::from(EXIT_NEGATIVE));
+                }
+            }
+            Command::Reload => {
+                client.call::<Value>(Method::Reload).await?;
+                output.done("Reloaded records");
+            }
+            Command::Status => {
+                let status: StatusInfo = client.call(Method::Status).await?;
+                let stats: StatsInfo = client.call(Method::Stats).await?;
+                let serving = status.state == "listening";
+                output.print(&StatusOutput { status, stats }, |output| {
+                    format!(
+                        "{}\nQueries served: {}\nRecords reloads: {}",
+                        output.status.description, output.stats.queries, output.stats.reloads
+                    )
+                })?;
+                if !serving {
+                    return Ok(ExitCode::from(EXIT_NEGATIVE));
+                }
+            }
+            Command::Merge { file } => {
+                // the app doesn't share our working directory
+                let path = std::path::absolute(&file)
+                    .with_context(|| format!("resolving {}", file.display()))?;
+                let summary: MergeInfo = client.call(Method::Merge { path }).await?;
+                output.print(&summary, |summary| {
+                    let mut text = format!("Added {} record(s)", summary.added);
+                    if !summary.conflicts.is_empty() {
+                        text.push_str("\nOverridden: ");
+                        text.push_str(&summary.conflicts.join(", "));
+                    }
+                    text
+                })?;
+            }
+            Command::Import { file, dnsmasq } => {
+                let (records, format) = if dnsmasq {
+                    (read_input(&file)?, ImportFormat::Dnsmasq)
+                } else {
+                    (read_records(&file)?, ImportFormat::Records)
+                };
+                let report: ImportInfo = client.call(Method::Import { records, format }).await?;
+                output.print(&report, import_text)?;
+                if !report.applied {
+                    return Ok(ExitCode::from(EXIT_REJECTED));
+                }
+            }
+            Command::Pause => {
+                client.call::<Value>(Method::Pause).await?;
+                output.done("Paused DNS serving");
+            }
+            Command::Resume => {
+                client.call::<Value>(Method::Resume).await?;
+                output.done("Resumed DNS serving");
+            }
+            Command::Diagnostics { file } => {
+                // the app doesn't share our working directory
+                let path = std::path::absolute(&file)
+                    .with_context(|| format!("resolving {}", file.display()))?;
+                client
+                    .call::<Value>(Method::DumpDiagnostics { path: path.clone() })
+                    .await?;
+                output.done(&format!("Wrote the diagnostics to {}", path.display()));
+            }
+            Command::Watch => {
+                client.call::<Value>(Method::Watch).await?;
+                loop {
+                    let notification = client.notification().await?;
+                    if notification.method != QUERY_NOTIFICATION {
+                        continue;
+                    }
+                    let query: QueryInfo = serde_json::from_value(notification.params)
+                        .context("decoding query notification")?;
+                    output.print(&query, |query| {
+                        let answer = query
+                            .answer
+                            .map_or_else(|| query.rcode.clone(), |addr| addr.to_string());
+                        format!(
+                            "{} {} ({}) → {answer} [{}]",
+                            Local::now().format("%H:%M:%S"),
+                            query.name,
+                            query.qtype,
+                            query.client
+                        )
+                    })?;
+                }
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Read the file (`-` for stdin).
+fn read_input(file: &Path) -> Result<String> {
+    if file == Path::new("-") {
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .context("reading stdin")?;
+        Ok(text)
+    } else {
+        std::fs::read_to_string(file).with_context(|| format!("reading {}", file.display()))
+    }
+}
+
+/// Read the records to import as lines in the records file format.
+fn read_records(file: &Path) -> Result<String> {
+    let text = read_input(file)?;
+    if !text.trim_start().starts_with('[') {
+        return Ok(text);
+    }
+    let records: Vec<ImportRecord> =
+        serde_json::from_str(&text).context("parsing the JSON records")?;
+    Ok(records
+        .iter()
+        .map(|record| format!("{}:{}\n", record.name, record.addr))
+        .collect())
+}
+
+fn import_text(report: &ImportInfo) -> String {
+    let mut lines: Vec<_> = report
+        .lines
+        .iter()
+        .filter(|line| line.status != "unchanged")
+        .map(|line| match (&line.name, line.addr, &line.error) {
+            (Some(name), Some(addr), _) => {
+                format!("line {}: {name} → {addr} ({})", line.line, line.status)
+            }
+            (_, _, error) => format!(
+                "line {}: {}{}",
+                line.line,
+                if line.status == "skipped" {
+                    "skipped, "
+                } else {
+                    ""
+                },
+                error.as_deref().unwrap_or("")
+            ),
+        })
+        .collect();
+    let count = |status| report.lines.iter().filter(|l| l.status == status).count();
+    lines.push(if report.applied {
+        format!(
+            "Imported: {} added, {} updated, {} unchanged, {} skipped",
+            count("added"),
+            count("updated"),
+            count("unchanged"),
+            count("skipped")
+        )
+    } else {
+        format!("Nothing imported: {} invalid line(s)", count("invalid"))
+    });
+    lines.join("\n")
+}
+
+impl Output {
+    fn print<T: Serialize>(&self, value: &T, text: impl FnOnce(&T) -> String) -> Result<()> {
+        if self.json {
+            println!("{}", serde_json::to_string(value)?);
+        } else {
+            let text = text(value);
+            if !text.is_empty() {
+                println!("{text}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Commands without a result only print a confirmation as text.
+    fn done(&self, message: &str) {
+        if !self.json {
+            println!("{message}");
+        }
+    }
+
+    /// Print the error (to stderr) and return the exit code.
+    fn error(&self, e: &Error) -> ExitCode {
+        let rpc_error = e.downcast_ref::<RpcError>();
+        if self.json {
+            let error = json!({
+                "error": {
+                    "code": rpc_error.map(|e| e.code),
+                    "message": rpc_error.map_or_else(|| format!("{e:#}"), |e| e.message.clone()),
+                }
+            });
+            eprintln!("{error}");
+        } else if rpc_error.is_some() {
+            eprintln!("Error: {e}");
+        } else {
+            eprintln!("Error: {e:#}");
+        }
+        ExitCode::from(if rpc_error.is_some() {
+            EXIT_REJECTED
+        } else {
+            EXIT_FAILED
+        })
+    }
+}
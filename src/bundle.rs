@@ -0,0 +1,119 @@
+use crate::prelude::*;
+use std::io::{Read, Seek};
+use zip::result::ZipError;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+const CONFIG_ENTRY: &str = "application.toml";
+const RECORDS_ENTRY: &str = "records.txt";
+
+/// Write the configuration, the records file and optionally the log files into a single zip
+/// archive.
+pub fn export_bundle(config: &AppConfig, target: &Path, include_logs: bool) -> Result<()> {
+    let file =
+        File::create(target).with_context(|| format!("creating archive: {}", target.display()))?;
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    add_file(&mut zip, CONFIG_ENTRY, &config.config_path, options)?;
+    if config.records_file.exists() {
+        add_file(&mut zip, RECORDS_ENTRY, &config.records_file, options)?;
+    }
+    if include_logs && config.logging_dir.exists() {
+        for entry in fs::read_dir(&config.logging_dir)? {
+            let path = entry?.path();
+            if path.is_file() {
+                let name = format!(
+                    "{LOGS_DIR_NAME}/{}",
+                    path.file_name().unwrap_or_default().to_string_lossy()
+                );
+                add_file(&mut zip, &name, &path, options)?;
+            }
+        }
+    }
+    zip.finish()?;
+    info!("Exported settings to: {}", target.display());
+    Ok(())
+}
+
+/// Import the settings and records from an archive created by [`export_bundle`]. Machine specific
+/// settings (paths, start at login) are kept and the current records file is backed up.
+pub fn import_bundle(
+    config: &mut AppConfig,
+    source: &Path,
+    change_source: ChangeSource,
+) -> Result<()> {
+    let file =
+        File::open(source).with_context(|| format!("opening archive: {}", source.display()))?;
+    let mut archive = ZipArchive::new(file)?;
+    let settings = read_entry(&mut archive, CONFIG_ENTRY)?
+        .ok_or_else(|| anyhow!("Archive does not contain {CONFIG_ENTRY}"))?;
+    let records = read_entry(&mut archive, RECORDS_ENTRY)?;
+    config.import_settings(&settings, change_source)?;
+    if let Some(records) = records {
+        if config.records_file.exists() {
+            fs::copy(&config.records_file, backup_path(&config.records_file))?;
+        }
+        fs::write(&config.records_file, records)?;
+    }
+    info!("Imported settings from: {}", source.display());
+    Ok(())
+}
+
+fn add_file(
+    zip: &mut ZipWriter<File>,
+    name: &str,
+    path: &Path,
+    options: SimpleFileOptions,
+) -> Result<()> {
+    let contents = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    zip.start_file(name, options)?;
+    zip.write_all(&contents)?;
+    Ok(())
+}
+
+fn read_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Option<String>> {
+    match archive.by_name(name) {
+        Ok(mut entry) => {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            Ok(Some(contents))
+        }
+        Err(ZipError::FileNotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn export_and_import_round_trip() {
+        let source_dir = tempdir().unwrap();
+        let mut source = AppConfig::with_dir(source_dir.path());
+        source.top_level_domain = ".test".to_owned();
+        source.port = 5353;
+        source.save_with_comments().unwrap();
+        fs::write(&source.records_file, "a.test:10.0.0.1\n").unwrap();
+        let archive = source_dir.path().join("bundle.zip");
+        export_bundle(&source, &archive, true).unwrap();
+
+        let target_dir = tempdir().unwrap();
+        let mut target = AppConfig::with_dir(target_dir.path());
+        fs::write(&target.records_file, "old.loc:10.0.0.2\n").unwrap();
+        import_bundle(&mut target, &archive, ChangeSource::Tray).unwrap();
+
+        assert_eq!(target.top_level_domain, ".test");
+        assert_eq!(target.port, 5353);
+        assert_eq!(target.records_file, target_dir.path().join("records.txt"));
+        assert_eq!(
+            fs::read_to_string(&target.records_file).unwrap(),
+            "a.test:10.0.0.1\n"
+        );
+        assert_eq!(
+            fs::read_to_string(backup_path(&target.records_file)).unwrap(),
+            "old.loc:10.0.0.2\n"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use crate::control::protocol::Method;
+use clap::{Parser, Subcommand};
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+
+/// A local DNS server that resolves addresses in the configured top level domain.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Cli {
+    /// Start with DNS serving paused (resume it from the tray menu).
+    #[arg(long)]
+    pub paused: bool,
+    /// Run only the DNS server, without the tray icon, dialogs or notifications (e.g. in CI or
+    /// without a desktop session). Runs until interrupted (Ctrl+C).
+    #[arg(long)]
+    pub headless: bool,
+    /// Also write the log to the console (the terminal the app was started from or a new one), e.g.
+    /// to diagnose startup failures.
+    #[arg(long)]
+    pub console: bool,
+    /// Run a separately configured instance (its own configuration, records, logs and startup
+    /// entry), e.g. to serve another top level domain on another port. Commands go to the running
+    /// instance of that name.
+    #[arg(long, value_name = "NAME", value_parser = parse_instance_name)]
+    pub instance: Option<String>,
+    /// Copy the app to a per-user location, create the default configuration, start it at login
+    /// and start it (turns the downloaded zip into an installed app).
+    #[arg(long, conflicts_with = "uninstall_cleanup")]
+    pub install: bool,
+    /// Undo the changes the app made to the system (startup entry, NRPT and firewall rules,
+    /// adapter DNS) before uninstalling, then exit. Combine with `--headless` in installers.
+    #[arg(long)]
+    pub uninstall_cleanup: bool,
+    /// With `--uninstall-cleanup`, also remove the configuration, records and logs.
+    #[arg(long, requires = "uninstall_cleanup")]
+    pub remove_data: bool,
+    /// Create the configuration (and records) from a provisioning seed without any UI, then exit.
+    /// Does nothing if the app is already configured.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["install", "uninstall_cleanup"])]
+    pub init_config: Option<PathBuf>,
+    /// Started by an instance that is exiting (e.g. to run as administrator): wait for it to
+    /// release the instance lock instead of forwarding to it.
+    #[arg(long, hide = true)]
+    pub relaunched: bool,
+    /// Started at login (by the startup entry): serving starts as configured in `[login_start]`.
+    #[arg(long, hide = true)]
+    pub at_login: bool,
+    /// Run a command in the app (the running instance if there is one).
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Instance names are part of directory, pipe and task names: lowercase letters, digits and hyphens.
+fn parse_instance_name(name: &str) -> Result<String, String> {
+    let valid = (1..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !name.starts_with('-');
+    if valid {
+        Ok(name.to_owned())
+    } else {
+        Err("up to 32 lowercase letters, digits and hyphens (not leading) are allowed".to_owned())
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Add a record (or change its address) in the records file.
+    Add { name: String, addr: Ipv4Addr },
+    /// Remove a record from the records file.
+    Remove { name: String },
+    /// Reload the records file.
+    Reload,
+    /// Stop answering queries until resumed.
+    Pause,
+    /// Resume answering queries.
+    Resume,
+    /// Write the diagnostics (configuration, records, status, stats and recent errors) as JSON to
+    /// the file, to attach to issues.
+    Diagnostics { path: PathBuf },
+}
+
+impl Command {
+    pub fn method(&self) -> Method {
+        match self {
+            Command::Add { name, addr } => Method::AddRecord {
+                name: name.clone(),
+                addr: *addr,
+            },
+            Command::Remove { name } => Method::RemoveRecord { name: name.clone() },
+            Command::Reload => Method::Reload,
+            Command::Pause => Method::Pause,
+            Command::Resume => Method::Resume,
+            Command::Diagnostics { path } => Method::DumpDiagnostics {
+                // the running instance doesn't share our working directory
+                path: std::path::absolute(path).unwrap_or_else(|_| path.clone()),
+            },
+        }
+    }
+
+    /// Describes the command once it succeeded.
+    pub fn done(&self) -> String {
+        match self {
+            Command::Add { name, addr } => format!("Added {name} → {addr}"),
+            Command::Remove { name } => format!("Removed {name}"),
+            Command::Reload => "Reloaded records".to_owned(),
+            Command::Pause => "Paused DNS serving".to_owned(),
+            Command::Resume => "Resumed DNS serving".to_owned(),
+            Command::Diagnostics { path } => format!("Wrote the diagnostics to {}", path.display()),
+        }
+    }
+}
@@ -0,0 +1,313 @@
+//! An opt-in HTTP admin API on the loopback interface, for tools that prefer REST over the
+//! JSON-RPC pipe (e.g. a dev portal registering hostnames). Every request but `/` and `/health` must
+//! carry the [API token](super::token) as `Authorization: Bearer <token>` or as a `token` query
+//! parameter (browsers can't set headers on web sockets).
+//!
+//! * `GET /` → the bundled web UI (no token required, it asks for one)
+//! * `GET /health` → the server status (no token required, `503` unless serving)
+//! * `GET /api/status`, `GET /api/stats`
+//! * `GET /api/records`, `POST /api/records` `{name, addr}`
+//! * `GET /api/records/{name}`, `PUT /api/records/{name}` `{addr}`, `DELETE /api/records/{name}`
+//! * `POST /api/reload`
+//! * `GET /events` → a web socket streaming every answered query as a
+//!   [`QueryInfo`](super::protocol::QueryInfo) JSON message
+//!
+//! Errors are returned as `{"error": "<message>"}`.
+
+use super::protocol::{Method, RecordInfo, StatsInfo, StatusInfo};
+use super::token::ApiToken;
+use super::{query_info, ControlServer};
+use crate::app_config::AdminApiConfig;
+use crate::prelude::*;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path as UrlPath, Query, Request, State};
+use axum::http::{header, HeaderMap, StatusCode, Uri};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio::select;
+use tokio::sync::broadcast;
+
+#[derive(Clone)]
+pub struct AdminApi {
+    control: ControlServer,
+    port: u16,
+}
+
+#[derive(Deserialize)]
+struct NewRecord {
+    name: String,
+    addr: Ipv4Addr,
+}
+
+#[derive(Deserialize)]
+struct RecordAddress {
+    addr: Ipv4Addr,
+}
+
+/// An error response with a JSON body.
+struct ApiError(StatusCode, String);
+
+type ApiResult<T> = std::result::Result<T, ApiError>;
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// Failed requests to the DNS server are (usually) invalid input, e.g. an invalid host name.
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        ApiError(StatusCode::BAD_REQUEST, format!("{e:#}"))
+    }
+}
+
+impl AdminApi {
+    pub fn new(control: ControlServer, config: &AdminApiConfig) -> Self {
+        Self {
+            control,
+            port: config.port,
+        }
+    }
+
+    pub async fn run(self) {
+        if let Err(e) = self.serve().await {
+            notify_error!("Admin API error: {e:#}");
+        }
+    }
+
+    async fn serve(self) -> Result<()> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, self.port))
+            .await
+            .with_context(|| format!("binding 127.0.0.1:{}", self.port))?;
+        info!("Admin API listening on {}", listener.local_addr()?);
+        axum::serve(listener, self.router())
+            .await
+            .context("serving the admin API")
+    }
+
+    fn router(self) -> Router {
+        let api = Router::new()
+            .route("/status", get(status))
+            .route("/stats", get(stats))
+            .route("/records", get(list_records).post(add_record))
+            .route(
+                "/records/{name}",
+                get(get_record).put(put_record).delete(delete_record),
+            )
+            .route("/reload", post(reload));
+        let protected = Router::new()
+            .nest("/api", api)
+            .route("/events", get(events))
+            .route_layer(middleware::from_fn_with_state(self.clone(), authenticate));
+        Router::new()
+            .route("/", get(web_ui))
+            .route("/health", get(health))
+            .merge(protected)
+            .with_state(self)
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: Method) -> Result<T> {
+        let result = self.control.call(method).await?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    async fn find_record(&self, name: &str) -> ApiResult<RecordInfo> {
+        let records: Vec<RecordInfo> = self.call(Method::ListRecords).await?;
+        records
+            .into_iter()
+            .find(|record| record.name == name)
+            .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("No such record: {name}")))
+    }
+}
+
+async fn authenticate(
+    State(api): State<AdminApi>,
+    Query(params): Query<HashMap<String, String>>,
+    request: Request,
+    next: Next,
+) -> ApiResult<Response> {
+    let token = &api.control.token;
+    let query_token = params.get("token").is_some_and(|t| token.matches(t));
+    if query_token || authorized(request.headers(), token) {
+        Ok(next.run(request).await)
+    } else {
+        warn!(
+            "Rejected unauthorized admin API request: {}",
+            redacted_uri(request.uri())
+        );
+        Err(ApiError(
+            StatusCode::UNAUTHORIZED,
+            "Missing or invalid token".to_owned(),
+        ))
+    }
+}
+
+fn authorized(headers: &HeaderMap, token: &ApiToken) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|provided| token.matches(provided))
+}
+
+/// The `uri` without the value of its `token` query parameter, for the log.
+fn redacted_uri(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.path().to_owned();
+    };
+    let params: Vec<_> = query
+        .split('&')
+        .map(|param| match param.split_once('=') {
+            Some(("token", _)) => "token=<redacted>",
+            _ => param,
+        })
+        .collect();
+    format!("{}?{}", uri.path(), params.join("&"))
+}
+
+async fn web_ui() -> Html<&'static str> {
+    Html(include_str!("admin_ui.html"))
+}
+
+async fn health(State(api): State<AdminApi>) -> ApiResult<(StatusCode, Json<StatusInfo>)> {
+    let status: StatusInfo = api.call(Method::Status).await?;
+    let code = if status.state == "listening" || status.state == "paused" {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok((code, Json(status)))
+}
+
+async fn status(State(api): State<AdminApi>) -> ApiResult<Json<StatusInfo>> {
+    Ok(Json(api.call(Method::Status).await?))
+}
+
+async fn stats(State(api): State<AdminApi>) -> ApiResult<Json<StatsInfo>> {
+    Ok(Json(api.call(Method::Stats).await?))
+}
+
+async fn list_records(State(api): State<AdminApi>) -> ApiResult<Json<Vec<RecordInfo>>> {
+    Ok(Json(api.call(Method::ListRecords).await?))
+}
+
+async fn get_record(
+    State(api): State<AdminApi>,
+    UrlPath(name): UrlPath<String>,
+) -> ApiResult<Json<RecordInfo>> {
+    Ok(Json(api.find_record(&name).await?))
+}
+
+async fn add_record(
+    State(api): State<AdminApi>,
+    Json(record): Json<NewRecord>,
+) -> ApiResult<(StatusCode, Json<RecordInfo>)> {
+    let NewRecord { name, addr } = record;
+    api.call::<()>(Method::AddRecord {
+        name: name.clone(),
+        addr,
+    })
+    .await?;
+    Ok((StatusCode::CREATED, Json(api.find_record(&name).await?)))
+}
+
+async fn put_record(
+    State(api): State<AdminApi>,
+    UrlPath(name): UrlPath<String>,
+    Json(record): Json<RecordAddress>,
+) -> ApiResult<Json<RecordInfo>> {
+    api.call::<()>(Method::AddRecord {
+        name: name.clone(),
+        addr: record.addr,
+    })
+    .await?;
+    Ok(Json(api.find_record(&name).await?))
+}
+
+async fn delete_record(
+    State(api): State<AdminApi>,
+    UrlPath(name): UrlPath<String>,
+) -> ApiResult<StatusCode> {
+    api.find_record(&name).await?;
+    api.call::<()>(Method::RemoveRecord { name }).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn reload(State(api): State<AdminApi>) -> ApiResult<StatusCode> {
+    api.call::<()>(Method::Reload)
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}")))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn events(State(api): State<AdminApi>, ws: WebSocketUpgrade) -> Response {
+    let queries = api.control.queries_tx.subscribe();
+    ws.on_upgrade(|socket| stream_events(socket, queries))
+}
+
+async fn stream_events(mut socket: WebSocket, mut queries: broadcast::Receiver<QueryEvent>) {
+    debug!("Admin API events client connected");
+    loop {
+        select! {
+            event = queries.recv() => match event {
+                Ok(event) => {
+                    let Ok(json) = serde_json::to_string(&query_info(&event)) else {
+                        continue;
+                    };
+                    if socket.send(Message::Text(json.into())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Admin API events client missed {skipped} queries");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            // reading also answers pings, anything but a close message is ignored
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    debug!("Admin API events client disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn requests_require_the_bearer_token() {
+        let with = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_static(value));
+            headers
+        };
+        let token = ApiToken::from("s3cret");
+        assert!(authorized(&with("Bearer s3cret"), &token));
+        assert!(!authorized(&with("Bearer s3cre"), &token));
+        assert!(!authorized(&with("Bearer s3cret2"), &token));
+        assert!(!authorized(&with("s3cret"), &token));
+        assert!(!authorized(&HeaderMap::new(), &token));
+    }
+
+    #[test]
+    fn logged_requests_leave_out_the_token() {
+        let redacted = |uri: &'static str| redacted_uri(&Uri::from_static(uri));
+        assert_eq!(redacted("/events?token=s3cret"), "/events?token=<redacted>");
+        assert_eq!(
+            redacted("/api/records?a=1&token=s3cret&b=2"),
+            "/api/records?a=1&token=<redacted>&b=2"
+        );
+        assert_eq!(redacted("/api/records"), "/api/records");
+    }
+}
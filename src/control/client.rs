@@ -0,0 +1,100 @@
+//! A client of the control pipe of the running app, used by the `dlocal` CLI and to forward
+//! commands from a second instance of the app.
+//!
+//! Like the protocol, this file is shared by the app and the CLI so it must not depend on the rest of
+//! the app.
+
+use super::protocol::{pipe_name, Method, RpcNotification, RpcRequest, RpcResponse};
+use super::token::read_token;
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+#[cfg(target_os = "windows")]
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+#[cfg(target_os = "windows")]
+use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeClient as Connection};
+#[cfg(target_os = "macos")]
+use tokio::net::UnixStream as Connection;
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Foundation::ERROR_PIPE_BUSY;
+
+#[cfg(target_os = "windows")]
+const CONNECT_ATTEMPTS: usize = 10;
+#[cfg(target_os = "windows")]
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// A connection to the control pipe of the running app.
+pub struct Client {
+    pipe: BufReader<Connection>,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connect to the instance (`None` for the default one) and authenticate with the API token of
+    /// the user.
+    pub async fn connect(instance: Option<&str>) -> Result<Self> {
+        let token = read_token(instance)?;
+        let mut client = Self {
+            pipe: BufReader::new(connect(&pipe_name(instance)).await?),
+            next_id: 1,
+        };
+        client
+            .call::<()>(Method::Authenticate { token })
+            .await
+            .context("authenticating with the app")?;
+        Ok(client)
+    }
+
+    /// Call a method. Error responses are returned as [`RpcError`](super::protocol::RpcError)s.
+    pub async fn call<T: DeserializeOwned>(&mut self, method: Method) -> Result<T> {
+        let request = RpcRequest::new(&method, self.next_id);
+        self.next_id += 1;
+        let mut line = serde_json::to_string(&request)?;
+        line.push('\n');
+        self.pipe.get_mut().write_all(line.as_bytes()).await?;
+        line.clear();
+        if self.pipe.read_line(&mut line).await? == 0 {
+            bail!("The app closed the connection");
+        }
+        let response: RpcResponse =
+            serde_json::from_str(&line).context("decoding the app response")?;
+        serde_json::from_value(response.into_result()?).context("decoding the app response")
+    }
+
+    /// Wait for the next notification from the app (e.g. after `watch`).
+    pub async fn notification(&mut self) -> Result<RpcNotification> {
+        let mut line = String::new();
+        if self.pipe.read_line(&mut line).await? == 0 {
+            bail!("The app closed the connection");
+        }
+        serde_json::from_str(&line).context("decoding the app notification")
+    }
+}
+
+#[cfg(target_os = "windows")]
+async fn connect(pipe_name: &str) -> Result<Connection> {
+    let mut attempt = 1;
+    loop {
+        match ClientOptions::new().open(pipe_name) {
+            Err(e)
+                if e.raw_os_error() == i32::try_from(ERROR_PIPE_BUSY).ok()
+                    && attempt < CONNECT_ATTEMPTS =>
+            {
+                attempt += 1;
+                tokio::time::sleep(BUSY_RETRY_DELAY).await;
+            }
+            result => {
+                return result.with_context(|| {
+                    format!("connecting to {pipe_name} (is DotLocal-DNS running?)")
+                })
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+async fn connect(socket_path: &str) -> Result<Connection> {
+    Connection::connect(socket_path)
+        .await
+        .with_context(|| format!("connecting to {socket_path} (is DotLocal-DNS running?)"))
+}
@@ -0,0 +1,370 @@
+mod admin_api;
+#[allow(dead_code)] // watching is only used by the dlocal CLI
+pub mod client;
+#[allow(dead_code)] // the client side of the protocol is used by the dlocal CLI
+pub mod protocol;
+mod security;
+pub mod token;
+
+pub use admin_api::AdminApi;
+
+use crate::dns::{ImportFormat, ImportOutcome, ImportReport, RecordMatch, LATENCY_BUCKETS};
+use crate::prelude::*;
+use protocol::{
+    pipe_name, ImportInfo, ImportLineInfo, LatencyBucket, LookupInfo, MergeInfo, Method, QueryInfo,
+    RecordInfo, RpcError, RpcNotification, RpcRequest, RpcResponse, StatsInfo, StatusInfo,
+    VersionInfo, INVALID_REQUEST, PARSE_ERROR, PROTOCOL_VERSION, QUERY_NOTIFICATION, SERVER_ERROR,
+    UNAUTHORIZED,
+};
+#[cfg(target_os = "macos")]
+use security::bind_user_socket;
+#[cfg(target_os = "windows")]
+use security::create_user_pipe;
+use serde::Serialize;
+use serde_json::{to_value, Value};
+use token::ApiToken;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{broadcast, watch};
+
+/// Requests are single lines (only imports are long), the connection is closed after a longer one.
+const MAX_REQUEST_LENGTH: u64 = 1024 * 1024;
+
+/// Serves JSON-RPC control requests (see [`protocol`]) from the `dlocal` CLI and other tools over a
+/// named pipe (a Unix socket on macOS) by routing them to the DNS server. Only the user running the
+/// app can connect to the pipe, and connections must authenticate with the [`ApiToken`].
+#[derive(Clone)]
+pub struct ControlServer {
+    notify_tx: Sender<Notification>,
+    status_rx: watch::Receiver<ServerStatus>,
+    stats_rx: watch::Receiver<ServerStats>,
+    /// Subscribed by every watching client.
+    queries_tx: broadcast::Sender<QueryEvent>,
+    token: ApiToken,
+}
+
+impl ControlServer {
+    pub fn new(dns_server: &DnsServer, token: ApiToken) -> Self {
+        Self {
+            notify_tx: dns_server.notify_tx.clone(),
+            status_rx: dns_server.subscribe_status(),
+            stats_rx: dns_server.subscribe_stats(),
+            queries_tx: dns_server.query_events_sender(),
+            token,
+        }
+    }
+
+    pub async fn run(self) {
+        if let Err(e) = self.serve().await {
+            notify_error!("Control server error (the dlocal CLI won't work): {e:#}");
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    async fn serve(&self) -> Result<()> {
+        let pipe_name = pipe_name(instance());
+        let mut pipe = create_user_pipe(&pipe_name, true)?;
+        info!("Control server listening on {pipe_name}");
+        loop {
+            pipe.connect().await.context("waiting for control client")?;
+            let client = pipe;
+            pipe = create_user_pipe(&pipe_name, false)?;
+            self.spawn_client(client);
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    async fn serve(&self) -> Result<()> {
+        let socket_path = pipe_name(instance());
+        let listener = bind_user_socket(&socket_path)?;
+        info!("Control server listening on {socket_path}");
+        loop {
+            let (client, _) = listener
+                .accept()
+                .await
+                .context("waiting for control client")?;
+            self.spawn_client(client);
+        }
+    }
+
+    fn spawn_client(&self, client: impl AsyncRead + AsyncWrite + Send + 'static) {
+        let server = self.clone();
+        tokio::spawn(async move {
+            server
+                .handle_client(client)
+                .await
+                .unwrap_or_else(|e| warn!("Error handling control client: {e:#}"));
+        });
+    }
+
+    async fn handle_client(&self, client: impl AsyncRead + AsyncWrite) -> Result<()> {
+        let (reader, mut writer) = tokio::io::split(client);
+        let mut reader = BufReader::new(reader);
+        let mut line = String::new();
+        let mut authenticated = false;
+        loop {
+            line.clear();
+            let read = (&mut reader)
+                .take(MAX_REQUEST_LENGTH + 1)
+                .read_line(&mut line)
+                .await?;
+            if read == 0 {
+                break;
+            }
+            if u64::try_from(read).unwrap_or(u64::MAX) > MAX_REQUEST_LENGTH {
+                let error = RpcError::new(INVALID_REQUEST, "Request too long");
+                write_message(&mut writer, &RpcResponse::failure(Value::Null, error)).await?;
+                break;
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            debug!("Control request: {line}");
+            let request: RpcRequest = match serde_json::from_str(line) {
+                Ok(request) => request,
+                Err(e) => {
+                    let error =
+                        RpcError::new(PARSE_ERROR, format!("Invalid JSON-RPC request: {e}"));
+                    write_message(&mut writer, &RpcResponse::failure(Value::Null, error)).await?;
+                    continue;
+                }
+            };
+            let watch = request.method() == Ok(Method::Watch);
+            if let Some(response) = self.handle_request(request, &mut authenticated).await {
+                write_message(&mut writer, &response).await?;
+            }
+            if watch && authenticated {
+                return self.stream_queries(&mut writer).await;
+            }
+        }
+        writer.shutdown().await?;
+        Ok(())
+    }
+
+    /// Send a notification for every answered query until the client disconnects.
+    async fn stream_queries(&self, writer: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+        let mut queries = self.queries_tx.subscribe();
+        loop {
+            match queries.recv().await {
+                Ok(event) if event.is_resolver_probe() => (),
+                Ok(event) => {
+                    let params = to_value(query_info(&event))?;
+                    let notification = RpcNotification::new(QUERY_NOTIFICATION, params);
+                    if write_message(writer, &notification).await.is_err() {
+                        debug!("Watching control client disconnected");
+                        return Ok(());
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Watching control client missed {skipped} queries");
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+
+    /// Handle a single JSON-RPC request. Returns `None` for notifications.
+    async fn handle_request(
+        &self,
+        request: RpcRequest,
+        authenticated: &mut bool,
+    ) -> Option<RpcResponse> {
+        let result = match request.method() {
+            Ok(Method::Authenticate { token }) => {
+                *authenticated = self.token.matches(&token);
+                if *authenticated {
+                    Ok(Value::Null)
+                } else {
+                    warn!("Rejected control client with an invalid token");
+                    Err(RpcError::new(UNAUTHORIZED, "Invalid API token"))
+                }
+            }
+            Ok(method) if !*authenticated && method != Method::Version => Err(RpcError::new(
+                UNAUTHORIZED,
+                "Not authenticated, call authenticate with the API token first",
+            )),
+            Ok(method) => self
+                .call(method)
+                .await
+                .map_err(|e| RpcError::new(SERVER_ERROR, format!("{e:#}"))),
+            Err(e) => Err(e),
+        };
+        let id = request.id?;
+        Some(match result {
+            Ok(result) => RpcResponse::success(id, result),
+            Err(error) => RpcResponse::failure(id, error),
+        })
+    }
+
+    pub async fn call(&self, method: Method) -> Result<Value> {
+        let tx = &self.notify_tx;
+        let result = match method {
+            Method::Version => to_value(VersionInfo {
+                protocol: PROTOCOL_VERSION,
+                app: APP_VERSION.to_owned(),
+            })?,
+            Method::Status => to_value(status_info(&self.status_rx.borrow()))?,
+            Method::Stats => {
+                let stats = self.stats_rx.borrow().clone();
+                to_value(StatsInfo {
+                    queries: stats.queries,
+                    reloads: stats.reloads,
+                    system_udp_receive_errors: stats.system_udp_receive_errors,
+                    latency: latency_buckets(&stats.latency),
+                })?
+            }
+            Method::ListRecords => {
+                let records: Vec<_> = request(tx, ListRecords)
+                    .await?
+                    .into_iter()
+                    .map(|r| RecordInfo {
+                        name: r.name,
+                        addr: r.addr,
+                        rtype: format!("{:?}", r.record_type),
+                        ttl: r.ttl,
+                        source: r.source.name().to_owned(),
+                        enabled: r.enabled,
+                    })
+                    .collect();
+                to_value(records)?
+            }
+            Method::AddRecord { name, addr } => {
+                request(tx, |reply| AddRecord(name, addr, reply)).await?;
+                Value::Null
+            }
+            Method::RemoveRecord { name } => {
+                request(tx, |reply| RemoveRecord(name, reply)).await?;
+                Value::Null
+            }
+            Method::Lookup { name } => {
+                let result = request(tx, |reply| ARecordQuery(name.clone(), reply)).await?;
+                let record = match &result.matched {
+                    RecordMatch::Exact => Some(name.clone()),
+                    RecordMatch::Suffix(record) => Some(record.clone()),
+                    RecordMatch::Default => None,
+                };
+                to_value(LookupInfo {
+                    description: result.matched.describe(&name),
+                    name,
+                    addr: result.addr,
+                    record,
+                })?
+            }
+            Method::Reload => {
+                request(tx, ReloadRecords).await?;
+                Value::Null
+            }
+            Method::Merge { path } => {
+                let summary = request(tx, |reply| MergeRecords(path, reply)).await?;
+                to_value(MergeInfo {
+                    added: summary.added,
+                    conflicts: summary.conflicts,
+                })?
+            }
+            Method::Import { records, format } => {
+                let format = match format {
+                    protocol::ImportFormat::Records => ImportFormat::Records,
+                    protocol::ImportFormat::Dnsmasq => ImportFormat::Dnsmasq,
+                };
+                let report = request(tx, |reply| ImportRecords(records, format, reply)).await?;
+                to_value(import_info(report))?
+            }
+            Method::Pause => {
+                tx.send(SetPaused(true))
+                    .await
+                    .context("sending pause request")?;
+                Value::Null
+            }
+            Method::Resume => {
+                tx.send(SetPaused(false))
+                    .await
+                    .context("sending resume request")?;
+                Value::Null
+            }
+            Method::DumpDiagnostics { path } => {
+                request(tx, |reply| DumpDiagnostics(path, reply)).await?;
+                Value::Null
+            }
+            // pipe connections are authenticated (and the queries are streamed after the response)
+            // by the pipe client handler, other callers are trusted
+            Method::Authenticate { .. } | Method::Watch => Value::Null,
+        };
+        Ok(result)
+    }
+}
+
+fn status_info(status: &ServerStatus) -> StatusInfo {
+    let (state, address, error) = match status {
+        ServerStatus::Starting => ("starting", None, None),
+        ServerStatus::LoadingRecords => ("loadingRecords", None, None),
+        ServerStatus::Listening(addr) => ("listening", Some(addr.to_string()), None),
+        ServerStatus::Paused(addr) => ("paused", Some(addr.to_string()), None),
+        ServerStatus::Failed(e) => ("failed", None, Some(e.clone())),
+        ServerStatus::Stopped => ("stopped", None, None),
+    };
+    StatusInfo {
+        state: state.to_owned(),
+        address,
+        error,
+        description: status.describe(),
+    }
+}
+
+fn latency_buckets(counts: &[u64]) -> Vec<LatencyBucket> {
+    let bounds = LATENCY_BUCKETS
+        .iter()
+        .map(|bound| Some(u64::try_from(bound.as_micros()).unwrap_or(u64::MAX)));
+    bounds
+        .chain([None])
+        .zip(counts)
+        .map(|(le_us, &count)| LatencyBucket { le_us, count })
+        .collect()
+}
+
+fn import_info(report: ImportReport) -> ImportInfo {
+    let lines = report
+        .lines
+        .into_iter()
+        .map(|(line, outcome)| {
+            let (status, record, error) = match outcome {
+                ImportOutcome::Added(name, addr) => ("added", Some((name, addr)), None),
+                ImportOutcome::Updated(name, addr) => ("updated", Some((name, addr)), None),
+                ImportOutcome::Unchanged(name, addr) => ("unchanged", Some((name, addr)), None),
+                ImportOutcome::Invalid(error) => ("invalid", None, Some(error)),
+                ImportOutcome::Skipped(reason) => ("skipped", None, Some(reason)),
+            };
+            let (name, addr) = record.unzip();
+            ImportLineInfo {
+                line,
+                status: status.to_owned(),
+                name,
+                addr,
+                error,
+            }
+        })
+        .collect();
+    ImportInfo {
+        applied: report.applied,
+        lines,
+    }
+}
+
+fn query_info(event: &QueryEvent) -> QueryInfo {
+    QueryInfo {
+        name: event.name.clone(),
+        qtype: format!("{:?}", event.qtype),
+        client: event.client.to_string(),
+        answer: event.answer,
+        rcode: format!("{:?}", event.rescode),
+    }
+}
+
+async fn write_message(
+    writer: &mut (impl AsyncWrite + Unpin),
+    message: &impl Serialize,
+) -> Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    Ok(())
+}
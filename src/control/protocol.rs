@@ -0,0 +1,635 @@
+//! The control protocol of the running app: [JSON-RPC 2.0](https://www.jsonrpc.org/specification)
+//! over the named pipe. Each request and response is a single line of JSON and a client may send
+//! any number of requests on a connection. Requests without an `id` are notifications and get no
+//! response.
+//!
+//! Connections must first authenticate with the per-install API token (see
+//! [`token`](super::token)), other requests (but `version`) fail with [`UNAUTHORIZED`] until then.
+//!
+//! Methods (params → result), their names are matched case-insensitively:
+//!
+//! * `authenticate` `{token}` → `null`
+//! * `version` → [`VersionInfo`]
+//! * `status` → [`StatusInfo`]
+//! * `stats` → [`StatsInfo`]
+//! * `listRecords` → [`RecordInfo`] array, the records of the records file (including the disabled
+//!   ones) and of the sync sources sorted by name
+//! * `addRecord` `{name, addr}` → `null`
+//! * `removeRecord` `{name}` → `null`
+//! * `lookup` `{name}` → [`LookupInfo`]
+//! * `reload` → `null`
+//! * `merge` `{path}` → [`MergeInfo`]
+//! * `import` `{records, format}` → [`ImportInfo`], the records (a file in the [`ImportFormat`]) are
+//!   only added if all the lines are valid
+//! * `pause` / `resume` → `null`
+//! * `dumpDiagnostics` `{path}` → `null`, writes the diagnostics (configuration, records, status,
+//!   stats and recent errors) as JSON to the (absolute) path
+//! * `watch` → `null`, then a `query` notification ([`QueryInfo`] params) is sent for every
+//!   answered query until the client disconnects. No other requests are handled on the connection.
+//!
+//! New methods and result fields may be added without changing [`PROTOCOL_VERSION`], it's only
+//! incremented on incompatible changes.
+//!
+//! This file is shared by the app and the `dlocal` CLI so it must not depend on the rest of the
+//! app.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+
+#[cfg(target_os = "windows")]
+const PIPE_NAME: &str = r"\\.\pipe\dot-local-dns";
+#[cfg(target_os = "macos")]
+const SOCKET_NAME: &str = "dot-local-dns";
+pub const PROTOCOL_VERSION: u32 = 2;
+const JSONRPC_VERSION: &str = "2.0";
+
+/// The control pipe of the instance (`--instance`), `None` for the default one.
+#[cfg(target_os = "windows")]
+pub fn pipe_name(instance: Option<&str>) -> String {
+    match instance {
+        Some(instance) => format!("{PIPE_NAME}-{instance}"),
+        None => PIPE_NAME.to_owned(),
+    }
+}
+
+/// The control socket of the instance (`--instance`), `None` for the default one. It's in the
+/// temporary directory of the user, which only the user may access.
+#[cfg(target_os = "macos")]
+pub fn pipe_name(instance: Option<&str>) -> String {
+    let name = match instance {
+        Some(instance) => format!("{SOCKET_NAME}-{instance}.sock"),
+        None => format!("{SOCKET_NAME}.sock"),
+    };
+    std::env::temp_dir().join(name).display().to_string()
+}
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The method failed (e.g. adding an invalid record).
+pub const SERVER_ERROR: i64 = -32000;
+/// The connection isn't authenticated (or the token is invalid).
+pub const UNAUTHORIZED: i64 = -32001;
+
+/// The names of the [`Method`]s, matched case-insensitively.
+const METHOD_NAMES: [&str; 15] = [
+    "authenticate",
+    "version",
+    "status",
+    "stats",
+    "listRecords",
+    "addRecord",
+    "removeRecord",
+    "lookup",
+    "reload",
+    "merge",
+    "import",
+    "pause",
+    "resume",
+    "dumpDiagnostics",
+    "watch",
+];
+
+/// The method of the notifications sent to `watch` clients.
+pub const QUERY_NOTIFICATION: &str = "query";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "method", content = "params", rename_all = "camelCase")]
+pub enum Method {
+    Authenticate {
+        token: String,
+    },
+    Version,
+    Status,
+    Stats,
+    ListRecords,
+    AddRecord {
+        name: String,
+        addr: Ipv4Addr,
+    },
+    RemoveRecord {
+        name: String,
+    },
+    Lookup {
+        name: String,
+    },
+    Reload,
+    Merge {
+        path: PathBuf,
+    },
+    Import {
+        records: String,
+        #[serde(default)]
+        format: ImportFormat,
+    },
+    Pause,
+    Resume,
+    DumpDiagnostics {
+        path: PathBuf,
+    },
+    Watch,
+}
+
+/// The format of the imported records.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportFormat {
+    /// `name:IP` lines, like the records file.
+    #[default]
+    Records,
+    /// dnsmasq `address=/name/IP` configuration lines, other lines are ignored.
+    Dnsmasq,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+    pub id: Value,
+}
+
+/// A request without an id, also sent by the server (e.g. to `watch` clients).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VersionInfo {
+    pub protocol: u32,
+    pub app: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusInfo {
+    /// One of `starting`, `loadingRecords`, `listening`, `paused`, `failed` or `stopped`.
+    pub state: String,
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+    pub description: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StatsInfo {
+    pub queries: u64,
+    pub reloads: u64,
+    /// The UDP (IPv4) receive errors of the whole system while the server ran, not only the
+    /// server's (0 from older apps).
+    #[serde(default, rename = "systemUdpReceiveErrors")]
+    pub system_udp_receive_errors: u64,
+    /// The answered queries by latency (empty from older apps).
+    #[serde(default)]
+    pub latency: Vec<LatencyBucket>,
+}
+
+/// The queries answered within `le_us` microseconds (and slower than the previous bucket), the last
+/// bucket (without `le_us`) counts the slower queries.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyBucket {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub le_us: Option<u64>,
+    pub count: u64,
+}
+
+/// A record of the records file or a sync source. Older apps only list the records file records,
+/// without the type, TTL and source.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordInfo {
+    pub name: String,
+    pub addr: Ipv4Addr,
+    /// The record type, `A`.
+    #[serde(default = "a_record", rename = "type")]
+    pub rtype: String,
+    #[serde(default)]
+    pub ttl: u32,
+    /// `records file` or the sync source, e.g. `docker`.
+    #[serde(default = "records_file")]
+    pub source: String,
+    pub enabled: bool,
+}
+
+/// The source of the records file records.
+pub const RECORDS_FILE_SOURCE: &str = "records file";
+
+fn a_record() -> String {
+    "A".to_owned()
+}
+
+fn records_file() -> String {
+    RECORDS_FILE_SOURCE.to_owned()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LookupInfo {
+    pub name: String,
+    pub addr: Ipv4Addr,
+    /// The record that answered the query (`null` if answered with the default address).
+    #[serde(default)]
+    pub record: Option<String>,
+    pub description: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MergeInfo {
+    pub added: usize,
+    /// Records that were already defined with a different address.
+    pub conflicts: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImportInfo {
+    /// `false` if any line is invalid, nothing is imported then.
+    pub applied: bool,
+    pub lines: Vec<ImportLineInfo>,
+}
+
+/// The result of a record line of an import (blank and comment lines are skipped).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImportLineInfo {
+    /// The (1 based) line number.
+    pub line: usize,
+    /// One of `added`, `updated`, `unchanged`, `invalid` or `skipped` (e.g. an IPv6 dnsmasq address,
+    /// the other lines are still imported). The `error` is why it's invalid or skipped.
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub addr: Option<Ipv4Addr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// An answered query.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QueryInfo {
+    pub name: String,
+    /// The query type, e.g. `A` or `AAAA`.
+    #[serde(rename = "type")]
+    pub qtype: String,
+    pub client: String,
+    /// The answered address (`null` if the answer has no A record).
+    #[serde(default)]
+    pub answer: Option<Ipv4Addr>,
+    /// The response code, e.g. `NOERROR` or `REFUSED`.
+    pub rcode: String,
+}
+
+impl RpcRequest {
+    pub fn new(method: &Method, id: u64) -> Self {
+        let call = serde_json::to_value(method).unwrap_or_default();
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_owned(),
+            method: call["method"].as_str().unwrap_or_default().to_owned(),
+            params: call.get("params").cloned().unwrap_or_default(),
+            id: Some(id.into()),
+        }
+    }
+
+    /// The typed method. Errors are the JSON-RPC error to respond with.
+    pub fn method(&self) -> Result<Method, RpcError> {
+        if self.jsonrpc != JSONRPC_VERSION {
+            return Err(RpcError::new(
+                INVALID_REQUEST,
+                format!("Unsupported jsonrpc version: {}", self.jsonrpc),
+            ));
+        }
+        let no_params = match &self.params {
+            Value::Null => true,
+            Value::Array(params) => params.is_empty(),
+            Value::Object(params) => params.is_empty(),
+            _ => false,
+        };
+        let method = METHOD_NAMES
+            .iter()
+            .find(|name| name.eq_ignore_ascii_case(&self.method))
+            .ok_or_else(|| {
+                RpcError::new(METHOD_NOT_FOUND, format!("Unknown method: {}", self.method))
+            })?;
+        let call = if no_params {
+            json!({ "method": method })
+        } else {
+            json!({ "method": method, "params": self.params })
+        };
+        serde_json::from_value(call)
+            .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid params: {e}")))
+    }
+}
+
+impl RpcResponse {
+    pub fn success(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_owned(),
+            result: Some(result),
+            error: None,
+            id,
+        }
+    }
+
+    pub fn failure(id: Value, error: RpcError) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_owned(),
+            result: None,
+            error: Some(error),
+            id,
+        }
+    }
+
+    pub fn into_result(self) -> Result<Value> {
+        match (self.result, self.error) {
+            (_, Some(error)) => Err(error.into()),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(anyhow!("Invalid response: no result or error")),
+        }
+    }
+}
+
+impl RpcNotification {
+    pub fn new(method: &str, params: Value) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_owned(),
+            method: method.to_owned(),
+            params,
+        }
+    }
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Method, RpcError> {
+        serde_json::from_str::<RpcRequest>(line).unwrap().method()
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn instances_have_their_own_pipe() {
+        assert_eq!(pipe_name(None), r"\\.\pipe\dot-local-dns");
+        assert_eq!(pipe_name(Some("test")), r"\\.\pipe\dot-local-dns-test");
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn instances_have_their_own_socket() {
+        let temp_dir = std::env::temp_dir();
+        assert_eq!(
+            PathBuf::from(pipe_name(None)),
+            temp_dir.join("dot-local-dns.sock")
+        );
+        assert_eq!(
+            PathBuf::from(pipe_name(Some("test"))),
+            temp_dir.join("dot-local-dns-test.sock")
+        );
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        let methods = [
+            Method::Authenticate {
+                token: "0123abcd".to_owned(),
+            },
+            Method::Version,
+            Method::Status,
+            Method::Stats,
+            Method::ListRecords,
+            Method::AddRecord {
+                name: "nas.loc".to_owned(),
+                addr: Ipv4Addr::new(192, 168, 1, 10),
+            },
+            Method::RemoveRecord {
+                name: "nas.loc".to_owned(),
+            },
+            Method::Lookup {
+                name: "nas.loc".to_owned(),
+            },
+            Method::Reload,
+            Method::Merge {
+                path: PathBuf::from(r"C:\Users\me\my records.txt"),
+            },
+            Method::Import {
+                records: "a.loc:10.0.0.1\n# comment\nb.loc:10.0.0.2\n".to_owned(),
+                format: ImportFormat::Records,
+            },
+            Method::Import {
+                records: "address=/a.loc/10.0.0.1\n".to_owned(),
+                format: ImportFormat::Dnsmasq,
+            },
+            Method::Pause,
+            Method::Resume,
+            Method::DumpDiagnostics {
+                path: PathBuf::from(r"C:\Users\me\diagnostics.json"),
+            },
+            Method::Watch,
+        ];
+        assert_eq!(methods.len() - 1, METHOD_NAMES.len());
+        for (id, method) in methods.into_iter().enumerate() {
+            let request = RpcRequest::new(&method, id as u64);
+            assert!(METHOD_NAMES.contains(&request.method.as_str()));
+            let line = serde_json::to_string(&request).unwrap();
+            assert!(!line.contains('\n'));
+            assert_eq!(parse(&line).unwrap(), method);
+        }
+    }
+
+    #[test]
+    fn method_names_are_case_insensitive() {
+        assert_eq!(
+            parse(r#"{"jsonrpc":"2.0","method":"ListRecords","id":1}"#).unwrap(),
+            Method::ListRecords
+        );
+        assert_eq!(
+            parse(r#"{"jsonrpc":"2.0","method":"REMOVERECORD","params":{"name":"a.loc"},"id":1}"#)
+                .unwrap(),
+            Method::RemoveRecord {
+                name: "a.loc".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn request_wire_format() {
+        let request = RpcRequest::new(
+            &Method::AddRecord {
+                name: "nas.loc".to_owned(),
+                addr: Ipv4Addr::new(10, 0, 0, 1),
+            },
+            7,
+        );
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "addRecord",
+                "params": {"name": "nas.loc", "addr": "10.0.0.1"},
+                "id": 7
+            })
+        );
+        let request = RpcRequest::new(&Method::Reload, 8);
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"jsonrpc":"2.0","method":"reload","id":8}"#
+        );
+    }
+
+    #[test]
+    fn import_format_defaults_to_records() {
+        assert_eq!(
+            parse(r#"{"jsonrpc":"2.0","method":"import","params":{"records":"a.loc:10.0.0.1"},"id":1}"#)
+                .unwrap(),
+            Method::Import {
+                records: "a.loc:10.0.0.1".to_owned(),
+                format: ImportFormat::Records,
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let code = |line| parse(line).unwrap_err().code;
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","method":"restart","id":1}"#),
+            METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            code(r#"{"jsonrpc":"1.0","method":"reload","id":1}"#),
+            INVALID_REQUEST
+        );
+        assert_eq!(
+            code(
+                r#"{"jsonrpc":"2.0","method":"addRecord","params":{"name":"a.loc","addr":"1.2.3"},"id":1}"#
+            ),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","method":"removeRecord","id":1}"#),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            code(
+                r#"{"jsonrpc":"2.0","method":"import","params":{"records":"","format":"hosts"},"id":1}"#
+            ),
+            INVALID_PARAMS
+        );
+    }
+
+    #[test]
+    fn responses() {
+        let ok = RpcResponse::success(1.into(), json!({"queries": 3, "reloads": 1}));
+        let line = serde_json::to_string(&ok).unwrap();
+        assert_eq!(
+            line,
+            r#"{"jsonrpc":"2.0","result":{"queries":3,"reloads":1},"id":1}"#
+        );
+        let stats: StatsInfo = serde_json::from_value(
+            serde_json::from_str::<RpcResponse>(&line)
+                .unwrap()
+                .into_result()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            stats,
+            StatsInfo {
+                queries: 3,
+                reloads: 1,
+                system_udp_receive_errors: 0,
+                latency: vec![],
+            }
+        );
+        let error = RpcResponse::failure(2.into(), RpcError::new(SERVER_ERROR, "No such record"));
+        assert!(error.into_result().is_err());
+    }
+
+    #[test]
+    fn records_from_older_apps_are_from_the_records_file() {
+        let record: RecordInfo =
+            serde_json::from_value(json!({"name": "nas.loc", "addr": "10.0.0.1", "enabled": true}))
+                .unwrap();
+        assert_eq!(
+            record,
+            RecordInfo {
+                name: "nas.loc".to_owned(),
+                addr: Ipv4Addr::new(10, 0, 0, 1),
+                rtype: "A".to_owned(),
+                ttl: 0,
+                source: RECORDS_FILE_SOURCE.to_owned(),
+                enabled: true,
+            }
+        );
+    }
+
+    #[test]
+    fn query_notifications() {
+        let query = QueryInfo {
+            name: "nas.loc".to_owned(),
+            qtype: "A".to_owned(),
+            client: "127.0.0.1:5000".to_owned(),
+            answer: Some(Ipv4Addr::new(192, 168, 1, 10)),
+            rcode: "NOERROR".to_owned(),
+        };
+        let notification =
+            RpcNotification::new(QUERY_NOTIFICATION, serde_json::to_value(&query).unwrap());
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "query",
+                "params": {
+                    "name": "nas.loc",
+                    "type": "A",
+                    "client": "127.0.0.1:5000",
+                    "answer": "192.168.1.10",
+                    "rcode": "NOERROR"
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use crate::prelude::*;
+#[cfg(target_os = "macos")]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(target_os = "windows")]
+use std::{io, ptr::null_mut};
+#[cfg(target_os = "windows")]
+use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+#[cfg(target_os = "macos")]
+use tokio::net::UnixListener;
+#[cfg(target_os = "windows")]
+use windows_strings::{HSTRING, PWSTR};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Foundation::{CloseHandle, LocalFree, HANDLE};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Security::Authorization::{
+    ConvertSidToStringSidW, ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Security::{
+    GetTokenInformation, TokenUser, PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES, TOKEN_QUERY,
+    TOKEN_USER,
+};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+/// Create a pipe instance only the current user (and the system) may connect to, so other users
+/// logged in to the machine can't control the app. Remote clients are always rejected.
+#[cfg(target_os = "windows")]
+pub fn create_user_pipe(name: &str, first_instance: bool) -> Result<NamedPipeServer> {
+    let sddl = format!("D:P(A;;GA;;;{})(A;;GA;;;SY)", current_user_sid()?);
+    let mut descriptor: PSECURITY_DESCRIPTOR = null_mut();
+    let converted = unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            HSTRING::from(sddl).as_ptr(),
+            SDDL_REVISION_1,
+            &raw mut descriptor,
+            null_mut(),
+        )
+    };
+    if converted == 0 {
+        return Err(io::Error::last_os_error()).context("creating pipe security descriptor");
+    }
+    let mut attributes = SECURITY_ATTRIBUTES {
+        nLength: u32::try_from(size_of::<SECURITY_ATTRIBUTES>()).unwrap_or_default(),
+        lpSecurityDescriptor: descriptor,
+        bInheritHandle: 0,
+    };
+    let pipe = unsafe {
+        ServerOptions::new()
+            .first_pipe_instance(first_instance)
+            .reject_remote_clients(true)
+            .create_with_security_attributes_raw(name, (&raw mut attributes).cast())
+    };
+    unsafe { LocalFree(descriptor) };
+    pipe.with_context(|| format!("creating named pipe {name}"))
+}
+
+/// Listen on a socket only the current user (and root) may connect to, so other users logged in to
+/// the machine can't control the app. A socket left behind by a killed instance is replaced (the
+/// instance lock is held, so it isn't in use).
+#[cfg(target_os = "macos")]
+pub fn bind_user_socket(path: &str) -> Result<UnixListener> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("removing the stale socket {path}"));
+        }
+        _ => (),
+    }
+    let listener = UnixListener::bind(path).with_context(|| format!("binding socket {path}"))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("restricting socket {path} to the user"))?;
+    Ok(listener)
+}
+
+/// The SID (e.g. `S-1-5-21-…`) of the user running the app.
+#[cfg(target_os = "windows")]
+fn current_user_sid() -> Result<String> {
+    let mut token: HANDLE = null_mut();
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &raw mut token) } == 0 {
+        return Err(io::Error::last_os_error()).context("opening process token");
+    }
+    let sid = token_user_sid(token);
+    unsafe { CloseHandle(token) };
+    sid
+}
+
+#[cfg(target_os = "windows")]
+fn token_user_sid(token: HANDLE) -> Result<String> {
+    let mut len = 0;
+    unsafe { GetTokenInformation(token, TokenUser, null_mut(), 0, &raw mut len) };
+    // u64 elements so the buffer is aligned for TOKEN_USER
+    let mut buffer = vec![0u64; usize::try_from(len).unwrap_or_default().div_ceil(8)];
+    let ok = unsafe {
+        GetTokenInformation(
+            token,
+            TokenUser,
+            buffer.as_mut_ptr().cast(),
+            len,
+            &raw mut len,
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error()).context("reading token user");
+    }
+    let user = unsafe { &*buffer.as_ptr().cast::<TOKEN_USER>() };
+    let mut sid = PWSTR(null_mut());
+    if unsafe { ConvertSidToStringSidW(user.User.Sid, &raw mut sid.0) } == 0 {
+        return Err(io::Error::last_os_error()).context("converting user SID");
+    }
+    let result = unsafe { sid.to_string() }.context("decoding user SID");
+    unsafe { LocalFree(sid.0.cast()) };
+    result
+}
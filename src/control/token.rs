@@ -0,0 +1,156 @@
+//! The per-install API token. It's generated on the first run, stored in the user's app config
+//! directory and required by the control pipe (`authenticate`), the admin API and its web socket,
+//! so not every local process can control the app. Clients running as the user read it from the
+//! file.
+//!
+//! This file is shared by the app and the `dlocal` CLI so it must not depend on the rest of the
+//! app.
+
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::fs;
+#[cfg(target_os = "macos")]
+use std::io::Read;
+#[cfg(target_os = "macos")]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "windows")]
+use std::ptr::null_mut;
+use std::sync::{Arc, PoisonError, RwLock};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Security::Cryptography::{
+    BCryptGenRandom, BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+};
+
+pub const TOKEN_FILE_NAME: &str = "api-token";
+const TOKEN_BYTES: u32 = 32;
+
+/// The token file in the app config directory of the instance (`None` for the default one), the
+/// `-dev` one for debug builds, like the app.
+pub fn token_path(instance: Option<&str>) -> Result<PathBuf> {
+    let mut app_dir = "DotLocal-DNS".to_owned();
+    if let Some(instance) = instance {
+        app_dir = format!("{app_dir}-{instance}");
+    }
+    if cfg!(debug_assertions) {
+        app_dir.push_str("-dev");
+    }
+    Ok(dirs::config_dir()
+        .context("Could not find config directory")?
+        .join(app_dir)
+        .join(TOKEN_FILE_NAME))
+}
+
+pub fn read_token(instance: Option<&str>) -> Result<String> {
+    let path = token_path(instance)?;
+    let token = fs::read_to_string(&path).with_context(|| {
+        format!(
+            "reading the API token from {} (has DotLocal-DNS run yet?)",
+            path.display()
+        )
+    })?;
+    Ok(token.trim().to_owned())
+}
+
+/// The token of the running app, shared by the servers checking it and the tray rotating it.
+#[derive(Clone)]
+pub struct ApiToken(Arc<RwLock<String>>);
+
+impl ApiToken {
+    /// Read the token of the instance, generating it on the first run.
+    pub fn load_or_create(instance: Option<&str>) -> Result<Self> {
+        let token = match read_token(instance) {
+            Ok(token) if !token.is_empty() => token,
+            _ => generate_and_save(instance)?,
+        };
+        Ok(Self::from(token.as_str()))
+    }
+
+    pub fn get(&self) -> String {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Compare without leaking how much of the token matched through the timing.
+    pub fn matches(&self, provided: &str) -> bool {
+        let token = self.get();
+        let (a, b) = (provided.as_bytes(), token.as_bytes());
+        a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+    }
+
+    /// Replace the token, clients using the old one are rejected from now on (connected pipe
+    /// clients stay authenticated).
+    #[cfg(target_os = "windows")]
+    pub fn rotate(&self, instance: Option<&str>) -> Result<()> {
+        let token = generate_and_save(instance)?;
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = token;
+        Ok(())
+    }
+}
+
+impl From<&str> for ApiToken {
+    fn from(token: &str) -> Self {
+        Self(Arc::new(RwLock::new(token.to_owned())))
+    }
+}
+
+fn generate_and_save(instance: Option<&str>) -> Result<String> {
+    let token = random_bytes()?
+        .iter()
+        .fold(String::new(), |mut token, byte| {
+            let _ = write!(token, "{byte:02x}");
+            token
+        });
+    let path = token_path(instance)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    save(&path, &token).with_context(|| format!("writing {}", path.display()))?;
+    Ok(token)
+}
+
+#[cfg(target_os = "windows")]
+fn random_bytes() -> Result<[u8; TOKEN_BYTES as usize]> {
+    let mut bytes = [0u8; TOKEN_BYTES as usize];
+    let status = unsafe {
+        BCryptGenRandom(
+            null_mut(),
+            bytes.as_mut_ptr(),
+            TOKEN_BYTES,
+            BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+        )
+    };
+    if status != 0 {
+        anyhow::bail!("generating the API token failed (NTSTATUS {status:#x})");
+    }
+    Ok(bytes)
+}
+
+#[cfg(target_os = "macos")]
+fn random_bytes() -> Result<[u8; TOKEN_BYTES as usize]> {
+    let mut bytes = [0u8; TOKEN_BYTES as usize];
+    fs::File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut bytes))
+        .context("generating the API token")?;
+    Ok(bytes)
+}
+
+/// The config directory is only readable by the user on Windows, the file is on macOS.
+#[cfg(target_os = "windows")]
+fn save(path: &Path, token: &str) -> std::io::Result<()> {
+    fs::write(path, token)
+}
+
+#[cfg(target_os = "macos")]
+fn save(path: &Path, token: &str) -> std::io::Result<()> {
+    use std::io::Write as _;
+    fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(token.as_bytes())
+}
@@ -0,0 +1,99 @@
+//! Crash reports: a panic writes a report (the panic message, backtrace, version and a summary of
+//! the configuration) to the logs directory and a dialog points to it, instead of the tray icon
+//! silently disappearing.
+
+use crate::prelude::*;
+use chrono::Local;
+use std::backtrace::Backtrace;
+use std::fmt::Write as _;
+use std::panic::{self, PanicHookInfo};
+
+/// Install the panic hook. The previous (default) hook still runs, e.g. printing to the console.
+pub fn install_panic_hook(app_config: &AppConfig) {
+    let logging_dir = app_config.logging_dir.clone();
+    let config_summary = config_summary(app_config);
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        previous(info);
+        let report = crash_report(info, &config_summary, &Backtrace::force_capture());
+        error!("{APP_NAME} crashed: {}", panic_message(info));
+        let file_name = format!("crash-{}.txt", Local::now().format("%Y%m%d-%H%M%S"));
+        let path = logging_dir.join(file_name);
+        let body = match fs::write(&path, report) {
+            Ok(()) => format!(
+                "{APP_NAME} crashed: {}\n\nA crash report was written to:\n{}\n\nPlease attach it when reporting the problem (Help → Report an Issue).",
+                panic_message(info),
+                path.display()
+            ),
+            Err(e) => {
+                error!("Error writing the crash report to {}: {e}", path.display());
+                format!("{APP_NAME} crashed: {}", panic_message(info))
+            }
+        };
+        // the hook may run on any thread, possibly without a runtime, so this blocks
+        error_message_blocking(&body);
+    }));
+}
+
+fn crash_report(info: &PanicHookInfo, config_summary: &str, backtrace: &Backtrace) -> String {
+    let thread = std::thread::current();
+    let location = info
+        .location()
+        .map_or_else(|| "unknown".to_owned(), ToString::to_string);
+    format!(
+        "{APP_NAME} crash report\n\nTime: {}\nVersion: {APP_VERSION}\nOS: {}\nThread: {}\nPanic: {}\nLocation: {location}\n\nConfiguration:\n{config_summary}\nBacktrace:\n{backtrace}\n",
+        Local::now().to_rfc3339(),
+        os_version(),
+        thread.name().unwrap_or("unnamed"),
+        panic_message(info),
+    )
+}
+
+fn panic_message<'a>(info: &'a PanicHookInfo) -> &'a str {
+    let payload = info.payload();
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// The settings relevant for diagnosing a crash (no paths, they may contain the user name).
+fn config_summary(config: &AppConfig) -> String {
+    let mut summary = String::new();
+    let settings: [(&str, &dyn std::fmt::Display); 10] = [
+        ("top_level_domain", &config.top_level_domain),
+        ("port", &config.port),
+        ("listen_address", &config.listen_address),
+        ("log_level", &config.log_level),
+        ("start_paused", &config.start_paused),
+        (
+            "flush_dns_cache_on_reload",
+            &config.flush_dns_cache_on_reload,
+        ),
+        ("query_log", &config.query_log),
+        ("adapter_dns", &config.adapter_dns),
+        ("admin_api", &config.admin_api.enabled),
+        ("dnstap", &config.dnstap.enabled),
+    ];
+    for (name, value) in settings {
+        let _ = writeln!(summary, "  {name}: {value}");
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn config_summary_has_no_paths() {
+        let dir = tempdir().unwrap();
+        let config = AppConfig::with_dir(dir.path());
+        let summary = config_summary(&config);
+        assert!(summary.contains("  top_level_domain: .loc\n"));
+        assert!(summary.contains("  admin_api: false\n"));
+        assert!(!summary.contains(&dir.path().display().to_string()));
+    }
+}
@@ -0,0 +1,288 @@
+use crate::prelude::*;
+use std::ptr::null_mut;
+use windows_strings::HSTRING;
+use windows_sys::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows_sys::Win32::UI::Controls::EM_SETSEL;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::EnableWindow;
+use windows_sys::Win32::UI::WindowsAndMessaging::{
+    DialogBoxIndirectParamW, EndDialog, GetDlgItem, GetDlgItemTextW, GetWindowLongPtrW,
+    GetWindowTextLengthW, SendDlgItemMessageW, SetDlgItemTextW, SetWindowLongPtrW,
+    BS_DEFPUSHBUTTON, BS_PUSHBUTTON, DLGTEMPLATE, DS_CENTER, DS_MODALFRAME, DS_SETFONT,
+    DS_SETFOREGROUND, EN_CHANGE, ES_AUTOHSCROLL, GWLP_USERDATA, IDCANCEL, IDOK, WM_COMMAND,
+    WM_INITDIALOG, WS_BORDER, WS_CAPTION, WS_CHILD, WS_EX_TOPMOST, WS_POPUP, WS_SYSMENU,
+    WS_TABSTOP, WS_VISIBLE,
+};
+
+const FONT: &str = "Segoe UI";
+const FONT_SIZE: u16 = 9;
+const PROMPT_ID: u16 = 100;
+const INPUT_ID: u16 = 101;
+const ERROR_ID: u16 = 102;
+const BUTTON_CLASS: u16 = 0x0080;
+pub(crate) const EDIT_CLASS: u16 = 0x0081;
+const STATIC_CLASS: u16 = 0x0082;
+
+/// Checks the (trimmed) input. The error is displayed under the text box and the OK button is
+/// disabled until the input is valid. Empty input is accepted only if the validator accepts it
+/// (without displaying an error).
+pub type Validator = Box<dyn Fn(&str) -> Result<()> + Send>;
+
+/// Ask the user for a line of text using a native dialog (standard controls, so it's themed,
+/// keyboard navigable and accessible). The dialog runs on a blocking thread, the runtime and event
+/// loop keep running while it's open. Returns the trimmed input or `None` if cancelled.
+pub async fn input_dialog(
+    title: &str,
+    prompt: &str,
+    initial: &str,
+    validate: impl Fn(&str) -> Result<()> + Send + 'static,
+) -> Option<String> {
+    let template = dialog_template(title, prompt);
+    let mut state = DialogState {
+        initial: initial.to_owned(),
+        validate: Box::new(validate),
+        value: None,
+    };
+    tokio::task::spawn_blocking(move || {
+        let result = unsafe {
+            DialogBoxIndirectParamW(
+                null_mut(),
+                template.as_ptr().cast::<DLGTEMPLATE>(),
+                null_mut(),
+                Some(dialog_proc),
+                (&raw mut state) as LPARAM,
+            )
+        };
+        if result == -1 {
+            error!(
+                "Error showing input dialog: {}",
+                Error::from(std::io::Error::last_os_error())
+            );
+        }
+        state.value
+    })
+    .await
+    .unwrap_or_else(|e| {
+        error!("Input dialog task failed: {e}");
+        None
+    })
+}
+
+/// Ask the user for a file to open with the native file dialog, starting in `dir`. Like
+/// [`input_dialog`] the dialog runs on a blocking thread. Returns `None` if cancelled.
+pub async fn open_file_dialog(title: &str, dir: &str) -> Option<String> {
+    let (title, dir) = (title.to_owned(), dir.to_owned());
+    tokio::task::spawn_blocking(move || tinyfiledialogs::open_file_dialog(&title, &dir, None))
+        .await
+        .unwrap_or_else(|e| {
+            error!("File dialog task failed: {e}");
+            None
+        })
+}
+
+/// Ask the user where to save a file matching `pattern` (e.g. `*.json`) with the native file
+/// dialog, suggesting `default`. The dialog runs on a blocking thread like [`open_file_dialog`].
+pub async fn save_file_dialog(
+    title: &str,
+    default: &Path,
+    pattern: &str,
+    description: &str,
+) -> Option<PathBuf> {
+    let (title, default) = (title.to_owned(), default.to_string_lossy().into_owned());
+    let (pattern, description) = (pattern.to_owned(), description.to_owned());
+    tokio::task::spawn_blocking(move || {
+        tinyfiledialogs::save_file_dialog_with_filter(&title, &default, &[&pattern], &description)
+    })
+    .await
+    .unwrap_or_else(|e| {
+        error!("File dialog task failed: {e}");
+        None
+    })
+    .map(PathBuf::from)
+}
+
+struct DialogState {
+    initial: String,
+    validate: Validator,
+    value: Option<String>,
+}
+
+unsafe extern "system" fn dialog_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> isize {
+    match msg {
+        WM_INITDIALOG => unsafe {
+            SetWindowLongPtrW(hwnd, GWLP_USERDATA, lparam);
+            let state = &*(lparam as *const DialogState);
+            SetDlgItemTextW(
+                hwnd,
+                i32::from(INPUT_ID),
+                HSTRING::from(&state.initial).as_ptr(),
+            );
+            SendDlgItemMessageW(hwnd, i32::from(INPUT_ID), EM_SETSEL, 0, -1);
+            validate_input(hwnd, state);
+            1 // focus the first tab stop (the text box)
+        },
+        WM_COMMAND => unsafe {
+            let state = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut DialogState;
+            let Some(state) = state.as_mut() else {
+                return 0;
+            };
+            let id = i32::try_from(wparam & 0xFFFF).unwrap_or_default();
+            let code = u32::try_from((wparam >> 16) & 0xFFFF).unwrap_or_default();
+            match id {
+                _ if id == i32::from(INPUT_ID) && code == EN_CHANGE => {
+                    validate_input(hwnd, state);
+                }
+                IDOK => {
+                    if let Some(value) = validate_input(hwnd, state) {
+                        state.value = Some(value);
+                        EndDialog(hwnd, IDOK as isize);
+                    }
+                }
+                IDCANCEL => {
+                    EndDialog(hwnd, IDCANCEL as isize);
+                }
+                _ => return 0,
+            }
+            1
+        },
+        _ => 0,
+    }
+}
+
+/// Validate the current input, update the error label and the OK button. Returns the (trimmed)
+/// input if it's valid.
+unsafe fn validate_input(hwnd: HWND, state: &DialogState) -> Option<String> {
+    let value = unsafe { input_text(hwnd) };
+    let (valid, error) = if value.is_empty() {
+        ((state.validate)(&value).is_ok(), String::new())
+    } else {
+        match (state.validate)(&value) {
+            Ok(()) => (true, String::new()),
+            Err(e) => (false, format!("{e}")),
+        }
+    };
+    unsafe {
+        SetDlgItemTextW(hwnd, i32::from(ERROR_ID), HSTRING::from(error).as_ptr());
+        EnableWindow(GetDlgItem(hwnd, IDOK), i32::from(valid));
+    }
+    valid.then_some(value)
+}
+
+unsafe fn input_text(hwnd: HWND) -> String {
+    let len = unsafe { GetWindowTextLengthW(GetDlgItem(hwnd, i32::from(INPUT_ID))) };
+    let mut buffer = vec![0u16; usize::try_from(len).unwrap_or_default() + 1];
+    let read = unsafe {
+        GetDlgItemTextW(
+            hwnd,
+            i32::from(INPUT_ID),
+            buffer.as_mut_ptr(),
+            i32::try_from(buffer.len()).unwrap_or(i32::MAX),
+        )
+    };
+    buffer.truncate(usize::try_from(read).unwrap_or_default());
+    String::from_utf16_lossy(&buffer).trim().to_owned()
+}
+
+/// An in-memory dialog template (`DLGTEMPLATE` followed by the `DLGITEMTEMPLATE`s) with a prompt,
+/// a text box, an error label and OK/Cancel buttons. Sizes are in dialog units.
+fn dialog_template(title: &str, prompt: &str) -> Vec<u32> {
+    let mut t = TemplateWriter::default();
+    t.dword(
+        WS_POPUP
+            | WS_CAPTION
+            | WS_SYSMENU
+            | (DS_MODALFRAME | DS_CENTER | DS_SETFONT | DS_SETFOREGROUND).cast_unsigned(),
+    );
+    t.dword(WS_EX_TOPMOST);
+    t.word(5); // number of items
+    t.rect(0, 0, 250, 92);
+    t.word(0); // no menu
+    t.word(0); // default dialog class
+    t.text(title);
+    t.word(FONT_SIZE);
+    t.text(FONT);
+    t.item(0, (7, 7, 236, 24), PROMPT_ID, STATIC_CLASS, prompt);
+    t.item(
+        WS_BORDER | WS_TABSTOP | ES_AUTOHSCROLL.cast_unsigned(),
+        (7, 34, 236, 14),
+        INPUT_ID,
+        EDIT_CLASS,
+        "",
+    );
+    t.item(0, (7, 52, 236, 16), ERROR_ID, STATIC_CLASS, "");
+    t.item(
+        WS_TABSTOP | BS_DEFPUSHBUTTON.cast_unsigned(),
+        (139, 72, 50, 14),
+        u16::try_from(IDOK).unwrap_or_default(),
+        BUTTON_CLASS,
+        "OK",
+    );
+    t.item(
+        WS_TABSTOP | BS_PUSHBUTTON.cast_unsigned(),
+        (193, 72, 50, 14),
+        u16::try_from(IDCANCEL).unwrap_or_default(),
+        BUTTON_CLASS,
+        "Cancel",
+    );
+    t.finish()
+}
+
+/// Writes the words of a dialog template. Items must be aligned to a `DWORD` boundary.
+#[derive(Default)]
+pub(crate) struct TemplateWriter(Vec<u16>);
+
+impl TemplateWriter {
+    pub(crate) fn word(&mut self, value: u16) {
+        self.0.push(value);
+    }
+
+    pub(crate) fn dword(&mut self, value: u32) {
+        let [a, b, c, d] = value.to_le_bytes();
+        self.0.push(u16::from_le_bytes([a, b]));
+        self.0.push(u16::from_le_bytes([c, d]));
+    }
+
+    pub(crate) fn rect(&mut self, x: i16, y: i16, cx: i16, cy: i16) {
+        for value in [x, y, cx, cy] {
+            self.word(value.cast_unsigned());
+        }
+    }
+
+    pub(crate) fn text(&mut self, text: &str) {
+        self.0.extend(text.encode_utf16());
+        self.0.push(0);
+    }
+
+    pub(crate) fn item(
+        &mut self,
+        style: u32,
+        (x, y, cx, cy): (i16, i16, i16, i16),
+        id: u16,
+        class: u16,
+        text: &str,
+    ) {
+        if self.0.len() % 2 == 1 {
+            self.0.push(0);
+        }
+        self.dword(style | WS_CHILD | WS_VISIBLE);
+        self.dword(0); // extended style
+        self.rect(x, y, cx, cy);
+        self.word(id);
+        self.word(0xFFFF); // predefined class follows
+        self.word(class);
+        self.text(text);
+        self.word(0); // no creation data
+    }
+
+    /// The template as `DWORD`s so the buffer is properly aligned.
+    pub(crate) fn finish(self) -> Vec<u32> {
+        self.0
+            .chunks(2)
+            .map(|c| u32::from(c[0]) | (u32::from(c.get(1).copied().unwrap_or_default()) << 16))
+            .collect()
+    }
+}
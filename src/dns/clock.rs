@@ -0,0 +1,72 @@
+//! The time of the server (when the queries are received and how long they take), behind a trait so
+//! the tests of timed behavior (e.g. expiring records, caches or rate limiters) set the time rather
+//! than sleeping.
+
+use std::fmt::Debug;
+#[cfg(test)]
+use std::sync::Mutex;
+#[cfg(test)]
+use std::time::Duration;
+use std::time::SystemTime;
+use tokio::time::Instant;
+
+pub trait Clock: Debug + Send + Sync {
+    /// For measuring durations and deadlines.
+    fn now(&self) -> Instant;
+    /// For the timestamps (e.g. of the dnstap events).
+    fn system_time(&self) -> SystemTime;
+}
+
+/// The real time.
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Time standing still until advanced.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct ManualClock {
+    start: Instant,
+    start_time: SystemTime,
+    elapsed: Mutex<Duration>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    /// A clock standing at the current time.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            start_time: SystemTime::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.start_time + self.elapsed()
+    }
+}
@@ -0,0 +1,147 @@
+//! The diagnostics dump ([`Notification::DumpDiagnostics`]): a JSON file with the configuration
+//! the app runs with, the served records and where they come from, the server status and stats
+//! and the recent warnings and errors of the log, to attach to issues.
+
+use super::{DnsServer, LATENCY_BUCKETS};
+use crate::logging::recent_log_entries;
+use crate::prelude::*;
+use chrono::Local;
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+use tracing::Level;
+
+/// The number of log entries (warnings and errors) in the dump.
+const RECENT_ERRORS: usize = 50;
+
+impl DnsServer {
+    /// Write the diagnostics to `path`. The log is read and the file written in a blocking task,
+    /// so the server goes on answering meanwhile.
+    pub(super) fn dump_diagnostics(&self, path: PathBuf) -> JoinHandle<Result<()>> {
+        let mut diagnostics = self.diagnostics();
+        let logging_dir = self
+            .app_config
+            .as_ref()
+            .map(|config| config.logging_dir.clone());
+        tokio::task::spawn_blocking(move || {
+            diagnostics["recentErrors"] = json!(recent_errors(logging_dir.as_deref()));
+            let json = serde_json::to_string_pretty(&diagnostics)?;
+            fs::write(&path, json).with_context(|| format!("writing {}", path.display()))?;
+            info!("Wrote the diagnostics to {}", path.display());
+            Ok(())
+        })
+    }
+
+    fn diagnostics(&self) -> Value {
+        let stats = self.stats_tx.borrow().clone();
+        let latency: Vec<_> = LATENCY_BUCKETS
+            .iter()
+            .map(|bound| Some(u64::try_from(bound.as_micros()).unwrap_or(u64::MAX)))
+            .chain([None])
+            .zip(stats.latency)
+            .map(|(le_us, count)| json!({ "leUs": le_us, "count": count }))
+            .collect();
+        json!({
+            "app": {
+                "version": APP_VERSION,
+                "os": os_version(),
+                "time": Local::now().to_rfc3339(),
+            },
+            "config": self.app_config,
+            "server": {
+                "status": self.status_tx.borrow().describe(),
+                "paused": self.paused,
+                "topLevelDomain": self.top_level_domain,
+                "localAddress": self.local_addr,
+                "listenAdapter": self.listen_adapter,
+                "flushCacheOnReload": self.flush_cache_on_reload,
+                "localhostZone": self.localhost_zone,
+                "loopbackAliases": self.loopback_aliases.is_some(),
+            },
+            "stats": {
+                "queries": stats.queries,
+                "reloads": stats.reloads,
+                "errors": stats.errors,
+                "systemUdpReceiveErrors": stats.system_udp_receive_errors,
+                "latency": latency,
+            },
+            "records": self.records_with_sources(),
+            "lastRecordsChange": self.last_records_change.as_ref().map(|(cause, diff)| {
+                let mut change = diff.to_json();
+                change["cause"] = json!(cause);
+                change
+            }),
+            "aliasAddresses": self.alias_addresses,
+            "recentErrors": [],
+        })
+    }
+
+    /// The records of the records file (including the disabled ones) and of the sync sources,
+    /// sorted by name.
+    fn records_with_sources(&self) -> Vec<Value> {
+        self.record_entries()
+            .iter()
+            .map(|record| {
+                json!({
+                    "name": record.name,
+                    "addr": record.addr,
+                    "source": record.source.name(),
+                    "enabled": record.enabled,
+                })
+            })
+            .collect()
+    }
+}
+
+fn recent_errors(logging_dir: Option<&Path>) -> Vec<String> {
+    let Some(logging_dir) = logging_dir else {
+        return vec![];
+    };
+    recent_log_entries(logging_dir, Level::WARN, RECENT_ERRORS).unwrap_or_else(|e| {
+        debug!("No recent errors in the diagnostics: {e:#}");
+        vec![]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn dumps_list_the_records_with_their_sources() {
+        let mut dns = DnsServer::new(0, "non-existent-file", ".loc")
+            .await
+            .unwrap();
+        dns.records = HashMap::from([("b.loc".into(), Ipv4Addr::new(10, 0, 0, 2))]);
+        dns.disabled = HashMap::from([("c.loc".into(), Ipv4Addr::new(10, 0, 0, 3))]);
+        dns.synced = HashMap::from([(
+            "docker".into(),
+            HashMap::from([("a.loc".into(), Ipv4Addr::LOCALHOST)]),
+        )]);
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("diagnostics.json");
+        dns.dump_diagnostics(path.clone()).await.unwrap().unwrap();
+        let dump: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            dump["records"],
+            json!([
+                { "name": "a.loc", "addr": "127.0.0.1", "source": "docker", "enabled": true },
+                { "name": "b.loc", "addr": "10.0.0.2", "source": "records file", "enabled": true },
+                { "name": "c.loc", "addr": "10.0.0.3", "source": "records file", "enabled": false },
+            ])
+        );
+        assert_eq!(dump["server"]["status"], "Starting…");
+        assert_eq!(dump["stats"]["latency"].as_array().unwrap().len(), 6);
+        assert_eq!(dump["config"], Value::Null);
+        assert_eq!(dump["lastRecordsChange"], Value::Null);
+
+        // the configuration as changed while running
+        let mut config = AppConfig::with_dir(dir.path());
+        config.flush_dns_cache_on_reload = true;
+        dns.handle_notification(SetAppConfig(Box::new(config)))
+            .await;
+        dns.dump_diagnostics(path.clone()).await.unwrap().unwrap();
+        let dump: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(dump["config"]["flush_dns_cache_on_reload"], true);
+    }
+}
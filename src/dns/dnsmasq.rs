@@ -0,0 +1,112 @@
+//! Import of dnsmasq `address=/name/IP` configuration lines, for users migrating from dnsmasq (e.g.
+//! on WSL). Like our records, dnsmasq addresses also answer the subdomains of the name.
+
+use super::records::ImportLine;
+use super::ImportOutcome;
+use crate::prelude::*;
+
+const ADDRESS_DIRECTIVE: &str = "address=";
+
+/// Parse the `address=` lines (with their 1 based line numbers) as records. A line may define a
+/// few names (`address=/a.local/b.local/127.0.0.1`). Other directives and comments are skipped,
+/// and so are the IPv6 addresses (reported, as the IPv4 address of the names is often on another
+/// line).
+pub fn import_lines(text: &str) -> Vec<ImportLine> {
+    let mut records = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let Some(value) = line.trim().strip_prefix(ADDRESS_DIRECTIVE) else {
+            continue;
+        };
+        match parse_address(value) {
+            Ok((names, ip)) => {
+                records.extend(names.into_iter().map(|name| (index + 1, Ok((name, ip)))));
+            }
+            Err(outcome) => records.push((index + 1, Err(outcome))),
+        }
+    }
+    records
+}
+
+/// Parse the `/name/.../IP` value of an `address=` directive.
+fn parse_address(value: &str) -> Result<(Vec<String>, Ipv4Addr), ImportOutcome> {
+    let invalid = |message: String| ImportOutcome::Invalid(message);
+    let malformed = || {
+        invalid(format!(
+            "Invalid dnsmasq address '{value}' (expected /hostname/IP)"
+        ))
+    };
+    let (names, ip) = value
+        .strip_prefix('/')
+        .and_then(|value| value.rsplit_once('/'))
+        .ok_or_else(malformed)?;
+    let names: Vec<_> = names
+        .split('/')
+        .map(|name| name.trim_start_matches('.').to_lowercase())
+        .collect();
+    if names.iter().any(String::is_empty) {
+        return Err(malformed());
+    }
+    if names.iter().any(|name| name == "#") {
+        return Err(invalid(
+            "Matching all domains (/#/) isn't supported".to_owned(),
+        ));
+    }
+    let ip = match ip.trim() {
+        "" => {
+            return Err(invalid(
+                "Local only domains (without an IP) aren't supported".to_owned(),
+            ))
+        }
+        "#" => return Err(invalid("Null addresses (#) aren't supported".to_owned())),
+        ip if ip.contains(':') => {
+            return Err(ImportOutcome::Skipped(format!(
+                "IPv6 addresses aren't supported ({ip})"
+            )))
+        }
+        ip => ip
+            .parse()
+            .map_err(|e| invalid(format!("Invalid IP '{ip}': {e}")))?,
+    };
+    Ok((names, ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_address_lines() {
+        let config = "\
+# dev domains
+address=/app.local/127.0.0.1
+server=8.8.8.8
+
+address=/.API.local/db.local/10.0.0.2
+address=/nas.local/::1
+address=/#/127.0.0.1
+address=app.local
+";
+        let lines = import_lines(config);
+        let ip = Ipv4Addr::new;
+        assert_eq!(
+            lines[0],
+            (2, Ok(("app.local".to_owned(), ip(127, 0, 0, 1))))
+        );
+        assert_eq!(lines[1], (5, Ok(("api.local".to_owned(), ip(10, 0, 0, 2)))));
+        assert_eq!(lines[2], (5, Ok(("db.local".to_owned(), ip(10, 0, 0, 2)))));
+        assert_eq!(
+            lines[3],
+            (
+                6,
+                Err(ImportOutcome::Skipped(
+                    "IPv6 addresses aren't supported (::1)".to_owned()
+                ))
+            )
+        );
+        let errors: Vec<_> = lines[4..]
+            .iter()
+            .map(|(line, record)| (*line, matches!(record, Err(ImportOutcome::Invalid(_)))))
+            .collect();
+        assert_eq!(errors, vec![(7, true), (8, true)]);
+    }
+}
@@ -0,0 +1,206 @@
+//! End-to-end tests: the server is started on an ephemeral port and queried over UDP, with
+//! hickory-resolver for the queries resolvers send and raw datagrams for the rest, so the socket
+//! (and its `WSAIoctl` setup), the workers and the wire format are tested together.
+
+use super::clock::{Clock, ManualClock};
+use super::protocol::*;
+use super::{DnsServer, Notification, ServerStats, ServerStatus};
+use crate::prelude::*;
+use hickory_resolver::config::{NameServerConfig, ResolverConfig, ResolverOpts};
+use hickory_resolver::name_server::{GenericConnector, TokioConnectionProvider};
+use hickory_resolver::proto::rr::RecordType;
+use hickory_resolver::proto::runtime::TokioRuntimeProvider;
+use hickory_resolver::proto::xfer::Protocol;
+use hickory_resolver::Resolver;
+use std::future::Future;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+use tokio::join;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::channel;
+use tokio::time::timeout;
+
+const RECORDS: &str = "a.loc:192.168.0.4\n";
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Run `test` against a server serving [`RECORDS`], the server stats once it stopped.
+async fn with_server<F: Future<Output = ()>>(test: impl FnOnce(SocketAddr) -> F) -> ServerStats {
+    with_configured_server(|_| (), test).await
+}
+
+/// [`with_server`] with the server configured by `configure` before it starts.
+async fn with_configured_server<F: Future<Output = ()>>(
+    configure: impl FnOnce(&mut DnsServer),
+    test: impl FnOnce(SocketAddr) -> F,
+) -> ServerStats {
+    let mut records_file = NamedTempFile::new().unwrap();
+    write!(records_file, "{RECORDS}").unwrap();
+    let mut dns = DnsServer::new(0, records_file.path(), ".loc")
+        .await
+        .unwrap();
+    configure(&mut dns);
+    let stats_rx = dns.subscribe_stats();
+    let mut status_rx = dns.subscribe_status();
+    let notify_tx = dns.notify_tx.clone();
+    let ((), dns_out) = join!(
+        async move {
+            let addr = match &*status_rx
+                .wait_for(|s| matches!(s, ServerStatus::Listening(_)))
+                .await
+                .unwrap()
+            {
+                ServerStatus::Listening(addr) => *addr,
+                _ => unreachable!(),
+            };
+            test(addr).await;
+            notify_tx.send(Notification::Shutdown).await.unwrap();
+        },
+        dns.run(),
+    );
+    dns_out.unwrap();
+    let stats = stats_rx.borrow().clone();
+    stats
+}
+
+fn resolver(addr: SocketAddr) -> Resolver<GenericConnector<TokioRuntimeProvider>> {
+    let name_server = NameServerConfig::new(addr, Protocol::Udp);
+    let config = ResolverConfig::from_parts(None, vec![], vec![name_server]);
+    let mut options = ResolverOpts::default();
+    options.timeout = RESPONSE_TIMEOUT;
+    options.attempts = 1;
+    options.cache_size = 0;
+    Resolver::builder_with_config(config, TokioConnectionProvider::default())
+        .with_options(options)
+        .build()
+}
+
+fn query(name: &str, qtype: QueryType) -> Vec<u8> {
+    let mut packet = DnsPacket::new();
+    packet.header.id = 7;
+    packet.header.recursion_desired = true;
+    packet
+        .questions
+        .push(DnsQuestion::new(name.to_owned(), qtype));
+    let mut buffer = BytePacketBuffer::new();
+    packet.write(&mut buffer).unwrap();
+    buffer.buf[..buffer.pos()].to_vec()
+}
+
+/// Send the `datagram` to the server, its response (if it answers in time).
+async fn exchange(addr: SocketAddr, datagram: &[u8]) -> Option<DnsPacket> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    socket.send_to(datagram, addr).await.unwrap();
+    let mut buffer = BytePacketBuffer::new();
+    let len = timeout(RESPONSE_TIMEOUT, socket.recv(&mut buffer.buf))
+        .await
+        .ok()?
+        .unwrap();
+    buffer.set_len(len);
+    Some(DnsPacket::from_buffer(&mut buffer).await.unwrap())
+}
+
+#[tokio::test]
+async fn a_queries_are_answered_with_the_records() {
+    with_server(|addr| async move {
+        let resolver = resolver(addr);
+        for (name, expected) in [
+            ("a.loc", Ipv4Addr::new(192, 168, 0, 4)),
+            ("www.A.loc", Ipv4Addr::new(192, 168, 0, 4)),
+            ("unknown.loc", Ipv4Addr::LOCALHOST),
+        ] {
+            let lookup = resolver.ipv4_lookup(name).await.unwrap();
+            let addresses: Vec<_> = lookup.iter().map(|a| a.0).collect();
+            assert_eq!(addresses, [expected], "{name}");
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn aaaa_queries_have_no_answers() {
+    with_server(|addr| async move {
+        let error = resolver(addr).ipv6_lookup("a.loc").await.unwrap_err();
+        assert!(error.is_no_records_found(), "{error}");
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn unknown_types_and_other_domains_fail() {
+    with_server(|addr| async move {
+        assert!(resolver(addr)
+            .lookup("a.loc", RecordType::Unknown(99))
+            .await
+            .is_err());
+        for datagram in [
+            query("a.loc", QueryType::UNKNOWN(99)),
+            query("example.com", QueryType::A),
+        ] {
+            let response = exchange(addr, &datagram).await.unwrap();
+            assert_eq!(response.header.id, 7);
+            assert!(response.header.response);
+            assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+            assert!(response.answers.is_empty());
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn bad_datagrams_are_dropped_and_counted() {
+    let stats = with_server(|addr| async move {
+        // larger than the server reads, and cut inside the name
+        let oversized = [query("a.loc", QueryType::A), vec![0; 512]].concat();
+        assert!(exchange(addr, &oversized).await.is_none());
+        assert!(exchange(addr, &query("a.loc", QueryType::A)[..15])
+            .await
+            .is_none());
+        // the server still answers
+        let response = exchange(addr, &query("a.loc", QueryType::A)).await.unwrap();
+        assert_eq!(
+            response.answers[..],
+            [DnsRecord::A {
+                domain: "a.loc".to_owned(),
+                addr: Ipv4Addr::new(192, 168, 0, 4),
+                ttl: 0,
+            }]
+        );
+    })
+    .await;
+    assert_eq!(stats.errors, 2);
+    assert_eq!(stats.queries, 1);
+}
+
+#[tokio::test]
+async fn queries_are_timed_by_the_server_clock() {
+    let clock = Arc::new(ManualClock::new());
+    let (tap_tx, mut tap_rx) = channel(4);
+    let mut recent = None;
+    let configure = |dns: &mut DnsServer| {
+        dns.set_clock(clock.clone());
+        dns.set_tap(tap_tx);
+        recent = Some(dns.subscribe_recent_queries());
+    };
+    let started = clock.system_time();
+    let advance = |by| clock.advance(by);
+    with_configured_server(configure, |addr| async move {
+        exchange(addr, &query("a.loc", QueryType::A)).await.unwrap();
+        let tapped = tap_rx.recv().await.unwrap();
+        assert_eq!(
+            (tapped.query_time, tapped.response_time),
+            (started, started)
+        );
+        advance(Duration::from_secs(90));
+        exchange(addr, &query("a.loc", QueryType::A)).await.unwrap();
+        let tapped = tap_rx.recv().await.unwrap();
+        assert_eq!(tapped.query_time, started + Duration::from_secs(90));
+    })
+    .await;
+    // the clock stood still while the queries were answered
+    let recent = recent.unwrap().borrow().clone();
+    assert_eq!(recent.len(), 2);
+    assert!(recent.iter().all(|query| query.duration == Duration::ZERO));
+    assert_eq!(recent[1].received, started + Duration::from_secs(90));
+}
@@ -0,0 +1,123 @@
+//! Loopback alias mode: every name pointing at `127.0.0.1` is answered with its own loopback
+//! address (`127.0.0.2`, `127.0.0.3`, …), so several local services can each listen on port 443 of
+//! their own address. Windows routes all of `127.0.0.0/8` to the loopback interface, so the
+//! addresses don't need configuring. Addresses are allocated on the first query of a name and
+//! saved (in the background, the queries don't wait for the file), so a name keeps its address
+//! across restarts.
+
+use crate::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+use tokio::select;
+use tokio::sync::{oneshot, Notify};
+
+const FIRST_ALIAS: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 2);
+const LAST_ALIAS: Ipv4Addr = Ipv4Addr::new(127, 255, 255, 254);
+
+/// The allocated loopback addresses by record name.
+pub struct LoopbackAliases {
+    path: PathBuf,
+    aliases: Mutex<HashMap<String, Ipv4Addr>>,
+    /// Addresses were allocated since the file was last saved.
+    unsaved: AtomicBool,
+    allocated: Notify,
+}
+
+impl LoopbackAliases {
+    /// Load the allocated addresses from `path` (a JSON object of names to addresses).
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let aliases = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("parsing {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e).context(format!("reading {}", path.display())),
+        };
+        Ok(Self {
+            path,
+            aliases: Mutex::new(aliases),
+            unsaved: AtomicBool::new(false),
+            allocated: Notify::new(),
+        })
+    }
+
+    /// The loopback address of the record `name`, allocating one if it has none.
+    pub(super) fn alias(&self, name: &str) -> Ipv4Addr {
+        let mut aliases = self.aliases.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(alias) = aliases.get(name) {
+            return *alias;
+        }
+        let Some(alias) = next_free(&aliases) else {
+            warn!("No free loopback address for {name}");
+            return Ipv4Addr::LOCALHOST;
+        };
+        aliases.insert(name.to_owned(), alias);
+        info!("Allocated the loopback address {alias} to {name}");
+        self.unsaved.store(true, Ordering::Relaxed);
+        self.allocated.notify_one();
+        alias
+    }
+
+    /// Save the addresses whenever new ones are allocated (the allocations while saving are saved
+    /// together next), until `stop` is sent, when the last allocations are saved.
+    pub(super) async fn save_allocations(&self, mut stop: oneshot::Receiver<()>) {
+        loop {
+            let stopped = select! {
+                () = self.allocated.notified() => false,
+                _ = &mut stop => true,
+            };
+            if self.unsaved.swap(false, Ordering::Relaxed) {
+                self.save().await;
+            }
+            if stopped {
+                return;
+            }
+        }
+    }
+
+    async fn save(&self) {
+        let json = {
+            let aliases = self.aliases.lock().unwrap_or_else(PoisonError::into_inner);
+            serde_json::to_string_pretty(&*aliases)
+        };
+        let saved = match json {
+            Ok(json) => tokio::fs::write(&self.path, json)
+                .await
+                .map_err(Error::from),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = saved {
+            warn!("Error saving {}: {e:#}", self.path.display());
+        }
+    }
+}
+
+/// The lowest loopback address that isn't allocated.
+fn next_free(aliases: &HashMap<String, Ipv4Addr>) -> Option<Ipv4Addr> {
+    (u32::from(FIRST_ALIAS)..=u32::from(LAST_ALIAS))
+        .map(Ipv4Addr::from)
+        .find(|address| !aliases.values().any(|alias| alias == address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn names_keep_their_allocated_addresses() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("loopback-aliases.json");
+        let aliases = LoopbackAliases::load(path.clone()).unwrap();
+        assert_eq!(aliases.alias("a.loc"), Ipv4Addr::new(127, 0, 0, 2));
+        assert_eq!(aliases.alias("b.loc"), Ipv4Addr::new(127, 0, 0, 3));
+        assert_eq!(aliases.alias("a.loc"), Ipv4Addr::new(127, 0, 0, 2));
+        // not saved by the lookups themselves
+        assert!(!path.exists());
+        let (stop_tx, stop_rx) = oneshot::channel();
+        stop_tx.send(()).unwrap();
+        aliases.save_allocations(stop_rx).await;
+        let reloaded = LoopbackAliases::load(path).unwrap();
+        assert_eq!(reloaded.alias("b.loc"), Ipv4Addr::new(127, 0, 0, 3));
+        assert_eq!(reloaded.alias("c.loc"), Ipv4Addr::new(127, 0, 0, 4));
+    }
+}
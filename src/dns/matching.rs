@@ -0,0 +1,26 @@
+//! How query names are matched against the records. Kept apart (it only depends on the prelude)
+//! so the benchmarks can build it on its own.
+
+use crate::prelude::*;
+
+/// The record answering for `host`: an exact match or else the longest record it's a subdomain of.
+pub(super) fn find_record<'a>(
+    host: &str,
+    records: &'a HashMap<String, Ipv4Addr>,
+) -> Option<(&'a str, Ipv4Addr)> {
+    records
+        .get_key_value(host)
+        .or_else(|| {
+            records
+                .iter()
+                .filter(|&(name, _)| is_subdomain(host, name))
+                .max_by_key(|&(name, _)| name.len())
+        })
+        .map(|(name, ip)| (name.as_str(), *ip))
+}
+
+/// Is `host` a subdomain of `name`? (without formatting `.{name}` for every record)
+fn is_subdomain(host: &str, name: &str) -> bool {
+    host.strip_suffix(name)
+        .is_some_and(|prefix| prefix.ends_with('.'))
+}
@@ -0,0 +1,114 @@
+//! `.local` is reserved for multicast DNS (RFC 6762). When an mDNS responder runs (e.g. Bonjour),
+//! Windows may resolve `.local` names with multicast instead of asking the server, which fails
+//! intermittently. Detect that on startup and offer to switch to another top level domain, once:
+//! declining is kept in the configuration. Windows' own responder (in the DNS Client's `svchost`)
+//! always runs, so it isn't reported.
+
+use super::port_conflict::{udp_port_owners, PortOwner};
+use super::records::rewrite_records_tld;
+use crate::prelude::*;
+
+const MDNS_TLD: &str = ".local";
+const MDNS_PORT: u16 = 5353;
+
+/// If the top level domain is `.local` and an mDNS responder other than Windows' own is running,
+/// explain the conflict and offer to switch to the default top level domain (rewriting the
+/// records).
+pub async fn offer_mdns_tld_change(app_config: &mut AppConfig) -> Result<()> {
+    if !app_config.top_level_domain.eq_ignore_ascii_case(MDNS_TLD) || app_config.keep_mdns_tld {
+        return Ok(());
+    }
+    let responders = match udp_port_owners(MDNS_PORT) {
+        Ok(owners) => other_responders(owners),
+        Err(e) => {
+            warn!("Error looking for mDNS responders: {e:#}");
+            return Ok(());
+        }
+    };
+    if responders.is_empty() {
+        return Ok(());
+    }
+    let msg = conflict_message(&responders);
+    warn!("{}", msg.replace("\n\n", " "));
+    if !confirm_message("Top Level Domain Conflicts With mDNS".to_owned(), msg).await {
+        info!("User kept the {MDNS_TLD} top level domain, not offering to switch again");
+        return app_config.set_keep_mdns_tld(true, ChangeSource::Application);
+    }
+    rewrite_records_tld(&app_config.records_file, MDNS_TLD, DEFAULT_TOP_LEVEL_DOMAIN).await?;
+    app_config.set_top_level_domain(DEFAULT_TOP_LEVEL_DOMAIN, ChangeSource::Application)?;
+    info!("Switched the top level domain from {MDNS_TLD} to {DEFAULT_TOP_LEVEL_DOMAIN}");
+    Ok(())
+}
+
+/// The responders other than Windows' own, which is part of the DNS Client service (`svchost`).
+fn other_responders(owners: Vec<PortOwner>) -> Vec<PortOwner> {
+    owners
+        .into_iter()
+        .filter(|owner| {
+            !owner
+                .name
+                .as_deref()
+                .is_some_and(|name| name.eq_ignore_ascii_case("svchost.exe"))
+        })
+        .collect()
+}
+
+fn conflict_message(responders: &[PortOwner]) -> String {
+    let names = responders
+        .iter()
+        .map(responder_name)
+        .collect::<Vec<_>>()
+        .join("; ");
+    format!(
+        "The top level domain is {MDNS_TLD}, which is reserved for multicast DNS, and an mDNS \
+responder is running ({names}). Windows may then resolve {MDNS_TLD} names with multicast instead \
+of asking {APP_NAME}, so lookups fail intermittently.\n\nSwitch to {DEFAULT_TOP_LEVEL_DOMAIN} \
+(the records are rewritten and a backup of the records file is saved)?"
+    )
+}
+
+fn responder_name(owner: &PortOwner) -> String {
+    match owner.name.as_deref() {
+        Some(name) if name.eq_ignore_ascii_case("mDNSResponder.exe") => {
+            format!("Bonjour, PID {}", owner.pid)
+        }
+        Some(name) => format!("{name}, PID {}", owner.pid),
+        None => format!("PID {}", owner.pid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflicts_name_the_responders() {
+        let msg = conflict_message(&[
+            PortOwner {
+                pid: 3400,
+                name: Some("mDNSResponder.exe".to_owned()),
+            },
+            PortOwner {
+                pid: 5600,
+                name: None,
+            },
+        ]);
+        assert!(msg.contains("responder is running (Bonjour, PID 3400; PID 5600)"));
+        assert!(msg.ends_with("a backup of the records file is saved)?"));
+    }
+
+    #[test]
+    fn windows_own_responder_is_not_a_conflict() {
+        let owner = |pid, name: &str| PortOwner {
+            pid,
+            name: Some(name.to_owned()),
+        };
+        let others = other_responders(vec![
+            owner(1200, "svchost.exe"),
+            owner(3400, "mDNSResponder.exe"),
+        ]);
+        assert_eq!(others.len(), 1);
+        assert_eq!(others[0].pid, 3400);
+        assert!(other_responders(vec![owner(1200, "SVCHOST.EXE")]).is_empty());
+    }
+}
@@ -1,97 +1,1000 @@
 #![allow(clippy::wildcard_imports)]
 
+mod clock;
+mod diagnostics;
+mod dnsmasq;
+#[cfg(test)]
+mod end_to_end;
+mod loopback_aliases;
+mod matching;
+mod mdns;
+mod port_conflict;
 mod protocol;
+mod receive_errors;
 mod records;
+mod records_diff;
+mod worker;
 
+use crate::address_aliases::{adapter_address, alias_addresses, AliasAddresses};
+use crate::app_config::validate_host_name;
+use crate::control::protocol::RECORDS_FILE_SOURCE;
 use crate::prelude::*;
-use failsafe::futures::CircuitBreaker;
+#[cfg(test)]
+use clock::Clock;
+use clock::SystemClock;
 use failsafe::Config;
+pub use loopback_aliases::LoopbackAliases;
+use matching::find_record;
+pub use mdns::offer_mdns_tld_change;
+use port_conflict::diagnose_bind_error;
 use protocol::*;
+pub use protocol::{QueryType, ResultCode};
+use receive_errors::ReceiveErrors;
 pub use records::safe_open_records_file;
+use records::{offer_tld_migration, offer_to_fix_records_file, ParseError};
+use records_diff::RecordsDiff;
+use std::collections::VecDeque;
+#[cfg(target_os = "windows")]
 use std::io::Error;
+#[cfg(target_os = "windows")]
 use std::os::windows::io::AsRawSocket;
+#[cfg(target_os = "windows")]
 use std::ptr::null_mut;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::net::UdpSocket;
-use tokio::select;
+use tokio::sync::{broadcast, watch, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::interval;
+use tokio::{join, select};
+#[cfg(target_os = "windows")]
 use windows_sys::core::BOOL;
+#[cfg(target_os = "windows")]
 use windows_sys::Win32::Foundation::FALSE;
+#[cfg(target_os = "windows")]
 use windows_sys::Win32::Networking::WinSock::{WSAIoctl, SIO_UDP_CONNRESET, SOCKET};
+use worker::{worker_count, Worker};
 
+const RECENT_QUERIES_CAPACITY: usize = 15;
+/// Queries buffered for slow [`DnsServer::subscribe_queries`] subscribers before they lag.
+const QUERY_EVENTS_CAPACITY: usize = 256;
+/// The TTL of the answers, clients don't cache them so changed records are used right away.
+pub const RECORD_TTL: u32 = 0;
+/// The start of the names resolved by the [resolver check](crate::resolver_check).
+pub const RESOLVER_PROBE_PREFIX: &str = "dot-local-dns-check-";
+
+#[allow(clippy::struct_excessive_bools)]
 pub struct DnsServer {
     top_level_domain: String,
     pub notify_tx: Sender<Notification>,
     port: u16,
+    listen_address: Ipv4Addr,
+    /// Listen on the address of this adapter (part of its name or description) while it's
+    /// connected, rather than on `listen_address`.
+    listen_adapter: Option<String>,
     db_path: PathBuf,
     records: HashMap<String, Ipv4Addr>,
+    /// Whether the records were loaded, otherwise they are loaded when serving starts.
+    records_loaded: bool,
+    disabled: HashMap<String, Ipv4Addr>,
+    /// Records discovered by the sync sources (e.g. docker), by source. Not in the records file.
+    synced: HashMap<String, HashMap<String, Ipv4Addr>>,
+    /// The addresses of the alias record values (e.g. `@tailscale`).
+    alias_addresses: AliasAddresses,
+    /// Answer the names pointing at localhost with their own loopback address.
+    loopback_aliases: Option<Arc<LoopbackAliases>>,
+    /// Answer `localhost` and its subdomains with the loopback addresses (RFC 6761).
+    localhost_zone: bool,
+    /// Refuse the queries of other domains without logging or recording them.
+    refuse_other_domains: bool,
     notify_rx: Receiver<Notification>,
+    status_tx: watch::Sender<ServerStatus>,
+    recent_queries_tx: watch::Sender<VecDeque<QueryEvent>>,
+    query_events_tx: broadcast::Sender<QueryEvent>,
+    records_tx: watch::Sender<Vec<RecordEntry>>,
+    stats_tx: watch::Sender<ServerStats>,
+    /// What the workers answer the queries from.
+    lookup_state_tx: watch::Sender<Arc<LookupState>>,
+    paused: bool,
+    flush_cache_on_reload: bool,
+    local_addr: Option<SocketAddr>,
+    /// Queries answered slower than this are logged as warnings.
+    slow_query: Option<Duration>,
+    /// Queries not answered by this long after they're received are answered with SERVFAIL.
+    query_deadline: Option<Duration>,
+    /// Received requests, for their correlation ids.
+    requests: Arc<AtomicU64>,
+    tap_tx: Option<Sender<TapEvent>>,
+    /// The configuration the app runs with, for the diagnostics dump.
+    app_config: Option<AppConfig>,
+    /// The last change of the records file records (`reload` or `merge`), for the diagnostics.
+    last_records_change: Option<(&'static str, RecordsDiff)>,
+    /// When the queries are received and how long they take.
+    clock: Arc<dyn clock::Clock>,
+}
+
+/// Counters of the server activity since it started.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    pub queries: u64,
+    pub reloads: u64,
+    /// Requests that couldn't be answered (e.g. malformed packets).
+    pub errors: u64,
+    /// The UDP (IPv4) receive errors of the whole system while the server runs. Windows doesn't
+    /// count them per socket, so they aren't the server's drops, only a hint when queries go
+    /// missing.
+    pub system_udp_receive_errors: u64,
+    /// The answered queries by latency, counted in the first bucket of [`LATENCY_BUCKETS`] they
+    /// fit in, the last count is of the slower queries.
+    pub latency: [u64; LATENCY_BUCKETS.len() + 1],
+}
+
+/// The upper bounds of the [`ServerStats::latency`] buckets.
+pub const LATENCY_BUCKETS: [Duration; 5] = [
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+];
+
+/// A record as currently served (or disabled) by the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordEntry {
+    pub name: String,
+    pub addr: Ipv4Addr,
+    /// Only `A` records are served.
+    pub record_type: QueryType,
+    pub ttl: u32,
+    pub source: RecordSource,
+    /// Synced records are always enabled.
+    pub enabled: bool,
+}
+
+/// Where a record comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordSource {
+    RecordsFile,
+    /// A sync source (e.g. `docker`).
+    Sync(String),
+}
+
+impl RecordSource {
+    /// [`RECORDS_FILE_SOURCE`] or the name of the sync source.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::RecordsFile => RECORDS_FILE_SOURCE,
+            Self::Sync(source) => source,
+        }
+    }
+}
+
+/// A query answered by the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryEvent {
+    pub name: String,
+    pub qtype: QueryType,
+    pub client: SocketAddr,
+    pub answer: Option<Ipv4Addr>,
+    pub rescode: ResultCode,
+    /// When the query was received.
+    pub received: SystemTime,
+    /// From receiving the query to sending the response.
+    pub duration: Duration,
+}
+
+impl QueryEvent {
+    /// Is it the query of the [resolver check](crate::resolver_check) rather than a client's? It
+    /// isn't counted in the stats nor kept in the recent queries and the query log.
+    pub fn is_resolver_probe(&self) -> bool {
+        self.name
+            .get(..RESOLVER_PROBE_PREFIX.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(RESOLVER_PROBE_PREFIX))
+    }
+}
+
+/// The packets of an answered query, for the [`dnstap`](crate::dnstap) output.
+#[derive(Debug, Clone)]
+pub struct TapEvent {
+    pub client: SocketAddr,
+    pub server: Option<SocketAddr>,
+    pub query: Vec<u8>,
+    pub query_time: SystemTime,
+    pub response: Vec<u8>,
+    pub response_time: SystemTime,
+}
+
+/// Which record produced a lookup answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordMatch {
+    /// The name is defined in the records.
+    Exact,
+    /// The name is a subdomain of the (contained) record.
+    Suffix(String),
+    /// No record matched, answered with the default address.
+    Default,
+}
+
+/// The result of a lookup requested through [`Notification::ARecordQuery`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookupResult {
+    pub addr: Ipv4Addr,
+    pub matched: RecordMatch,
+}
+
+/// The state of the DNS server, published to subscribers (e.g. the tray icon).
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerStatus {
+    Starting,
+    /// Loading the records before binding (see [`DnsServer::with_lazy_records`]).
+    LoadingRecords,
+    Listening(SocketAddr),
+    /// Bound to the address but refusing queries until resumed.
+    Paused(SocketAddr),
+    Failed(String),
+    Stopped,
+}
+
+impl ServerStatus {
+    /// A human readable description of the status.
+    pub fn describe(&self) -> String {
+        match self {
+            ServerStatus::Starting => "Starting…".to_owned(),
+            ServerStatus::LoadingRecords => "Loading the records…".to_owned(),
+            ServerStatus::Listening(addr) => format!("Listening on {addr} — OK"),
+            ServerStatus::Paused(addr) => format!("Paused on {addr} — refusing queries"),
+            ServerStatus::Failed(e) => format!("Error: {e}"),
+            ServerStatus::Stopped => "Stopped".to_owned(),
+        }
+    }
+}
+
+impl RecordMatch {
+    /// Explain how `host` was answered.
+    pub fn describe(&self, host: &str) -> String {
+        match self {
+            RecordMatch::Exact => format!("Answered by the record: {host}"),
+            RecordMatch::Suffix(record) => {
+                format!("Answered by the record: {record} ({host} is a subdomain of it)")
+            }
+            RecordMatch::Default => {
+                "No record matches, answered with the default address".to_owned()
+            }
+        }
+    }
+}
+
+/// The outcome of merging records from a file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeSummary {
+    /// Number of records that were not previously defined.
+    pub added: usize,
+    /// Records that were already defined with a different address (and were overridden).
+    pub conflicts: Vec<String>,
+}
+
+/// The format of imported records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// `name:IP` lines, like the records file.
+    Records,
+    /// dnsmasq `address=/name/IP` configuration lines, other lines are ignored.
+    Dnsmasq,
+}
+
+/// The outcome of importing a line of records (see [`Notification::ImportRecords`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportOutcome {
+    Added(String, Ipv4Addr),
+    /// The record was defined with a different address.
+    Updated(String, Ipv4Addr),
+    Unchanged(String, Ipv4Addr),
+    Invalid(String),
+    /// The line isn't imported but doesn't keep the others from being (e.g. an IPv6 address).
+    Skipped(String),
+}
+
+/// The outcome of importing records. Imports are all or nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// `false` if any line is invalid, nothing is imported then.
+    pub applied: bool,
+    /// The (1 based) number and outcome of every record line (blank and comment lines are skipped).
+    pub lines: Vec<(usize, ImportOutcome)>,
 }
 
 #[derive(Debug)]
 enum Signal {
     Shutdown,
+    /// Listen on another address (the address of the listen adapter changed): the socket bound
+    /// ahead on the new address, or none to fall back to the listen address.
+    Rebind(Option<UdpSocket>),
 }
 
 #[derive(Debug)]
 pub enum Notification {
     Shutdown,
     Reload,
-    ARecordQuery(String, oneshot::Sender<Result<Ipv4Addr>>),
-    MergeRecords(PathBuf, oneshot::Sender<Result<()>>),
+    /// Reload the records and reply with the result.
+    ReloadRecords(oneshot::Sender<Result<()>>),
+    ARecordQuery(String, oneshot::Sender<Result<LookupResult>>),
+    MergeRecords(PathBuf, oneshot::Sender<Result<MergeSummary>>),
+    /// Add (or change) a record in the records file and the running server.
+    AddRecord(String, Ipv4Addr, oneshot::Sender<Result<()>>),
+    /// Add (or change) all the records (the text of a file in the given format) in the records file
+    /// and the running server, or none of them if any line is invalid.
+    ImportRecords(String, ImportFormat, oneshot::Sender<Result<ImportReport>>),
+    /// Remove the record from the records file and the running server.
+    RemoveRecord(String, oneshot::Sender<Result<()>>),
+    /// Temporarily disable (or re-enable) a record. Responds with the new enabled state.
+    #[cfg_attr(target_os = "macos", allow(dead_code))] // only the tray sends it
+    ToggleRecord(String, oneshot::Sender<Result<bool>>),
+    /// Pause (refuse all queries) or resume serving.
+    SetPaused(bool),
+    /// Flush the Windows DNS client cache after successfully reloading the records.
+    #[cfg_attr(target_os = "macos", allow(dead_code))] // only the tray sends it
+    SetFlushCacheOnReload(bool),
+    /// All the records (including the disabled and synced ones) sorted by name, for the records
+    /// menu, the `dlocal` CLI and the admin API.
+    ListRecords(oneshot::Sender<Result<Vec<RecordEntry>>>),
+    /// Find all the records pointing at the address.
+    #[cfg_attr(target_os = "macos", allow(dead_code))] // only the tray sends it
+    ReverseSearch(Ipv4Addr, oneshot::Sender<Result<Vec<RecordEntry>>>),
+    /// Replace the records discovered by a sync source (e.g. `docker`).
+    SyncRecords(String, HashMap<String, Ipv4Addr>),
+    /// The addresses of the alias record values changed.
+    AliasesChanged(AliasAddresses),
+    /// The listen adapter has another address than the one the server listens on.
+    ListenAdapterMoved(Ipv4Addr),
+    /// Write the diagnostics (configuration, records, status, stats and recent errors) as JSON to
+    /// the file.
+    DumpDiagnostics(PathBuf, oneshot::Sender<Result<()>>),
+    /// The configuration changed while running (e.g. from the tray), for the diagnostics.
+    SetAppConfig(Box<AppConfig>),
 }
 
 impl DnsServer {
+    /// A server with its records loaded (the app loads them when serving starts, see
+    /// [`DnsServer::with_lazy_records`]).
+    #[cfg(test)]
     pub async fn new(port: u16, db_path: impl AsRef<Path>, top_level_domain: &str) -> Result<Self> {
+        let mut server = Self::with_lazy_records(port, db_path, top_level_domain);
+        server.records =
+            records::load(&server.db_path, top_level_domain, &server.alias_addresses).await?;
+        server.records_loaded = true;
+        server.publish_lookup_state();
+        Ok(server)
+    }
+
+    /// A server loading its records when it starts running rather than when it's created, so a
+    /// slow (e.g. on a network share) or broken records file doesn't hold up the app.
+    pub fn with_lazy_records(port: u16, db_path: impl AsRef<Path>, top_level_domain: &str) -> Self {
         let db_path = db_path.as_ref().to_owned();
-        let records = records::load(&db_path, top_level_domain).await?;
+        let alias_addresses = alias_addresses();
         let (notify_tx, notify_rx) = mpsc::channel::<Notification>(4);
-        Ok(Self {
+        let (status_tx, _) = watch::channel(ServerStatus::Starting);
+        let (recent_queries_tx, _) = watch::channel(VecDeque::new());
+        let (query_events_tx, _) = broadcast::channel(QUERY_EVENTS_CAPACITY);
+        let (records_tx, _) = watch::channel(vec![]);
+        let (stats_tx, _) = watch::channel(ServerStats::default());
+        let (lookup_state_tx, _) = watch::channel(Arc::new(LookupState::default()));
+        let server = Self {
             top_level_domain: top_level_domain.to_owned(),
             notify_tx,
             port,
+            listen_address: Ipv4Addr::LOCALHOST,
+            listen_adapter: None,
             db_path,
-            records,
+            records: HashMap::new(),
+            records_loaded: false,
+            disabled: HashMap::new(),
+            synced: HashMap::new(),
+            alias_addresses,
+            loopback_aliases: None,
+            localhost_zone: true,
+            refuse_other_domains: false,
             notify_rx,
-        })
+            status_tx,
+            recent_queries_tx,
+            query_events_tx,
+            records_tx,
+            stats_tx,
+            lookup_state_tx,
+            paused: false,
+            flush_cache_on_reload: false,
+            local_addr: None,
+            slow_query: None,
+            query_deadline: None,
+            requests: Arc::new(AtomicU64::new(0)),
+            tap_tx: None,
+            clock: Arc::new(SystemClock),
+            app_config: None,
+            last_records_change: None,
+        };
+        server.publish_lookup_state();
+        server
+    }
+
+    pub fn subscribe_status(&self) -> watch::Receiver<ServerStatus> {
+        self.status_tx.subscribe()
+    }
+
+    /// Pause (or resume) serving. Paused servers refuse all queries.
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused != paused {
+            info!(
+                "{} DNS serving",
+                if paused { "Pausing" } else { "Resuming" }
+            );
+        }
+        self.paused = paused;
+        self.publish_status();
+        self.publish_lookup_state();
+    }
+
+    /// Listen on `address` instead of localhost (e.g. to serve the LAN).
+    pub fn set_listen_address(&mut self, address: Ipv4Addr) {
+        self.listen_address = address;
+    }
+
+    /// Listen on the address of `adapter` (resolved when binding, and rebound whenever
+    /// [`ListenAdapterMoved`] tells it changed), e.g. a virtual switch whose address changes across
+    /// reboots. The listen address is used while it isn't connected or can't be bound.
+    pub fn set_listen_adapter(&mut self, adapter: String) {
+        self.listen_adapter = Some(adapter);
+    }
+
+    /// Answer every name pointing at localhost with its own loopback address.
+    pub fn set_loopback_aliases(&mut self, aliases: LoopbackAliases) {
+        self.loopback_aliases = Some(Arc::new(aliases));
+        self.publish_lookup_state();
+    }
+
+    /// Answer (or not) `localhost` and its subdomains outside the top level domain.
+    pub fn set_localhost_zone(&mut self, enabled: bool) {
+        self.localhost_zone = enabled;
+        self.publish_lookup_state();
+    }
+
+    /// Refuse the queries outside the top level domain (and the localhost zone) quietly, rather
+    /// than warning about them and counting them as answered: in the adapter DNS mode all the
+    /// system's queries are sent to the server first, and Windows asks the next server when
+    /// they're refused.
+    pub fn set_refuse_other_domains(&mut self, refuse: bool) {
+        self.refuse_other_domains = refuse;
+        self.publish_lookup_state();
+    }
+
+    pub fn set_flush_cache_on_reload(&mut self, flush: bool) {
+        self.flush_cache_on_reload = flush;
+    }
+
+    /// Log the queries answered slower than `threshold` (from receiving to sending the response).
+    pub fn set_slow_query_threshold(&mut self, threshold: Option<Duration>) {
+        self.slow_query = threshold;
+    }
+
+    /// Answer the queries not answered within `deadline` of receiving them with SERVFAIL, rather
+    /// than letting the client wait for a lookup stuck e.g. saving the loopback aliases.
+    pub fn set_query_deadline(&mut self, deadline: Option<Duration>) {
+        self.query_deadline = deadline;
+    }
+
+    /// Send the packets of every answered query to `tx`. Queries are dropped (not delayed) while
+    /// the receiver is behind.
+    pub fn set_tap(&mut self, tx: Sender<TapEvent>) {
+        self.tap_tx = Some(tx);
+    }
+
+    /// Time the queries by `clock` rather than the system's.
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Include `config` (the configuration the app runs with) in the diagnostics dumps. It's replaced
+    /// by [`Notification::SetAppConfig`] as the configuration changes.
+    pub fn set_app_config(&mut self, config: AppConfig) {
+        self.app_config = Some(config);
+    }
+
+    fn publish_status(&self) {
+        if let Some(addr) = self.local_addr {
+            let status = if self.paused {
+                ServerStatus::Paused(addr)
+            } else {
+                ServerStatus::Listening(addr)
+            };
+            self.status_tx.send_replace(status);
+        }
+    }
+
+    pub fn subscribe_stats(&self) -> watch::Receiver<ServerStats> {
+        self.stats_tx.subscribe()
+    }
+
+    /// The last answered queries (oldest first).
+    #[cfg(any(target_os = "windows", test))]
+    pub fn subscribe_recent_queries(&self) -> watch::Receiver<VecDeque<QueryEvent>> {
+        self.recent_queries_tx.subscribe()
+    }
+
+    /// Every answered query as it happens (unlike the recent queries, updates are not coalesced).
+    pub fn subscribe_queries(&self) -> broadcast::Receiver<QueryEvent> {
+        self.query_events_tx.subscribe()
+    }
+
+    /// The sender of the answered queries, for subscribing later (e.g. per client) without keeping
+    /// a receiver that lags meanwhile.
+    pub fn query_events_sender(&self) -> broadcast::Sender<QueryEvent> {
+        self.query_events_tx.clone()
+    }
+
+    /// Snapshot of all the records (sorted by name), updated whenever the records change.
+    #[cfg(any(target_os = "windows", test))]
+    pub fn subscribe_records(&self) -> watch::Receiver<Vec<RecordEntry>> {
+        self.publish_records();
+        self.records_tx.subscribe()
     }
 
     pub async fn run(&mut self) -> Result<()> {
-        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, self.port));
-        let socket = mk_udp_socket(&addr).await?;
-        info!("Listening on: localhost:{}", self.port);
-        let circuit_breaker = Config::new().build();
+        let result = match self.loopback_aliases.clone() {
+            Some(aliases) => {
+                let (stop_tx, stop_rx) = oneshot::channel();
+                let serve = async {
+                    let result = self.serve().await;
+                    let _ = stop_tx.send(());
+                    result
+                };
+                join!(serve, aliases.save_allocations(stop_rx)).0
+            }
+            None => self.serve().await,
+        };
+        let status = match &result {
+            Ok(()) => ServerStatus::Stopped,
+            Err(e) => ServerStatus::Failed(format!("{e}")),
+        };
+        self.status_tx.send_replace(status);
+        result
+    }
+
+    async fn serve(&mut self) -> Result<()> {
+        if !self.records_loaded {
+            self.load_records().await;
+        }
+        let mut bound = self.bind().await?;
+        loop {
+            let addr = bound.local_addr()?;
+            info!("Listening on: {addr}");
+            self.local_addr = Some(addr);
+            self.publish_status();
+            let socket = Arc::new(bound);
+            let mut queries_rx = self.query_events_tx.subscribe();
+            let circuit_breaker = Config::new().build();
+            let mut workers = JoinSet::new();
+            for _ in 0..worker_count() {
+                workers.spawn(self.worker().run(socket.clone(), circuit_breaker.clone()));
+            }
+            debug!("Answering queries with {} workers", workers.len());
+            let result = self
+                .handle_notifications(&mut workers, &mut queries_rx)
+                .await;
+            workers.shutdown().await;
+            // count the queries answered before the workers stopped
+            loop {
+                match queries_rx.try_recv() {
+                    Ok(event) => self.record_query(event),
+                    Err(broadcast::error::TryRecvError::Lagged(missed)) => {
+                        self.record_missed_queries(missed);
+                    }
+                    Err(_) => break,
+                }
+            }
+            bound = match result? {
+                Signal::Shutdown => return Ok(()),
+                Signal::Rebind(Some(socket)) => socket,
+                Signal::Rebind(None) => self.bind_listen_address().await?,
+            };
+        }
+    }
+
+    /// Bind the address of the listen adapter if it's connected, falling back to the listen
+    /// address (when it isn't or when binding its address fails).
+    async fn bind(&self) -> Result<UdpSocket> {
+        let address = self.bind_address();
+        if address != self.listen_address {
+            match mk_udp_socket(&SocketAddr::from((address, self.port))).await {
+                Ok(socket) => return Ok(socket),
+                Err(e) => warn!(
+                    "Failed to listen on {address}, listening on {} until the next adapter check: {e}",
+                    self.listen_address
+                ),
+            }
+        }
+        self.bind_listen_address().await
+    }
+
+    async fn bind_listen_address(&self) -> Result<UdpSocket> {
+        let addr = SocketAddr::from((self.listen_address, self.port));
+        mk_udp_socket(&addr)
+            .await
+            .map_err(|e| diagnose_bind_error(e, &addr))
+    }
+
+    /// Bind the new address of the listen adapter ahead of rebinding, so a failure keeps serving:
+    /// on the listen address (the watcher tells the server again at its next check).
+    async fn rebind(&self, address: Ipv4Addr) -> Option<Signal> {
+        if self.local_addr.is_some_and(|local| local.ip() == address) {
+            return None;
+        }
+        let adapter = self.listen_adapter.as_deref().unwrap_or_default();
+        info!("The address of the {adapter} adapter changed to {address}");
+        match mk_udp_socket(&SocketAddr::from((address, self.port))).await {
+            Ok(socket) => return Some(Signal::Rebind(Some(socket))),
+            Err(e) => warn!(
+                "Failed to listen on {address}, listening on {} until the next adapter check: {e}",
+                self.listen_address
+            ),
+        }
+        self.local_addr
+            .is_none_or(|local| local.ip() != self.listen_address)
+            .then_some(Signal::Rebind(None))
+    }
+
+    /// The address of the listen adapter if it's connected, otherwise the listen address.
+    fn bind_address(&self) -> Ipv4Addr {
+        let Some(adapter) = &self.listen_adapter else {
+            return self.listen_address;
+        };
+        adapter_address(adapter).unwrap_or_else(|| {
+            warn!(
+                "The {adapter} adapter isn't connected, listening on {} until it is",
+                self.listen_address
+            );
+            self.listen_address
+        })
+    }
+
+    /// Handle the notifications until shutdown (or until the listen adapter moves), or
+    /// until a worker fails. Counts the answered queries and the dropped datagrams meanwhile.
+    async fn handle_notifications(
+        &mut self,
+        workers: &mut JoinSet<Result<()>>,
+        queries_rx: &mut broadcast::Receiver<QueryEvent>,
+    ) -> Result<Signal> {
+        let mut receive_errors = ReceiveErrors::start();
+        let mut samples = interval(receive_errors::SAMPLE_INTERVAL);
         loop {
-            let mut req_buffer = BytePacketBuffer::new();
             select! {
                 biased;
                 notification = self.notify_rx.recv() => {
                     debug!("DNS server received notification: {notification:?}");
                     if let Some(notification) = notification {
-                        if let Some(Signal::Shutdown) = self.handle_notification(notification).await {
-                            return Ok(());
+                        if let Some(signal) = self.handle_notification(notification).await {
+                            return Ok(signal);
                         }
                     }
                 }
-                received = socket.recv_from(&mut req_buffer.buf) => {
-                    let handler = self.handle_request(received, &mut req_buffer, &socket);
-                    match circuit_breaker.call(handler).await {
-                        Ok(()) => {},
-                        Err(failsafe::Error::Inner(e)) => {
-                            error!("DNS server error: {e}");
-                        },
-                        Err(failsafe::Error::Rejected) => {
-                            error!("Circuit breaker rejected");
-                            return Err(anyhow!("Multiple Errors on DNS Server! Quitting! Check the logs!"));
-                        }
+                // the workers only stop when the circuit breaker trips (or they panic)
+                Some(stopped) = workers.join_next() => {
+                    stopped??;
+                    return Ok(Signal::Shutdown);
+                }
+                query = queries_rx.recv() => match query {
+                    Ok(event) => self.record_query(event),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        self.record_missed_queries(missed);
+                    }
+                    // the server holds the sender
+                    Err(broadcast::error::RecvError::Closed) => {}
+                },
+                _ = samples.tick(), if receive_errors.is_some() => {
+                    if let Some(receive_errors) = &mut receive_errors {
+                        self.record_receive_errors(receive_errors.sample());
                     }
                 }
             }
         }
     }
 
-    async fn reload_records(&mut self) -> Result<()> {
-        let records = records::load_from_file(&self.db_path, &self.top_level_domain).await?;
+    /// Count an answered query in the stats and keep it in the recent queries.
+    fn record_query(&self, event: QueryEvent) {
+        if event.is_resolver_probe() {
+            return;
+        }
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| event.duration <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.stats_tx.send_modify(|stats| {
+            stats.queries += 1;
+            stats.latency[bucket] += 1;
+        });
+        self.recent_queries_tx.send_modify(|queries| {
+            if queries.len() == RECENT_QUERIES_CAPACITY {
+                queries.pop_front();
+            }
+            queries.push_back(event);
+        });
+    }
+
+    /// Count the queries missed when lagging behind the workers, without their latency.
+    fn record_missed_queries(&self, missed: u64) {
+        debug!("Missed {missed} answered queries, counting them without their latency");
+        self.stats_tx.send_modify(|stats| stats.queries += missed);
+    }
+
+    fn record_receive_errors(&self, errors: u64) {
+        if errors > 0 {
+            debug!("{errors} UDP receive errors system wide (any socket, not only the server's)");
+            self.stats_tx
+                .send_modify(|stats| stats.system_udp_receive_errors += errors);
+        }
+    }
+
+    /// Load the records of a lazily created server, offering to migrate them to the top level
+    /// domain first. Serves without them when they can't be loaded, they can be reloaded once the
+    /// records file is fixed (or reachable).
+    async fn load_records(&mut self) {
+        self.status_tx.send_replace(ServerStatus::LoadingRecords);
+        if let Err(e) = offer_tld_migration(&self.db_path, &self.top_level_domain).await {
+            notify_error!("Error migrating the records to the configured top level domain: {e:#}");
+        }
+        match records::load(&self.db_path, &self.top_level_domain, &self.alias_addresses).await {
+            Ok(records) => {
+                info!("Loaded {} records", records.len());
+                self.records = records;
+                self.publish_records();
+            }
+            Err(e) => {
+                let path = self.db_path.display();
+                notify_error!(
+                    "Error loading the records file ({path}), serving without its records: {e}"
+                );
+                if let Some(parse_error) = e.downcast_ref::<ParseError>() {
+                    offer_to_fix_records_file(self.db_path.clone(), parse_error.clone());
+                }
+            }
+        }
+        self.records_loaded = true;
+    }
+
+    async fn reload_records(&mut self) -> Result<RecordsDiff> {
+        let records =
+            records::load_from_file(&self.db_path, &self.top_level_domain, &self.alias_addresses)
+                .await?;
+        let previous = self.file_records();
+        self.disabled.retain(|name, _| records.contains_key(name));
         self.records = records;
-        info!("Records reloaded");
+        self.apply_disabled();
+        let diff = RecordsDiff::between(&previous, &self.file_records());
+        info!("Records reloaded: {diff}");
+        self.last_records_change = Some(("reload", diff.clone()));
+        Ok(diff)
+    }
+
+    /// The records of the records file, including the disabled ones.
+    fn file_records(&self) -> HashMap<String, Ipv4Addr> {
+        self.records
+            .iter()
+            .chain(&self.disabled)
+            .map(|(name, addr)| (name.clone(), *addr))
+            .collect()
+    }
+
+    /// Make sure disabled records are not served (e.g. after loading records).
+    fn apply_disabled(&mut self) {
+        for (name, ip) in &mut self.disabled {
+            if let Some(new_ip) = self.records.remove(name) {
+                *ip = new_ip;
+            }
+        }
+        self.publish_records();
+    }
+
+    fn publish_records(&self) {
+        self.records_tx.send_replace(self.record_entries());
+        self.publish_lookup_state();
+    }
+
+    /// Replace the snapshot the workers answer from, after the records or the settings changed.
+    fn publish_lookup_state(&self) {
+        self.lookup_state_tx
+            .send_replace(Arc::new(self.lookup_state()));
+    }
+
+    fn lookup_state(&self) -> LookupState {
+        LookupState {
+            top_level_domain: self.top_level_domain.clone(),
+            records: self.records.clone(),
+            synced: self.synced.clone(),
+            loopback_aliases: self.loopback_aliases.clone(),
+            localhost_zone: self.localhost_zone,
+            refuse_other_domains: self.refuse_other_domains,
+            paused: self.paused,
+        }
+    }
+
+    /// A worker answering the queries with the current settings.
+    fn worker(&self) -> Worker {
+        Worker {
+            lookup_state_rx: self.lookup_state_tx.subscribe(),
+            stats_tx: self.stats_tx.clone(),
+            query_events_tx: self.query_events_tx.clone(),
+            tap_tx: self.tap_tx.clone(),
+            clock: self.clock.clone(),
+            local_addr: self.local_addr,
+            slow_query: self.slow_query,
+            query_deadline: self.query_deadline,
+            late_lookup: Arc::new(Semaphore::new(1)),
+            requests: self.requests.clone(),
+        }
+    }
+
+    /// All the records (including the disabled and synced ones) sorted by name.
+    fn record_entries(&self) -> Vec<RecordEntry> {
+        let entry = |name: &String, addr: &Ipv4Addr, source: RecordSource, enabled| RecordEntry {
+            name: name.clone(),
+            addr: *addr,
+            record_type: QueryType::A,
+            ttl: RECORD_TTL,
+            source,
+            enabled,
+        };
+        let enabled = self
+            .records
+            .iter()
+            .map(|(name, addr)| entry(name, addr, RecordSource::RecordsFile, true));
+        let disabled = self
+            .disabled
+            .iter()
+            .map(|(name, addr)| entry(name, addr, RecordSource::RecordsFile, false));
+        let synced = self.synced.iter().flat_map(|(source, records)| {
+            records
+                .iter()
+                .map(|(name, addr)| entry(name, addr, RecordSource::Sync(source.clone()), true))
+        });
+        let mut entries = enabled.chain(disabled).chain(synced).collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        entries
+    }
+
+    /// The records (including the disabled and synced ones) pointing at `addr`.
+    fn reverse_search(&self, addr: Ipv4Addr) -> Vec<RecordEntry> {
+        debug!("DNS server received reverse search: {addr}");
+        let mut entries = self.record_entries();
+        entries.retain(|entry| entry.addr == addr);
+        entries
+    }
+
+    async fn add_record(&mut self, name: &str, ip: Ipv4Addr) -> Result<()> {
+        validate_host_name(name, &self.top_level_domain)?;
+        records::add_to_file(&self.db_path, name, ip).await?;
+        self.disabled.remove(name);
+        self.records.insert(name.to_owned(), ip);
+        info!("Added record: {name} ({ip})");
+        self.publish_records();
+        Ok(())
+    }
+
+    async fn import_records(&mut self, text: &str, format: ImportFormat) -> Result<ImportReport> {
+        let lines = match format {
+            ImportFormat::Records => records::import_lines(text),
+            ImportFormat::Dnsmasq => dnsmasq::import_lines(text),
+        };
+        let mut report = ImportReport::default();
+        let mut batch: Vec<(String, Ipv4Addr)> = vec![];
+        for (line, record) in lines {
+            let outcome = match self.check_import_record(record, &batch) {
+                Ok((name, ip)) => {
+                    let existing = self.records.get(&name).or(self.disabled.get(&name));
+                    if !batch.contains(&(name.clone(), ip)) {
+                        batch.push((name.clone(), ip));
+                    }
+                    match existing {
+                        None => ImportOutcome::Added(name, ip),
+                        Some(old) if *old != ip => ImportOutcome::Updated(name, ip),
+                        Some(_) => ImportOutcome::Unchanged(name, ip),
+                    }
+                }
+                Err(outcome) => outcome,
+            };
+            report.lines.push((line, outcome));
+        }
+        report.applied = !report
+            .lines
+            .iter()
+            .any(|(_, outcome)| matches!(outcome, ImportOutcome::Invalid(_)));
+        if !report.applied {
+            info!("Not importing records, some lines are invalid");
+            return Ok(report);
+        }
+        batch.retain(|(name, ip)| self.records.get(name).or(self.disabled.get(name)) != Some(ip));
+        if !batch.is_empty() {
+            records::add_all_to_file(&self.db_path, &batch).await?;
+            for (name, ip) in batch {
+                self.disabled.remove(&name);
+                self.records.insert(name, ip);
+            }
+            self.publish_records();
+        }
+        info!("Imported {} record(s)", report.lines.len());
+        Ok(report)
+    }
+
+    fn check_import_record(
+        &self,
+        record: Result<(String, Ipv4Addr), ImportOutcome>,
+        batch: &[(String, Ipv4Addr)],
+    ) -> Result<(String, Ipv4Addr), ImportOutcome> {
+        let (name, ip) = record?;
+        validate_host_name(&name, &self.top_level_domain)
+            .map_err(|e| ImportOutcome::Invalid(format!("{e}")))?;
+        if batch.iter().any(|(n, old)| *n == name && *old != ip) {
+            return Err(ImportOutcome::Invalid(format!(
+                "Duplicate hostname ({name}) with a different address"
+            )));
+        }
+        Ok((name, ip))
+    }
+
+    async fn remove_record(&mut self, name: &str) -> Result<()> {
+        let in_memory = self
+            .records
+            .remove(name)
+            .or_else(|| self.disabled.remove(name));
+        let in_file = records::remove_from_file(&self.db_path, name).await?;
+        if in_memory.is_none() && !in_file {
+            return Err(anyhow!("No such record: {name}"));
+        }
+        info!("Removed record: {name}");
+        self.publish_records();
+        Ok(())
+    }
+
+    fn toggle_record(&mut self, name: &str) -> Result<bool> {
+        let enabled = if let Some(ip) = self.records.remove(name) {
+            self.disabled.insert(name.to_owned(), ip);
+            false
+        } else if let Some(ip) = self.disabled.remove(name) {
+            self.records.insert(name.to_owned(), ip);
+            true
+        } else {
+            return Err(anyhow!("No such record: {name}"));
+        };
+        info!("Record {name} enabled: {enabled}");
+        self.publish_records();
+        Ok(enabled)
+    }
+
+    async fn handle_reload(&mut self) -> Result<()> {
+        info!("Reloading Records");
+        let diff = match self.reload_records().await {
+            Ok(diff) => diff,
+            Err(e) => {
+                let path = &self.db_path.to_string_lossy();
+                notify_error!(NotificationEvent::ReloadFailure => "Error reloading records file ({path}): {e}");
+                if let Some(parse_error) = e.downcast_ref::<ParseError>() {
+                    if should_notify(NotificationEvent::ReloadFailure) {
+                        offer_to_fix_records_file(self.db_path.clone(), parse_error.clone());
+                    }
+                }
+                return Err(e);
+            }
+        };
+        self.stats_tx.send_modify(|stats| stats.reloads += 1);
+        if self.flush_cache_on_reload {
+            flush_dns_cache().await.unwrap_or_else(|e| {
+                notify_error!("Error flushing the DNS client cache: {e:#}");
+            });
+        }
+        if should_notify(NotificationEvent::ReloadSuccess) {
+            send_notification(
+                "Reloaded Records",
+                &format!("Reloaded records file: {}", diff.summary()),
+            );
+        }
         Ok(())
     }
 
@@ -102,16 +1005,13 @@ impl DnsServer {
                 Some(Signal::Shutdown)
             }
             Reload => {
-                info!("Reloading Records");
-                self.reload_records()
-                    .await
-                    .inspect(|()| {
-                        send_notification("Reloaded Records", "Reloaded records file successfully");
-                    })
-                    .unwrap_or_else(|e| {
-                        let path = &self.db_path.to_string_lossy();
-                        notify_error!("Error reloading records file ({path}): {e}");
-                    });
+                // failures are already reported to the user
+                let _ = self.handle_reload().await;
+                None
+            }
+            ReloadRecords(tx) => {
+                let result = self.handle_reload().await;
+                reply(tx, result);
                 None
             }
             ARecordQuery(query, tx) => {
@@ -120,8 +1020,8 @@ impl DnsServer {
             }
             MergeRecords(path, tx) => {
                 match self.handle_merge_records(path).await {
-                    Ok(()) => {
-                        if tx.send(Ok(())).is_err() {
+                    Ok(summary) => {
+                        if tx.send(Ok(summary)).is_err() {
                             notify_error!("Records merged but encountered internal communication error, best to restart the app");
                         }
                     }
@@ -133,28 +1033,69 @@ impl DnsServer {
                 }
                 None
             }
+            AddRecord(name, ip, tx) => {
+                let result = self.add_record(&name, ip).await;
+                reply(tx, result);
+                None
+            }
+            ImportRecords(text, format, tx) => {
+                let result = self.import_records(&text, format).await;
+                reply(tx, result);
+                None
+            }
+            RemoveRecord(name, tx) => {
+                let result = self.remove_record(&name).await;
+                reply(tx, result);
+                None
+            }
+            ToggleRecord(name, tx) => {
+                let result = self.toggle_record(&name);
+                reply(tx, result);
+                None
+            }
+            SetPaused(paused) => {
+                self.set_paused(paused);
+                None
+            }
+            SetFlushCacheOnReload(flush) => {
+                self.set_flush_cache_on_reload(flush);
+                None
+            }
+            ListRecords(tx) => {
+                reply(tx, Ok(self.record_entries()));
+                None
+            }
+            ReverseSearch(addr, tx) => {
+                reply(tx, Ok(self.reverse_search(addr)));
+                None
+            }
+            SyncRecords(source, records) => {
+                self.sync_records(source, records);
+                None
+            }
+            AliasesChanged(addresses) => {
+                self.set_alias_addresses(addresses).await;
+                None
+            }
+            ListenAdapterMoved(address) => self.rebind(address).await,
+            DumpDiagnostics(path, tx) => {
+                let dump = self.dump_diagnostics(path);
+                tokio::spawn(async move {
+                    reply(
+                        tx,
+                        dump.await.map_err(|e| anyhow!(e)).and_then(|result| result),
+                    );
+                });
+                None
+            }
+            SetAppConfig(config) => {
+                self.app_config = Some(*config);
+                None
+            }
         }
     }
 
-    #[allow(clippy::similar_names)]
-    async fn handle_request(
-        &mut self,
-        received: std::io::Result<(usize, SocketAddr)>,
-        req_buffer: &mut BytePacketBuffer,
-        socket: &UdpSocket,
-    ) -> Result<()> {
-        let (_len, peer) = received?;
-        let request = DnsPacket::from_buffer(req_buffer).await?;
-        let mut response = self.lookup(&request);
-        let mut res_buffer = BytePacketBuffer::new();
-        response.write(&mut res_buffer)?;
-        let pos = res_buffer.pos();
-        let data = res_buffer.get_range(0, pos)?;
-        socket.send_to(data, peer).await?;
-        Ok(())
-    }
-
-    fn handle_name_lookup(&self, host: String, tx: oneshot::Sender<Result<Ipv4Addr>>) {
+    fn handle_name_lookup(&self, host: String, tx: oneshot::Sender<Result<LookupResult>>) {
         debug!("DNS server received lookup channel: {host}");
         let res = self.lookup_name(host);
         if tx.send(res).is_err() {
@@ -162,21 +1103,90 @@ impl DnsServer {
         }
     }
 
-    async fn handle_merge_records(&mut self, path: PathBuf) -> Result<()> {
+    async fn handle_merge_records(&mut self, path: PathBuf) -> Result<MergeSummary> {
         info!(
             "DNS server received merge records from file: {}",
             path.display()
         );
-        let records = records::load_from_file(path, &self.top_level_domain).await?;
-        self.records.extend(records);
-        Ok(())
+        let records =
+            records::load_from_file(path, &self.top_level_domain, &self.alias_addresses).await?;
+        let previous = self.file_records();
+        let mut summary = MergeSummary::default();
+        for (name, ip) in records {
+            let existing = self.records.get(&name).or(self.disabled.get(&name));
+            match existing {
+                None => summary.added += 1,
+                Some(old) if *old != ip => summary.conflicts.push(name.clone()),
+                Some(_) => {}
+            }
+            self.records.insert(name, ip);
+        }
+        summary.conflicts.sort();
+        self.apply_disabled();
+        let diff = RecordsDiff::between(&previous, &self.file_records());
+        info!("Records merged: {diff}");
+        self.last_records_change = Some(("merge", diff));
+        Ok(summary)
+    }
+
+    /// Replace the records of a sync source, dropping names outside the top level domain.
+    fn sync_records(&mut self, source: String, mut records: HashMap<String, Ipv4Addr>) {
+        records.retain(
+            |name, ip| match validate_host_name(name, &self.top_level_domain) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Ignoring {source} record {name} ({ip}): {e}");
+                    false
+                }
+            },
+        );
+        if self.synced.get(&source) == Some(&records) {
+            return;
+        }
+        let mut names: Vec<_> = records
+            .iter()
+            .map(|(name, ip)| format!("{name} ({ip})"))
+            .collect();
+        names.sort();
+        info!(
+            "Synced {} {source} records: {}",
+            records.len(),
+            names.join(", ")
+        );
+        self.synced.insert(source, records);
+        self.publish_records();
+    }
+
+    /// Point the alias records at the new addresses (reloading the records file).
+    async fn set_alias_addresses(&mut self, addresses: AliasAddresses) {
+        if addresses == self.alias_addresses {
+            return;
+        }
+        info!("The alias addresses changed: {addresses:?}");
+        self.alias_addresses = addresses;
+        if let Err(e) = self.reload_records().await {
+            warn!("Error reloading the records for the new alias addresses: {e:#}");
+            return;
+        }
+        if self.flush_cache_on_reload {
+            flush_dns_cache().await.unwrap_or_else(|e| {
+                notify_error!("Error flushing the DNS client cache: {e:#}");
+            });
+        }
     }
 
-    fn lookup_name(&self, host: String) -> Result<Ipv4Addr> {
+    /// Answer `host` like the workers do.
+    fn lookup_name(&self, host: String) -> Result<LookupResult> {
+        let state = self.lookup_state_tx.borrow().clone();
+        let matched = match state.find_served_record(&host) {
+            Some((name, _)) if name == host => RecordMatch::Exact,
+            Some((name, _)) => RecordMatch::Suffix(name.to_owned()),
+            None => RecordMatch::Default,
+        };
         let mut query = DnsPacket::new();
         let question = DnsQuestion::new(host, QueryType::A);
         query.questions.push(question);
-        let response = &self.lookup(&query);
+        let response = &state.lookup(&query);
         let result_code = ResultCode::from_num(response.header.opcode);
         if response.answers.is_empty() {
             return Err(anyhow!(
@@ -184,10 +1194,63 @@ impl DnsServer {
             ));
         }
         match response.answers.first() {
-            Some(DnsRecord::A { ref addr, .. }) => Ok(*addr),
+            Some(DnsRecord::A { ref addr, .. }) => Ok(LookupResult {
+                addr: *addr,
+                matched,
+            }),
             _ => Err(anyhow!("DNS responded with")),
         }
     }
+}
+
+/// A snapshot of what the queries are answered from: the records and the settings affecting the
+/// answers. Shared by the workers, and replaced (rather than changed) whenever they change.
+#[derive(Default)]
+struct LookupState {
+    top_level_domain: String,
+    records: HashMap<String, Ipv4Addr>,
+    synced: HashMap<String, HashMap<String, Ipv4Addr>>,
+    loopback_aliases: Option<Arc<LoopbackAliases>>,
+    localhost_zone: bool,
+    refuse_other_domains: bool,
+    paused: bool,
+}
+
+impl LookupState {
+    /// The record answering for `host`: the most specific name, from the records file when a
+    /// synced record is as specific.
+    fn find_served_record(&self, host: &str) -> Option<(&str, Ipv4Addr)> {
+        self.synced
+            .values()
+            .filter_map(|records| find_record(host, records))
+            .fold(find_record(host, &self.records), |best, found| match best {
+                Some(best) if best.0.len() >= found.0.len() => Some(best),
+                _ => Some(found),
+            })
+    }
+
+    fn serves(&self, name: &str) -> bool {
+        name.ends_with(&self.top_level_domain) || (self.localhost_zone && is_localhost(name))
+    }
+
+    /// Is `request` for another domain, refused without logging or recording it (see
+    /// [`DnsServer::set_refuse_other_domains`])?
+    fn refuses_quietly(&self, request: &DnsPacket) -> bool {
+        self.refuse_other_domains
+            && request
+                .questions
+                .first()
+                .is_some_and(|query| !self.serves(&query.name))
+    }
+
+    /// The address answering for the record `name` pointing at `ip`: its loopback alias in the
+    /// loopback alias mode.
+    fn served_address(&self, name: &str, ip: Ipv4Addr) -> Ipv4Addr {
+        match &self.loopback_aliases {
+            Some(aliases) if ip == Ipv4Addr::LOCALHOST => aliases.alias(name),
+            _ => ip,
+        }
+    }
 
     fn lookup(&self, request: &DnsPacket) -> DnsPacket {
         let id = &request.header.id;
@@ -211,6 +1274,12 @@ impl DnsServer {
             return response;
         }
 
+        if self.paused {
+            debug!("refusing query while paused (id: {}): {}", &id, &query.name);
+            response.header.rescode = ResultCode::REFUSED;
+            return response;
+        }
+
         if request.header.opcode != 0 {
             warn!("received non-zero opcode (id: {})", &id);
             response.header.rescode = ResultCode::NOTIMP;
@@ -218,6 +1287,19 @@ impl DnsServer {
         }
 
         if !query.name.ends_with(&self.top_level_domain) {
+            if self.localhost_zone && is_localhost(&query.name) {
+                answer_localhost(query, &mut response);
+                return response;
+            }
+            if self.refuse_other_domains {
+                trace!(
+                    "refusing query of another domain (id: {}): {}",
+                    &id,
+                    &query.name
+                );
+                response.header.rescode = ResultCode::REFUSED;
+                return response;
+            }
             warn!("unsupported domain (id: {}): {}", &id, &query.name);
             response.header.rescode = ResultCode::SERVFAIL;
             return response;
@@ -226,9 +1308,13 @@ impl DnsServer {
         match &query.qtype {
             QueryType::A => {
                 let record = DnsRecord::A {
-                    addr: ip_from_domain_or_default(&query.name, &self.records),
-                    domain: query.name.to_string(),
-                    ttl: 0,
+                    addr: self
+                        .find_served_record(&query.name)
+                        .map_or(Ipv4Addr::LOCALHOST, |(name, ip)| {
+                            self.served_address(name, ip)
+                        }),
+                    domain: query.name.clone(),
+                    ttl: RECORD_TTL,
                 };
                 response.answers.push(record);
             }
@@ -246,13 +1332,49 @@ impl DnsServer {
     }
 }
 
-fn ip_from_domain_or_default(host: &str, domain: &HashMap<String, Ipv4Addr>) -> Ipv4Addr {
-    domain
-        .iter()
-        .find(|&(name, _)| name == host || host.ends_with(&format!(".{name}")))
-        .map_or(Ipv4Addr::LOCALHOST, |(_, ip)| *ip)
+/// Send a notification to the server and wait for its reply.
+pub async fn request<T>(
+    notification_tx: &Sender<Notification>,
+    notification: impl FnOnce(oneshot::Sender<Result<T>>) -> Notification,
+) -> Result<T> {
+    let (tx, rx) = oneshot::channel();
+    notification_tx
+        .send(notification(tx))
+        .await
+        .context("sending request channel")?;
+    rx.await?
+}
+
+fn reply<T>(tx: oneshot::Sender<Result<T>>, result: Result<T>) {
+    if tx.send(result).is_err() {
+        error!("Error sending response to notification channel");
+    }
+}
+
+/// RFC 6761 reserves `localhost` and its subdomains for the loopback addresses, and many frameworks
+/// default to `<app>.localhost` names.
+fn is_localhost(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name == "localhost" || name.ends_with(".localhost")
 }
 
+fn answer_localhost(query: &DnsQuestion, response: &mut DnsPacket) {
+    match query.qtype {
+        QueryType::A => response.answers.push(DnsRecord::A {
+            domain: query.name.clone(),
+            addr: Ipv4Addr::LOCALHOST,
+            ttl: 0,
+        }),
+        QueryType::AAAA => response.answers.push(DnsRecord::AAAA {
+            domain: query.name.clone(),
+            addr: Ipv6Addr::LOCALHOST,
+            ttl: 0,
+        }),
+        _ => debug!("received request for undefined query type: {:?}", &query),
+    }
+}
+
+#[cfg(target_os = "windows")]
 #[allow(clippy::cast_possible_truncation)]
 async fn mk_udp_socket(addr: &SocketAddr) -> std::io::Result<UdpSocket> {
     let socket = UdpSocket::bind(addr).await?;
@@ -279,12 +1401,20 @@ async fn mk_udp_socket(addr: &SocketAddr) -> std::io::Result<UdpSocket> {
     Ok(socket)
 }
 
+/// Unlike Windows, macOS doesn't report the ICMP port unreachable of a reply on the next receive.
+#[cfg(target_os = "macos")]
+async fn mk_udp_socket(addr: &SocketAddr) -> std::io::Result<UdpSocket> {
+    UdpSocket::bind(addr).await
+}
+
 #[cfg(test)]
 mod tests {
     use super::protocol::*;
+    use super::{find_record, ImportFormat, ImportOutcome, RECORD_TTL};
     use crate::dns::records::RecordsDB;
     use crate::prelude::*;
     use std::str::FromStr;
+    use std::time::SystemTime;
     use tempfile::NamedTempFile;
     use tokio::join;
     use tokio::time::{sleep, timeout, Duration};
@@ -433,6 +1563,32 @@ mod tests {
         assert_eq!(response.answers.len(), 0);
     }
 
+    #[tokio::test]
+    async fn localhost_names_are_answered_with_loopback_addresses() {
+        let query = packet_with_question("App.localhost".to_string(), QueryType::A);
+        let response = basic_query_and_validation(query, ResultCode::NOERROR, records()).await;
+        assert_eq!(
+            response.answers[..],
+            [DnsRecord::A {
+                domain: "App.localhost".to_string(),
+                addr: Ipv4Addr::LOCALHOST,
+                ttl: 0
+            }]
+        );
+        let query = packet_with_question("api.app.localhost".to_string(), QueryType::AAAA);
+        let response = basic_query_and_validation(query, ResultCode::NOERROR, records()).await;
+        assert_eq!(
+            response.answers[..],
+            [DnsRecord::AAAA {
+                domain: "api.app.localhost".to_string(),
+                addr: Ipv6Addr::LOCALHOST,
+                ttl: 0
+            }]
+        );
+        let query = packet_with_question("notlocalhost".to_string(), QueryType::A);
+        basic_query_and_validation(query, ResultCode::SERVFAIL, records()).await;
+    }
+
     #[tokio::test]
     async fn service_starts_with_no_db_file() {
         let mut dns = DnsServer::new(0, "non-existent-file", TOP_LEVEL)
@@ -449,6 +1605,160 @@ mod tests {
         dns_out.unwrap(); // assert did not return error.
     }
 
+    #[tokio::test]
+    async fn lazy_servers_load_the_records_when_they_start() {
+        let mut records_file = NamedTempFile::new().unwrap();
+        writeln!(records_file, "a.loc:192.168.0.4").unwrap();
+        let mut dns = DnsServer::with_lazy_records(0, records_file.path(), TOP_LEVEL);
+        assert!(dns.records.is_empty());
+        let mut status = dns.subscribe_status();
+        let notify_tx = dns.notify_tx.clone();
+        let ((), dns_out) = join!(
+            async move {
+                status
+                    .wait_for(|s| matches!(s, ServerStatus::Listening(_)))
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    run_lookup("a.loc", notify_tx.clone()).await.unwrap(),
+                    Ipv4Addr::new(192, 168, 0, 4)
+                );
+                notify_tx.send(Shutdown).await.unwrap();
+            },
+            dns.run(),
+        );
+        dns_out.unwrap();
+    }
+
+    #[tokio::test]
+    async fn status_reports_listening_and_stopped() {
+        let mut dns = DnsServer::new(0, "non-existent-file", TOP_LEVEL)
+            .await
+            .unwrap();
+        let mut status = dns.subscribe_status();
+        let notify_tx = dns.notify_tx.clone();
+        let ((), dns_out) = join!(
+            async move {
+                status
+                    .wait_for(|s| matches!(s, ServerStatus::Listening(_)))
+                    .await
+                    .unwrap();
+                notify_tx.send(Shutdown).await.unwrap();
+            },
+            dns.run(),
+        );
+        dns_out.unwrap();
+        assert_eq!(*dns.subscribe_status().borrow(), ServerStatus::Stopped);
+    }
+
+    #[tokio::test]
+    async fn moved_listen_adapter_rebinds_or_falls_back() {
+        let mut dns = DnsServer::new(0, "non-existent-file", TOP_LEVEL)
+            .await
+            .unwrap();
+        dns.set_listen_adapter("test adapter".to_owned());
+        let mut status = dns.subscribe_status();
+        let notify_tx = dns.notify_tx.clone();
+        let listening_on = |ip: Ipv4Addr| move |s: &ServerStatus| matches!(s, ServerStatus::Listening(addr) if addr.ip() == ip);
+        let ((), dns_out) = join!(
+            async move {
+                status
+                    .wait_for(listening_on(Ipv4Addr::LOCALHOST))
+                    .await
+                    .unwrap();
+                let moved = Ipv4Addr::new(127, 0, 0, 2);
+                notify_tx.send(ListenAdapterMoved(moved)).await.unwrap();
+                status.wait_for(listening_on(moved)).await.unwrap();
+                // not an address of this machine
+                let unbound = Ipv4Addr::new(192, 0, 2, 1);
+                notify_tx.send(ListenAdapterMoved(unbound)).await.unwrap();
+                status
+                    .wait_for(listening_on(Ipv4Addr::LOCALHOST))
+                    .await
+                    .unwrap();
+                notify_tx.send(Shutdown).await.unwrap();
+            },
+            dns.run(),
+        );
+        dns_out.unwrap();
+    }
+
+    #[tokio::test]
+    async fn paused_server_refuses_queries() {
+        let mut dns = DnsServer::new(0, "non-existent-file", TOP_LEVEL)
+            .await
+            .unwrap();
+        dns.set_paused(true);
+        let mut status = dns.subscribe_status();
+        let notify_tx = dns.notify_tx.clone();
+        let ((), dns_out) = join!(
+            async move {
+                status
+                    .wait_for(|s| matches!(s, ServerStatus::Paused(_)))
+                    .await
+                    .unwrap();
+                assert!(run_lookup("host.loc", notify_tx.clone()).await.is_err());
+                notify_tx.send(SetPaused(false)).await.unwrap();
+                status
+                    .wait_for(|s| matches!(s, ServerStatus::Listening(_)))
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    run_lookup("host.loc", notify_tx.clone()).await.unwrap(),
+                    Ipv4Addr::LOCALHOST
+                );
+                notify_tx.send(Shutdown).await.unwrap();
+            },
+            dns.run(),
+        );
+        dns_out.unwrap();
+    }
+
+    #[tokio::test]
+    async fn recent_queries_keep_the_last_queries() {
+        let ds = DnsServer::new(0, "non-existent-file", TOP_LEVEL)
+            .await
+            .unwrap();
+        let client = SocketAddr::from((Ipv4Addr::LOCALHOST, 5000));
+        let mut events = ds.subscribe_queries();
+        let worker = ds.worker();
+        for i in 0..20 {
+            let query = packet_with_question(format!("host{i}.loc"), QueryType::A);
+            let response = ds.lookup_state().lookup(&query);
+            worker.record_query(&response, client, SystemTime::now(), Duration::ZERO);
+        }
+        for i in 0..20 {
+            let event = events.try_recv().unwrap();
+            assert_eq!(event.name, format!("host{i}.loc"));
+            ds.record_query(event);
+        }
+        let recent = ds.subscribe_recent_queries();
+        let recent = recent.borrow();
+        assert_eq!(recent.len(), 15);
+        assert_eq!(recent.front().unwrap().name, "host5.loc");
+        assert_eq!(recent.back().unwrap().answer, Some(Ipv4Addr::LOCALHOST));
+        assert_eq!(ds.subscribe_stats().borrow().queries, 20);
+    }
+
+    #[tokio::test]
+    async fn queries_are_counted_by_latency() {
+        let ds = DnsServer::new(0, "non-existent-file", TOP_LEVEL)
+            .await
+            .unwrap();
+        let client = SocketAddr::from((Ipv4Addr::LOCALHOST, 5000));
+        let response = ds
+            .lookup_state()
+            .lookup(&packet_with_question("host.loc".to_owned(), QueryType::A));
+        let mut events = ds.subscribe_queries();
+        let worker = ds.worker();
+        for millis in [0, 1, 2, 5_000] {
+            let duration = Duration::from_millis(millis);
+            worker.record_query(&response, client, SystemTime::now(), duration);
+            ds.record_query(events.try_recv().unwrap());
+        }
+        assert_eq!(ds.subscribe_stats().borrow().latency, [1, 1, 1, 0, 0, 1]);
+    }
+
     #[tokio::test]
     async fn reloading_records_updates_live_service() {
         timeout(Duration::from_secs(1), async {
@@ -463,13 +1773,13 @@ mod tests {
                 async move {
                     let (tx1, rx2) = oneshot::channel();
                     let _ = notify_tx.send(ARecordQuery(host.clone(), tx1)).await;
-                    let ip1 = rx2.await.unwrap().unwrap();
+                    let ip1 = rx2.await.unwrap().unwrap().addr;
                     assert_eq!(ip1, Ipv4Addr::LOCALHOST);
                     _ = notify_tx.send(Reload).await;
                     writeln!(records_file, "{host}:192.168.0.1").unwrap();
                     let (tx2, rx2) = oneshot::channel();
                     let _ = notify_tx.send(ARecordQuery(host, tx2)).await;
-                    let ip2 = rx2.await.unwrap().unwrap();
+                    let ip2 = rx2.await.unwrap().unwrap().addr;
                     assert_eq!(ip2, "192.168.0.1".parse::<Ipv4Addr>().unwrap());
                     notify_tx.send(Shutdown).await.unwrap();
                 },
@@ -497,7 +1807,8 @@ mod tests {
                 async move {
                     let (tx1, rx1) = oneshot::channel();
                     notification_tx.send(MergeRecords(merged_file.path().into(), tx1)).await.unwrap();
-                    rx1.await.unwrap().unwrap(); // panic if it's error
+                    let summary = rx1.await.unwrap().unwrap(); // panic if it's error
+                    assert_eq!(summary, MergeSummary { added: 1, conflicts: vec!["b-host.loc".to_owned()] });
                     assert_eq!(run_lookup("a.host.loc", notification_tx.clone()).await.unwrap(), Ipv4Addr::from_str("192.168.0.4").unwrap(), "records-only host should remain the same");
                     assert_eq!(run_lookup("b-host.loc", notification_tx.clone()).await.unwrap(), Ipv4Addr::from_str("192.168.1.1").unwrap(), "merge should overwrite original");
                     assert_eq!(run_lookup("c.host.loc", notification_tx.clone()).await.unwrap(), Ipv4Addr::from_str("192.168.1.1").unwrap(), "merge only host should resolve");
@@ -511,6 +1822,146 @@ mod tests {
         }).await.unwrap();
     }
 
+    #[rustfmt::skip]
+    #[tokio::test]
+    async fn reloads_and_merges_record_what_changed() {
+        let mut records_file = NamedTempFile::new().unwrap();
+        writeln!(records_file, "a.host.loc:192.168.0.4\r\nb-host.loc:192.168.0.4").unwrap();
+        let mut merged_file = NamedTempFile::new().unwrap();
+        writeln!(merged_file, "c.host.loc:192.168.1.1\r\nb-host.loc:192.168.1.1").unwrap();
+        let mut dns = DnsServer::new(0, records_file.path(), TOP_LEVEL).await.unwrap();
+
+        dns.handle_merge_records(merged_file.path().into()).await.unwrap();
+        let (cause, diff) = dns.last_records_change.clone().unwrap();
+        assert_eq!(cause, "merge");
+        assert_eq!(diff.to_string(), "1 added, 1 changed: +c.host.loc (192.168.1.1), b-host.loc (192.168.0.4 -> 192.168.1.1)");
+
+        // the reload undoes the merge
+        dns.reload_records().await.unwrap();
+        let (cause, diff) = dns.last_records_change.clone().unwrap();
+        assert_eq!(cause, "reload");
+        assert_eq!(diff.to_string(), "1 removed, 1 changed: -c.host.loc (192.168.1.1), b-host.loc (192.168.1.1 -> 192.168.0.4)");
+    }
+
+    #[tokio::test]
+    async fn toggle_and_remove_records() {
+        let mut records_file = NamedTempFile::new().unwrap();
+        writeln!(
+            records_file,
+            "# comment\na.loc:192.168.0.4\nb.loc:192.168.0.5"
+        )
+        .unwrap();
+        let mut dns = DnsServer::new(0, records_file.path(), TOP_LEVEL)
+            .await
+            .unwrap();
+        let records = dns.subscribe_records();
+        let addr = |ds: &DnsServer, host: &str| ds.lookup_name(host.to_owned()).unwrap().addr;
+
+        assert!(!dns.toggle_record("a.loc").unwrap());
+        assert_eq!(addr(&dns, "a.loc"), Ipv4Addr::LOCALHOST);
+        assert!(!records.borrow()[0].enabled);
+        dns.reload_records().await.unwrap();
+        assert_eq!(
+            addr(&dns, "a.loc"),
+            Ipv4Addr::LOCALHOST,
+            "disabled after reload"
+        );
+        assert!(dns.toggle_record("a.loc").unwrap());
+        assert_eq!(addr(&dns, "a.loc"), Ipv4Addr::new(192, 168, 0, 4));
+
+        dns.add_record("c.loc", Ipv4Addr::new(192, 168, 0, 6))
+            .await
+            .unwrap();
+        dns.add_record("a.loc", Ipv4Addr::new(192, 168, 0, 7))
+            .await
+            .unwrap();
+        assert_eq!(addr(&dns, "a.loc"), Ipv4Addr::new(192, 168, 0, 7));
+        assert!(dns.add_record("d.com", Ipv4Addr::LOCALHOST).await.is_err());
+        let contents = fs::read_to_string(records_file.path()).unwrap();
+        assert_eq!(
+            contents,
+            "# comment\nb.loc:192.168.0.5\nc.loc:192.168.0.6\na.loc:192.168.0.7\n"
+        );
+        dns.remove_record("c.loc").await.unwrap();
+        dns.add_record("a.loc", Ipv4Addr::new(192, 168, 0, 4))
+            .await
+            .unwrap();
+
+        dns.remove_record("b.loc").await.unwrap();
+        assert_eq!(addr(&dns, "b.loc"), Ipv4Addr::LOCALHOST);
+        let contents = fs::read_to_string(records_file.path()).unwrap();
+        assert_eq!(contents, "# comment\na.loc:192.168.0.4\n");
+        assert_eq!(records.borrow().len(), 1);
+        assert!(dns.remove_record("b.loc").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn import_records_all_or_nothing() {
+        let mut records_file = NamedTempFile::new().unwrap();
+        writeln!(records_file, "a.loc:192.168.0.4\nb.loc:192.168.0.5").unwrap();
+        let mut dns = DnsServer::new(0, records_file.path(), TOP_LEVEL)
+            .await
+            .unwrap();
+        let report = dns
+            .import_records(
+                "c.loc:10.0.0.1\n\n# comment\nd.com:10.0.0.2\ne.loc",
+                ImportFormat::Records,
+            )
+            .await
+            .unwrap();
+        assert!(!report.applied);
+        assert_eq!(report.lines.len(), 3);
+        assert_eq!(
+            report.lines[0],
+            (
+                1,
+                ImportOutcome::Added("c.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 1))
+            )
+        );
+        assert_eq!(report.lines[1].0, 4);
+        assert!(matches!(report.lines[2].1, ImportOutcome::Invalid(_)));
+        assert!(!dns.records.contains_key("c.loc"), "nothing is imported");
+
+        let report = dns
+            .import_records(
+                "c.loc:10.0.0.1\na.loc:192.168.0.4\nb.loc:10.0.0.2",
+                ImportFormat::Records,
+            )
+            .await
+            .unwrap();
+        assert!(report.applied);
+        assert_eq!(
+            report
+                .lines
+                .into_iter()
+                .map(|(_, outcome)| outcome)
+                .collect::<Vec<_>>(),
+            vec![
+                ImportOutcome::Added("c.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 1)),
+                ImportOutcome::Unchanged("a.loc".to_owned(), Ipv4Addr::new(192, 168, 0, 4)),
+                ImportOutcome::Updated("b.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 2)),
+            ]
+        );
+        let contents = fs::read_to_string(records_file.path()).unwrap();
+        assert_eq!(
+            contents,
+            "a.loc:192.168.0.4\nc.loc:10.0.0.1\nb.loc:10.0.0.2\n"
+        );
+        assert_eq!(dns.records.get("b.loc"), Some(&Ipv4Addr::new(10, 0, 0, 2)));
+
+        // IPv6 dnsmasq addresses are skipped without failing the import
+        let report = dns
+            .import_records(
+                "address=/d.loc/::1\naddress=/d.loc/10.0.0.4\n",
+                ImportFormat::Dnsmasq,
+            )
+            .await
+            .unwrap();
+        assert!(report.applied);
+        assert!(matches!(report.lines[0], (1, ImportOutcome::Skipped(_))));
+        assert_eq!(dns.records.get("d.loc"), Some(&Ipv4Addr::new(10, 0, 0, 4)));
+    }
+
     async fn basic_query_and_validation(
         query: DnsPacket,
         result: ResultCode,
@@ -520,12 +1971,29 @@ mod tests {
             .await
             .unwrap();
         ds.records = records;
-        let response = ds.lookup(&query);
+        let response = ds.lookup_state().lookup(&query);
         assert_eq!(query.header.id, response.header.id);
         assert_eq!(response.header.rescode, result);
         response
     }
 
+    #[tokio::test]
+    async fn other_domains_are_refused_quietly_in_the_adapter_dns_mode() {
+        let mut ds = DnsServer::new(0, "non-existent-file", TOP_LEVEL)
+            .await
+            .unwrap();
+        ds.set_refuse_other_domains(true);
+        let state = ds.lookup_state();
+        let query = packet_with_question("example.com".to_string(), QueryType::A);
+        assert!(state.refuses_quietly(&query));
+        assert_eq!(state.lookup(&query).header.rescode, ResultCode::REFUSED);
+        for name in ["test.loc", "app.localhost"] {
+            let query = packet_with_question(name.to_string(), QueryType::A);
+            assert!(!state.refuses_quietly(&query), "{name}");
+            assert_eq!(state.lookup(&query).header.rescode, ResultCode::NOERROR);
+        }
+    }
+
     fn records() -> HashMap<String, Ipv4Addr> {
         HashMap::from([("registered.loc".into(), "192.168.0.1".parse().unwrap())])
     }
@@ -540,6 +2008,131 @@ mod tests {
     async fn run_lookup(host: &str, notify_tx: Sender<Notification>) -> Result<Ipv4Addr> {
         let (tx, rx) = oneshot::channel();
         notify_tx.send(ARecordQuery(host.into(), tx)).await?;
-        rx.await?
+        rx.await?.map(|result| result.addr)
+    }
+
+    #[tokio::test]
+    async fn reverse_search_finds_all_names() {
+        let mut records_file = NamedTempFile::new().unwrap();
+        writeln!(
+            records_file,
+            "a.loc:192.168.0.4\nb.loc:192.168.0.5\nc.loc:192.168.0.4"
+        )
+        .unwrap();
+        let mut dns = DnsServer::new(0, records_file.path(), TOP_LEVEL)
+            .await
+            .unwrap();
+        dns.toggle_record("c.loc").unwrap();
+        let names = |entries: Vec<RecordEntry>| {
+            entries
+                .into_iter()
+                .map(|e| (e.name, e.enabled))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(dns.reverse_search(Ipv4Addr::new(192, 168, 0, 4))),
+            vec![("a.loc".to_owned(), true), ("c.loc".to_owned(), false)]
+        );
+        assert!(dns.reverse_search(Ipv4Addr::new(10, 0, 0, 1)).is_empty());
+    }
+
+    #[test]
+    fn find_record_prefers_exact_then_longest_suffix() {
+        let records = HashMap::from([
+            ("nas.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 1)),
+            ("a.nas.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 2)),
+        ]);
+        assert_eq!(
+            find_record("nas.loc", &records),
+            Some(("nas.loc", Ipv4Addr::new(10, 0, 0, 1)))
+        );
+        assert_eq!(
+            find_record("x.a.nas.loc", &records),
+            Some(("a.nas.loc", Ipv4Addr::new(10, 0, 0, 2)))
+        );
+        assert_eq!(
+            find_record("b.nas.loc", &records),
+            Some(("nas.loc", Ipv4Addr::new(10, 0, 0, 1)))
+        );
+        assert_eq!(find_record("xnas.loc", &records), None);
+    }
+
+    #[tokio::test]
+    async fn synced_records_are_served_unless_the_file_is_more_specific() {
+        let mut dns = DnsServer::new(0, "non-existent-file", TOP_LEVEL)
+            .await
+            .unwrap();
+        dns.records
+            .insert("app.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 1));
+        dns.sync_records(
+            "docker".to_owned(),
+            HashMap::from([
+                ("web.app.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 2)),
+                ("app.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 3)),
+                ("web.example.com".to_owned(), Ipv4Addr::new(10, 0, 0, 4)),
+            ]),
+        );
+        assert_eq!(dns.synced["docker"].len(), 2);
+        let state = dns.lookup_state_tx.borrow().clone();
+        assert_eq!(
+            state.find_served_record("x.web.app.loc"),
+            Some(("web.app.loc", Ipv4Addr::new(10, 0, 0, 2)))
+        );
+        assert_eq!(
+            state.find_served_record("db.app.loc"),
+            Some(("app.loc", Ipv4Addr::new(10, 0, 0, 1)))
+        );
+        dns.sync_records("docker".to_owned(), HashMap::new());
+        assert_eq!(
+            dns.lookup_state_tx
+                .borrow()
+                .find_served_record("web.app.loc"),
+            Some(("app.loc", Ipv4Addr::new(10, 0, 0, 1)))
+        );
+    }
+
+    #[tokio::test]
+    async fn records_are_listed_with_their_source() {
+        let mut records_file = NamedTempFile::new().unwrap();
+        writeln!(records_file, "b.loc:10.0.0.2\nc.loc:10.0.0.3").unwrap();
+        let mut dns = DnsServer::new(0, records_file.path(), TOP_LEVEL)
+            .await
+            .unwrap();
+        let records_rx = dns.subscribe_records();
+        dns.toggle_record("c.loc").unwrap();
+        dns.sync_records(
+            "docker".to_owned(),
+            HashMap::from([("a.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 1))]),
+        );
+        let record = |name: &str, addr, source, enabled| RecordEntry {
+            name: name.to_owned(),
+            addr,
+            record_type: QueryType::A,
+            ttl: RECORD_TTL,
+            source,
+            enabled,
+        };
+        let expected = vec![
+            record(
+                "a.loc",
+                Ipv4Addr::new(10, 0, 0, 1),
+                RecordSource::Sync("docker".to_owned()),
+                true,
+            ),
+            record(
+                "b.loc",
+                Ipv4Addr::new(10, 0, 0, 2),
+                RecordSource::RecordsFile,
+                true,
+            ),
+            record(
+                "c.loc",
+                Ipv4Addr::new(10, 0, 0, 3),
+                RecordSource::RecordsFile,
+                false,
+            ),
+        ];
+        assert_eq!(dns.record_entries(), expected);
+        assert_eq!(*records_rx.borrow(), expected);
     }
 }
@@ -0,0 +1,306 @@
+//! Diagnose a failure to bind the DNS port: find the processes listening on it and suggest what to
+//! do about them, instead of a bare "address in use" error.
+
+use crate::prelude::*;
+use std::io::ErrorKind;
+#[cfg(target_os = "windows")]
+use std::mem::size_of;
+#[cfg(target_os = "windows")]
+use std::net::IpAddr;
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Foundation::{CloseHandle, ERROR_INSUFFICIENT_BUFFER, NO_ERROR};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::NetworkManagement::IpHelper::{
+    GetExtendedUdpTable, MIB_UDP6ROW_OWNER_PID, MIB_UDPROW_OWNER_PID, UDP_TABLE_OWNER_PID,
+};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Networking::WinSock::{ADDRESS_FAMILY, AF_INET, AF_INET6};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::System::Threading::{
+    OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+};
+
+/// The PID of the kernel ("System") process.
+const SYSTEM_PID: u32 = 4;
+
+/// A process listening on the port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct PortOwner {
+    pub(super) pid: u32,
+    /// The executable file name, if the process could be opened.
+    pub(super) name: Option<String>,
+}
+
+/// Replace a bind error caused by another process owning the port with a description of that
+/// process and how to resolve the conflict. Other errors are returned as they are.
+pub(super) fn diagnose_bind_error(error: std::io::Error, addr: &SocketAddr) -> Error {
+    if !matches!(
+        error.kind(),
+        ErrorKind::AddrInUse | ErrorKind::PermissionDenied
+    ) {
+        return Error::new(error).context(format!("Binding {addr}"));
+    }
+    let owners = match udp_port_owners(addr.port()) {
+        Ok(owners) => owners,
+        Err(e) => {
+            warn!(
+                "Error listing the owners of UDP port {}: {e:#}",
+                addr.port()
+            );
+            vec![]
+        }
+    };
+    if owners.is_empty() && error.kind() == ErrorKind::PermissionDenied {
+        return Error::new(error).context(format!("Binding {addr}"));
+    }
+    let description = describe_conflict(addr.port(), &owners);
+    error!("Binding {addr} failed ({error}): {description}");
+    anyhow!(description)
+}
+
+fn describe_conflict(port: u16, owners: &[PortOwner]) -> String {
+    let Some(owner) = owners.first() else {
+        return format!(
+            "UDP port {port} is already in use by another process (it couldn't be identified). \
+Stop the other DNS server or change the port in the configuration."
+        );
+    };
+    let name = owner.name.clone().unwrap_or_else(|| "a process".to_owned());
+    let (culprit, remedy) = culprit(owner);
+    format!(
+        "UDP port {port} is already in use by {name} (PID {}), {culprit}.\n\n{remedy}",
+        owner.pid
+    )
+}
+
+/// What the process probably is and how to free the port.
+fn culprit(owner: &PortOwner) -> (&'static str, &'static str) {
+    if owner.pid == SYSTEM_PID {
+        return (
+            "a Windows kernel component (e.g. the DNS Server role or a network driver)",
+            "Remove the Windows DNS Server role or change the port in the configuration.",
+        );
+    }
+    let name = owner.name.as_deref().unwrap_or_default().to_lowercase();
+    match name.strip_suffix(".exe").unwrap_or(&name) {
+        "com.docker.backend" | "com.docker.proxy" | "com.docker.vpnkit" | "vpnkit"
+        | "vpnkit-bridge" | "dockerd" => (
+            "Docker Desktop",
+            "A container publishes port 53 (e.g. `-p 53:53/udp`). Stop it or publish another port.",
+        ),
+        "wslrelay" | "wslhost" => (
+            "WSL",
+            "A DNS server inside WSL (e.g. dnsmasq) is forwarded to Windows. Stop it or disable \
+localhostForwarding in .wslconfig.",
+        ),
+        "svchost" => (
+            "a Windows service, usually Internet Connection Sharing (or Mobile Hotspot)",
+            "Turn off Mobile Hotspot / Internet Connection Sharing, or stop the SharedAccess service.",
+        ),
+        "dnscrypt-proxy" | "acrylicservice" | "unbound" | "named" | "dnsmasq"
+        | "dnsserverapp" | "coredns" => (
+            "another DNS resolver",
+            "Stop it, or run one of the servers on another port (the other resolver can forward \
+the top level domain to DotLocal-DNS).",
+        ),
+        _ => (
+            "another program",
+            "Stop it or change the port in the configuration.",
+        ),
+    }
+}
+
+/// The processes listening on UDP `port` on all addresses or on localhost (IPv4 or IPv6).
+#[cfg(target_os = "windows")]
+pub(super) fn udp_port_owners(port: u16) -> Result<Vec<PortOwner>> {
+    let v4 = udp_table::<MIB_UDPROW_OWNER_PID>(AF_INET)?
+        .into_iter()
+        .map(|row| {
+            let addr = IpAddr::from(Ipv4Addr::from(u32::from_be(row.dwLocalAddr)));
+            (addr, row.dwLocalPort, row.dwOwningPid)
+        });
+    let v6 = udp_table::<MIB_UDP6ROW_OWNER_PID>(AF_INET6)?
+        .into_iter()
+        .map(|row| {
+            let addr = IpAddr::from(Ipv6Addr::from(row.ucLocalAddr));
+            (addr, row.dwLocalPort, row.dwOwningPid)
+        });
+    let mut owners: Vec<PortOwner> = vec![];
+    for (local_addr, local_port, pid) in v4.chain(v6) {
+        // the port is in network byte order in the low 16 bits
+        let local_port = u16::from_be((local_port & 0xffff) as u16);
+        if local_port == port
+            && (local_addr.is_unspecified() || local_addr.is_loopback())
+            && !owners.iter().any(|o| o.pid == pid)
+        {
+            owners.push(PortOwner {
+                pid,
+                name: process_name(pid),
+            });
+        }
+    }
+    Ok(owners)
+}
+
+/// The rows of the UDP table of `family` (`MIB_UDPTABLE_OWNER_PID` or `MIB_UDP6TABLE_OWNER_PID`):
+/// the number of rows followed by the rows.
+#[cfg(target_os = "windows")]
+fn udp_table<Row: Copy>(family: ADDRESS_FAMILY) -> Result<Vec<Row>> {
+    let mut size = 0;
+    let mut buffer: Vec<u32> = vec![];
+    loop {
+        let result = unsafe {
+            GetExtendedUdpTable(
+                buffer.as_mut_ptr().cast(),
+                &raw mut size,
+                0,
+                u32::from(family),
+                UDP_TABLE_OWNER_PID,
+                0,
+            )
+        };
+        match result {
+            NO_ERROR => break,
+            ERROR_INSUFFICIENT_BUFFER => buffer.resize((size as usize).div_ceil(4), 0),
+            code => {
+                return Err(std::io::Error::from_raw_os_error(
+                    i32::try_from(code).unwrap_or(i32::MAX),
+                ))
+                .context("GetExtendedUdpTable");
+            }
+        }
+    }
+    let Some((&count, rows)) = buffer.split_first() else {
+        return Ok(vec![]);
+    };
+    let count = (count as usize).min(rows.len() * 4 / size_of::<Row>());
+    let rows = unsafe { std::slice::from_raw_parts(rows.as_ptr().cast::<Row>(), count) };
+    Ok(rows.to_vec())
+}
+
+/// The executable file name of `pid`, `None` if the process can't be opened (e.g. protected).
+#[cfg(target_os = "windows")]
+fn process_name(pid: u32) -> Option<String> {
+    let handle = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+    if handle.is_null() {
+        return None;
+    }
+    let mut buffer = [0u16; 1024];
+    let mut size = u32::try_from(buffer.len()).unwrap_or_default();
+    let ok = unsafe {
+        QueryFullProcessImageNameW(
+            handle,
+            PROCESS_NAME_WIN32,
+            buffer.as_mut_ptr(),
+            &raw mut size,
+        )
+    };
+    unsafe { CloseHandle(handle) };
+    if ok == 0 {
+        return None;
+    }
+    let path = String::from_utf16_lossy(&buffer[..size as usize]);
+    Path::new(&path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+}
+
+/// The processes listening on UDP `port` on all addresses or on localhost (IPv4 or IPv6). `lsof`
+/// only lists the processes of the user (the others' are unidentified).
+#[cfg(target_os = "macos")]
+pub(super) fn udp_port_owners(port: u16) -> Result<Vec<PortOwner>> {
+    let output = std::process::Command::new("lsof")
+        .args(["-nP", "+c0", &format!("-iUDP:{port}"), "-Fpcn"])
+        .output()
+        .context("running lsof")?;
+    // lsof also fails when nothing listens on the port
+    Ok(lsof_port_owners(
+        &String::from_utf8_lossy(&output.stdout),
+        port,
+    ))
+}
+
+/// The processes of `lsof -F pcn` output (a `p`id line, then the `c`ommand and the `n`ame of every
+/// socket) with a socket on local `port` on all addresses or on localhost.
+#[cfg(target_os = "macos")]
+fn lsof_port_owners(output: &str, port: u16) -> Vec<PortOwner> {
+    let mut owners: Vec<PortOwner> = vec![];
+    let mut process: Option<PortOwner> = None;
+    for line in output.lines() {
+        let Some(field) = line.chars().next() else {
+            continue;
+        };
+        let value = &line[field.len_utf8()..];
+        match field {
+            'p' => process = value.parse().ok().map(|pid| PortOwner { pid, name: None }),
+            'c' => {
+                if let Some(process) = &mut process {
+                    process.name = Some(value.to_owned());
+                }
+            }
+            'n' => {
+                let local = value.split("->").next().unwrap_or_default();
+                let Some((host, local_port)) = local.rsplit_once(':') else {
+                    continue;
+                };
+                let listening = matches!(host, "*" | "localhost" | "[::1]" | "[::]")
+                    || host.starts_with("127.");
+                if let Some(process) = process.as_ref().filter(|p| {
+                    listening
+                        && local_port == port.to_string()
+                        && !owners.iter().any(|o| o.pid == p.pid)
+                }) {
+                    owners.push(process.clone());
+                }
+            }
+            _ => (),
+        }
+    }
+    owners
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owner(pid: u32, name: Option<&str>) -> PortOwner {
+        PortOwner {
+            pid,
+            name: name.map(ToOwned::to_owned),
+        }
+    }
+
+    #[test]
+    fn conflicts_name_the_culprit() {
+        let docker = describe_conflict(53, &[owner(1234, Some("com.docker.backend.exe"))]);
+        assert!(docker.starts_with(
+            "UDP port 53 is already in use by com.docker.backend.exe (PID 1234), Docker Desktop."
+        ));
+        assert!(docker.contains("publish another port"));
+        let ics = describe_conflict(53, &[owner(2000, Some("svchost.exe"))]);
+        assert!(ics.contains("Internet Connection Sharing"));
+        let unknown = describe_conflict(5353, &[owner(42, None)]);
+        assert!(unknown.starts_with(
+            "UDP port 5353 is already in use by a process (PID 42), another program."
+        ));
+        assert!(describe_conflict(53, &[]).contains("couldn't be identified"));
+        assert!(describe_conflict(53, &[owner(SYSTEM_PID, Some("System"))]).contains("kernel"));
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn lsof_lists_the_listening_processes() {
+        let output = "p310\ncmDNSResponder\nf12\nn*:53\np4242\ncdnsmasq\nf5\nn127.0.0.1:53\n\
+f6\nn[::1]:53\np777\ncfirefox\nf40\nn192.168.1.5:61234->8.8.8.8:53\n";
+        assert_eq!(
+            lsof_port_owners(output, 53),
+            [
+                owner(310, Some("mDNSResponder")),
+                owner(4242, Some("dnsmasq"))
+            ]
+        );
+        assert!(lsof_port_owners("", 53).is_empty());
+        let dnsmasq = describe_conflict(53, &[owner(4242, Some("dnsmasq"))]);
+        assert!(dnsmasq.contains("another DNS resolver"));
+    }
+}
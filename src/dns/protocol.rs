@@ -1,10 +1,17 @@
 #![allow(clippy::upper_case_acronyms, clippy::pedantic)]
 
 use crate::prelude::*;
+use smallvec::SmallVec;
+
+/// The longest name (RFC 1035), names are read into strings of this capacity and longer names are
+/// rejected.
+const MAX_NAME_LENGTH: usize = 255;
 
 pub struct BytePacketBuffer {
     pub buf: [u8; 512],
     pub pos: usize,
+    /// The end of the received data, reads past it fail.
+    len: usize,
 }
 
 impl BytePacketBuffer {
@@ -12,14 +19,23 @@ impl BytePacketBuffer {
         BytePacketBuffer {
             buf: [0; 512],
             pos: 0,
+            len: 512,
         }
     }
 
+    /// Only read the first `len` bytes (the received datagram).
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len.min(self.buf.len());
+    }
+
     pub(super) fn pos(&self) -> usize {
         self.pos
     }
 
     fn step(&mut self, steps: usize) -> Result<()> {
+        if self.pos + steps > self.len {
+            return Err(anyhow!("End of buffer"));
+        }
         self.pos += steps;
 
         Ok(())
@@ -32,7 +48,7 @@ impl BytePacketBuffer {
     }
 
     fn read(&mut self) -> Result<u8> {
-        if self.pos >= 512 {
+        if self.pos >= self.len {
             return Err(anyhow!("End of buffer"));
         }
         let res = self.buf[self.pos];
@@ -42,14 +58,14 @@ impl BytePacketBuffer {
     }
 
     fn get(&mut self, pos: usize) -> Result<u8> {
-        if pos >= 512 {
+        if pos >= self.len {
             return Err(anyhow!("End of buffer"));
         }
         Ok(self.buf[pos])
     }
 
     pub(super) fn get_range(&mut self, start: usize, len: usize) -> Result<&[u8]> {
-        if start + len >= 512 {
+        if start + len > self.len {
             return Err(anyhow!("End of buffer"));
         }
         Ok(&self.buf[start..start + len])
@@ -71,6 +87,7 @@ impl BytePacketBuffer {
     }
 
     fn read_qname(&mut self, outstr: &mut String) -> Result<()> {
+        outstr.reserve(MAX_NAME_LENGTH);
         let mut pos = self.pos();
         let mut jumped = false;
 
@@ -114,9 +131,19 @@ impl BytePacketBuffer {
             }
 
             outstr.push_str(delim);
+            if outstr.len() + len as usize > MAX_NAME_LENGTH {
+                return Err(anyhow!(
+                    "Name exceeds {MAX_NAME_LENGTH} characters of length"
+                ));
+            }
 
-            let str_buffer = self.get_range(pos, len as usize)?;
-            outstr.push_str(&String::from_utf8_lossy(str_buffer).to_lowercase());
+            let label = self.get_range(pos, len as usize)?;
+            if label.is_ascii() {
+                // the usual case, lowercased in place rather than through a temporary string
+                outstr.extend(label.iter().map(|b| char::from(b.to_ascii_lowercase())));
+            } else {
+                outstr.push_str(&String::from_utf8_lossy(label).to_lowercase());
+            }
 
             delim = ".";
 
@@ -139,6 +166,16 @@ impl BytePacketBuffer {
         Ok(())
     }
 
+    fn write_bytes(&mut self, val: &[u8]) -> Result<()> {
+        let end = self.pos + val.len();
+        if end > 512 {
+            return Err(anyhow!("End of buffer"));
+        }
+        self.buf[self.pos..end].copy_from_slice(val);
+        self.pos = end;
+        Ok(())
+    }
+
     fn write_u8(&mut self, val: u8) -> Result<()> {
         self.write(val)?;
 
@@ -162,18 +199,15 @@ impl BytePacketBuffer {
     }
 
     fn write_qname(&mut self, qname: &str) -> Result<()> {
-        let split_str = qname.split('.').collect::<Vec<&str>>();
-
-        for label in split_str {
+        // the root (empty) name is just the terminating empty label
+        for label in qname.split('.').filter(|label| !label.is_empty()) {
             let len = label.len();
             if len > 0x3f {
                 return Err(anyhow!("Single label exceeds 63 characters of length"));
             }
 
             self.write_u8(len as u8)?;
-            for b in label.as_bytes() {
-                self.write_u8(*b)?;
-            }
+            self.write_bytes(label.as_bytes())?;
         }
 
         self.write_u8(0)?;
@@ -182,6 +216,9 @@ impl BytePacketBuffer {
     }
 
     fn set(&mut self, pos: usize, val: u8) -> Result<()> {
+        if pos >= self.buf.len() {
+            return Err(anyhow!("End of buffer"));
+        }
         self.buf[pos] = val;
 
         Ok(())
@@ -218,7 +255,7 @@ impl ResultCode {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct DnsHeader {
     pub id: u16, // 16 bits
 
@@ -675,11 +712,13 @@ impl DnsRecord {
     }
 }
 
-#[derive(Clone, Debug)]
+/// Queries have a single question and the responses (at most) a single answer, so these are stored
+/// inline rather than allocated.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct DnsPacket {
     pub header: DnsHeader,
-    pub questions: Vec<DnsQuestion>,
-    pub answers: Vec<DnsRecord>,
+    pub questions: SmallVec<[DnsQuestion; 1]>,
+    pub answers: SmallVec<[DnsRecord; 1]>,
     pub authorities: Vec<DnsRecord>,
     pub resources: Vec<DnsRecord>,
 }
@@ -688,8 +727,8 @@ impl DnsPacket {
     pub fn new() -> DnsPacket {
         DnsPacket {
             header: DnsHeader::new(),
-            questions: Vec::new(),
-            answers: Vec::new(),
+            questions: SmallVec::new(),
+            answers: SmallVec::new(),
             authorities: Vec::new(),
             resources: Vec::new(),
         }
@@ -700,7 +739,7 @@ impl DnsPacket {
         result.header.read(buffer)?;
 
         for _ in 0..result.header.questions {
-            let mut question = DnsQuestion::new("".to_string(), QueryType::UNKNOWN(0));
+            let mut question = DnsQuestion::new(String::new(), QueryType::UNKNOWN(0));
             question.read(buffer)?;
             result.questions.push(question);
         }
@@ -723,9 +762,9 @@ impl DnsPacket {
 
     pub fn write(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
         self.header.questions = self.questions.len() as u16;
-        self.header.answers = self.answers.len() as u16;
-        self.header.authoritative_entries = self.authorities.len() as u16;
-        self.header.resource_entries = self.resources.len() as u16;
+        self.header.answers = written_count(&self.answers);
+        self.header.authoritative_entries = written_count(&self.authorities);
+        self.header.resource_entries = written_count(&self.resources);
 
         self.header.write(buffer)?;
 
@@ -745,3 +784,246 @@ impl DnsPacket {
         Ok(())
     }
 }
+
+/// The number of `records` that are written, unknown records are skipped (their data isn't kept).
+fn written_count(records: &[DnsRecord]) -> u16 {
+    records
+        .iter()
+        .filter(|record| !matches!(record, DnsRecord::UNKNOWN { .. }))
+        .count() as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    fn query(name: &str) -> BytePacketBuffer {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 7;
+        packet
+            .questions
+            .push(DnsQuestion::new(name.to_owned(), QueryType::A));
+        let mut buffer = BytePacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.pos = 0;
+        buffer
+    }
+
+    fn response(request: &DnsPacket) -> DnsPacket {
+        let mut response = DnsPacket::new();
+        response.header.id = request.header.id;
+        response.header.response = true;
+        let question = request.questions[0].clone();
+        response.answers.push(DnsRecord::A {
+            domain: question.name.clone(),
+            addr: Ipv4Addr::LOCALHOST,
+            ttl: 0,
+        });
+        response.questions.push(question);
+        response
+    }
+
+    #[tokio::test]
+    async fn names_are_read_lowercased() {
+        let request = DnsPacket::from_buffer(&mut query("Www.Exämple.LOC"))
+            .await
+            .unwrap();
+        assert_eq!(request.questions[0].name, "www.exämple.loc");
+        let mut buffer = BytePacketBuffer::new();
+        response(&request).write(&mut buffer).unwrap();
+        buffer.pos = 0;
+        let parsed = DnsPacket::from_buffer(&mut buffer).await.unwrap();
+        assert_eq!(
+            parsed.answers[..],
+            [DnsRecord::A {
+                domain: "www.exämple.loc".to_owned(),
+                addr: Ipv4Addr::LOCALHOST,
+                ttl: 0,
+            }]
+        );
+    }
+
+    fn received(data: &[u8]) -> BytePacketBuffer {
+        let mut buffer = BytePacketBuffer::new();
+        buffer.buf[..data.len()].copy_from_slice(data);
+        buffer.set_len(data.len());
+        buffer
+    }
+
+    const HEADER: [u8; 12] = [0, 7, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+
+    #[tokio::test]
+    async fn compression_loops_are_rejected() {
+        // the name points at itself
+        let mut request = received(&[&HEADER[..], &[0xC0, 12, 0, 1, 0, 1]].concat());
+        assert!(DnsPacket::from_buffer(&mut request).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn truncated_datagrams_are_rejected() {
+        let name = "api.my-service.loc";
+        let query = query(name);
+        // the labels (a length byte each, instead of the dots), the terminator, type and class
+        let end = HEADER.len() + 1 + name.len() + 1 + 4;
+        for len in [HEADER.len() + 5, end - 1] {
+            let mut request = received(&query.buf[..len]);
+            assert!(DnsPacket::from_buffer(&mut request).await.is_err());
+        }
+        let mut request = received(&query.buf[..end]);
+        assert!(DnsPacket::from_buffer(&mut request).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn parsed_packets_are_written_back() {
+        let mut packet = DnsPacket::new();
+        packet
+            .questions
+            .push(DnsQuestion::new(String::new(), QueryType::NS));
+        packet.answers.push(DnsRecord::UNKNOWN {
+            domain: "a.loc".to_owned(),
+            qtype: 99,
+            data_len: 0,
+            ttl: 0,
+        });
+        packet.resources.push(DnsRecord::A {
+            domain: "a.loc".to_owned(),
+            addr: Ipv4Addr::LOCALHOST,
+            ttl: 0,
+        });
+        let mut buffer = BytePacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        let mut written = received(&buffer.buf[..buffer.pos()]);
+        let parsed = DnsPacket::from_buffer(&mut written).await.unwrap();
+        assert_eq!(parsed.questions[..], packet.questions[..]);
+        assert!(parsed.answers.is_empty());
+        assert_eq!(parsed.resources, packet.resources);
+    }
+
+    /// Parse the datagram `data`, with the number of bytes read.
+    fn parse(data: &[u8]) -> Result<(DnsPacket, usize)> {
+        let mut buffer = received(data);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let packet = runtime.block_on(DnsPacket::from_buffer(&mut buffer))?;
+        Ok((packet, buffer.pos()))
+    }
+
+    fn name() -> impl Strategy<Value = String> {
+        vec("[a-z0-9-]{1,10}", 1..4).prop_map(|labels| labels.join("."))
+    }
+
+    fn header() -> impl Strategy<Value = DnsHeader> {
+        (any::<u16>(), any::<[bool; 8]>(), 0..16u8, 0..6u8).prop_map(
+            |(id, flags, opcode, rescode)| DnsHeader {
+                id,
+                recursion_desired: flags[0],
+                truncated_message: flags[1],
+                authoritative_answer: flags[2],
+                opcode,
+                response: flags[3],
+                rescode: ResultCode::from_num(rescode),
+                checking_disabled: flags[4],
+                authed_data: flags[5],
+                z: flags[6],
+                recursion_available: flags[7],
+                // set when written
+                ..DnsHeader::new()
+            },
+        )
+    }
+
+    fn question() -> impl Strategy<Value = DnsQuestion> {
+        (name(), any::<u16>())
+            .prop_map(|(name, qtype)| DnsQuestion::new(name, QueryType::from_num(qtype)))
+    }
+
+    /// The records that are written (unknown records aren't).
+    fn record() -> impl Strategy<Value = DnsRecord> {
+        prop_oneof![
+            (name(), any::<Ipv4Addr>(), any::<u32>())
+                .prop_map(|(domain, addr, ttl)| DnsRecord::A { domain, addr, ttl }),
+            (name(), any::<Ipv6Addr>(), any::<u32>())
+                .prop_map(|(domain, addr, ttl)| DnsRecord::AAAA { domain, addr, ttl }),
+            (name(), name(), any::<u32>()).prop_map(|(domain, host, ttl)| DnsRecord::NS {
+                domain,
+                host,
+                ttl
+            }),
+            (name(), name(), any::<u32>()).prop_map(|(domain, host, ttl)| DnsRecord::CNAME {
+                domain,
+                host,
+                ttl
+            }),
+            (name(), any::<u16>(), name(), any::<u32>()).prop_map(
+                |(domain, priority, host, ttl)| DnsRecord::MX {
+                    domain,
+                    priority,
+                    host,
+                    ttl,
+                }
+            ),
+            (name(), name(), name(), any::<[u32; 6]>()).prop_map(
+                |(domain, m_name, r_name, [serial, refresh, retry, expire, minimum, ttl])| {
+                    DnsRecord::SOA {
+                        domain,
+                        m_name,
+                        r_name,
+                        serial,
+                        refresh,
+                        retry,
+                        expire,
+                        minimum,
+                        ttl,
+                    }
+                }
+            ),
+        ]
+    }
+
+    fn packet() -> impl Strategy<Value = DnsPacket> {
+        (
+            header(),
+            vec(question(), 0..3),
+            vec(record(), 0..4),
+            vec(record(), 0..3),
+            vec(record(), 0..3),
+        )
+            .prop_map(
+                |(header, questions, answers, authorities, resources)| DnsPacket {
+                    header,
+                    questions: questions.into(),
+                    answers: answers.into(),
+                    authorities,
+                    resources,
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn written_packets_are_parsed_back(mut packet in packet()) {
+            let mut buffer = BytePacketBuffer::new();
+            // packets that don't fit in a datagram aren't written
+            prop_assume!(packet.write(&mut buffer).is_ok());
+            let header = &packet.header;
+            prop_assert_eq!(usize::from(header.questions), packet.questions.len());
+            prop_assert_eq!(usize::from(header.answers), packet.answers.len());
+            prop_assert_eq!(usize::from(header.authoritative_entries), packet.authorities.len());
+            prop_assert_eq!(usize::from(header.resource_entries), packet.resources.len());
+            let (parsed, read) = parse(&buffer.buf[..buffer.pos()]).unwrap();
+            prop_assert_eq!(read, buffer.pos());
+            prop_assert_eq!(parsed, packet);
+        }
+    }
+
+    #[test]
+    fn long_labels_are_not_written() {
+        let mut buffer = BytePacketBuffer::new();
+        assert!(buffer
+            .write_qname(&format!("{}.loc", "a".repeat(64)))
+            .is_err());
+    }
+}
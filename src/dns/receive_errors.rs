@@ -0,0 +1,135 @@
+//! The UDP (IPv4) receive errors of the whole system, sampled while the server runs. Windows doesn't
+//! count the datagrams dropped per socket, so these include every UDP socket's errors (e.g. bursts
+//! overflowing a receive buffer) and are only reported as a system wide counter. macOS counts them
+//! in the UDP statistics of `netstat`.
+
+use crate::prelude::*;
+use std::time::Duration;
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Foundation::NO_ERROR;
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::NetworkManagement::IpHelper::{GetUdpStatisticsEx2, MIB_UDPSTATS2};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Networking::WinSock::AF_INET;
+
+/// How often the receive errors are sampled.
+pub(super) const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The receive errors counted since the server started.
+pub(super) struct ReceiveErrors {
+    /// The last sample of the (wrapping) system counter.
+    last: u32,
+}
+
+impl ReceiveErrors {
+    /// Start counting, `None` when the system counter isn't available.
+    pub(super) fn start() -> Option<Self> {
+        match udp_receive_errors() {
+            Ok(last) => Some(Self { last }),
+            Err(e) => {
+                warn!("Not monitoring the system UDP receive errors: {e:#}");
+                None
+            }
+        }
+    }
+
+    /// The errors since the previous sample.
+    pub(super) fn sample(&mut self) -> u64 {
+        match udp_receive_errors() {
+            Ok(current) => self.update(current),
+            Err(e) => {
+                debug!("Error sampling the system UDP receive errors: {e:#}");
+                0
+            }
+        }
+    }
+
+    fn update(&mut self, current: u32) -> u64 {
+        let errors = current.wrapping_sub(self.last);
+        self.last = current;
+        u64::from(errors)
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn udp_receive_errors() -> Result<u32> {
+    let mut stats = MIB_UDPSTATS2 {
+        dw64InDatagrams: 0,
+        dwNoPorts: 0,
+        dwInErrors: 0,
+        dw64OutDatagrams: 0,
+        dwNumAddrs: 0,
+    };
+    let result = unsafe { GetUdpStatisticsEx2(&raw mut stats, u32::from(AF_INET)) };
+    if result != NO_ERROR {
+        return Err(anyhow!(
+            "GetUdpStatisticsEx2 failed: {}",
+            std::io::Error::from_raw_os_error(i32::try_from(result).unwrap_or(i32::MAX))
+        ));
+    }
+    Ok(stats.dwInErrors)
+}
+
+#[cfg(target_os = "macos")]
+fn udp_receive_errors() -> Result<u32> {
+    let output = std::process::Command::new("netstat")
+        .args(["-s", "-p", "udp"])
+        .output()
+        .context("running netstat")?;
+    if !output.status.success() {
+        return Err(anyhow!("netstat -s -p udp failed ({})", output.status));
+    }
+    Ok(netstat_receive_errors(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// The datagrams dropped because they were malformed or the socket buffer was full, in the UDP
+/// statistics of `netstat -s -p udp`. It wraps like the Windows counter.
+#[cfg(target_os = "macos")]
+#[allow(clippy::cast_possible_truncation)]
+fn netstat_receive_errors(statistics: &str) -> u32 {
+    const ERRORS: [&str; 4] = [
+        "with incomplete header",
+        "with bad data length field",
+        "with bad checksum",
+        "dropped due to full socket buffers",
+    ];
+    let errors: u64 = statistics
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| line.split_once(' '))
+        .filter(|(_, counter)| ERRORS.contains(counter))
+        .filter_map(|(count, _)| count.parse::<u64>().ok())
+        .sum();
+    errors as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_count_the_new_errors_across_wraps() {
+        let mut receive_errors = ReceiveErrors { last: u32::MAX - 1 };
+        assert_eq!(receive_errors.update(u32::MAX - 1), 0);
+        assert_eq!(receive_errors.update(2), 4);
+        assert_eq!(receive_errors.update(5), 3);
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn netstat_counts_the_malformed_and_dropped_datagrams() {
+        let statistics = "udp:
+\t123456 datagrams received
+\t\t0 with incomplete header
+\t\t1 with bad data length field
+\t\t3 with bad checksum
+\t\t12 with no checksum
+\t\t5 dropped due to no socket
+\t\t7 dropped due to full socket buffers
+\t654321 datagrams output
+";
+        assert_eq!(netstat_receive_errors(statistics), 11);
+    }
+}
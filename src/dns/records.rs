@@ -1,17 +1,64 @@
+use super::ImportOutcome;
+use crate::address_aliases::{alias_names, is_alias, AliasAddresses};
 use crate::prelude::*;
+use std::fmt;
 use tokio::fs;
 
 pub type RecordsDB = HashMap<String, Ipv4Addr>;
 
+/// A record to import (or why it's invalid or skipped) with its (1 based) line number.
+pub type ImportLine = (usize, Result<(String, Ipv4Addr), ImportOutcome>);
+
+/// An invalid line in the records file. Line and column are 1 based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The value of a record line: an address, or an alias (e.g. `@tailscale`) resolved when loading
+/// (see [`crate::address_aliases`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RecordValue {
+    Addr(Ipv4Addr),
+    Alias(String),
+}
+
+impl fmt::Display for RecordValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordValue::Addr(ip) => write!(f, "{ip}"),
+            RecordValue::Alias(alias) => write!(f, "{alias}"),
+        }
+    }
+}
+
 /// Load the records from the supplied file path. The format of the file is lines of name to IPv4.
 /// Name must end with .loc. Returns empty [`RecordsDB`] if file does not exist.
 ///
 /// e.g.:
 ///
 /// zero.loc:0.0.0.0
-pub async fn load(file: impl AsRef<Path>, tld: &str) -> Result<RecordsDB> {
+pub async fn load(
+    file: impl AsRef<Path>,
+    tld: &str,
+    aliases: &AliasAddresses,
+) -> Result<RecordsDB> {
     if fs::try_exists(&file).await? {
-        load_from_file(file, tld).await
+        load_from_file(file, tld, aliases).await
     } else {
         debug!("Using empty records");
         Ok(HashMap::new())
@@ -19,61 +66,295 @@ pub async fn load(file: impl AsRef<Path>, tld: &str) -> Result<RecordsDB> {
 }
 
 /// Load the records from the supplied file path. The format of the file is lines of name to IPv4.
-/// Name must end with .loc. Returns error if file does not exist.
+/// Name must end with .loc. Returns error if file does not exist. Records with an alias value point
+/// at its address in `aliases`, they are skipped while the alias has no address.
 ///
 /// e.g.:
 ///
 /// zero.loc:0.0.0.0
-pub async fn load_from_file(file: impl AsRef<Path>, tld: &str) -> Result<RecordsDB> {
+/// laptop.loc:@tailscale
+pub async fn load_from_file(
+    file: impl AsRef<Path>,
+    tld: &str,
+    aliases: &AliasAddresses,
+) -> Result<RecordsDB> {
     debug!("Loading records from file: {}", file.as_ref().display());
     let contents = fs::read_to_string(&file).await?;
     let mut records = HashMap::new();
-    for line in contents.lines() {
+    let mut invalid_tld = vec![];
+    for (index, line) in contents.lines().enumerate() {
         match line {
             "" => (),
             s if s.starts_with('#') => (),
             s => {
-                let (name, ip) = parse_line(s).context(format!("trying to parse '{s}'"))?;
+                let (name, value) = parse_line(s).map_err(|(column, message)| ParseError {
+                    line: index + 1,
+                    column,
+                    message,
+                })?;
+                let ip = match value {
+                    RecordValue::Addr(ip) => ip,
+                    RecordValue::Alias(alias) => {
+                        if let Some(ip) = aliases.get(&alias) {
+                            *ip
+                        } else {
+                            warn!("Ignoring record {name}, {alias} has no address (is the adapter connected?)");
+                            continue;
+                        }
+                    }
+                };
                 if records.contains_key(&name) {
-                    handle_duplicate_hostname(&name, ip, &records)?;
+                    handle_duplicate_hostname(&name, ip, &records).map_err(|message| {
+                        ParseError {
+                            line: index + 1,
+                            column: 1,
+                            message,
+                        }
+                    })?;
                 }
                 if !name.ends_with(tld) {
-                    send_notification(
-                        "Invalid record in records file",
-                        &format!("Invalid TopLevelDomain in: {name}"),
-                    );
+                    warn!("Ignoring record with invalid top level domain: {name}");
+                    invalid_tld.push(name);
                     continue;
                 }
                 records.insert(name, ip);
             }
         }
     }
+    if !invalid_tld.is_empty() {
+        send_warning(
+            "Invalid records in records file",
+            &format!(
+                "Ignored {} record(s) not ending with '{tld}': {}",
+                invalid_tld.len(),
+                invalid_tld.join(", ")
+            ),
+        );
+    }
     Ok(records)
 }
 
-fn parse_line(line: &str) -> Result<(String, Ipv4Addr)> {
+/// Detect a records file written for a different top level domain (e.g. after the TLD was changed
+/// in the configuration) and offer to rewrite the record suffixes. A backup of the original file
+/// is saved next to it.
+pub async fn offer_tld_migration(file: impl AsRef<Path>, tld: &str) -> Result<()> {
+    if !fs::try_exists(&file).await? {
+        return Ok(());
+    }
+    let contents = fs::read_to_string(&file).await?;
+    let Some(previous) = detect_previous_tld(&contents, tld) else {
+        return Ok(());
+    };
+    let msg = format!(
+        concat!(
+            "None of the records in the records file end with the configured top level domain ({}) ",
+            "but they all end with '{}'.\n\nDo you want to rewrite the records to use '{}'? ",
+            "A backup of the records file will be saved."
+        ),
+        tld, previous, tld
+    );
+    if confirm_message("Top Level Domain Changed".to_owned(), msg).await {
+        migrate_tld(file.as_ref(), &contents, &previous, tld).await?;
+    } else {
+        info!("User declined rewriting records from '{previous}' to '{tld}'");
+    }
+    Ok(())
+}
+
+/// Rewrite the records ending with `from` to end with `to` instead (e.g. after changing the top
+/// level domain), saving a backup of the records file first.
+pub async fn rewrite_records_tld(file: impl AsRef<Path>, from: &str, to: &str) -> Result<()> {
+    if !fs::try_exists(&file).await? {
+        return Ok(());
+    }
+    let contents = fs::read_to_string(&file).await?;
+    migrate_tld(file.as_ref(), &contents, from, to).await
+}
+
+async fn migrate_tld(path: &Path, contents: &str, from: &str, to: &str) -> Result<()> {
+    fs::copy(path, backup_path(path)).await?;
+    let (rewritten, count) = rewrite_tld(contents, from, to);
+    fs::write(path, rewritten).await?;
+    info!("Rewrote {count} record(s) from '{from}' to '{to}'");
+    Ok(())
+}
+
+/// Remove all the lines of the supplied hostname from the records file. Returns `true` if any line
+/// was removed.
+pub async fn remove_from_file(file: impl AsRef<Path>, name: &str) -> Result<bool> {
+    if !fs::try_exists(&file).await? {
+        return Ok(false);
+    }
+    let contents = fs::read_to_string(&file).await?;
+    let mut removed = false;
+    let mut out = String::with_capacity(contents.len());
+    for line in contents.lines() {
+        if record_line(line).is_some_and(|(n, _)| n == name) {
+            removed = true;
+        } else {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    if removed {
+        fs::write(&file, out).await?;
+    }
+    Ok(removed)
+}
+
+/// Set the address of the hostname in the records file, replacing any existing lines of it. The
+/// records file is created if it doesn't exist.
+pub async fn add_to_file(file: impl AsRef<Path>, name: &str, ip: Ipv4Addr) -> Result<()> {
+    add_all_to_file(file, &[(name.to_owned(), ip)]).await
+}
+
+/// Set the addresses of all the hostnames in a single write (see [`add_to_file`]).
+pub async fn add_all_to_file(file: impl AsRef<Path>, records: &[(String, Ipv4Addr)]) -> Result<()> {
+    let file = file.as_ref();
+    if !fs::try_exists(file).await? {
+        create_records_file(file)?;
+    }
+    let contents = fs::read_to_string(file).await?;
+    let mut lines: Vec<String> = contents
+        .lines()
+        .filter(|line| {
+            record_line(line).is_none_or(|(n, _)| !records.iter().any(|(name, _)| *name == n))
+        })
+        .map(str::to_owned)
+        .collect();
+    lines.extend(records.iter().map(|(name, ip)| format!("{name}:{ip}")));
+    lines.push(String::new());
+    fs::write(file, lines.join("\n")).await?;
+    Ok(())
+}
+
+/// Returns the common last label (e.g. `.local`) if no record matches `tld` and all records share
+/// the same last label.
+fn detect_previous_tld(contents: &str, tld: &str) -> Option<String> {
+    let mut previous: Option<String> = None;
+    for (name, _) in contents.lines().filter_map(record_line) {
+        if name.ends_with(tld) {
+            return None;
+        }
+        let suffix = &name[name.rfind('.')?..];
+        match &previous {
+            Some(p) if p != suffix => return None,
+            Some(_) => (),
+            None => previous = Some(suffix.to_owned()),
+        }
+    }
+    previous
+}
+
+/// Replace the `from` suffix with `to` in every record line. Returns the new contents and the
+/// number of rewritten records.
+fn rewrite_tld(contents: &str, from: &str, to: &str) -> (String, usize) {
+    let mut count = 0;
+    let mut out = String::with_capacity(contents.len());
+    for line in contents.lines() {
+        match record_line(line) {
+            Some((name, value)) if name.ends_with(from) => {
+                let base = &name[..name.len() - from.len()];
+                out.push_str(base);
+                out.push_str(to);
+                out.push(':');
+                out.push_str(&value.to_string());
+                count += 1;
+            }
+            _ => out.push_str(line),
+        }
+        out.push('\n');
+    }
+    (out, count)
+}
+
+fn record_line(line: &str) -> Option<(String, RecordValue)> {
+    if line.is_empty() || line.starts_with('#') {
+        None
+    } else {
+        parse_line(line).ok()
+    }
+}
+
+/// Parse the record lines (`name:IP`) to import with their (1 based) line numbers. Blank and
+/// comment lines are skipped.
+pub fn import_lines(text: &str) -> Vec<ImportLine> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            let record = match parse_line(line) {
+                Ok((name, RecordValue::Addr(ip))) => Ok((name, ip)),
+                Ok((name, RecordValue::Alias(alias))) => Err(ImportOutcome::Invalid(format!(
+                    "{name}: {alias} records can only be added in the records file"
+                ))),
+                Err((_, message)) => Err(ImportOutcome::Invalid(message)),
+            };
+            (line_number, record)
+        })
+        .collect()
+}
+
+/// Parse a `name:IP` (or `name:@alias`) line. Errors contain the (1 based) column of the problem
+/// and a description.
+fn parse_line(line: &str) -> Result<(String, RecordValue), (usize, String)> {
     debug!("parsing line: {line}");
-    let mut parts = line.splitn(2, ':');
-    let name = parts.next().ok_or(anyhow!("Missing hostname"))?;
-    let ip: Ipv4Addr = parts.next().ok_or(anyhow!("Missing IP"))?.parse()?;
-    Ok((name.to_owned(), ip))
+    let Some((name, ip)) = line.split_once(':') else {
+        return Err((
+            line.chars().count() + 1,
+            format!("Missing IP in '{line}' (expected hostname:IP)"),
+        ));
+    };
+    if ip.starts_with('@') {
+        if !is_alias(ip) {
+            return Err((
+                name.chars().count() + 2,
+                format!("Unknown alias '{ip}' (expected one of {})", alias_names()),
+            ));
+        }
+        return Ok((name.to_owned(), RecordValue::Alias(ip.to_owned())));
+    }
+    let ip = ip
+        .parse()
+        .map_err(|e| (name.chars().count() + 2, format!("Invalid IP '{ip}': {e}")))?;
+    Ok((name.to_owned(), RecordValue::Addr(ip)))
 }
 
-fn handle_duplicate_hostname(name: &str, ip: Ipv4Addr, records: &RecordsDB) -> Result<()> {
+fn handle_duplicate_hostname(name: &str, ip: Ipv4Addr, records: &RecordsDB) -> Result<(), String> {
     let existing_ip = records.get(name).unwrap(); // safe to unwrap because we just checked for existence
     if existing_ip == &ip {
-        send_notification(
+        send_warning(
             "Duplicate hostname in records file",
             &format!("Duplicate hostname: {name} with IP {ip}"),
         );
         Ok(())
     } else {
-        Err(anyhow!(
+        Err(format!(
             "Duplicate hostname ({name}) with different values is not supported!"
         ))
     }
 }
 
+/// Point the user at the invalid line and offer to open the records file to fix it. Runs in the
+/// background so the server keeps serving while the dialog is open.
+pub fn offer_to_fix_records_file(file: PathBuf, error: ParseError) {
+    tokio::spawn(async move {
+        let msg = format!(
+            "Error in {} at line {}, column {}:\n\n{}\n\nOpen the records file to fix it?",
+            file.display(),
+            error.line,
+            error.column,
+            error.message
+        );
+        if confirm_message("Invalid Records File".to_owned(), msg).await {
+            safe_open_records_file(&file).unwrap_or_else(|e| {
+                notify_error!("Error opening records file: {e}");
+            });
+        }
+    });
+}
+
 pub fn safe_open_records_file(f: &PathBuf) -> Result<()> {
     if !f.exists() {
         create_records_file(f)?;
@@ -98,10 +379,69 @@ mod tests {
         let records_contents = "hello.loc:127.0.0.1\nhello.com:127.0.0.1\n";
         let mut file = NamedTempFile::new().unwrap();
         file.write_all(records_contents.as_bytes()).unwrap();
-        let records = load_from_file(file.path(), "loc").await.unwrap();
+        let records = load_from_file(file.path(), "loc", &HashMap::new())
+            .await
+            .unwrap();
         assert!(
             !records.contains_key("hello.com"),
             "hello.com should not be in records"
         );
     }
+
+    #[tokio::test]
+    async fn parse_errors_report_line_and_column() {
+        let check = |contents: &'static str, line, column| async move {
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(contents.as_bytes()).unwrap();
+            let e = load_from_file(file.path(), "loc", &HashMap::new())
+                .await
+                .unwrap_err();
+            let e = e.downcast_ref::<ParseError>().unwrap();
+            assert_eq!((e.line, e.column), (line, column), "{e}");
+        };
+        check("# comment\na.loc:127.0.0.1\nb.loc:127.0.0.x\n", 3, 7).await;
+        check("\na.loc 127.0.0.1\n", 2, 16).await;
+        check("a.loc:127.0.0.1\na.loc:10.0.0.1\n", 2, 1).await;
+    }
+
+    #[tokio::test]
+    async fn alias_records_point_at_the_alias_address() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"a.loc:127.0.0.1\nlaptop.loc:@tailscale\nvm.loc:@vbox-hostonly\n")
+            .unwrap();
+        let tailscale = Ipv4Addr::new(100, 101, 102, 103);
+        let aliases = HashMap::from([("@tailscale".to_owned(), tailscale)]);
+        let records = load_from_file(file.path(), "loc", &aliases).await.unwrap();
+        assert_eq!(records.get("laptop.loc"), Some(&tailscale));
+        assert!(!records.contains_key("vm.loc"));
+        assert!(records.contains_key("a.loc"));
+        let imported = import_lines("laptop.loc:@tailscale");
+        assert!(imported[0].1.is_err());
+        let unknown = parse_line("a.loc:@wifi").unwrap_err();
+        assert_eq!(unknown.0, 7);
+    }
+
+    #[test]
+    fn detect_previous_tld_only_when_no_record_matches() {
+        let changed = "# comment\na.local:127.0.0.1\nb.x.local:10.0.0.1\n";
+        assert_eq!(
+            detect_previous_tld(changed, ".loc"),
+            Some(".local".to_owned())
+        );
+        let partial = "a.loc:127.0.0.1\nb.com:10.0.0.1\n";
+        assert_eq!(detect_previous_tld(partial, ".loc"), None);
+        let mixed = "a.local:127.0.0.1\nb.com:10.0.0.1\n";
+        assert_eq!(detect_previous_tld(mixed, ".loc"), None);
+    }
+
+    #[test]
+    fn rewrite_tld_keeps_comments() {
+        let contents = "# a.local comment\na.local:127.0.0.1\nb.x.local:10.0.0.1";
+        let (rewritten, count) = rewrite_tld(contents, ".local", ".test");
+        assert_eq!(count, 2);
+        assert_eq!(
+            rewritten,
+            "# a.local comment\na.test:127.0.0.1\nb.x.test:10.0.0.1\n"
+        );
+    }
 }
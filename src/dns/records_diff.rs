@@ -0,0 +1,143 @@
+//! What changed in the records file records (including the disabled ones) when they were reloaded
+//! or merged, for the log, the notification and the diagnostics.
+
+use crate::prelude::*;
+use serde_json::{json, Value};
+use std::fmt;
+
+/// Changed records listed in the log, the rest are only counted.
+const MAX_LOGGED_CHANGES: usize = 10;
+
+/// The records added, removed and changed, sorted by name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordsDiff {
+    pub added: Vec<(String, Ipv4Addr)>,
+    pub removed: Vec<(String, Ipv4Addr)>,
+    /// The records pointing at another address: the name, previous and new address.
+    pub changed: Vec<(String, Ipv4Addr, Ipv4Addr)>,
+}
+
+impl RecordsDiff {
+    pub fn between(previous: &HashMap<String, Ipv4Addr>, new: &HashMap<String, Ipv4Addr>) -> Self {
+        let mut diff = Self::default();
+        for (name, addr) in new {
+            match previous.get(name) {
+                None => diff.added.push((name.clone(), *addr)),
+                Some(old) if old != addr => diff.changed.push((name.clone(), *old, *addr)),
+                Some(_) => {}
+            }
+        }
+        diff.removed = previous
+            .iter()
+            .filter(|(name, _)| !new.contains_key(*name))
+            .map(|(name, addr)| (name.clone(), *addr))
+            .collect();
+        diff.added.sort();
+        diff.removed.sort();
+        diff.changed.sort();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// The number of changes, e.g. `2 added, 1 changed`.
+    pub fn summary(&self) -> String {
+        if self.is_empty() {
+            return "no changes".to_owned();
+        }
+        [
+            (self.added.len(), "added"),
+            (self.removed.len(), "removed"),
+            (self.changed.len(), "changed"),
+        ]
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, change)| format!("{count} {change}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+
+    pub fn to_json(&self) -> Value {
+        let record = |(name, addr): &(String, Ipv4Addr)| json!({ "name": name, "addr": addr });
+        json!({
+            "added": self.added.iter().map(record).collect::<Vec<_>>(),
+            "removed": self.removed.iter().map(record).collect::<Vec<_>>(),
+            "changed": self
+                .changed
+                .iter()
+                .map(|(name, from, to)| json!({ "name": name, "from": from, "to": to }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// The summary with the first changed records, e.g. `1 added, 1 changed: +a.loc (10.0.0.1),
+/// b.loc (10.0.0.2 -> 10.0.0.3)`.
+impl fmt::Display for RecordsDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.summary())?;
+        if self.is_empty() {
+            return Ok(());
+        }
+        let mut changes: Vec<_> = self
+            .added
+            .iter()
+            .map(|(name, addr)| format!("+{name} ({addr})"))
+            .chain(
+                self.removed
+                    .iter()
+                    .map(|(name, addr)| format!("-{name} ({addr})")),
+            )
+            .chain(
+                self.changed
+                    .iter()
+                    .map(|(name, from, to)| format!("{name} ({from} -> {to})")),
+            )
+            .collect();
+        if changes.len() > MAX_LOGGED_CHANGES {
+            let more = changes.len() - MAX_LOGGED_CHANGES;
+            changes.truncate(MAX_LOGGED_CHANGES);
+            changes.push(format!("… and {more} more"));
+        }
+        write!(f, ": {}", changes.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_list_the_added_removed_and_changed_records() {
+        let previous = HashMap::from([
+            ("a.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 1)),
+            ("b.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 2)),
+            ("c.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 3)),
+        ]);
+        let new = HashMap::from([
+            ("a.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 1)),
+            ("c.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 4)),
+            ("e.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 5)),
+            ("d.loc".to_owned(), Ipv4Addr::new(10, 0, 0, 6)),
+        ]);
+        let diff = RecordsDiff::between(&previous, &new);
+        assert_eq!(
+            diff.to_string(),
+            "2 added, 1 removed, 1 changed: +d.loc (10.0.0.6), +e.loc (10.0.0.5), \
+-b.loc (10.0.0.2), c.loc (10.0.0.3 -> 10.0.0.4)"
+        );
+        assert_eq!(RecordsDiff::between(&new, &new).to_string(), "no changes");
+    }
+
+    #[test]
+    fn long_diffs_list_only_the_first_changes() {
+        let new = (0..12)
+            .map(|i| (format!("host{i:02}.loc"), Ipv4Addr::new(10, 0, 0, i)))
+            .collect();
+        let diff = RecordsDiff::between(&HashMap::new(), &new).to_string();
+        assert!(diff.starts_with("12 added: +host00.loc (10.0.0.0), "));
+        assert!(diff.ends_with(", +host09.loc (10.0.0.9), … and 2 more"));
+    }
+}
@@ -0,0 +1,299 @@
+//! The query workers: several tasks (one per CPU) receive on the server socket and answer the
+//! queries in parallel from the published [`LookupState`], so a burst of queries isn't answered one
+//! at a time by the task handling the notifications. The answered queries are broadcast as
+//! [`QueryEvent`]s, the stats, recent queries, query log etc. are kept by their subscribers.
+
+use super::clock::Clock;
+use super::protocol::*;
+use super::{LookupState, QueryEvent, ServerStats, TapEvent};
+use crate::prelude::*;
+use failsafe::futures::CircuitBreaker;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, watch, Semaphore};
+use tokio::time::{timeout_at, Instant};
+use tracing::{field, info_span, Instrument, Span};
+
+/// The number of workers: one per CPU.
+pub(super) fn worker_count() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// What a worker answers and reports the queries with, cloned into every worker task.
+#[derive(Clone)]
+pub(super) struct Worker {
+    pub(super) lookup_state_rx: watch::Receiver<Arc<LookupState>>,
+    /// Counts the errors, the answered queries are counted by the server.
+    pub(super) stats_tx: watch::Sender<ServerStats>,
+    pub(super) query_events_tx: broadcast::Sender<QueryEvent>,
+    pub(super) tap_tx: Option<Sender<TapEvent>>,
+    pub(super) clock: Arc<dyn Clock>,
+    pub(super) local_addr: Option<SocketAddr>,
+    /// Queries answered slower than this are logged as warnings.
+    pub(super) slow_query: Option<Duration>,
+    /// Queries not answered by this long after they're received are answered with SERVFAIL.
+    pub(super) query_deadline: Option<Duration>,
+    /// The lookup of this worker running past the deadline (see [`lookup_by`]).
+    pub(super) late_lookup: Arc<Semaphore>,
+    /// Received requests (by all the workers), for their correlation ids.
+    pub(super) requests: Arc<AtomicU64>,
+}
+
+impl Worker {
+    /// Answer the queries received on `socket` until the (shared) circuit breaker trips.
+    pub(super) async fn run(
+        self,
+        socket: Arc<UdpSocket>,
+        circuit_breaker: impl CircuitBreaker,
+    ) -> Result<()> {
+        loop {
+            let mut req_buffer = BytePacketBuffer::new();
+            let received = receive(&socket, &mut req_buffer).await;
+            let span = self.request_span();
+            let handler = self
+                .handle_request(received, &mut req_buffer, &socket)
+                .instrument(span.clone());
+            match circuit_breaker.call(handler).await {
+                Ok(()) => {}
+                Err(failsafe::Error::Inner(e)) => {
+                    let _span = span.enter();
+                    self.stats_tx.send_modify(|stats| stats.errors += 1);
+                    notify_error!(NotificationEvent::ServerError => "DNS server error: {e}");
+                }
+                Err(failsafe::Error::Rejected) => {
+                    error!("Circuit breaker rejected");
+                    return Err(anyhow!(
+                        "Multiple Errors on DNS Server! Quitting! Check the logs!"
+                    ));
+                }
+            }
+        }
+    }
+
+    #[allow(clippy::similar_names)]
+    async fn handle_request(
+        &self,
+        received: std::io::Result<(usize, SocketAddr)>,
+        req_buffer: &mut BytePacketBuffer,
+        socket: &UdpSocket,
+    ) -> Result<()> {
+        let (len, peer) = received?;
+        req_buffer.set_len(len);
+        let started = self.clock.now();
+        let query_time = self.clock.system_time();
+        // the span of the request (see `request_span`)
+        let span = Span::current();
+        span.record("client", field::display(peer));
+        let request = DnsPacket::from_buffer(req_buffer).await?;
+        span.record("id", request.header.id);
+        if let Some(question) = request.questions.first() {
+            span.record("name", question.name.as_str());
+            span.record("qtype", field::debug(question.qtype));
+        }
+        // don't hold the lock of the snapshot while answering
+        let state = self.lookup_state_rx.borrow().clone();
+        let quietly_refused = state.refuses_quietly(&request);
+        let mut response = match self.query_deadline {
+            Some(deadline) => {
+                let lookup = move |request: &DnsPacket| state.lookup(request);
+                lookup_by(started + deadline, &self.late_lookup, &request, lookup).await?
+            }
+            None => state.lookup(&request),
+        };
+        let mut res_buffer = BytePacketBuffer::new();
+        response.write(&mut res_buffer)?;
+        let pos = res_buffer.pos();
+        let data = res_buffer.get_range(0, pos)?;
+        if let Some(tap_tx) = &self.tap_tx {
+            let event = TapEvent {
+                client: peer,
+                server: self.local_addr,
+                query: req_buffer.buf[..len].to_vec(),
+                query_time,
+                response: data.to_vec(),
+                response_time: self.clock.system_time(),
+            };
+            if tap_tx.try_send(event).is_err() {
+                debug!("Dropped the dnstap event of a query");
+            }
+        }
+        socket.send_to(data, peer).await?;
+        if !quietly_refused {
+            let duration = self.clock.now().saturating_duration_since(started);
+            self.record_query(&response, peer, query_time, duration);
+        }
+        Ok(())
+    }
+
+    /// Everything logged while handling a request (parsing, lookup, response and errors) carries a
+    /// short correlation id (`req`) and the query details, so interleaved requests can be told
+    /// apart.
+    fn request_span(&self) -> Span {
+        let request = self.requests.fetch_add(1, Ordering::Relaxed) + 1;
+        info_span!(
+            "query",
+            req = %format_args!("{request:x}"),
+            id = field::Empty,
+            client = field::Empty,
+            name = field::Empty,
+            qtype = field::Empty,
+        )
+    }
+
+    pub(super) fn record_query(
+        &self,
+        response: &DnsPacket,
+        client: SocketAddr,
+        received: SystemTime,
+        duration: Duration,
+    ) {
+        let Some(question) = response.questions.first() else {
+            return;
+        };
+        let answer = response.answers.iter().find_map(|record| match record {
+            DnsRecord::A { addr, .. } => Some(*addr),
+            _ => None,
+        });
+        let event = QueryEvent {
+            name: question.name.clone(),
+            qtype: question.qtype,
+            client,
+            answer,
+            rescode: response.header.rescode,
+            received,
+            duration,
+        };
+        if self
+            .slow_query
+            .is_some_and(|threshold| duration >= threshold)
+        {
+            warn!(
+                "Slow query: {} ({:?}) from {client} answered with {} in {}ms",
+                event.name,
+                event.qtype,
+                answer.map_or_else(|| format!("{:?}", event.rescode), |addr| addr.to_string()),
+                duration.as_millis()
+            );
+        }
+        // no subscribers is not an error
+        let _ = self.query_events_tx.send(event);
+    }
+}
+
+/// Receive a datagram into `buffer`, failing when it's larger than the buffer (Windows fails the
+/// receive itself).
+#[cfg(target_os = "windows")]
+async fn receive(
+    socket: &UdpSocket,
+    buffer: &mut BytePacketBuffer,
+) -> std::io::Result<(usize, SocketAddr)> {
+    socket.recv_from(&mut buffer.buf).await
+}
+
+/// Receive a datagram into `buffer`, failing when it's larger than the buffer. macOS truncates the
+/// larger datagrams silently, so they're received with a byte to spare to tell.
+#[cfg(target_os = "macos")]
+async fn receive(
+    socket: &UdpSocket,
+    buffer: &mut BytePacketBuffer,
+) -> std::io::Result<(usize, SocketAddr)> {
+    let mut datagram = [0; 513];
+    let (len, peer) = socket.recv_from(&mut datagram).await?;
+    if len > buffer.buf.len() {
+        return Err(std::io::Error::other(format!(
+            "datagram from {peer} is larger than {} bytes",
+            buffer.buf.len()
+        )));
+    }
+    buffer.buf[..len].copy_from_slice(&datagram[..len]);
+    Ok((len, peer))
+}
+
+/// Answer `request` with `lookup` in a blocking task, or with SERVFAIL when it isn't answered by
+/// the `deadline`. The lookup can't be interrupted, its late answer is dropped. Each worker has at
+/// most one lookup running past the deadline (holding the `late_lookup` permit): until it's done
+/// the queries fail right away, rather than taking another thread each.
+async fn lookup_by(
+    deadline: Instant,
+    late_lookup: &Arc<Semaphore>,
+    request: &DnsPacket,
+    lookup: impl FnOnce(&DnsPacket) -> DnsPacket + Send + 'static,
+) -> Result<DnsPacket> {
+    if let Ok(permit) = late_lookup.clone().try_acquire_owned() {
+        let span = Span::current();
+        let query = request.clone();
+        let answer = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            span.in_scope(|| lookup(&query))
+        });
+        if let Ok(response) = timeout_at(deadline, answer).await {
+            return Ok(response?);
+        }
+        warn!("The query wasn't answered in time, answering with SERVFAIL");
+    } else {
+        warn!("A previous lookup is still running past the deadline, answering with SERVFAIL");
+    }
+    let mut response = DnsPacket::new();
+    response.header.response = true;
+    response.header.id = request.header.id;
+    response.header.opcode = request.header.opcode;
+    response.header.recursion_desired = request.header.recursion_desired;
+    response.header.rescode = ResultCode::SERVFAIL;
+    response
+        .questions
+        .extend(request.questions.first().cloned());
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn queries_not_answered_by_the_deadline_fail() {
+        let late_lookup = Arc::new(Semaphore::new(1));
+        let mut request = DnsPacket::new();
+        request.header.id = 7;
+        request.header.opcode = 2;
+        request
+            .questions
+            .push(DnsQuestion::new("a.loc".to_owned(), QueryType::A));
+        let answered = |request: &DnsPacket| {
+            let mut response = request.clone();
+            response.header.response = true;
+            response
+        };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let response = lookup_by(deadline, &late_lookup, &request, answered)
+            .await
+            .unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+
+        let stuck = move |request: &DnsPacket| {
+            std::thread::sleep(Duration::from_secs(1));
+            answered(request)
+        };
+        let deadline = Instant::now() + Duration::from_millis(10);
+        let response = lookup_by(deadline, &late_lookup, &request, stuck)
+            .await
+            .unwrap();
+        assert_eq!(response.header.id, 7);
+        assert_eq!(response.header.opcode, 2);
+        assert!(response.header.response);
+        assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+        assert_eq!(response.questions, request.questions);
+        assert!(response.answers.is_empty());
+
+        // fails right away while the stuck lookup still runs
+        let started = Instant::now();
+        let deadline = started + Duration::from_secs(5);
+        let response = lookup_by(deadline, &late_lookup, &request, answered)
+            .await
+            .unwrap();
+        assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
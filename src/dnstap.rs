@@ -0,0 +1,332 @@
+//! The dnstap output: every answered query as a dnstap `AUTH_QUERY` and `AUTH_RESPONSE` message
+//! (protobuf, see <https://dnstap.info>) in Frame Streams framing, so standard tools (`dnstap-read`,
+//! `fstrm_capture`, Wireshark etc.) can consume the traffic like from any other server. The
+//! messages are written to a file or streamed to a Frame Streams collector over TCP (reconnecting
+//! when the connection is lost, queries answered while disconnected are not sent).
+
+use crate::app_config::DnstapConfig;
+use crate::dns::TapEvent;
+use crate::prelude::*;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const CONTENT_TYPE: &[u8] = b"protobuf:dnstap.Dnstap";
+const EVENTS_CAPACITY: usize = 1024;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+/// Collectors answer the handshake with a small control frame, anything larger is bogus.
+const MAX_CONTROL_FRAME_LENGTH: u32 = 512;
+
+// Frame Streams control frame types
+const CONTROL_ACCEPT: u32 = 0x01;
+const CONTROL_START: u32 = 0x02;
+const CONTROL_STOP: u32 = 0x03;
+const CONTROL_READY: u32 = 0x04;
+const CONTROL_FIELD_CONTENT_TYPE: u32 = 0x01;
+
+// dnstap.proto field numbers and values
+const DNSTAP_VERSION: u32 = 2;
+const DNSTAP_MESSAGE: u32 = 14;
+const DNSTAP_TYPE: u32 = 15;
+const DNSTAP_TYPE_MESSAGE: u64 = 1;
+const MESSAGE_TYPE: u32 = 1;
+const MESSAGE_SOCKET_FAMILY: u32 = 2;
+const MESSAGE_SOCKET_PROTOCOL: u32 = 3;
+const MESSAGE_QUERY_ADDRESS: u32 = 4;
+const MESSAGE_RESPONSE_ADDRESS: u32 = 5;
+const MESSAGE_QUERY_PORT: u32 = 6;
+const MESSAGE_RESPONSE_PORT: u32 = 7;
+const MESSAGE_QUERY_TIME_SEC: u32 = 8;
+const MESSAGE_QUERY_TIME_NSEC: u32 = 9;
+const MESSAGE_QUERY_MESSAGE: u32 = 10;
+const MESSAGE_RESPONSE_TIME_SEC: u32 = 12;
+const MESSAGE_RESPONSE_TIME_NSEC: u32 = 13;
+const MESSAGE_RESPONSE_MESSAGE: u32 = 14;
+const AUTH_QUERY: u64 = 1;
+const AUTH_RESPONSE: u64 = 2;
+const FAMILY_INET: u64 = 1;
+const FAMILY_INET6: u64 = 2;
+const PROTOCOL_UDP: u64 = 1;
+
+/// Where the dnstap output goes.
+enum Output {
+    File(PathBuf),
+    Tcp(SocketAddr),
+}
+
+/// Start tapping the server's queries. Does nothing if dnstap is disabled or misconfigured (the
+/// error is reported).
+pub fn start_dnstap(dns_server: &mut DnsServer, config: &DnstapConfig, logging_dir: &Path) {
+    if !config.enabled {
+        return;
+    }
+    let output = match (&config.file, config.address) {
+        (Some(_), Some(_)) => {
+            notify_error!("Invalid dnstap configuration: set either file or address, not both");
+            return;
+        }
+        (Some(file), None) => Output::File(file.clone()),
+        (None, Some(address)) => Output::Tcp(address),
+        (None, None) => Output::File(logging_dir.join("dnstap.fstrm")),
+    };
+    let (tx, rx) = mpsc::channel(EVENTS_CAPACITY);
+    dns_server.set_tap(tx);
+    tokio::spawn(async move {
+        let result = match output {
+            Output::File(path) => write_file(&path, rx).await,
+            Output::Tcp(address) => stream(address, rx).await,
+        };
+        if let Err(e) = result {
+            notify_error!("dnstap output error: {e:#}");
+        }
+    });
+}
+
+async fn write_file(path: &Path, mut events: Receiver<TapEvent>) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)
+        .await
+        .with_context(|| format!("creating {}", path.display()))?;
+    info!("Writing dnstap to {}", path.display());
+    file.write_all(&control_frame(CONTROL_START, true)).await?;
+    while let Some(event) = events.recv().await {
+        write_event(&mut file, &event).await?;
+        file.flush().await?;
+    }
+    file.write_all(&control_frame(CONTROL_STOP, false)).await?;
+    Ok(())
+}
+
+async fn stream(address: SocketAddr, mut events: Receiver<TapEvent>) -> Result<()> {
+    loop {
+        match connect(address).await {
+            Ok(mut connection) => {
+                info!("Streaming dnstap to {address}");
+                loop {
+                    let Some(event) = events.recv().await else {
+                        // best effort, the server is stopping
+                        let _ = connection
+                            .write_all(&control_frame(CONTROL_STOP, false))
+                            .await;
+                        return Ok(());
+                    };
+                    if let Err(e) = write_event(&mut connection, &event).await {
+                        warn!("dnstap connection to {address} lost: {e}");
+                        break;
+                    }
+                }
+            }
+            Err(e) => warn!("Error connecting to the dnstap collector at {address}: {e:#}"),
+        }
+        tokio::time::sleep(RECONNECT_INTERVAL).await;
+        // don't send the backlog of queries answered while disconnected
+        while events.try_recv().is_ok() {}
+    }
+}
+
+/// Connect and perform the bidirectional Frame Streams handshake (`READY`, `ACCEPT`, `START`).
+async fn connect(address: SocketAddr) -> Result<TcpStream> {
+    let mut connection = TcpStream::connect(address).await?;
+    connection
+        .write_all(&control_frame(CONTROL_READY, true))
+        .await?;
+    let escape = connection.read_u32().await?;
+    let length = connection.read_u32().await?;
+    if escape != 0 || length > MAX_CONTROL_FRAME_LENGTH {
+        return Err(anyhow!("The collector sent an invalid control frame"));
+    }
+    let mut frame = vec![0; length as usize];
+    connection.read_exact(&mut frame).await?;
+    if !frame.starts_with(&CONTROL_ACCEPT.to_be_bytes()) {
+        return Err(anyhow!("The collector didn't accept the dnstap stream"));
+    }
+    connection
+        .write_all(&control_frame(CONTROL_START, true))
+        .await?;
+    Ok(connection)
+}
+
+async fn write_event(writer: &mut (impl AsyncWrite + Unpin), event: &TapEvent) -> Result<()> {
+    for message in [query_message(event), response_message(event)] {
+        let frame = dnstap_frame(&message);
+        writer.write_all(&frame).await?;
+    }
+    Ok(())
+}
+
+/// A Frame Streams control frame, `START` and `READY` carry the dnstap content type.
+fn control_frame(control_type: u32, with_content_type: bool) -> Vec<u8> {
+    let mut payload = control_type.to_be_bytes().to_vec();
+    if with_content_type {
+        payload.extend(CONTROL_FIELD_CONTENT_TYPE.to_be_bytes());
+        payload.extend(frame_length(CONTENT_TYPE));
+        payload.extend(CONTENT_TYPE);
+    }
+    let mut frame = 0u32.to_be_bytes().to_vec();
+    frame.extend(frame_length(&payload));
+    frame.extend(payload);
+    frame
+}
+
+/// A Frame Streams data frame with a `Dnstap` message wrapping `message`.
+fn dnstap_frame(message: &[u8]) -> Vec<u8> {
+    let mut dnstap = vec![];
+    put_bytes(&mut dnstap, DNSTAP_VERSION, APP_VERSION.as_bytes());
+    put_bytes(&mut dnstap, DNSTAP_MESSAGE, message);
+    put_varint_field(&mut dnstap, DNSTAP_TYPE, DNSTAP_TYPE_MESSAGE);
+    let mut frame = frame_length(&dnstap).to_vec();
+    frame.extend(dnstap);
+    frame
+}
+
+fn query_message(event: &TapEvent) -> Vec<u8> {
+    let mut message = message_header(event, AUTH_QUERY);
+    put_time(
+        &mut message,
+        MESSAGE_QUERY_TIME_SEC,
+        MESSAGE_QUERY_TIME_NSEC,
+        event.query_time,
+    );
+    put_bytes(&mut message, MESSAGE_QUERY_MESSAGE, &event.query);
+    message
+}
+
+fn response_message(event: &TapEvent) -> Vec<u8> {
+    let mut message = message_header(event, AUTH_RESPONSE);
+    put_time(
+        &mut message,
+        MESSAGE_QUERY_TIME_SEC,
+        MESSAGE_QUERY_TIME_NSEC,
+        event.query_time,
+    );
+    put_time(
+        &mut message,
+        MESSAGE_RESPONSE_TIME_SEC,
+        MESSAGE_RESPONSE_TIME_NSEC,
+        event.response_time,
+    );
+    put_bytes(&mut message, MESSAGE_RESPONSE_MESSAGE, &event.response);
+    message
+}
+
+/// The `Message` fields shared by queries and responses: the type and the addresses.
+fn message_header(event: &TapEvent, message_type: u64) -> Vec<u8> {
+    let mut message = vec![];
+    put_varint_field(&mut message, MESSAGE_TYPE, message_type);
+    let family = if event.client.is_ipv4() {
+        FAMILY_INET
+    } else {
+        FAMILY_INET6
+    };
+    put_varint_field(&mut message, MESSAGE_SOCKET_FAMILY, family);
+    put_varint_field(&mut message, MESSAGE_SOCKET_PROTOCOL, PROTOCOL_UDP);
+    put_address(&mut message, MESSAGE_QUERY_ADDRESS, event.client);
+    if let Some(server) = event.server {
+        put_address(&mut message, MESSAGE_RESPONSE_ADDRESS, server);
+    }
+    put_varint_field(
+        &mut message,
+        MESSAGE_QUERY_PORT,
+        u64::from(event.client.port()),
+    );
+    if let Some(server) = event.server {
+        put_varint_field(
+            &mut message,
+            MESSAGE_RESPONSE_PORT,
+            u64::from(server.port()),
+        );
+    }
+    message
+}
+
+fn frame_length(data: &[u8]) -> [u8; 4] {
+    u32::try_from(data.len()).unwrap_or(u32::MAX).to_be_bytes()
+}
+
+fn put_address(buf: &mut Vec<u8>, field: u32, addr: SocketAddr) {
+    match addr {
+        SocketAddr::V4(addr) => put_bytes(buf, field, &addr.ip().octets()),
+        SocketAddr::V6(addr) => put_bytes(buf, field, &addr.ip().octets()),
+    }
+}
+
+fn put_time(buf: &mut Vec<u8>, sec_field: u32, nsec_field: u32, time: SystemTime) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    put_varint_field(buf, sec_field, since_epoch.as_secs());
+    // fixed32
+    put_varint(buf, u64::from(nsec_field << 3 | 5));
+    buf.extend(since_epoch.subsec_nanos().to_le_bytes());
+}
+
+fn put_varint_field(buf: &mut Vec<u8>, field: u32, value: u64) {
+    put_varint(buf, u64::from(field << 3));
+    put_varint(buf, value);
+}
+
+fn put_bytes(buf: &mut Vec<u8>, field: u32, value: &[u8]) {
+    put_varint(buf, u64::from(field << 3 | 2));
+    put_varint(buf, value.len() as u64);
+    buf.extend(value);
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        #[allow(clippy::cast_possible_truncation)]
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_frames() {
+        let start = control_frame(CONTROL_START, true);
+        let mut expected = vec![0, 0, 0, 0, 0, 0, 0, 34, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 22];
+        expected.extend(CONTENT_TYPE);
+        assert_eq!(start, expected);
+        assert_eq!(
+            control_frame(CONTROL_STOP, false),
+            vec![0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 3]
+        );
+    }
+
+    #[test]
+    fn query_messages() {
+        let event = TapEvent {
+            client: "127.0.0.1:50000".parse().unwrap(),
+            server: Some("127.0.0.1:53".parse().unwrap()),
+            query: vec![0xab, 0xcd],
+            query_time: UNIX_EPOCH + Duration::new(1_700_000_000, 5),
+            response: vec![0xef],
+            response_time: UNIX_EPOCH + Duration::new(1_700_000_001, 0),
+        };
+        let mut expected = vec![
+            0x08, 1, // type AUTH_QUERY
+            0x10, 1, // socket family INET
+            0x18, 1, // socket protocol UDP
+            0x22, 4, 127, 0, 0, 1, // query address
+            0x2a, 4, 127, 0, 0, 1, // response address
+            0x30, 0xd0, 0x86, 0x03, // query port 50000
+            0x38, 53, // response port
+            0x40, 0x80, 0xe2, 0xcf, 0xaa, 0x06, // query time sec
+            0x4d, 5, 0, 0, 0, // query time nsec
+        ];
+        expected.extend([0x52, 2, 0xab, 0xcd]);
+        assert_eq!(query_message(&event), expected);
+        let response = response_message(&event);
+        assert_eq!(response[1], 2, "AUTH_RESPONSE");
+        assert!(response.ends_with(&[0x72, 1, 0xef]));
+        let frame = dnstap_frame(&query_message(&event));
+        assert_eq!(frame[..4], frame_length(&frame[4..]));
+        assert!(frame.ends_with(&[0x78, 1]), "Dnstap type MESSAGE");
+    }
+}
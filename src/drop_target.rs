@@ -0,0 +1,96 @@
+use crate::prelude::*;
+use windows_strings::HSTRING;
+use windows_sys::Win32::Foundation::{HWND, RECT};
+use windows_sys::Win32::Graphics::Gdi::{
+    DrawTextW, FillRect, FrameRect, GetDC, GetSysColor, GetSysColorBrush, ReleaseDC, SetBkColor,
+    SetTextColor, COLOR_HIGHLIGHT, COLOR_WINDOW, COLOR_WINDOWTEXT, DT_CENTER, DT_SINGLELINE,
+    DT_VCENTER,
+};
+use windows_sys::Win32::UI::WindowsAndMessaging::GetClientRect;
+use winit::dpi::LogicalSize;
+use winit::event_loop::ActiveEventLoop;
+use winit::raw_window_handle::{HasWindowHandle, RawWindowHandle};
+use winit::window::{Window, WindowId, WindowLevel};
+
+const TITLE: &str = "Merge Records";
+const LABEL: &str = "Drop a records file here to merge";
+const SIZE: LogicalSize<f64> = LogicalSize::new(260.0, 90.0);
+
+/// A small borderless, always on top window. Records files dropped on it are merged (after
+/// confirmation) into the records file.
+pub struct DropTarget {
+    window: Window,
+    hovered: bool,
+}
+
+impl DropTarget {
+    pub fn open(event_loop: &ActiveEventLoop) -> Result<Self> {
+        let attributes = Window::default_attributes()
+            .with_title(TITLE)
+            .with_inner_size(SIZE)
+            .with_resizable(false)
+            .with_decorations(false)
+            .with_window_level(WindowLevel::AlwaysOnTop);
+        let window = event_loop
+            .create_window(attributes)
+            .context("creating drop target window")?;
+        debug!("Opened merge drop target");
+        Ok(Self {
+            window,
+            hovered: false,
+        })
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    /// The window has no decorations, so it's moved by dragging its contents.
+    pub fn start_drag(&self) {
+        self.window
+            .drag_window()
+            .unwrap_or_else(|e| debug!("Error dragging drop target: {e}"));
+    }
+
+    /// Draw the label framed by a border, highlighted while a file is dragged over the window.
+    pub fn paint(&self) {
+        let Some(hwnd) = self.hwnd() else {
+            return;
+        };
+        let label = HSTRING::from(LABEL);
+        let frame = if self.hovered {
+            COLOR_HIGHLIGHT
+        } else {
+            COLOR_WINDOWTEXT
+        };
+        unsafe {
+            let mut rect = RECT::default();
+            GetClientRect(hwnd, &raw mut rect);
+            let hdc = GetDC(hwnd);
+            FillRect(hdc, &raw const rect, GetSysColorBrush(COLOR_WINDOW));
+            FrameRect(hdc, &raw const rect, GetSysColorBrush(frame));
+            SetBkColor(hdc, GetSysColor(COLOR_WINDOW));
+            SetTextColor(hdc, GetSysColor(COLOR_WINDOWTEXT));
+            DrawTextW(
+                hdc,
+                label.as_ptr(),
+                -1,
+                &raw mut rect,
+                DT_CENTER | DT_VCENTER | DT_SINGLELINE,
+            );
+            ReleaseDC(hwnd, hdc);
+        }
+    }
+
+    pub fn set_hovered(&mut self, hovered: bool) {
+        self.hovered = hovered;
+        self.window.request_redraw();
+    }
+
+    fn hwnd(&self) -> Option<HWND> {
+        match self.window.window_handle().ok()?.as_raw() {
+            RawWindowHandle::Win32(handle) => Some(handle.hwnd.get() as HWND),
+            _ => None,
+        }
+    }
+}
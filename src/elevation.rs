@@ -0,0 +1,177 @@
+//! Operations that need administrator rights (NRPT and firewall rules, binding a port another
+//! process holds exclusively). The app runs unelevated, so these either run in an elevated
+//! PowerShell or relaunch the app as administrator, after explaining why Windows is about to ask.
+
+use crate::prelude::*;
+use std::fmt;
+use std::mem::size_of;
+use std::os::windows::process::CommandExt;
+use std::process::Command;
+use std::ptr::{from_mut, null};
+use windows_strings::HSTRING;
+use windows_sys::core::w;
+use windows_sys::Win32::Foundation::{CloseHandle, ERROR_CANCELLED, HANDLE};
+use windows_sys::Win32::Security::{
+    GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY,
+};
+use windows_sys::Win32::System::Threading::{
+    GetCurrentProcess, GetExitCodeProcess, OpenProcessToken, WaitForSingleObject, CREATE_NO_WINDOW,
+    INFINITE,
+};
+use windows_sys::Win32::UI::Shell::{
+    ShellExecuteExW, SEE_MASK_NOASYNC, SEE_MASK_NOCLOSEPROCESS, SHELLEXECUTEINFOW,
+};
+use windows_sys::Win32::UI::WindowsAndMessaging::{SW_HIDE, SW_SHOWNORMAL};
+
+const POWERSHELL: &str = "powershell.exe";
+/// Tells the relaunched instance to wait for this one to exit (see [`crate::cli::Cli`]).
+pub const RELAUNCHED_ARG: &str = "--relaunched";
+
+/// The user declined the elevation (our prompt or the UAC one). Not an error worth reporting.
+#[derive(Debug)]
+pub struct ElevationDeclined;
+
+impl fmt::Display for ElevationDeclined {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "administrator rights were declined")
+    }
+}
+
+impl std::error::Error for ElevationDeclined {}
+
+/// Does the app run as administrator?
+pub fn is_elevated() -> bool {
+    let mut token: HANDLE = std::ptr::null_mut();
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &raw mut token) } == 0 {
+        return false;
+    }
+    let mut elevation = TOKEN_ELEVATION::default();
+    let mut size = 0;
+    let ok = unsafe {
+        GetTokenInformation(
+            token,
+            TokenElevation,
+            from_mut(&mut elevation).cast(),
+            u32::try_from(size_of::<TOKEN_ELEVATION>()).unwrap_or_default(),
+            &raw mut size,
+        )
+    };
+    unsafe { CloseHandle(token) };
+    ok != 0 && elevation.TokenIsElevated != 0
+}
+
+/// Run a PowerShell script as administrator and wait for it. Unless the app is already elevated,
+/// the user is first told what `action` (e.g. "Adding an NRPT rule") needs the rights for, then
+/// Windows shows the UAC prompt. Fails with [`ElevationDeclined`] if either is declined.
+pub async fn run_powershell_as_admin(action: &str, script: String) -> Result<()> {
+    if is_elevated() {
+        return run_powershell(script).await.map(drop);
+    }
+    let msg = format!(
+        "{action} requires administrator rights. Windows will ask you to allow PowerShell to make \
+changes to your device.\n\nContinue?"
+    );
+    if !confirm_message("Administrator Rights Required".to_owned(), msg).await {
+        return Err(ElevationDeclined.into());
+    }
+    tokio::task::spawn_blocking(move || run_powershell_elevated_blocking(&script)).await?
+}
+
+/// Run a PowerShell script as administrator and wait for it, after the UAC prompt unless the app is
+/// already elevated. Fails with [`ElevationDeclined`] if the prompt is declined.
+pub fn run_powershell_elevated_blocking(script: &str) -> Result<()> {
+    if is_elevated() {
+        return run_powershell_blocking(script).map(drop);
+    }
+    let parameters = format!("-NoProfile -NonInteractive -Command \"{script}\"");
+    debug!("Running elevated: {POWERSHELL} {parameters}");
+    match shell_execute_as_admin(POWERSHELL, &parameters, SW_HIDE, true)? {
+        Some(0) | None => Ok(()),
+        Some(code) => Err(anyhow!("the elevated PowerShell failed (exit code {code})")),
+    }
+}
+
+/// Run a PowerShell script (unelevated) and return its output.
+pub async fn run_powershell(script: String) -> Result<String> {
+    tokio::task::spawn_blocking(move || run_powershell_blocking(&script)).await?
+}
+
+pub fn run_powershell_blocking(script: &str) -> Result<String> {
+    debug!("Running: {POWERSHELL} {script}");
+    let output = Command::new(POWERSHELL)
+        .args(["-NoProfile", "-NonInteractive", "-Command", script])
+        .creation_flags(CREATE_NO_WINDOW)
+        .output()
+        .context("running PowerShell")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "PowerShell failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Start a new instance of the app as administrator (after the UAC prompt). The caller should exit
+/// right away, the new instance waits for it to release the instance lock.
+pub fn relaunch_as_admin() -> Result<()> {
+    let exe = std::env::current_exe().context("finding the executable")?;
+    let parameters = std::iter::once(RELAUNCHED_ARG.to_owned())
+        .chain(instance_args())
+        .collect::<Vec<_>>()
+        .join(" ");
+    shell_execute_as_admin(&exe.to_string_lossy(), &parameters, SW_SHOWNORMAL, false)?;
+    info!("Relaunched as administrator");
+    Ok(())
+}
+
+/// Is `error` a failure that running as administrator would fix?
+pub fn needs_elevation(error: &Error) -> bool {
+    !is_elevated()
+        && error
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied)
+}
+
+/// Start `program` with the `runas` verb. Returns the exit code if `wait`.
+fn shell_execute_as_admin(
+    program: &str,
+    parameters: &str,
+    show: i32,
+    wait: bool,
+) -> Result<Option<u32>> {
+    let program = HSTRING::from(program);
+    let parameters = HSTRING::from(parameters);
+    let mut info = SHELLEXECUTEINFOW {
+        cbSize: u32::try_from(size_of::<SHELLEXECUTEINFOW>()).unwrap_or(u32::MAX),
+        fMask: SEE_MASK_NOCLOSEPROCESS | SEE_MASK_NOASYNC,
+        lpVerb: w!("runas"),
+        lpFile: program.as_ptr(),
+        lpParameters: parameters.as_ptr(),
+        lpDirectory: null(),
+        nShow: show,
+        ..Default::default()
+    };
+    if unsafe { ShellExecuteExW(&raw mut info) } == 0 {
+        let error = std::io::Error::last_os_error();
+        if error.raw_os_error() == i32::try_from(ERROR_CANCELLED).ok() {
+            return Err(ElevationDeclined.into());
+        }
+        return Err(error).context("starting an elevated process");
+    }
+    let mut exit_code = None;
+    let mut code = 0;
+    if wait {
+        unsafe { WaitForSingleObject(info.hProcess, INFINITE) };
+        if unsafe { GetExitCodeProcess(info.hProcess, &raw mut code) } != 0 {
+            exit_code = Some(code);
+        }
+    }
+    let error = std::io::Error::last_os_error();
+    unsafe { CloseHandle(info.hProcess) };
+    if wait && exit_code.is_none() {
+        return Err(error).context("getting the exit code of the elevated process");
+    }
+    Ok(exit_code)
+}
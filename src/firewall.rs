@@ -0,0 +1,83 @@
+//! The Windows Firewall rule for LAN mode: when the server listens on a LAN address, Windows blocks
+//! the queries of the other devices unless an inbound rule allows them. The rule is scoped to the
+//! app, the DNS port and the local subnet on private and domain networks.
+
+use crate::elevation::{run_powershell, run_powershell_as_admin};
+use crate::prelude::*;
+
+/// Does the firewall rule exist? Doesn't require elevation.
+pub async fn firewall_rule_exists() -> Result<bool> {
+    let output = run_powershell(format!(
+        "@(Get-NetFirewallRule -Group '{}' -ErrorAction SilentlyContinue).Count",
+        instance_app_name()
+    ))
+    .await
+    .context("running Get-NetFirewallRule")?;
+    let count: u32 = output
+        .trim()
+        .parse()
+        .context("parsing the number of firewall rules")?;
+    Ok(count > 0)
+}
+
+/// Add (or replace) the rule allowing queries to `address:port` (asks for administrator rights).
+pub async fn add_firewall_rule(address: Ipv4Addr, port: u16) -> Result<()> {
+    let program = std::env::current_exe().context("finding the executable")?;
+    let script = add_rule_script(address, port, &program);
+    run_powershell_as_admin("Adding a Windows Firewall rule for LAN queries", script).await?;
+    info!("Added a firewall rule allowing DNS queries to {address}:{port} from the local subnet");
+    Ok(())
+}
+
+/// Remove the rule (asks for administrator rights).
+pub async fn remove_firewall_rule() -> Result<()> {
+    run_powershell_as_admin(
+        "Removing the Windows Firewall rule for LAN queries",
+        remove_rule_script(),
+    )
+    .await?;
+    info!("Removed the firewall rule for LAN queries");
+    Ok(())
+}
+
+fn add_rule_script(address: Ipv4Addr, port: u16, program: &Path) -> String {
+    let local_address = if address.is_unspecified() {
+        "Any".to_owned()
+    } else {
+        address.to_string()
+    };
+    let program = program.display().to_string().replace('\'', "''");
+    let group = instance_app_name();
+    format!(
+        "{}; New-NetFirewallRule -DisplayName '{group} (LAN queries)' -Group '{group}' -Direction Inbound -Action Allow -Protocol UDP -LocalAddress {local_address} -LocalPort {port} -RemoteAddress LocalSubnet -Profile Domain,Private -Program '{program}' -ErrorAction Stop | Out-Null",
+        remove_rule_script()
+    )
+}
+
+fn remove_rule_script() -> String {
+    format!(
+        "Remove-NetFirewallRule -Group '{}' -ErrorAction SilentlyContinue",
+        instance_app_name()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rule_scripts() {
+        let script = add_rule_script(
+            Ipv4Addr::new(192, 168, 1, 5),
+            53,
+            Path::new(r"C:\Users\O'Brien\dot-local-dns.exe"),
+        );
+        assert!(script.starts_with("Remove-NetFirewallRule -Group 'DotLocal-DNS'"));
+        assert!(
+            script.contains(" -LocalAddress 192.168.1.5 -LocalPort 53 -RemoteAddress LocalSubnet ")
+        );
+        assert!(script.contains(r" -Program 'C:\Users\O''Brien\dot-local-dns.exe' "));
+        let any = add_rule_script(Ipv4Addr::UNSPECIFIED, 5353, Path::new("dns.exe"));
+        assert!(any.contains(" -LocalAddress Any -LocalPort 5353 "));
+    }
+}
@@ -0,0 +1,79 @@
+//! `--install`: set the app up from the downloaded zip without an installer. The executables are
+//! copied to a stable per-user location (the zip may be deleted), the default configuration is
+//! created, the app is set to start at login and the installed copy is started.
+
+use crate::elevation::RELAUNCHED_ARG;
+use crate::prelude::*;
+use crate::provision::load_config;
+use std::process::Command;
+
+/// The command line client, installed next to the app when it's next to the running executable.
+const CLIENT_FILE_NAME: &str = "dlocal.exe";
+
+/// Install the running executable and start the installed app. The caller should exit right away,
+/// the installed app waits for it to release the instance lock.
+pub fn install() -> Result<()> {
+    let source = std::env::current_exe().context("finding the executable")?;
+    let dir = install_dir()?;
+    fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    let installed = copy_to(&source, &dir)?;
+    if let Some(client) = source.parent().map(|parent| parent.join(CLIENT_FILE_NAME)) {
+        if client.exists() {
+            copy_to(&client, &dir)?;
+        }
+    }
+    let mut app_config = load_config()?;
+    let auto_launch = mk_auto_launch_for(
+        &installed,
+        app_config.start_at_login_method,
+        &app_config.login_start.args,
+    )?;
+    let startup = match auto_launch.enable() {
+        Ok(()) => {
+            app_config.set_start_at_login(true, ChangeSource::Application)?;
+            "It will start at login."
+        }
+        Err(e) => {
+            warn!("Error enabling start at login: {e:#}");
+            "Enable Startup at Login from the tray menu."
+        }
+    };
+    Command::new(&installed)
+        .arg(RELAUNCHED_ARG)
+        .args(instance_args())
+        .spawn()
+        .with_context(|| format!("starting {}", installed.display()))?;
+    let msg = format!(
+        "{APP_NAME} was installed in {}. {startup}\n\nThe configuration is in {}.",
+        dir.display(),
+        app_config.config_dir().display()
+    );
+    println!("{msg}");
+    send_notification(&format!("{APP_NAME} Installed"), &msg);
+    Ok(())
+}
+
+/// `%LOCALAPPDATA%\Programs\DotLocal-DNS`, where per-user installers put programs.
+fn install_dir() -> Result<PathBuf> {
+    let local = dirs::data_local_dir().context("Could not find the local app data directory")?;
+    Ok(local.join("Programs").join(APP_NAME))
+}
+
+/// Copy `file` into `dir` (unless it's already there). Returns the installed path.
+fn copy_to(file: &Path, dir: &Path) -> Result<PathBuf> {
+    let name = file
+        .file_name()
+        .context("the executable has no file name")?;
+    let target = dir.join(name);
+    if target != file {
+        fs::copy(file, &target).with_context(|| {
+            format!(
+                "copying {} to {} (is the installed app running?)",
+                file.display(),
+                target.display()
+            )
+        })?;
+        info!("Installed {}", target.display());
+    }
+    Ok(target)
+}
@@ -0,0 +1,127 @@
+//! Only one instance of the app (of every `--instance` name) runs in a session. Starting another one
+//! forwards its command (e.g. `dot-local-dns add nas.loc 192.168.1.10`) to the running instance
+//! instead of failing to bind the DNS port.
+
+use crate::cli::Command;
+use crate::control::client::Client;
+use crate::control::ControlServer;
+use crate::prelude::*;
+use serde_json::Value;
+#[cfg(target_os = "windows")]
+use std::io;
+#[cfg(target_os = "windows")]
+use std::ptr::null;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+#[cfg(target_os = "windows")]
+use windows_strings::HSTRING;
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Foundation::{CloseHandle, GetLastError, ERROR_ALREADY_EXISTS, HANDLE};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::System::Threading::CreateMutexW;
+
+const LOCK_NAME: &str = "dev.babysnakes.dot-local-dns";
+const RELAUNCH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Held for the lifetime of the running instance.
+#[cfg(target_os = "windows")]
+pub struct InstanceLock(HANDLE);
+
+/// Held for the lifetime of the running instance (the lock is released when the file is closed,
+/// also when the process is killed).
+#[cfg(target_os = "macos")]
+pub struct InstanceLock {
+    _file: File,
+}
+
+/// The name of the lock of this instance.
+fn lock_name() -> String {
+    match instance() {
+        Some(instance) => format!("{LOCK_NAME}.{instance}"),
+        None => LOCK_NAME.to_owned(),
+    }
+}
+
+impl InstanceLock {
+    /// Returns `None` if another instance of the app holds the lock.
+    #[cfg(target_os = "windows")]
+    pub fn acquire() -> Result<Option<Self>> {
+        let name = format!(r"Local\{}", lock_name());
+        let handle = unsafe { CreateMutexW(null(), 0, HSTRING::from(name).as_ptr()) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error()).context("creating the instance mutex");
+        }
+        if unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+            unsafe { CloseHandle(handle) };
+            return Ok(None);
+        }
+        Ok(Some(Self(handle)))
+    }
+
+    /// Returns `None` if another instance of the app holds the lock.
+    #[cfg(target_os = "macos")]
+    pub fn acquire() -> Result<Option<Self>> {
+        let path = std::env::temp_dir().join(format!("{}.lock", lock_name()));
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(Self { _file: file })),
+            Err(fs::TryLockError::WouldBlock) => Ok(None),
+            Err(fs::TryLockError::Error(e)) => Err(e).context("locking the instance lock file"),
+        }
+    }
+
+    /// Like [`Self::acquire`], but waits up to `timeout` for the other instance to exit.
+    pub async fn acquire_within(timeout: Duration) -> Result<Option<Self>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let lock = Self::acquire()?;
+            if lock.is_some() || Instant::now() >= deadline {
+                return Ok(lock);
+            }
+            sleep(RELAUNCH_POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// Run the command in the running instance (over the control pipe).
+pub async fn forward_to_running_instance(command: Option<Command>) -> Result<()> {
+    let Some(command) = command else {
+        info_message(
+            APP_NAME.to_owned(),
+            format!(
+                "{} is already running, use the tray icon to control it.",
+                instance_app_name()
+            ),
+        );
+        return Ok(());
+    };
+    let mut client = Client::connect(instance()).await?;
+    client
+        .call::<Value>(command.method())
+        .await
+        .context("running the command in the running instance")?;
+    send_notification(APP_NAME, &command.done());
+    Ok(())
+}
+
+/// Run the command given on the command line of the (just started) instance.
+pub async fn run_command(control: ControlServer, command: Command) {
+    match control.call(command.method()).await {
+        Ok(_) => send_notification(APP_NAME, &command.done()),
+        Err(e) => {
+            notify_error!("Error running the command line command: {e:#}");
+        }
+    }
+}
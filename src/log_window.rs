@@ -0,0 +1,182 @@
+//! A window following the log (see [`LogTail`]): the entries of the active log file matching the
+//! level and search term, refreshed while it's open. New entries scroll into view, unless the log
+//! was scrolled up to read older ones.
+
+use crate::dialogs::{TemplateWriter, EDIT_CLASS};
+use crate::logging::LogTail;
+use crate::prelude::*;
+use std::ptr::null_mut;
+use windows_strings::HSTRING;
+use windows_sys::Win32::Foundation::{HWND, LPARAM, RECT, WPARAM};
+use windows_sys::Win32::UI::Controls::{
+    EM_GETFIRSTVISIBLELINE, EM_LINESCROLL, EM_SCROLLCARET, EM_SETLIMITTEXT, EM_SETSEL,
+};
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::SetFocus;
+use windows_sys::Win32::UI::WindowsAndMessaging::{
+    DialogBoxIndirectParamW, EndDialog, GetClientRect, GetDlgItem, GetScrollInfo,
+    GetWindowLongPtrW, GetWindowTextLengthW, KillTimer, MoveWindow, SendMessageW, SetTimer,
+    SetWindowLongPtrW, SetWindowTextW, DLGTEMPLATE, DS_CENTER, DS_SETFONT, ES_AUTOHSCROLL,
+    ES_AUTOVSCROLL, ES_MULTILINE, ES_READONLY, GWLP_USERDATA, IDCANCEL, SB_VERT, SCROLLINFO,
+    SIF_ALL, WM_COMMAND, WM_INITDIALOG, WM_SIZE, WM_TIMER, WS_BORDER, WS_CAPTION, WS_HSCROLL,
+    WS_MAXIMIZEBOX, WS_MINIMIZEBOX, WS_POPUP, WS_SYSMENU, WS_THICKFRAME, WS_VSCROLL,
+};
+
+const FONT: &str = "Consolas";
+const FONT_SIZE: u16 = 9;
+const LOG_ID: u16 = 100;
+const REFRESH_TIMER_ID: usize = 1;
+const REFRESH_INTERVAL_MS: u32 = 1000;
+
+/// Open a log window following `tail`. It runs on a blocking thread, the runtime and event loop
+/// keep running while it's open.
+pub fn show_log_window(mut tail: LogTail) {
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = tail.poll().context("viewing logs") {
+            error!("Error: {e:#}");
+            notify_failure(&format!("Error: {e:#}"));
+            return;
+        }
+        let template = window_template(&format!("{APP_NAME} Log ({})", tail.description()));
+        let result = unsafe {
+            DialogBoxIndirectParamW(
+                null_mut(),
+                template.as_ptr().cast::<DLGTEMPLATE>(),
+                null_mut(),
+                Some(window_proc),
+                (&raw mut tail) as LPARAM,
+            )
+        };
+        if result == -1 {
+            error!(
+                "Error showing the log window: {}",
+                Error::from(std::io::Error::last_os_error())
+            );
+        }
+    });
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> isize {
+    match msg {
+        WM_INITDIALOG => unsafe {
+            SetWindowLongPtrW(hwnd, GWLP_USERDATA, lparam);
+            let log = GetDlgItem(hwnd, i32::from(LOG_ID));
+            SendMessageW(log, EM_SETLIMITTEXT, 0, 0); // as much text as the control can hold
+            resize_log(hwnd);
+            show_entries(log, &*(lparam as *const LogTail), true);
+            SetTimer(hwnd, REFRESH_TIMER_ID, REFRESH_INTERVAL_MS, None);
+            SetFocus(log);
+            0 // the focus is set, without selecting the whole log
+        },
+        WM_TIMER => unsafe {
+            let tail = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut LogTail;
+            let Some(tail) = tail.as_mut() else {
+                return 0;
+            };
+            match tail.poll() {
+                Ok(true) => {
+                    let log = GetDlgItem(hwnd, i32::from(LOG_ID));
+                    show_entries(log, tail, scrolled_to_end(log));
+                }
+                Ok(false) => {}
+                Err(e) => debug!("Error following the log: {e:#}"),
+            }
+            1
+        },
+        WM_SIZE => {
+            unsafe { resize_log(hwnd) };
+            1
+        }
+        WM_COMMAND if i32::try_from(wparam & 0xFFFF).unwrap_or_default() == IDCANCEL => unsafe {
+            KillTimer(hwnd, REFRESH_TIMER_ID);
+            EndDialog(hwnd, IDCANCEL as isize);
+            1
+        },
+        _ => 0,
+    }
+}
+
+/// Replace the text of the `log` control with the entries, scrolled to the end when `follow`ing
+/// the log, otherwise to the lines that were shown.
+unsafe fn show_entries(log: HWND, tail: &LogTail, follow: bool) {
+    let first_line = unsafe { SendMessageW(log, EM_GETFIRSTVISIBLELINE, 0, 0) };
+    let text = tail.text().replace('\n', "\r\n");
+    unsafe { SetWindowTextW(log, HSTRING::from(text).as_ptr()) };
+    if follow {
+        let end = unsafe { GetWindowTextLengthW(log) };
+        let end = usize::try_from(end).unwrap_or_default();
+        unsafe {
+            SendMessageW(log, EM_SETSEL, end, end.cast_signed());
+            SendMessageW(log, EM_SCROLLCARET, 0, 0);
+        }
+    } else {
+        unsafe { SendMessageW(log, EM_LINESCROLL, 0, first_line) };
+    }
+}
+
+/// Is the end of the log in view (or all of it, without a scroll bar)?
+unsafe fn scrolled_to_end(log: HWND) -> bool {
+    let mut info = SCROLLINFO {
+        cbSize: u32::try_from(size_of::<SCROLLINFO>()).unwrap_or_default(),
+        fMask: SIF_ALL,
+        ..Default::default()
+    };
+    if unsafe { GetScrollInfo(log, SB_VERT, &raw mut info) } == 0 {
+        return true;
+    }
+    let page = i32::try_from(info.nPage).unwrap_or(i32::MAX);
+    info.nPos.saturating_add(page) > info.nMax
+}
+
+/// The log control fills the window.
+unsafe fn resize_log(hwnd: HWND) {
+    let mut rect = RECT::default();
+    unsafe {
+        GetClientRect(hwnd, &raw mut rect);
+        MoveWindow(
+            GetDlgItem(hwnd, i32::from(LOG_ID)),
+            0,
+            0,
+            rect.right,
+            rect.bottom,
+            1,
+        );
+    }
+}
+
+/// A resizable window with a read-only multiline text box. Sizes are in dialog units.
+fn window_template(title: &str) -> Vec<u32> {
+    let mut t = TemplateWriter::default();
+    t.dword(
+        WS_POPUP
+            | WS_CAPTION
+            | WS_SYSMENU
+            | WS_THICKFRAME
+            | WS_MINIMIZEBOX
+            | WS_MAXIMIZEBOX
+            | (DS_CENTER | DS_SETFONT).cast_unsigned(),
+    );
+    t.dword(0); // no extended style
+    t.word(1); // number of items
+    t.rect(0, 0, 480, 280);
+    t.word(0); // no menu
+    t.word(0); // default dialog class
+    t.text(title);
+    t.word(FONT_SIZE);
+    t.text(FONT);
+    t.item(
+        WS_BORDER
+            | WS_VSCROLL
+            | WS_HSCROLL
+            | (ES_MULTILINE | ES_READONLY | ES_AUTOVSCROLL | ES_AUTOHSCROLL).cast_unsigned(),
+        (0, 0, 480, 280),
+        LOG_ID,
+        EDIT_CLASS,
+        "",
+    );
+    t.finish()
+}
@@ -1,23 +1,564 @@
+use crate::app_config::{LogFormat, LogRotationConfig, RotationCriterion, RotationNaming};
 use crate::prelude::*;
-use flexi_logger::{detailed_format, Cleanup, Criterion, FileSpec, Logger, Naming};
+use chrono::{Local, SecondsFormat};
+use flexi_logger::writers::{ArcFileLogWriter, FileLogWriter, FileLogWriterHandle};
+use flexi_logger::{Age, Cleanup, Criterion, FileSpec, Naming};
+use serde_json::{json, Map, Value};
+#[cfg(any(target_os = "windows", test))]
+use std::collections::VecDeque;
+use std::fmt;
+#[cfg(any(target_os = "windows", test))]
+use std::io::{Read, Seek, SeekFrom};
+use std::str::FromStr;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::layer::{self, SubscriberExt};
+use tracing_subscriber::registry::{LookupSpan, Scope};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
 
-pub fn configure_logging(log_level: &str, logging_dir: &PathBuf) -> Result<()> {
+const LOG_BASENAME: &str = "application";
+#[cfg(any(target_os = "windows", test))]
+const MAX_LOG_VIEW_ENTRIES: usize = 2000;
+
+/// Keeps the log file open until it's dropped (at exit), then the log is flushed and closed.
+pub struct LogGuard {
+    _file: Option<FileLogWriterHandle>,
+}
+
+/// Release builds log to a rotating file, `to_stderr` also writes the log to stderr (see
+/// `--console`). Records of the `log` crate (used by some dependencies) are logged too. The
+/// `log_level` is a level (`info`) or a spec with per-module levels (see [`log_filter`]).
+pub fn configure_logging(
+    log_level: &str,
+    log_format: LogFormat,
+    rotation: &LogRotationConfig,
+    logging_dir: &Path,
+    to_stderr: bool,
+) -> Result<LogGuard> {
+    let filter = log_filter(log_level)?;
     if cfg!(debug_assertions) {
-        Logger::try_with_str(log_level)?.start()?;
+        subscriber(filter, log_format, std::io::stderr).try_init()?;
+        return Ok(LogGuard { _file: None });
+    }
+    let (file, handle) = rotating_file_writer(logging_dir, LOG_BASENAME, rotation, false)?;
+    let file = move || file.clone();
+    if to_stderr {
+        subscriber(filter, log_format, file.and(std::io::stderr)).try_init()?;
+    } else {
+        subscriber(filter, log_format, file).try_init()?;
+    }
+    Ok(LogGuard {
+        _file: Some(handle),
+    })
+}
+
+/// Parse a log spec: comma separated levels of modules (`module=level`) and the default level of
+/// the other modules, e.g. `info, dot_local_dns::dns=trace`.
+fn log_filter(spec: &str) -> Result<Targets> {
+    let directives: Vec<_> = spec
+        .split(',')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .collect();
+    Targets::from_str(&directives.join(",")).with_context(|| format!("Invalid log level: {spec}"))
+}
+
+/// A log file in the logs directory rotated by the configured policy. The current file of a
+/// `direct` log is `<basename>.log`, otherwise `<basename>_rCURRENT.log`.
+pub fn rotating_file_writer(
+    logging_dir: &Path,
+    basename: &str,
+    rotation: &LogRotationConfig,
+    direct: bool,
+) -> Result<(ArcFileLogWriter, FileLogWriterHandle)> {
+    let max_size = rotation.max_size_mb.max(1) * 1_000_000;
+    let criterion = match rotation.criterion {
+        RotationCriterion::Size => Criterion::Size(max_size),
+        RotationCriterion::Daily => Criterion::AgeOrSize(Age::Day, max_size),
+        RotationCriterion::Hourly => Criterion::AgeOrSize(Age::Hour, max_size),
+    };
+    let naming = match (rotation.naming, direct) {
+        (RotationNaming::Numbers, false) => Naming::Numbers,
+        (RotationNaming::Numbers, true) => Naming::NumbersDirect,
+        (RotationNaming::Timestamps, false) => Naming::Timestamps,
+        (RotationNaming::Timestamps, true) => Naming::TimestampsDirect,
+    };
+    let cleanup = if rotation.compress {
+        Cleanup::KeepCompressedFiles(rotation.keep_files)
     } else {
-        Logger::try_with_str(log_level)?
-            .log_to_file(
-                FileSpec::default()
-                    .directory(logging_dir)
-                    .basename("application"),
-            )
-            .rotate(
-                Criterion::Size(10_000_000),
-                Naming::Numbers,
-                Cleanup::KeepLogFiles(7),
-            )
-            .format(detailed_format)
-            .start()?;
-    }
-    Ok(())
+        Cleanup::KeepLogFiles(rotation.keep_files)
+    };
+    let writer = FileLogWriter::builder(
+        FileSpec::default()
+            .directory(logging_dir)
+            .basename(basename),
+    )
+    .rotate(criterion, naming, cleanup)
+    .try_build_with_handle()?;
+    Ok(writer)
+}
+
+fn subscriber<W>(filter: Targets, format: LogFormat, writer: W) -> impl Subscriber + Send + Sync
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(false);
+    let (span_fields, layer) = match format {
+        LogFormat::Text => (
+            None,
+            layer
+                .with_timer(Timestamp)
+                .with_file(true)
+                .with_line_number(true)
+                .boxed(),
+        ),
+        LogFormat::Json => (Some(SpanFields), layer.event_format(JsonFormat).boxed()),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(span_fields)
+        .with(layer)
+}
+
+/// `[2025-01-01 10:00:00.000000 +02:00]`, the text format starts with the timestamp and level
+/// (see [`entry_level`]).
+struct Timestamp;
+
+impl FormatTime for Timestamp {
+    fn format_time(&self, w: &mut Writer<'_>) -> fmt::Result {
+        write!(w, "[{}]", Local::now().format("%Y-%m-%d %H:%M:%S%.6f %:z"))
+    }
+}
+
+/// Formats the event as a JSON object on a single line (`timestamp`, `level`, `module`, `file`,
+/// `line`, `message` and the `fields` of the event and its spans, if any).
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        // records of the log crate carry their metadata in fields
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        let mut fields = Fields::default();
+        for span in ctx.event_scope().into_iter().flat_map(Scope::from_root) {
+            if let Some(span_fields) = span.extensions().get::<Fields>() {
+                fields.0.extend(span_fields.0.clone());
+            }
+        }
+        event.record(&mut fields);
+        let message = fields.0.remove("message").unwrap_or_default();
+        let mut line = json!({
+            "timestamp": Local::now().to_rfc3339_opts(SecondsFormat::Micros, false),
+            "level": metadata.level().as_str(),
+            "module": metadata.module_path().unwrap_or_else(|| metadata.target()),
+            "file": metadata.file(),
+            "line": metadata.line(),
+            "message": message,
+        });
+        if !fields.0.is_empty() {
+            line["fields"] = fields.0.into();
+        }
+        writeln!(writer, "{line}")
+    }
+}
+
+/// Keeps the fields of every span for [`JsonFormat`] (the text format formats them itself).
+struct SpanFields;
+
+impl<S> Layer<S> for SpanFields
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: layer::Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: layer::Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Fields(Map<String, Value>);
+
+impl Fields {
+    fn insert(&mut self, field: &Field, value: Value) {
+        // the metadata of log crate records (see `normalized_metadata`)
+        if !field.name().starts_with("log.") {
+            self.0.insert(field.name().to_owned(), value);
+        }
+    }
+}
+
+impl Visit for Fields {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{value:?}").into());
+    }
+}
+
+/// Follows the active log file for the log window: keeps its most recent entries matching the level
+/// and search term, reading only what was appended since the last [`LogTail::poll`], and starts
+/// over when the log rotates.
+#[cfg(any(target_os = "windows", test))]
+pub struct LogTail {
+    logging_dir: PathBuf,
+    max_level: Level,
+    /// Lowercase, like the entries it's matched against.
+    search: Option<String>,
+    file: Option<PathBuf>,
+    position: u64,
+    /// The bytes read after the last complete line.
+    partial: Vec<u8>,
+    /// The last entry read, it may still get continuation lines.
+    last: Option<(Option<Level>, String)>,
+    entries: VecDeque<String>,
+}
+
+#[cfg(any(target_os = "windows", test))]
+impl LogTail {
+    pub fn new(logging_dir: &Path, max_level: Level, search: Option<&str>) -> Self {
+        Self {
+            logging_dir: logging_dir.to_owned(),
+            max_level,
+            search: search.map(str::to_lowercase),
+            file: None,
+            position: 0,
+            partial: vec![],
+            last: None,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// The filter, e.g. `level: INFO and above, matching: nas`.
+    pub fn description(&self) -> String {
+        let search = self
+            .search
+            .as_ref()
+            .map(|s| format!(", matching: {s}"))
+            .unwrap_or_default();
+        format!("level: {} and above{search}", self.max_level)
+    }
+
+    /// Read what was appended to the active log file since the last poll (all of it the first
+    /// time). Returns whether there are new lines.
+    pub fn poll(&mut self) -> Result<bool> {
+        let log_file = active_log_file(&self.logging_dir)?;
+        let len = fs::metadata(&log_file)
+            .with_context(|| format!("reading log file: {}", log_file.display()))?
+            .len();
+        if self.file.as_ref() != Some(&log_file) || len < self.position {
+            self.complete_last();
+            self.file = Some(log_file.clone());
+            self.position = 0;
+            self.partial.clear();
+        }
+        if len == self.position {
+            return Ok(false);
+        }
+        let mut file = File::open(&log_file)
+            .with_context(|| format!("reading log file: {}", log_file.display()))?;
+        file.seek(SeekFrom::Start(self.position))?;
+        let read = file
+            .take(len - self.position)
+            .read_to_end(&mut self.partial)?;
+        self.position += u64::try_from(read).unwrap_or_default();
+        let Some(end) = self.partial.iter().rposition(|&b| b == b'\n') else {
+            return Ok(false);
+        };
+        let lines: Vec<u8> = self.partial.drain(..=end).collect();
+        for line in String::from_utf8_lossy(&lines).lines() {
+            self.add_line(line);
+        }
+        Ok(true)
+    }
+
+    /// The matching entries, oldest first.
+    pub fn text(&self) -> String {
+        let last = self
+            .last
+            .as_ref()
+            .filter(|(level, entry)| self.matches(*level, entry))
+            .map(|(_, entry)| entry.as_str());
+        self.entries
+            .iter()
+            .map(String::as_str)
+            .chain(last)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Lines without a level belong to the previous entry.
+    fn add_line(&mut self, line: &str) {
+        match (entry_level(line), &mut self.last) {
+            (None, Some((_, entry))) => {
+                entry.push('\n');
+                entry.push_str(line);
+            }
+            (level, _) => {
+                self.complete_last();
+                self.last = Some((level, line.to_owned()));
+            }
+        }
+    }
+
+    fn complete_last(&mut self) {
+        if let Some((level, entry)) = self.last.take() {
+            if self.matches(level, &entry) {
+                self.entries.push_back(entry);
+                if self.entries.len() > MAX_LOG_VIEW_ENTRIES {
+                    self.entries.pop_front();
+                }
+            }
+        }
+    }
+
+    fn matches(&self, level: Option<Level>, entry: &str) -> bool {
+        entry_matches(level, entry, self.max_level, self.search.as_deref())
+    }
+}
+
+/// The last `count` entries of the active log file at `max_level` and above (e.g. the recent
+/// warnings and errors for a diagnostics dump).
+pub fn recent_log_entries(
+    logging_dir: &Path,
+    max_level: Level,
+    count: usize,
+) -> Result<Vec<String>> {
+    let log_file = active_log_file(logging_dir)?;
+    let contents = fs::read_to_string(&log_file)
+        .with_context(|| format!("reading log file: {}", log_file.display()))?;
+    let mut entries = filter_entries(&contents, max_level, None);
+    entries.drain(..entries.len().saturating_sub(count));
+    Ok(entries)
+}
+
+/// The most recently modified application log file (the query log is skipped).
+fn active_log_file(logging_dir: &Path) -> Result<PathBuf> {
+    fs::read_dir(logging_dir)
+        .with_context(|| format!("reading logs directory: {}", logging_dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(LOG_BASENAME))
+        })
+        .max_by_key(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .ok_or_else(|| anyhow!("No log files in {}", logging_dir.display()))
+}
+
+/// Split the log into entries (lines without a level belong to the previous entry) and keep the
+/// ones matching the level and search term (case insensitive).
+fn filter_entries(contents: &str, max_level: Level, search: Option<&str>) -> Vec<String> {
+    let mut entries: Vec<(Option<Level>, String)> = vec![];
+    for line in contents.lines() {
+        match (entry_level(line), entries.last_mut()) {
+            (None, Some((_, entry))) => {
+                entry.push('\n');
+                entry.push_str(line);
+            }
+            (level, _) => entries.push((level, line.to_owned())),
+        }
+    }
+    let search = search.map(str::to_lowercase);
+    entries
+        .into_iter()
+        .filter(|(level, entry)| entry_matches(*level, entry, max_level, search.as_deref()))
+        .map(|(_, entry)| entry)
+        .collect()
+}
+
+/// Is the entry at `max_level` or above and does it contain the (lowercase) search term?
+fn entry_matches(
+    level: Option<Level>,
+    entry: &str,
+    max_level: Level,
+    search: Option<&str>,
+) -> bool {
+    level.is_some_and(|l| l <= max_level) && search.is_none_or(|s| entry.to_lowercase().contains(s))
+}
+
+/// The level of a line in the text format (`[timestamp] LEVEL ...`) or the JSON format.
+fn entry_level(line: &str) -> Option<Level> {
+    if line.starts_with('{') {
+        let entry: serde_json::Value = serde_json::from_str(line).ok()?;
+        return Level::from_str(entry["level"].as_str()?).ok();
+    }
+    let rest = line.strip_prefix('[')?;
+    let (_, rest) = rest.split_once("] ")?;
+    let level = rest.split_whitespace().next()?;
+    Level::from_str(level).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    const LOG: &str = "\
+[2025-01-01 10:00:00.000000 +02:00] INFO [dot_local_dns::dns] src/dns/mod.rs:1: Loaded 3 records
+[2025-01-01 10:00:01.000000 +02:00] WARN [dot_local_dns::dns::records] src/dns/records.rs:2: Invalid record: nas.com
+[2025-01-01 10:00:02.000000 +02:00] ERROR [dot_local_dns::dns] src/dns/mod.rs:3: Error reloading records
+  caused by: file not found
+[2025-01-01 10:00:03.000000 +02:00] DEBUG [dot_local_dns::dns] src/dns/mod.rs:4: Lookup nas.loc";
+
+    #[test]
+    fn filter_entries_by_level_and_search() {
+        let warnings = filter_entries(LOG, Level::WARN, None);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[1].ends_with("caused by: file not found"));
+        assert_eq!(filter_entries(LOG, Level::TRACE, None).len(), 4);
+        let nas = filter_entries(LOG, Level::TRACE, Some("NAS"));
+        assert_eq!(nas.len(), 2);
+        assert!(nas[0].contains("Invalid record"));
+        assert_eq!(filter_entries(LOG, Level::ERROR, Some("records")).len(), 1);
+    }
+
+    #[test]
+    fn log_tail_follows_appended_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(format!("{LOG_BASENAME}_rCURRENT.log"));
+        let (first, rest) = LOG.split_once("  caused by").unwrap();
+        fs::write(&path, first).unwrap();
+        let mut tail = LogTail::new(dir.path(), Level::WARN, None);
+        assert!(tail.poll().unwrap());
+        assert_eq!(tail.text().lines().count(), 2);
+        assert!(!tail.poll().unwrap());
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "  caused by{rest}").unwrap();
+        assert!(tail.poll().unwrap());
+        let text = tail.text();
+        assert!(text.ends_with("caused by: file not found"), "{text}");
+        assert!(!text.contains("Lookup nas.loc"));
+        fs::write(&path, "").unwrap();
+        assert!(!tail.poll().unwrap());
+        assert_eq!(tail.text(), text);
+        let mut errors = LogTail::new(dir.path(), Level::ERROR, Some("RECORDS"));
+        fs::write(&path, LOG).unwrap();
+        errors.poll().unwrap();
+        assert!(errors.text().starts_with("[2025-01-01 10:00:02"));
+        assert_eq!(
+            errors.description(),
+            "level: ERROR and above, matching: records"
+        );
+    }
+
+    #[test]
+    fn log_specs() {
+        let filter = log_filter("info, dot_local_dns::dns=trace ,").unwrap();
+        assert!(filter.would_enable("dot_local_dns::dns::records", &Level::TRACE));
+        assert!(filter.would_enable("dot_local_dns::tray_app", &Level::INFO));
+        assert!(!filter.would_enable("dot_local_dns::tray_app", &Level::DEBUG));
+        assert!(log_filter("warn")
+            .unwrap()
+            .would_enable("hyper", &Level::WARN));
+        assert!(log_filter("info, dot_local_dns=loud").is_err());
+    }
+
+    #[test]
+    fn text_lines_start_with_timestamp_and_level() {
+        let (buffer, subscriber) = capture(LogFormat::Text);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("query", id = 7, name = "nas.loc");
+            span.in_scope(|| info!("Answered"));
+        });
+        let output = buffer.contents();
+        let line = output.lines().next().unwrap();
+        assert_eq!(entry_level(line), Some(Level::INFO));
+        assert!(line.contains("query{id=7 name=\"nas.loc\"}"), "{line}");
+        assert!(line.ends_with("Answered"), "{line}");
+    }
+
+    #[test]
+    fn json_lines_include_span_fields() {
+        let (buffer, subscriber) = capture(LogFormat::Json);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("query", id = 7, name = "nas.loc");
+            span.in_scope(|| info!(records = 3, "Loaded {} records", 3));
+            debug!("filtered out");
+        });
+        let output = buffer.contents();
+        assert_eq!(output.lines().count(), 1);
+        let line = output.lines().next().unwrap();
+        let entry: Value = serde_json::from_str(line).unwrap();
+        assert_eq!(entry["level"], "INFO");
+        assert_eq!(entry["module"], "dot_local_dns::logging::tests");
+        assert_eq!(entry["message"], "Loaded 3 records");
+        assert_eq!(
+            entry["fields"],
+            json!({"id": 7, "name": "nas.loc", "records": 3})
+        );
+        assert!(entry["timestamp"].is_string());
+        assert_eq!(entry_level(line), Some(Level::INFO));
+        let log = format!("{line}\n{}", LOG.lines().next().unwrap());
+        assert_eq!(filter_entries(&log, Level::TRACE, Some("loaded")).len(), 2);
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Buffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capture(format: LogFormat) -> (Buffer, impl Subscriber + Send + Sync) {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = subscriber(log_filter("info").unwrap(), format, move || writer.clone());
+        (buffer, subscriber)
+    }
 }
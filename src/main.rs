@@ -2,24 +2,88 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::enum_glob_use)]
 
+#[cfg(target_os = "windows")]
+mod adapter_dns;
+mod address_aliases;
 mod app_config;
+mod audit;
+mod auto_pause;
 mod autolaunch_manager;
+#[cfg(target_os = "windows")]
+mod bundle;
+mod cli;
+mod control;
+mod crash_report;
+#[cfg(target_os = "windows")]
+mod dialogs;
 mod dns;
+mod dnstap;
+#[cfg(target_os = "windows")]
+mod drop_target;
+#[cfg(target_os = "windows")]
+mod elevation;
+#[cfg(target_os = "windows")]
+mod firewall;
+#[cfg(target_os = "windows")]
+mod install;
+mod instance;
+#[cfg(target_os = "windows")]
+mod log_window;
 mod logging;
+#[cfg(target_os = "macos")]
+mod menu_bar;
+mod notifier;
+#[cfg(target_os = "windows")]
+mod nrpt;
+#[cfg(target_os = "windows")]
+mod perf_counters;
+mod provision;
+mod query_log;
+mod resolver_check;
+#[cfg(target_os = "macos")]
+mod resolver_file;
 mod shared;
+mod stats_summary;
+mod sync;
+#[cfg(target_os = "windows")]
+mod theme;
+#[cfg(target_os = "windows")]
 mod tray_app;
+#[cfg(target_os = "windows")]
+mod uninstall;
+#[cfg(target_os = "windows")]
+mod updates;
 
 mod prelude {
     pub(crate) use crate::app_config::AppConfig;
+    #[cfg(target_os = "windows")]
+    pub(crate) use crate::audit::history_path;
+    pub(crate) use crate::audit::ChangeSource;
+    #[cfg(target_os = "windows")]
+    pub(crate) use crate::autolaunch_manager::mk_auto_launch_for;
     pub(crate) use crate::autolaunch_manager::{mk_auto_launch, AutoLaunchManager};
-    pub(crate) use crate::dns::safe_open_records_file;
-    pub(crate) use crate::dns::DnsServer;
-    pub(crate) use crate::dns::Notification::{self, ARecordQuery, MergeRecords, Reload, Shutdown};
+    #[cfg(target_os = "windows")]
+    pub(crate) use crate::bundle::{export_bundle, import_bundle};
+    pub(crate) use crate::dns::Notification::{
+        self, ARecordQuery, AddRecord, AliasesChanged, DumpDiagnostics, ImportRecords, ListRecords,
+        ListenAdapterMoved, MergeRecords, Reload, ReloadRecords, RemoveRecord, ReverseSearch,
+        SetAppConfig, SetFlushCacheOnReload, SetPaused, Shutdown, SyncRecords, ToggleRecord,
+    };
+    pub(crate) use crate::dns::{offer_mdns_tld_change, request, safe_open_records_file};
+    pub(crate) use crate::dns::{
+        DnsServer, LoopbackAliases, QueryEvent, ServerStats, ServerStatus,
+    };
+    #[cfg(any(target_os = "windows", test))]
+    pub(crate) use crate::dns::{MergeSummary, RecordEntry, RecordSource};
     pub(crate) use crate::logging::configure_logging;
+    #[cfg(target_os = "macos")]
+    pub(crate) use crate::menu_bar::{forward_server_events, MenuBar, UserEvent};
     pub(crate) use crate::shared::*;
-    pub(crate) use crate::tray_app::{Application, UserEvent};
+    #[cfg(target_os = "windows")]
+    pub(crate) use crate::tray_app::{forward_server_events, Application, UserEvent};
+    #[cfg(target_os = "windows")]
+    pub(crate) use crate::updates::{auto_update, check_for_updates, launch_installer};
     pub(crate) use anyhow::{anyhow, Context, Error, Result};
-    pub(crate) use log::{debug, error, info, trace, warn};
     pub(crate) use std::collections::HashMap;
     pub(crate) use std::fs::{self, File};
     pub(crate) use std::io::Write;
@@ -27,42 +91,550 @@ mod prelude {
     pub(crate) use std::path::{Path, PathBuf};
     pub(crate) use tokio::sync::mpsc::{self, Receiver, Sender};
     pub(crate) use tokio::sync::oneshot;
+    pub(crate) use tracing::{debug, error, info, trace, warn};
 }
 
+#[cfg(target_os = "windows")]
+use adapter_dns::AdapterDns;
+use address_aliases::{network_connected, watch_alias_addresses, watch_listen_adapter};
+use app_config::LoginStartConfig;
+use auto_pause::auto_pause;
+use clap::Parser;
+use cli::Cli;
+use control::token::ApiToken;
+use control::{AdminApi, ControlServer};
+use crash_report::install_panic_hook;
+use dnstap::start_dnstap;
+#[cfg(target_os = "windows")]
+use elevation::needs_elevation;
+#[cfg(target_os = "windows")]
+use install::install;
+use instance::{forward_to_running_instance, run_command, InstanceLock};
+#[cfg(target_os = "windows")]
+use perf_counters::publish_performance_counters;
 use prelude::*;
+use provision::{init_config, load_config};
+use query_log::write_query_log;
+use resolver_check::check_system_resolver;
+use stats_summary::log_stats_summaries;
+use std::future::Future;
+use std::time::Duration;
+use sync::docker::sync_docker;
+use sync::kubernetes::sync_kubernetes;
+use sync::machine::sync_machine;
+use sync::proxies::sync_proxies;
+use tokio::select;
+#[cfg(target_os = "macos")]
+use tokio::signal::unix::{signal, SignalKind};
+#[cfg(target_os = "windows")]
+use tokio::signal::windows;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+#[cfg(target_os = "windows")]
+use uninstall::uninstall_cleanup;
+#[cfg(target_os = "windows")]
 use winit::event_loop::EventLoop;
+use winit::event_loop::EventLoopProxy;
+
+const LOOPBACK_ALIASES_FILE_NAME: &str = "loopback-aliases.json";
+/// How long a relaunched instance waits for the previous one to exit.
+const RELAUNCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the network is checked when waiting for it at login.
+const NETWORK_POLL_INTERVAL: Duration = Duration::from_secs(2);
 
 #[tokio::main]
-#[cfg(target_os = "windows")]
+#[cfg(any(target_os = "windows", target_os = "macos"))]
 async fn main() {
     if let Err(e) = run().await {
         error!("DNS server error: {e}");
-        error_message(format!("{e}"));
+        // the log may not be configured yet, this is seen with --console
+        eprintln!("DNS server error: {e}");
+        error_message_blocking(&format!("{e}"));
+        std::process::exit(1);
+    }
+}
+
+/// The options affecting the whole process: the UI, the console and the instance.
+#[cfg_attr(target_os = "macos", allow(clippy::unnecessary_wraps))] // attaching the console
+fn apply_process_options(cli: &Cli) -> Result<()> {
+    if cli.headless || cli.init_config.is_some() {
+        set_headless();
+    }
+    #[cfg(target_os = "windows")]
+    if cli.console {
+        attach_console()?;
     }
+    if let Some(name) = &cli.instance {
+        set_instance(name);
+    }
+    Ok(())
 }
 
 async fn run() -> Result<()> {
-    let mut app_config = AppConfig::new()?;
-    configure_logging(&app_config.log_level, &app_config.logging_dir)?;
-    let mut dns_server = DnsServer::new(
+    let mut cli = Cli::try_parse()?;
+    apply_process_options(&cli)?;
+    let instance_lock = if cli.relaunched {
+        InstanceLock::acquire_within(RELAUNCH_TIMEOUT).await?
+    } else {
+        InstanceLock::acquire()?
+    };
+    let Some(_instance_lock) = instance_lock else {
+        if cli.uninstall_cleanup || cli.install || cli.init_config.is_some() {
+            return Err(anyhow!("{APP_NAME} is running, quit it first"));
+        }
+        return forward_to_running_instance(cli.command).await;
+    };
+    #[cfg(target_os = "windows")]
+    if cli.uninstall_cleanup {
+        return uninstall_cleanup(&AppConfig::new()?, cli.remove_data).await;
+    }
+    #[cfg(target_os = "windows")]
+    if cli.install {
+        return install();
+    }
+    #[cfg(target_os = "macos")]
+    if cli.uninstall_cleanup || cli.install {
+        return Err(anyhow!("Installing is only supported on Windows"));
+    }
+    if let Some(seed) = &cli.init_config {
+        return init_config(seed);
+    }
+    let mut app_config = load_config()?;
+    let _log = configure_logging(
+        &app_config.log_level,
+        app_config.log_format,
+        &app_config.log_rotation,
+        &app_config.logging_dir,
+        cli.console,
+    )?;
+    install_panic_hook(&app_config);
+    configure_notifications(app_config.notifications.clone());
+    offer_mdns_tld_change(&mut app_config)
+        .await
+        .context("Changing the top level domain")?;
+    let dns_server = create_server(&app_config, cli.paused)?;
+    let api_token = ApiToken::load_or_create(instance()).context("Loading the API token")?;
+    let control_server = ControlServer::new(&dns_server, api_token.clone());
+    if let Some(command) = cli.command.take() {
+        tokio::spawn(run_command(control_server.clone(), command));
+    }
+    if app_config.admin_api.enabled {
+        tokio::spawn(AdminApi::new(control_server.clone(), &app_config.admin_api).run());
+    }
+    tokio::spawn(control_server.run());
+    let ready = ready_to_serve(cli.at_login, &app_config);
+    spawn_server_tasks(&dns_server, &app_config, &ready);
+    serve(dns_server, app_config, api_token, &cli, ready).await
+}
+
+/// Serve with the tray (or headless), configuring the adapter DNS meanwhile.
+#[cfg(target_os = "windows")]
+async fn serve(
+    dns_server: DnsServer,
+    mut app_config: AppConfig,
+    api_token: ApiToken,
+    cli: &Cli,
+    ready: watch::Receiver<bool>,
+) -> Result<()> {
+    let adapter_dns = spawn_when_ready(&ready, {
+        let config_dir = app_config.config_dir().to_owned();
+        let (enabled, port) = (app_config.adapter_dns, app_config.port);
+        let listen_address = app_config
+            .listen_adapter
+            .is_none()
+            .then_some(app_config.listen_address);
+        async move { AdapterDns::start(enabled, &config_dir, listen_address, port).await }
+    });
+    if cli.headless {
+        let result = run_headless(dns_server, ready.clone()).await;
+        restore_adapter_dns(adapter_dns, &ready).await;
+        return result;
+    }
+    let event_loop = EventLoop::<UserEvent>::with_user_event().build()?;
+    let notify_tx = dns_server.notify_tx.clone();
+    let stats_rx = dns_server.subscribe_stats();
+    forward_server_events(&dns_server, &event_loop);
+    if app_config.updates.automatic {
+        tokio::spawn(auto_update(
+            app_config.updates.clone(),
+            app_config.config_dir().join(UPDATES_DIR_NAME),
+            event_loop.create_proxy(),
+        ));
+    }
+    if cli.console {
+        tokio::spawn(quit_on_console_signal(event_loop.create_proxy()));
+    }
+    let auto = mk_auto_launch(
+        app_config.start_at_login_method,
+        &app_config.login_start.args,
+    )?;
+    tokio::spawn(run_server(
+        dns_server,
+        ready.clone(),
+        event_loop.create_proxy(),
+    ));
+    let mut app = Application::new(
+        &event_loop,
+        notify_tx,
+        stats_rx,
+        &mut app_config,
+        auto,
+        api_token,
+    );
+    event_loop.run_app(&mut app)?;
+    restore_adapter_dns(adapter_dns, &ready).await;
+    Ok(())
+}
+
+/// Whether the app may serve, which is right away unless started at login (see [`wait_at_login`]).
+fn ready_to_serve(at_login: bool, app_config: &AppConfig) -> watch::Receiver<bool> {
+    let (ready_tx, ready) = watch::channel(!at_login);
+    if at_login {
+        let wait = wait_at_login(
+            app_config.login_start.clone(),
+            app_config.records_file.clone(),
+        );
+        tokio::spawn(async move {
+            wait.await;
+            ready_tx.send_replace(true);
+        });
+    }
+    ready
+}
+
+/// Run `task` once the app may serve (see [`wait_at_login`]).
+fn spawn_when_ready<F>(ready: &watch::Receiver<bool>, task: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let mut ready = ready.clone();
+    tokio::spawn(async move {
+        // the sender is only dropped once it sent `true`
+        _ = ready.wait_for(|ready| *ready).await;
+        task.await
+    })
+}
+
+/// Restore the adapter DNS configuration once it was changed (it's changed in the background, so
+/// a slow `PowerShell` doesn't delay the tray). It isn't changed when quitting while still waiting
+/// at login.
+#[cfg(target_os = "windows")]
+async fn restore_adapter_dns(adapter_dns: JoinHandle<AdapterDns>, ready: &watch::Receiver<bool>) {
+    if !*ready.borrow() {
+        adapter_dns.abort();
+        return;
+    }
+    match adapter_dns.await {
+        Ok(adapter_dns) => adapter_dns.restore().await,
+        Err(e) => error!("Error configuring the adapter DNS: {e}"),
+    }
+}
+
+/// The DNS server configured by `app_config` (not running yet). The records are loaded when it
+/// starts running, so the tray shows up while a slow records file is read.
+fn create_server(app_config: &AppConfig, paused: bool) -> Result<DnsServer> {
+    let mut dns_server = DnsServer::with_lazy_records(
         app_config.port,
         &app_config.records_file,
         &app_config.top_level_domain,
-    )
-    .await?;
-    let event_loop = EventLoop::<UserEvent>::with_user_event().build()?;
+    );
+    dns_server.set_listen_address(app_config.listen_address);
+    if let Some(adapter) = &app_config.listen_adapter {
+        dns_server.set_listen_adapter(adapter.clone());
+    }
+    dns_server.set_app_config(app_config.clone());
+    if app_config.loopback_aliases {
+        let path = app_config.config_dir().join(LOOPBACK_ALIASES_FILE_NAME);
+        dns_server.set_loopback_aliases(LoopbackAliases::load(path)?);
+    }
+    dns_server.set_localhost_zone(app_config.localhost_zone);
+    dns_server.set_refuse_other_domains(app_config.adapter_dns);
+    dns_server.set_paused(paused || app_config.start_paused);
+    dns_server.set_flush_cache_on_reload(app_config.flush_dns_cache_on_reload);
+    dns_server.set_slow_query_threshold(
+        (app_config.slow_query_ms > 0).then(|| Duration::from_millis(app_config.slow_query_ms)),
+    );
+    dns_server.set_query_deadline(
+        (app_config.query_deadline_ms > 0)
+            .then(|| Duration::from_millis(app_config.query_deadline_ms)),
+    );
+    start_dnstap(&mut dns_server, &app_config.dnstap, &app_config.logging_dir);
+    Ok(dns_server)
+}
+
+/// Start the tasks following the answered queries (the stats summary and the query log) and the
+/// record sync sources, once the app may serve (see [`wait_at_login`]).
+fn spawn_server_tasks(
+    dns_server: &DnsServer,
+    app_config: &AppConfig,
+    ready: &watch::Receiver<bool>,
+) {
+    if app_config.stats_summary_minutes > 0 {
+        spawn_when_ready(
+            ready,
+            log_stats_summaries(
+                dns_server.subscribe_queries(),
+                dns_server.subscribe_stats(),
+                app_config.stats_summary_minutes,
+            ),
+        );
+    }
+    if app_config.query_log {
+        spawn_when_ready(
+            ready,
+            write_query_log(
+                dns_server.subscribe_queries(),
+                app_config.logging_dir.clone(),
+                app_config.log_rotation.clone(),
+            ),
+        );
+    }
+    #[cfg(target_os = "windows")]
+    if app_config.performance_counters && instance().is_some() {
+        warn!("Only the default instance publishes the performance counters");
+    } else if app_config.performance_counters {
+        spawn_when_ready(
+            ready,
+            publish_performance_counters(
+                app_config.config_dir().to_owned(),
+                dns_server.subscribe_queries(),
+                dns_server.subscribe_stats(),
+            ),
+        );
+    }
+    if app_config.resolver_check_minutes > 0 {
+        spawn_when_ready(
+            ready,
+            check_system_resolver(
+                app_config.top_level_domain.clone(),
+                app_config.resolver_check_minutes,
+                dns_server.subscribe_queries(),
+                dns_server.subscribe_status(),
+            ),
+        );
+    }
+    spawn_sync_sources(dns_server, app_config, ready);
+    if !app_config.auto_pause.adapters.is_empty() {
+        spawn_when_ready(
+            ready,
+            auto_pause(
+                app_config.auto_pause.adapters.clone(),
+                dns_server.notify_tx.clone(),
+                dns_server.subscribe_status(),
+            ),
+        );
+    }
+    spawn_when_ready(ready, watch_alias_addresses(dns_server.notify_tx.clone()));
+    if let Some(adapter) = &app_config.listen_adapter {
+        spawn_when_ready(
+            ready,
+            watch_listen_adapter(
+                adapter.clone(),
+                dns_server.subscribe_status(),
+                dns_server.notify_tx.clone(),
+            ),
+        );
+    }
+}
+
+/// Start the record sync sources, once the app may serve.
+fn spawn_sync_sources(
+    dns_server: &DnsServer,
+    app_config: &AppConfig,
+    ready: &watch::Receiver<bool>,
+) {
+    if app_config.docker.enabled {
+        spawn_when_ready(
+            ready,
+            sync_docker(
+                app_config.docker.clone(),
+                app_config.top_level_domain.clone(),
+                dns_server.notify_tx.clone(),
+            ),
+        );
+    }
+    if app_config.kubernetes.enabled {
+        spawn_when_ready(
+            ready,
+            sync_kubernetes(
+                app_config.kubernetes.clone(),
+                app_config.top_level_domain.clone(),
+                dns_server.notify_tx.clone(),
+            ),
+        );
+    }
+    if app_config.proxies.enabled {
+        spawn_when_ready(
+            ready,
+            sync_proxies(
+                app_config.proxies.clone(),
+                app_config.top_level_domain.clone(),
+                dns_server.notify_tx.clone(),
+            ),
+        );
+    }
+    if app_config.machine.computer_name || app_config.machine.wsl {
+        spawn_when_ready(
+            ready,
+            sync_machine(
+                app_config.machine.clone(),
+                app_config.listen_address,
+                app_config.top_level_domain.clone(),
+                dns_server.notify_tx.clone(),
+            ),
+        );
+    }
+}
+
+/// Serve with the menu bar icon (or headless).
+#[cfg(target_os = "macos")]
+async fn serve(
+    dns_server: DnsServer,
+    mut app_config: AppConfig,
+    _api_token: ApiToken,
+    cli: &Cli,
+    ready: watch::Receiver<bool>,
+) -> Result<()> {
+    if cli.headless {
+        return run_headless(dns_server, ready).await;
+    }
+    let event_loop = menu_bar::event_loop()?;
     let notify_tx = dns_server.notify_tx.clone();
-    let shutdown_proxy = event_loop.create_proxy();
-    let auto = mk_auto_launch()?;
-    tokio::spawn(async move {
-        dns_server.run().await.unwrap_or_else(|e| {
-            error!("DNS server error: {e}");
-            error_message(format!("{e}"));
-            _ = shutdown_proxy.send_event(UserEvent::Shutdown);
-        });
-    });
-    let mut app = Application::new(&event_loop, notify_tx, &mut app_config, &auto)
-        .context("Creating system tray application")?;
-    event_loop.run_app(&mut app)?;
+    forward_server_events(&dns_server, &event_loop);
+    if cli.console {
+        tokio::spawn(quit_on_console_signal(event_loop.create_proxy()));
+    }
+    let auto = mk_auto_launch(
+        app_config.start_at_login_method,
+        &app_config.login_start.args,
+    )?;
+    tokio::spawn(run_server(dns_server, ready));
+    let mut menu_bar = MenuBar::new(&event_loop, notify_tx, &mut app_config, auto);
+    event_loop.run_app(&mut menu_bar)?;
     Ok(())
 }
+
+/// Serve in the background of the menu bar icon (once the app may serve, see [`wait_at_login`]).
+#[cfg(target_os = "macos")]
+async fn run_server(mut dns_server: DnsServer, mut ready: watch::Receiver<bool>) {
+    _ = ready.wait_for(|ready| *ready).await;
+    if let Err(e) = dns_server.run().await {
+        error!("DNS server error: {e:#}");
+        error_message(format!("{e}"));
+    }
+}
+
+/// Serve in the background of the tray (once the app may serve, see [`wait_at_login`]), offering to
+/// restart as administrator when that would fix the failure.
+#[cfg(target_os = "windows")]
+async fn run_server(
+    mut dns_server: DnsServer,
+    mut ready: watch::Receiver<bool>,
+    proxy: EventLoopProxy<UserEvent>,
+) {
+    _ = ready.wait_for(|ready| *ready).await;
+    if let Err(e) = dns_server.run().await {
+        error!("DNS server error: {e:#}");
+        if needs_elevation(&e) {
+            _ = proxy.send_event(UserEvent::ElevationRequired(format!("{e:#}")));
+        } else {
+            error_message(format!("{e}"));
+        }
+    }
+}
+
+/// Serve without the tray until interrupted (Ctrl+C, the console is closed or the system shuts
+/// down) or the server fails. When interrupted the server is shut down like quitting the tray does,
+/// and stops (counting the queries answered meanwhile) before the adapter DNS is restored.
+async fn run_headless(mut dns_server: DnsServer, mut ready: watch::Receiver<bool>) -> Result<()> {
+    info!("Running headless");
+    let notify_tx = dns_server.notify_tx.clone();
+    let server = async {
+        _ = ready.wait_for(|ready| *ready).await;
+        dns_server.run().await
+    };
+    tokio::pin!(server);
+    let signal = select! {
+        result = &mut server => return result,
+        signal = console_signal() => signal?,
+    };
+    info!("Shutting down ({signal})");
+    notify_tx
+        .send(Shutdown)
+        .await
+        .context("sending the shutdown notification")?;
+    server.await
+}
+
+/// Wait as configured before serving when started at login, while the network and the mapped drive
+/// of the records file may not be ready. The server, its tasks and the adapter DNS wait for it.
+async fn wait_at_login(config: LoginStartConfig, records_file: PathBuf) {
+    if config.delay_secs > 0 {
+        info!("Started at login, serving in {}s", config.delay_secs);
+        sleep(Duration::from_secs(config.delay_secs)).await;
+    }
+    if !config.wait_for_network {
+        return;
+    }
+    let ready = async {
+        loop {
+            let records_file = records_file.clone();
+            // a mapped drive that isn't connected yet may block
+            let ready =
+                tokio::task::spawn_blocking(move || network_connected() && records_file.exists());
+            if ready.await.unwrap_or(false) {
+                return;
+            }
+            sleep(NETWORK_POLL_INTERVAL).await;
+        }
+    };
+    let limit = Duration::from_secs(config.network_timeout_secs);
+    if timeout(limit, ready).await.is_err() {
+        warn!(
+            "The network or the records file isn't ready after {}s, serving anyway",
+            limit.as_secs()
+        );
+    }
+}
+
+/// Quit the tray when interrupted in the console it was started with (`--console`).
+async fn quit_on_console_signal(proxy: EventLoopProxy<UserEvent>) {
+    match console_signal().await {
+        Ok(signal) => {
+            if proxy.send_event(UserEvent::Quit(signal)).is_err() {
+                debug!("Event loop closed, not quitting on {signal}");
+            }
+        }
+        Err(e) => warn!("Interrupting the console won't quit: {e:#}"),
+    }
+}
+
+/// Wait for Ctrl+C, the console closing or the system shutting down, which of them happened.
+/// Windows waits for the process to exit (up to a timeout) after the console closes.
+#[cfg(target_os = "windows")]
+async fn console_signal() -> Result<&'static str> {
+    let mut ctrl_c = windows::ctrl_c()?;
+    let mut ctrl_close = windows::ctrl_close()?;
+    let mut ctrl_shutdown = windows::ctrl_shutdown()?;
+    Ok(select! {
+        _ = ctrl_c.recv() => "interrupted",
+        _ = ctrl_close.recv() => "console closed",
+        _ = ctrl_shutdown.recv() => "system shutdown",
+    })
+}
+
+/// Wait for Ctrl+C, the terminal closing or the system shutting down (`launchd` stops the launch
+/// agent), which of them happened.
+#[cfg(target_os = "macos")]
+async fn console_signal() -> Result<&'static str> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(select! {
+        _ = interrupt.recv() => "interrupted",
+        _ = hangup.recv() => "console closed",
+        _ = terminate.recv() => "system shutdown",
+    })
+}
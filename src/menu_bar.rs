@@ -0,0 +1,430 @@
+//! The menu bar icon of the app on macOS: the server status, pausing, the records, routing the top
+//! level domain with a [resolver file](crate::resolver_file) and starting at login (a launch
+//! agent). The rest of what the Windows tray offers is available with the `dlocal` CLI.
+
+use crate::autolaunch_manager::{reconcile_start_at_login, Registration, StartupEntry};
+use crate::prelude::*;
+use crate::resolver_file::{
+    add_resolver_file, remove_resolver_file, resolver_file_exists, AuthorizationCanceled,
+};
+use image::imageops::FilterType;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tray_icon::menu::{CheckMenuItem, Menu, MenuEvent, MenuId, MenuItem, PredefinedMenuItem};
+use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
+use winit::application::ApplicationHandler;
+use winit::event::{StartCause, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, EventLoop, EventLoopProxy};
+use winit::platform::macos::{ActivationPolicy, EventLoopBuilderExtMacOS};
+use winit::window::WindowId;
+
+const QUIT_ID: &str = "quit";
+const RELOAD_ID: &str = "reload";
+const LOGS_ID: &str = "log_dir";
+const STARTUP_ID: &str = "startup";
+const RECORDS_ID: &str = "edit_records";
+const PAUSE_ID: &str = "pause";
+const FLUSH_CACHE_ID: &str = "flush_dns_cache";
+const RESOLVER_ID: &str = "resolver_file";
+const ICON_DATA: &[u8] = include_bytes!("../resources/Icon.png");
+/// The menu bar is 22 points high, the icon is drawn at twice the size for retina displays.
+const ICON_SIZE: u32 = 44;
+
+pub struct MenuBar<'a> {
+    icon: Option<TrayIcon>,
+    notification_tx: Sender<Notification>,
+    proxy: EventLoopProxy<UserEvent>,
+    app_config: &'a mut AppConfig,
+    auto_launch_manager: Arc<dyn AutoLaunchManager>,
+    server_status: ServerStatus,
+    status_item: MenuItem,
+    pause_menu: CheckMenuItem,
+    resolver_menu: CheckMenuItem,
+    startup_menu: CheckMenuItem,
+}
+
+#[derive(Debug)]
+pub(crate) enum UserEvent {
+    MenuEvent(MenuEvent),
+    ServerStatus(ServerStatus),
+    /// Whether a resolver file routes the top level domain to the server.
+    ResolverFile(bool),
+    /// Quit because the console was interrupted (e.g. Ctrl+C with `--console`).
+    Quit(&'static str),
+    /// The startup entry, read at startup.
+    StartupEntry(Registration),
+}
+
+/// The event loop of an accessory app: only the menu bar icon, no Dock icon or application menu.
+pub fn event_loop() -> Result<EventLoop<UserEvent>> {
+    Ok(EventLoop::<UserEvent>::with_user_event()
+        .with_activation_policy(ActivationPolicy::Accessory)
+        .build()?)
+}
+
+impl<'a> MenuBar<'a> {
+    pub fn new(
+        event_loop: &EventLoop<UserEvent>,
+        notification_tx: Sender<Notification>,
+        app_config: &'a mut AppConfig,
+        auto_launch_manager: Box<dyn AutoLaunchManager>,
+    ) -> Self {
+        let proxy = event_loop.create_proxy();
+        MenuEvent::set_event_handler(Some(move |event| {
+            proxy
+                .send_event(UserEvent::MenuEvent(event))
+                .unwrap_or_else(|e| {
+                    notify_error!("Failed forwarding event: {e}");
+                });
+        }));
+        set_notifications_application();
+        let start_flag = app_config.start_at_login;
+        let resolver_label = format!("Route {} With /etc/resolver", app_config.top_level_domain);
+        let menu_bar = Self {
+            icon: None,
+            notification_tx,
+            proxy: event_loop.create_proxy(),
+            app_config,
+            auto_launch_manager: auto_launch_manager.into(),
+            server_status: ServerStatus::Starting,
+            status_item: MenuItem::new(status_label(&ServerStatus::Starting), false, None),
+            pause_menu: CheckMenuItem::with_id(PAUSE_ID, "Pause DNS Serving", true, false, None),
+            resolver_menu: CheckMenuItem::with_id(RESOLVER_ID, resolver_label, true, false, None),
+            startup_menu: CheckMenuItem::with_id(
+                STARTUP_ID,
+                "Start at Login",
+                true,
+                start_flag,
+                None,
+            ),
+        };
+        tokio::spawn(offer_resolver_file(
+            menu_bar.app_config.top_level_domain.clone(),
+            menu_bar.app_config.port,
+            menu_bar.proxy.clone(),
+        ));
+        tokio::spawn(read_startup_entry(
+            menu_bar.auto_launch_manager.clone(),
+            menu_bar.proxy.clone(),
+            UserEvent::StartupEntry,
+        ));
+        menu_bar
+    }
+
+    fn create_icon(&self) -> TrayIcon {
+        TrayIconBuilder::new()
+            .with_menu(Box::new(self.create_menu()))
+            .with_tooltip(status_tooltip(&self.server_status))
+            .with_icon(status_icon(&self.server_status))
+            .build()
+            .unwrap_or_else(|e| {
+                panic_with_error!("Error creating menu bar icon: {e}");
+            })
+    }
+
+    fn create_menu(&self) -> Menu {
+        let reload_i = MenuItem::with_id(RELOAD_ID, "Reload Records", true, None);
+        let records_i = MenuItem::with_id(RECORDS_ID, "Edit Records File", true, None);
+        let flush_i = MenuItem::with_id(FLUSH_CACHE_ID, "Flush DNS Cache", true, None);
+        let logs_i = MenuItem::with_id(LOGS_ID, "Open Logs Directory", true, None);
+        let quit_i = MenuItem::with_id(QUIT_ID, format!("Quit {APP_NAME}"), true, None);
+        Menu::with_items(&[
+            &self.status_item,
+            &PredefinedMenuItem::separator(),
+            &self.pause_menu,
+            &reload_i,
+            &records_i,
+            &PredefinedMenuItem::separator(),
+            &self.resolver_menu,
+            &flush_i,
+            &self.startup_menu,
+            &logs_i,
+            &PredefinedMenuItem::separator(),
+            &quit_i,
+        ])
+        .unwrap_or_else(|e| {
+            panic_with_error!("Error creating menu: {e}");
+        })
+    }
+
+    fn update_server_status(&mut self, status: ServerStatus) {
+        debug!("Server status: {status:?}");
+        self.server_status = status;
+        self.status_item.set_text(status_label(&self.server_status));
+        self.pause_menu
+            .set_checked(matches!(self.server_status, ServerStatus::Paused(_)));
+        if let Some(icon) = &self.icon {
+            icon.set_icon(Some(status_icon(&self.server_status)))
+                .and_then(|()| icon.set_tooltip(Some(status_tooltip(&self.server_status))))
+                .unwrap_or_else(|e| error!("Error updating menu bar icon: {e}"));
+        }
+    }
+
+    fn handle_menu_event(&mut self, event_loop: &ActiveEventLoop, id: &str) {
+        match id {
+            QUIT_ID => self.quit(event_loop),
+            RELOAD_ID => {
+                debug!("Reloading Records");
+                self.send(Reload, "reload records");
+            }
+            PAUSE_ID => {
+                let paused = self.pause_menu.is_checked();
+                debug!("Setting DNS serving paused: {paused}");
+                self.send(SetPaused(paused), "pause");
+            }
+            RECORDS_ID => {
+                debug!("Edit records file");
+                if let Err(e) = safe_open_records_file(&self.app_config.records_file)
+                    .context("opening records file")
+                {
+                    error!("Error: {e:#}");
+                    notify_failure(&format!("Error: {e:#}"));
+                }
+            }
+            LOGS_ID => {
+                debug!("Open logs directory");
+                if let Err(e) = open_path(&self.app_config.logging_dir) {
+                    notify_error!("Error opening logs directory: {e}");
+                }
+            }
+            FLUSH_CACHE_ID => {
+                tokio::spawn(handle_flush_cache_request());
+            }
+            RESOLVER_ID => {
+                tokio::spawn(handle_resolver_request(
+                    self.resolver_menu.is_checked(),
+                    self.app_config.top_level_domain.clone(),
+                    self.app_config.port,
+                    self.proxy.clone(),
+                ));
+            }
+            STARTUP_ID => self.toggle_start_at_login(),
+            _ => {}
+        }
+    }
+
+    /// Send the server a notification off the event loop.
+    fn send(&self, notification: Notification, description: &'static str) {
+        let tx = self.notification_tx.clone();
+        tokio::spawn(async move {
+            tx.send(notification).await.unwrap_or_else(|e| {
+                notify_error!("Error sending {description} message: {e}");
+            });
+        });
+    }
+
+    fn quit(&self, event_loop: &ActiveEventLoop) {
+        info!("Shutting down");
+        self.send(Shutdown, "shutdown");
+        event_loop.exit();
+    }
+
+    fn toggle_start_at_login(&mut self) {
+        let enabled = self.startup_menu.is_checked();
+        let verb = if enabled { "setting" } else { "disabling" };
+        self.set_auto_launch(enabled).unwrap_or_else(|e| {
+            error!("Error {verb} start at login: {e}");
+            notify_failure(&format!("Error {verb} start at login: {e}"));
+        });
+    }
+
+    fn set_auto_launch(&mut self, launch: bool) -> Result<()> {
+        self.app_config
+            .set_start_at_login(launch, ChangeSource::Tray)?;
+        self.publish_config();
+        if launch {
+            self.auto_launch_manager.enable()
+        } else {
+            self.auto_launch_manager.disable()
+        }
+    }
+
+    /// Match the configuration and the menu to the launch agent read at startup. A launch agent
+    /// starting another copy of the app is left for the user to replace (by toggling the menu).
+    fn reconcile_startup_entry(&mut self, registration: Registration) {
+        let startup_entry = std::env::current_exe()
+            .context("identifying the application path")
+            .and_then(|exe| {
+                reconcile_start_at_login(
+                    self.app_config,
+                    registration,
+                    self.auto_launch_manager.as_ref(),
+                    &exe,
+                )
+            });
+        match startup_entry {
+            Ok(startup_entry) => {
+                self.publish_config();
+                self.startup_menu
+                    .set_checked(startup_entry != StartupEntry::Disabled);
+                if let StartupEntry::Stale(registered) = startup_entry {
+                    let msg = format!(
+                        "The launch agent starts {}, so this copy won't start at login. Turn Start at Login off and on to start this copy instead.",
+                        registered.display()
+                    );
+                    send_warning("Start at Login", &msg);
+                }
+            }
+            Err(e) => {
+                notify_error!("Error checking the startup entry: {e:#}");
+            }
+        }
+    }
+
+    /// Tell the server about a configuration change that applies right away, for the diagnostics
+    /// dumps.
+    fn publish_config(&self) {
+        let tx = self.notification_tx.clone();
+        let config = Box::new(self.app_config.clone());
+        tokio::spawn(async move {
+            tx.send(SetAppConfig(config))
+                .await
+                .unwrap_or_else(|e| debug!("Not updating the server's configuration: {e}"));
+        });
+    }
+}
+
+impl ApplicationHandler<UserEvent> for MenuBar<'_> {
+    fn new_events(&mut self, _event_loop: &ActiveEventLoop, cause: StartCause) {
+        if StartCause::Init == cause {
+            self.icon = Some(self.create_icon());
+        }
+    }
+
+    fn resumed(&mut self, _event_loop: &ActiveEventLoop) {}
+
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: UserEvent) {
+        match event {
+            UserEvent::MenuEvent(MenuEvent { id: MenuId(id) }) => {
+                self.handle_menu_event(event_loop, &id);
+            }
+            UserEvent::ServerStatus(status) => self.update_server_status(status),
+            UserEvent::ResolverFile(exists) => self.resolver_menu.set_checked(exists),
+            UserEvent::StartupEntry(registration) => self.reconcile_startup_entry(registration),
+            UserEvent::Quit(signal) => {
+                info!("Quitting ({signal})");
+                self.quit(event_loop);
+            }
+        }
+    }
+
+    fn window_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        _window_id: WindowId,
+        _event: WindowEvent,
+    ) {
+    }
+}
+
+/// Forward the server status to the menu bar.
+pub fn forward_server_events(dns_server: &DnsServer, event_loop: &EventLoop<UserEvent>) {
+    tokio::spawn(forward_server_status(
+        dns_server.subscribe_status(),
+        event_loop.create_proxy(),
+    ));
+}
+
+async fn forward_server_status(
+    mut status_rx: watch::Receiver<ServerStatus>,
+    proxy: EventLoopProxy<UserEvent>,
+) {
+    loop {
+        let status = status_rx.borrow_and_update().clone();
+        if proxy.send_event(UserEvent::ServerStatus(status)).is_err() {
+            return; // event loop is closed
+        }
+        if status_rx.changed().await.is_err() {
+            // The DNS server is gone without reporting (e.g. it panicked)
+            let last = status_rx.borrow().clone();
+            if !matches!(last, ServerStatus::Stopped | ServerStatus::Failed(_)) {
+                let failed = ServerStatus::Failed("DNS server stopped unexpectedly".to_owned());
+                _ = proxy.send_event(UserEvent::ServerStatus(failed));
+            }
+            return;
+        }
+    }
+}
+
+/// Show the toasts as the installed app (`NSUserNotification` needs a bundle identifier), or as
+/// Finder when it isn't installed.
+fn set_notifications_application() {
+    let bundle = notify_rust::get_bundle_identifier_or_default(APP_NAME);
+    if let Err(e) = notify_rust::set_application(&bundle) {
+        warn!("Error setting the notifications application: {e}");
+    }
+}
+
+/// The icon of the app, dimmed while the server doesn't answer (e.g. paused).
+fn status_icon(status: &ServerStatus) -> Icon {
+    let mut image = image::load_from_memory(ICON_DATA)
+        .unwrap_or_else(|e| {
+            panic_with_error!("Error loading icon: {e}");
+        })
+        .resize(ICON_SIZE, ICON_SIZE, FilterType::Lanczos3)
+        .into_rgba8();
+    if !matches!(status, ServerStatus::Listening(_)) {
+        for pixel in image.pixels_mut() {
+            pixel[3] /= 2;
+        }
+    }
+    let (width, height) = image.dimensions();
+    Icon::from_rgba(image.into_raw(), width, height).unwrap_or_else(|e| {
+        panic_with_error!("Error loading icon: {e}");
+    })
+}
+
+/// Write (`add`) or remove the resolver file, then show the actual state in the menu.
+async fn handle_resolver_request(
+    add: bool,
+    tld: String,
+    port: u16,
+    proxy: EventLoopProxy<UserEvent>,
+) {
+    let result = if add {
+        add_resolver_file(&tld, port).await
+    } else {
+        remove_resolver_file(&tld).await
+    };
+    if let Err(e) = result.or_else(ignore_canceled_authorization) {
+        error!("Error updating the resolver file: {e:#}");
+        notify_failure(&format!("Error updating the resolver file: {e:#}"));
+    }
+    refresh_resolver_file(tld, port, proxy).await;
+}
+
+/// Offer to write the resolver file if it doesn't route the top level domain to the server, macOS
+/// doesn't ask the server otherwise.
+async fn offer_resolver_file(tld: String, port: u16, proxy: EventLoopProxy<UserEvent>) {
+    if matches!(resolver_file_exists(&tld, port).await, Ok(false)) {
+        let msg = format!(
+            "macOS only sends the {tld} queries to {APP_NAME} when a resolver file (/etc/resolver/{}) routes them to port {port}.\n\nWrite the resolver file? It can be removed from the menu bar icon.",
+            tld.trim_start_matches('.')
+        );
+        if confirm_message(format!("Route {tld}"), msg).await {
+            handle_resolver_request(true, tld, port, proxy).await;
+            return;
+        }
+    }
+    refresh_resolver_file(tld, port, proxy).await;
+}
+
+async fn refresh_resolver_file(tld: String, port: u16, proxy: EventLoopProxy<UserEvent>) {
+    match resolver_file_exists(&tld, port).await {
+        Ok(exists) => {
+            if proxy.send_event(UserEvent::ResolverFile(exists)).is_err() {
+                debug!("Event loop closed, not updating the resolver file menu");
+            }
+        }
+        Err(e) => warn!("Error checking the resolver file: {e:#}"),
+    }
+}
+
+fn ignore_canceled_authorization(e: Error) -> Result<()> {
+    if e.is::<AuthorizationCanceled>() {
+        debug!("{e}");
+        Ok(())
+    } else {
+        Err(e)
+    }
+}
@@ -0,0 +1,212 @@
+//! Where notifications and error dialogs are shown: toasts, message boxes or only the log, selected
+//! by the `notifications.backend` configuration. Without a desktop session (`--headless`) they're
+//! always only logged, and the tests record them instead of showing them.
+//!
+//! Only fatal errors are shown in a system modal dialog, other problems are notifications (shown
+//! for longer than information) so they don't interrupt e.g. a presentation.
+
+use crate::app_config::NotificationBackend;
+use crate::prelude::*;
+use notify_rust::{Notification, Timeout};
+#[cfg(target_os = "windows")]
+use windows_strings::HSTRING;
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::UI::WindowsAndMessaging::{
+    MessageBoxW, MB_ICONERROR, MB_ICONINFORMATION, MB_ICONWARNING, MB_OK, MB_SYSTEMMODAL,
+    MB_TOPMOST, MESSAGEBOX_STYLE,
+};
+
+/// Long toasts are shown for about 25 seconds, short ones for about 7.
+const LONG_TOAST: Timeout = Timeout::Milliseconds(25_000);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    /// A recoverable error, e.g. a failed action.
+    Error,
+}
+
+impl Severity {
+    fn timeout(self) -> Timeout {
+        match self {
+            Self::Info => Timeout::Default,
+            Self::Warning | Self::Error => LONG_TOAST,
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    fn icon(self) -> MESSAGEBOX_STYLE {
+        match self {
+            Self::Info => MB_ICONINFORMATION,
+            Self::Warning => MB_ICONWARNING,
+            Self::Error => MB_ICONERROR,
+        }
+    }
+
+    /// The `AppleScript` alert type.
+    #[cfg(target_os = "macos")]
+    fn alert_type(self) -> &'static str {
+        match self {
+            Self::Info => "informational",
+            Self::Warning => "warning",
+            Self::Error => "critical",
+        }
+    }
+}
+
+pub trait Notifier: Sync {
+    /// A notification the user doesn't have to act on.
+    fn notify(&self, severity: Severity, summary: &str, body: &str);
+    /// Show information and wait for the user to dismiss it.
+    fn info(&self, title: &str, body: &str);
+    /// Show a fatal error and wait for the user to dismiss it.
+    fn fatal(&self, title: &str, body: &str);
+}
+
+/// Toast notifications (`NSUserNotification` on macOS), message boxes for information and errors.
+struct Toasts;
+
+/// Message boxes for everything, for systems where toasts are disabled (e.g. by a group policy).
+struct MessageBoxes;
+
+/// Only logged.
+struct LogOnly;
+
+impl Notifier for Toasts {
+    fn notify(&self, severity: Severity, summary: &str, body: &str) {
+        let shown = Notification::new()
+            .summary(summary)
+            .body(body)
+            .timeout(severity.timeout())
+            .show();
+        if let Err(e) = shown {
+            error!("{e}");
+        }
+    }
+
+    fn info(&self, title: &str, body: &str) {
+        message_box(title, body, Severity::Info, false);
+    }
+
+    fn fatal(&self, title: &str, body: &str) {
+        message_box(title, body, Severity::Error, true);
+    }
+}
+
+impl Notifier for MessageBoxes {
+    fn notify(&self, severity: Severity, summary: &str, body: &str) {
+        let (summary, body) = (summary.to_owned(), body.to_owned());
+        tokio::task::spawn_blocking(move || message_box(&summary, &body, severity, false));
+    }
+
+    fn info(&self, title: &str, body: &str) {
+        message_box(title, body, Severity::Info, false);
+    }
+
+    fn fatal(&self, title: &str, body: &str) {
+        message_box(title, body, Severity::Error, true);
+    }
+}
+
+impl Notifier for LogOnly {
+    fn notify(&self, severity: Severity, summary: &str, body: &str) {
+        match severity {
+            Severity::Info => info!("{summary}: {body}"),
+            Severity::Warning => warn!("{summary}: {body}"),
+            Severity::Error => error!("{summary}: {body}"),
+        }
+    }
+
+    fn info(&self, title: &str, body: &str) {
+        info!("{title}: {body}");
+    }
+
+    fn fatal(&self, _title: &str, body: &str) {
+        error!("{body}");
+    }
+}
+
+/// Show a message box and wait for the user to dismiss it, `modal` blocks the other windows too.
+#[cfg(target_os = "windows")]
+fn message_box(title: &str, body: &str, severity: Severity, modal: bool) {
+    let modal = if modal { MB_SYSTEMMODAL } else { 0 };
+    unsafe {
+        MessageBoxW(
+            0 as _,
+            HSTRING::from(body).as_ptr(),
+            HSTRING::from(title).as_ptr(),
+            MB_OK | MB_TOPMOST | severity.icon() | modal,
+        );
+    }
+}
+
+/// Show an alert and wait for the user to dismiss it. Alerts have no system modal variant.
+#[cfg(target_os = "macos")]
+fn message_box(title: &str, body: &str, severity: Severity, _modal: bool) {
+    let script = format!(
+        "display alert {} message {} as {}",
+        apple_script_string(title),
+        apple_script_string(body),
+        severity.alert_type()
+    );
+    if let Err(e) = run_apple_script(&script) {
+        error!("Error showing {title}: {e:#}");
+    }
+}
+
+/// The notifier of the `backend`, [`LogOnly`] when `headless`.
+#[cfg_attr(test, allow(dead_code))] // the tests use the [`Recorder`]
+pub fn notifier(backend: NotificationBackend, headless: bool) -> &'static dyn Notifier {
+    if headless {
+        return &LogOnly;
+    }
+    match backend {
+        NotificationBackend::Toast => &Toasts,
+        NotificationBackend::MessageBox => &MessageBoxes,
+        NotificationBackend::Log => &LogOnly,
+    }
+}
+
+#[cfg(test)]
+pub use recorded::{recorded_notifications, Recorder};
+
+#[cfg(test)]
+mod recorded {
+    use super::{Notifier, Severity};
+    use std::sync::{Mutex, PoisonError};
+
+    static NOTIFICATIONS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+    /// Records the notifications for the tests to assert on, rather than showing them.
+    pub struct Recorder;
+
+    impl Notifier for Recorder {
+        fn notify(&self, _severity: Severity, summary: &str, body: &str) {
+            record(summary, body);
+        }
+
+        fn info(&self, title: &str, body: &str) {
+            record(title, body);
+        }
+
+        fn fatal(&self, title: &str, body: &str) {
+            record(title, body);
+        }
+    }
+
+    fn record(title: &str, body: &str) {
+        NOTIFICATIONS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((title.to_owned(), body.to_owned()));
+    }
+
+    /// The notifications (title and body) shown by all the tests so far.
+    pub fn recorded_notifications() -> Vec<(String, String)> {
+        NOTIFICATIONS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
@@ -0,0 +1,76 @@
+//! Name Resolution Policy Table (NRPT) rules: route the configured top level domain to the server
+//! without changing the DNS servers of the network adapters. Adding and removing rules requires
+//! administrator rights, so those run in an elevated PowerShell. NRPT rules can't specify a port,
+//! Windows always queries port 53.
+
+use crate::elevation::{run_powershell, run_powershell_as_admin};
+use crate::prelude::*;
+
+const NAME_SERVER: &str = "127.0.0.1";
+
+/// Is there an NRPT rule sending `tld` to the local server? Doesn't require elevation.
+pub async fn nrpt_rule_exists(tld: &str) -> Result<bool> {
+    let output = run_powershell(format!("@({}).Count", rules_filter(tld)))
+        .await
+        .context("running Get-DnsClientNrptRule")?;
+    let count: u32 = output
+        .trim()
+        .parse()
+        .context("parsing the number of NRPT rules")?;
+    Ok(count > 0)
+}
+
+/// Add an NRPT rule sending `tld` to the local server (asks for administrator rights).
+pub async fn add_nrpt_rule(tld: &str) -> Result<()> {
+    let action = format!("Adding an NRPT rule for {tld}");
+    run_powershell_as_admin(&action, add_rule_script(tld)).await?;
+    info!("Added an NRPT rule for {tld}");
+    Ok(())
+}
+
+/// Remove the NRPT rules sending `tld` to the local server (asks for administrator rights).
+pub async fn remove_nrpt_rule(tld: &str) -> Result<()> {
+    let action = format!("Removing the NRPT rules for {tld}");
+    run_powershell_as_admin(&action, remove_rule_script(tld)).await?;
+    info!("Removed the NRPT rules for {tld}");
+    Ok(())
+}
+
+/// The rules for `tld` with the local server as (one of) their name servers. The top level domain
+/// is validated (letters, digits and hyphens), so it's safe to quote.
+fn rules_filter(tld: &str) -> String {
+    format!(
+        "Get-DnsClientNrptRule | Where-Object {{ $_.Namespace -contains '{tld}' -and $_.NameServers -contains '{NAME_SERVER}' }}"
+    )
+}
+
+fn add_rule_script(tld: &str) -> String {
+    format!(
+        "if (-not ({})) {{ Add-DnsClientNrptRule -Namespace '{tld}' -NameServers '{NAME_SERVER}' -Comment '{APP_NAME}' -ErrorAction Stop }}",
+        rules_filter(tld)
+    )
+}
+
+fn remove_rule_script(tld: &str) -> String {
+    format!(
+        "{} | Remove-DnsClientNrptRule -Force -ErrorAction Stop",
+        rules_filter(tld)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rule_scripts() {
+        assert_eq!(
+            add_rule_script(".loc"),
+            "if (-not (Get-DnsClientNrptRule | Where-Object { $_.Namespace -contains '.loc' -and $_.NameServers -contains '127.0.0.1' })) { Add-DnsClientNrptRule -Namespace '.loc' -NameServers '127.0.0.1' -Comment 'DotLocal-DNS' -ErrorAction Stop }"
+        );
+        assert_eq!(
+            remove_rule_script(".test"),
+            "Get-DnsClientNrptRule | Where-Object { $_.Namespace -contains '.test' -and $_.NameServers -contains '127.0.0.1' } | Remove-DnsClientNrptRule -Force -ErrorAction Stop"
+        );
+    }
+}
@@ -0,0 +1,334 @@
+//! Windows performance counters of the server (the `DotLocal-DNS` counter set in perfmon): the
+//! query and error rates, the answered queries and the average latency, so the monitoring tools IT
+//! already uses can watch the resolver. The counter set is registered once with `lodctr` (as
+//! administrator) from `resources/performance-counters.man`, its names are string resources of the
+//! executable.
+
+use crate::elevation::{run_powershell_as_admin, ElevationDeclined};
+use crate::prelude::*;
+use std::ptr::null_mut;
+use tokio::sync::{broadcast, watch};
+use windows_sys::core::{w, GUID};
+use windows_sys::Win32::Foundation::{ERROR_SUCCESS, HANDLE};
+use windows_sys::Win32::System::Performance::{
+    PerfCreateInstance, PerfDeleteInstance, PerfSetCounterSetInfo, PerfSetULongCounterValue,
+    PerfSetULongLongCounterValue, PerfStartProviderEx, PerfStopProvider, PERF_COUNTERSET_INFO,
+    PERF_COUNTERSET_INSTANCE, PERF_COUNTERSET_SINGLE_INSTANCE, PERF_COUNTER_INFO,
+    PERF_DETAIL_NOVICE,
+};
+use windows_sys::Win32::System::Registry::{
+    RegCloseKey, RegOpenKeyExW, HKEY, HKEY_LOCAL_MACHINE, KEY_READ,
+};
+
+const MANIFEST: &str = include_str!("../resources/performance-counters.man");
+const MANIFEST_FILE_NAME: &str = "performance-counters.man";
+const PROVIDER_GUID: GUID = GUID::from_u128(0xd7f5_6cf7_16e6_41dc_988c_2001_0af6_7f9b);
+const COUNTER_SET_GUID: GUID = GUID::from_u128(0xc0b7_66f8_b255_4893_92d1_c506_8989_d489);
+
+// The counter ids of the manifest.
+const QUERIES_PER_SEC: u32 = 1;
+const ERRORS_PER_SEC: u32 = 2;
+const QUERIES: u32 = 3;
+const AVERAGE_LATENCY: u32 = 4;
+const AVERAGE_LATENCY_BASE: u32 = 5;
+
+// The counter types of the manifest (winperf.h).
+const PERF_COUNTER_BULK_COUNT: u32 = 0x1041_0500;
+const PERF_COUNTER_LARGE_RAWCOUNT: u32 = 0x0001_0100;
+const PERF_AVERAGE_BULK: u32 = 0x4002_0500;
+const PERF_AVERAGE_BASE: u32 = 0x4003_0402;
+
+/// The totals the counters are computed from.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Totals {
+    queries: u64,
+    errors: u64,
+    latency_micros: u64,
+}
+
+impl Totals {
+    fn add(&mut self, event: &QueryEvent) {
+        self.queries += 1;
+        self.latency_micros += u64::try_from(event.duration.as_micros()).unwrap_or(u64::MAX);
+    }
+}
+
+/// Publish the counters until the server stops, registering the counter set first if needed.
+pub async fn publish_performance_counters(
+    config_dir: PathBuf,
+    mut queries: broadcast::Receiver<QueryEvent>,
+    mut stats_rx: watch::Receiver<ServerStats>,
+) {
+    if !is_registered() {
+        match register_counters(&config_dir).await {
+            Ok(()) => {}
+            Err(e) if e.is::<ElevationDeclined>() => {
+                info!("Not publishing performance counters: {e}");
+                return;
+            }
+            Err(e) => {
+                warn!("Error registering the performance counters: {e:#}");
+                return;
+            }
+        }
+    }
+    let provider = match Provider::start() {
+        Ok(provider) => provider,
+        Err(e) => {
+            warn!("Error publishing the performance counters: {e:#}");
+            return;
+        }
+    };
+    let mut totals = Totals::default();
+    loop {
+        tokio::select! {
+            event = queries.recv() => match event {
+                Ok(event) if event.is_resolver_probe() => (),
+                Ok(event) => totals.add(&event),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("The performance counters missed {skipped} queries");
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            changed = stats_rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                totals.errors = stats_rx.borrow_and_update().errors;
+            }
+        }
+        provider.set(&totals);
+    }
+}
+
+/// Is the counter set registered? Doesn't require elevation.
+pub fn is_registered() -> bool {
+    let mut key: HKEY = null_mut();
+    let result = unsafe {
+        RegOpenKeyExW(
+            HKEY_LOCAL_MACHINE,
+            w!("SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\Perflib\\_V2Providers\\{d7f56cf7-16e6-41dc-988c-20010af67f9b}"),
+            0,
+            KEY_READ,
+            &raw mut key,
+        )
+    };
+    if result != ERROR_SUCCESS {
+        return false;
+    }
+    unsafe { RegCloseKey(key) };
+    true
+}
+
+/// Register the counter set of the running executable (asks for administrator rights).
+async fn register_counters(config_dir: &Path) -> Result<()> {
+    let exe = std::env::current_exe().context("finding the executable")?;
+    let (Some(name), Some(dir)) = (exe.file_name(), exe.parent()) else {
+        return Err(anyhow!("Unexpected executable path: {}", exe.display()));
+    };
+    let manifest = write_manifest(config_dir, &name.to_string_lossy())?;
+    let script = format!(
+        "lodctr '/m:{}' '{}'; exit $LASTEXITCODE",
+        quote(&manifest),
+        quote(dir)
+    );
+    run_powershell_as_admin("Registering the performance counters", script).await?;
+    info!("Registered the performance counters");
+    Ok(())
+}
+
+/// Unregister the counter set (asks for administrator rights).
+pub async fn unregister_counters(config_dir: &Path) -> Result<()> {
+    let manifest = write_manifest(config_dir, APP_NAME)?;
+    let script = format!("unlodctr '/m:{}'; exit $LASTEXITCODE", quote(&manifest));
+    run_powershell_as_admin("Removing the performance counters", script).await?;
+    info!("Unregistered the performance counters");
+    Ok(())
+}
+
+fn write_manifest(config_dir: &Path, application: &str) -> Result<PathBuf> {
+    let path = config_dir.join(MANIFEST_FILE_NAME);
+    let application = application.replace('&', "&amp;").replace('"', "&quot;");
+    fs::write(&path, MANIFEST.replace("{application}", &application))
+        .with_context(|| format!("writing {}", path.display()))?;
+    Ok(path)
+}
+
+fn quote(path: &Path) -> String {
+    path.display().to_string().replace('\'', "''")
+}
+
+/// The counter set layout: the counters are stored by value, in the order of their ids.
+#[repr(C)]
+struct CounterSetTemplate {
+    info: PERF_COUNTERSET_INFO,
+    counters: [PERF_COUNTER_INFO; 5],
+}
+
+fn counter(id: u32, kind: u32, size: u32, offset: u32) -> PERF_COUNTER_INFO {
+    PERF_COUNTER_INFO {
+        CounterId: id,
+        Type: kind,
+        Attrib: 0,
+        Size: size,
+        DetailLevel: PERF_DETAIL_NOVICE,
+        Scale: 0,
+        Offset: offset,
+    }
+}
+
+/// A started counter set provider with its (single) instance.
+struct Provider {
+    handle: HANDLE,
+    instance: *mut PERF_COUNTERSET_INSTANCE,
+}
+
+// SAFETY: Perflib handles and instance blocks can be used from any thread.
+unsafe impl Send for Provider {}
+
+impl Provider {
+    fn start() -> Result<Self> {
+        let mut handle: HANDLE = null_mut();
+        check(
+            unsafe { PerfStartProviderEx(&PROVIDER_GUID, std::ptr::null(), &raw mut handle) },
+            "PerfStartProviderEx",
+        )?;
+        let mut provider = Self {
+            handle,
+            instance: null_mut(),
+        };
+        let mut template = CounterSetTemplate {
+            info: PERF_COUNTERSET_INFO {
+                CounterSetGuid: COUNTER_SET_GUID,
+                ProviderGuid: PROVIDER_GUID,
+                NumCounters: 5,
+                InstanceType: PERF_COUNTERSET_SINGLE_INSTANCE,
+            },
+            counters: [
+                counter(QUERIES_PER_SEC, PERF_COUNTER_BULK_COUNT, 8, 0),
+                counter(ERRORS_PER_SEC, PERF_COUNTER_BULK_COUNT, 8, 8),
+                counter(QUERIES, PERF_COUNTER_LARGE_RAWCOUNT, 8, 16),
+                counter(AVERAGE_LATENCY, PERF_AVERAGE_BULK, 8, 24),
+                counter(AVERAGE_LATENCY_BASE, PERF_AVERAGE_BASE, 4, 32),
+            ],
+        };
+        check(
+            unsafe {
+                PerfSetCounterSetInfo(
+                    handle,
+                    (&raw mut template).cast(),
+                    u32::try_from(size_of::<CounterSetTemplate>())?,
+                )
+            },
+            "PerfSetCounterSetInfo",
+        )?;
+        provider.instance =
+            unsafe { PerfCreateInstance(handle, &COUNTER_SET_GUID, w!("DotLocal-DNS"), 0) };
+        if provider.instance.is_null() {
+            return Err(anyhow!(
+                "PerfCreateInstance failed: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(provider)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn set(&self, totals: &Totals) {
+        unsafe {
+            PerfSetULongLongCounterValue(
+                self.handle,
+                self.instance,
+                QUERIES_PER_SEC,
+                totals.queries,
+            );
+            PerfSetULongLongCounterValue(self.handle, self.instance, ERRORS_PER_SEC, totals.errors);
+            PerfSetULongLongCounterValue(self.handle, self.instance, QUERIES, totals.queries);
+            PerfSetULongLongCounterValue(
+                self.handle,
+                self.instance,
+                AVERAGE_LATENCY,
+                totals.latency_micros,
+            );
+            // the base is a 32 bit counter, only its deltas matter so it may wrap
+            PerfSetULongCounterValue(
+                self.handle,
+                self.instance,
+                AVERAGE_LATENCY_BASE,
+                totals.queries as u32,
+            );
+        }
+    }
+}
+
+impl Drop for Provider {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.instance.is_null() {
+                PerfDeleteInstance(self.handle, self.instance);
+            }
+            PerfStopProvider(self.handle);
+        }
+    }
+}
+
+fn check(result: u32, function: &str) -> Result<()> {
+    if result == ERROR_SUCCESS {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "{function} failed: {}",
+        std::io::Error::from_raw_os_error(i32::try_from(result).unwrap_or(i32::MAX))
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{QueryType, ResultCode};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn totals_sum_the_latency_of_the_queries() {
+        let mut totals = Totals::default();
+        let event = QueryEvent {
+            name: "a.loc".to_owned(),
+            qtype: QueryType::A,
+            client: "127.0.0.1:5000".parse().unwrap(),
+            answer: Some(Ipv4Addr::LOCALHOST),
+            rescode: ResultCode::NOERROR,
+            received: SystemTime::UNIX_EPOCH,
+            duration: Duration::from_micros(120),
+        };
+        totals.add(&event);
+        totals.add(&QueryEvent {
+            duration: Duration::from_micros(80),
+            ..event
+        });
+        assert_eq!(
+            totals,
+            Totals {
+                queries: 2,
+                errors: 0,
+                latency_micros: 200,
+            }
+        );
+    }
+
+    #[test]
+    fn the_manifest_defines_the_counters() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = write_manifest(dir.path(), "a&b.exe").unwrap();
+        let manifest = fs::read_to_string(manifest).unwrap();
+        assert!(manifest.contains(r#"applicationIdentity="a&amp;b.exe""#));
+        for id in [
+            QUERIES_PER_SEC,
+            ERRORS_PER_SEC,
+            QUERIES,
+            AVERAGE_LATENCY,
+            AVERAGE_LATENCY_BASE,
+        ] {
+            assert!(manifest.contains(&format!(r#"<counter id="{id}""#)));
+        }
+    }
+}
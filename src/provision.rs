@@ -0,0 +1,128 @@
+//! Silent provisioning for managed rollouts: a seed file pre-populates the configuration and the
+//! records when they are created, without any dialogs. The seed is either passed with
+//! `--init-config <path>` (e.g. from a deployment script) or pushed by IT to
+//! `%ProgramData%\DotLocal-DNS\provision.toml`, which is applied on the first run of every user.
+//!
+//! A seed contains any subset of the configuration file's settings, plus an optional `records`
+//! string in the records file format (a top level key, so it must come before the sections).
+
+use crate::prelude::*;
+
+const PROVISION_FILE_NAME: &str = "provision.toml";
+const RECORDS_KEY: &str = "records";
+
+struct Seed {
+    settings: toml::Table,
+    records: Option<String>,
+}
+
+/// `--init-config`: create the configuration from the seed at `path`. Does nothing when the app is
+/// already configured, so deployment scripts can run it on every login.
+pub fn init_config(path: &Path) -> Result<()> {
+    if AppConfig::exists()? {
+        println!(
+            "{APP_NAME} is already configured, ignoring {}",
+            path.display()
+        );
+        return Ok(());
+    }
+    let seed = read_seed(path)?;
+    let mut app_config = AppConfig::new()?;
+    provision(&mut app_config, seed)?;
+    println!(
+        "Created the configuration in {} from {}",
+        app_config.config_dir().display(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Load the configuration, provisioning it from the machine-wide seed when it's created.
+pub fn load_config() -> Result<AppConfig> {
+    let first_run = !AppConfig::exists()?;
+    let seed = match machine_seed() {
+        Some(path) if first_run => Some(read_seed(&path)?),
+        _ => None,
+    };
+    let mut app_config = AppConfig::new()?;
+    if let Some(seed) = seed {
+        provision(&mut app_config, seed)?;
+    }
+    Ok(app_config)
+}
+
+/// The seed pushed by IT, if there is one.
+fn machine_seed() -> Option<PathBuf> {
+    let path = PathBuf::from(std::env::var_os("ProgramData")?)
+        .join(APP_NAME)
+        .join(PROVISION_FILE_NAME);
+    path.exists().then_some(path)
+}
+
+fn read_seed(path: &Path) -> Result<Seed> {
+    fs::read_to_string(path)
+        .map_err(Error::from)
+        .and_then(|contents| parse_seed(&contents))
+        .with_context(|| format!("reading the provisioning seed {}", path.display()))
+}
+
+fn parse_seed(contents: &str) -> Result<Seed> {
+    let mut settings: toml::Table = toml::from_str(contents)?;
+    let records = match settings.remove(RECORDS_KEY) {
+        None => None,
+        Some(toml::Value::String(records)) => Some(records),
+        Some(_) => return Err(anyhow!("`{RECORDS_KEY}` must be a string")),
+    };
+    Ok(Seed { settings, records })
+}
+
+/// Apply the seed's settings, write its records (backing up the current ones) and enable start at
+/// login if the seed asks for it.
+fn provision(app_config: &mut AppConfig, seed: Seed) -> Result<()> {
+    app_config.apply_seed(seed.settings, ChangeSource::Provisioning)?;
+    if let Some(records) = seed.records {
+        let records_file = &app_config.records_file;
+        if records_file.exists() {
+            fs::copy(records_file, backup_path(records_file))?;
+        } else if let Some(dir) = records_file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(records_file, records)
+            .with_context(|| format!("writing {}", records_file.display()))?;
+    }
+    if app_config.start_at_login {
+        mk_auto_launch(
+            app_config.start_at_login_method,
+            &app_config.login_start.args,
+        )?
+        .enable()
+        .context("enabling start at login")?;
+    }
+    info!("Provisioned the configuration");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_split_records_from_settings() {
+        let seed = parse_seed(
+            r#"
+            top_level_domain = ".corp"
+            records = """
+            wiki.corp:10.1.2.3
+            """
+
+            [updates]
+            automatic = false
+        "#,
+        )
+        .unwrap();
+        assert_eq!(seed.records.unwrap().trim(), "wiki.corp:10.1.2.3");
+        assert_eq!(seed.settings.len(), 2);
+        assert!(seed.settings.contains_key("updates"));
+        assert!(parse_seed("records = 1").is_err());
+    }
+}
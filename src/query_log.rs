@@ -0,0 +1,93 @@
+//! The query log: one JSON line per answered query in `queries.log` (in the logs directory),
+//! separate from the application log so it can be followed or searched without the noise. It's
+//! rotated like the application log and enabled by `query_log = true` in the configuration.
+
+use crate::app_config::LogRotationConfig;
+use crate::logging::rotating_file_writer;
+use crate::prelude::*;
+use chrono::{DateTime, Local, SecondsFormat};
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+
+const QUERY_LOG_BASENAME: &str = "queries";
+
+/// Write every answered query until the server stops.
+pub async fn write_query_log(
+    mut queries: broadcast::Receiver<QueryEvent>,
+    logging_dir: PathBuf,
+    rotation: LogRotationConfig,
+) {
+    let (mut writer, _handle) =
+        match rotating_file_writer(&logging_dir, QUERY_LOG_BASENAME, &rotation, true) {
+            Ok(file) => file,
+            Err(e) => {
+                notify_error!("Error opening the query log: {e:#}");
+                return;
+            }
+        };
+    info!("Writing the query log to {}", logging_dir.display());
+    loop {
+        match queries.recv().await {
+            Ok(event) if event.is_resolver_probe() => (),
+            Ok(event) => {
+                let line = query_line(&event);
+                if let Err(e) = writeln!(writer, "{line}") {
+                    warn!("Error writing the query log: {e}");
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("The query log missed {skipped} queries");
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+fn query_line(event: &QueryEvent) -> Value {
+    let timestamp = DateTime::<Local>::from(event.received);
+    json!({
+        "timestamp": timestamp.to_rfc3339_opts(SecondsFormat::Micros, false),
+        "client": event.client.to_string(),
+        "name": event.name,
+        "type": format!("{:?}", event.qtype),
+        "rcode": format!("{:?}", event.rescode),
+        "answer": event.answer,
+        "duration_us": u64::try_from(event.duration.as_micros()).unwrap_or(u64::MAX),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{QueryType, ResultCode};
+    use std::time::Duration;
+
+    #[test]
+    fn query_lines() {
+        let timestamp = DateTime::parse_from_rfc3339("2025-01-01T10:00:00+02:00").unwrap();
+        let event = QueryEvent {
+            name: "nas.loc".to_owned(),
+            qtype: QueryType::A,
+            client: "127.0.0.1:50000".parse().unwrap(),
+            answer: Some(Ipv4Addr::new(192, 168, 1, 10)),
+            rescode: ResultCode::NOERROR,
+            received: timestamp.into(),
+            duration: Duration::from_micros(250),
+        };
+        let line = query_line(&event);
+        assert_eq!(line["client"], "127.0.0.1:50000");
+        assert_eq!(line["name"], "nas.loc");
+        assert_eq!(line["type"], "A");
+        assert_eq!(line["rcode"], "NOERROR");
+        assert_eq!(line["answer"], "192.168.1.10");
+        assert_eq!(line["duration_us"], 250);
+        let parsed = DateTime::parse_from_rfc3339(line["timestamp"].as_str().unwrap()).unwrap();
+        assert_eq!(parsed, timestamp);
+        let failed = QueryEvent {
+            answer: None,
+            rescode: ResultCode::NXDOMAIN,
+            ..event
+        };
+        assert!(query_line(&failed)["answer"].is_null());
+    }
+}
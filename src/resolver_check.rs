@@ -0,0 +1,138 @@
+//! Check that the system actually sends the queries of the top level domain to the server (through
+//! the NRPT rule or the adapter DNS, the resolver file on macOS), since other software may change
+//! that behind our back (e.g. a VPN client rewriting the DNS settings). A unique name is resolved
+//! through the system resolver and the check passes if the server received it.
+
+use crate::dns::RESOLVER_PROBE_PREFIX;
+use crate::prelude::*;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::lookup_host;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+use tokio::time::{interval_at, timeout, Instant};
+
+/// The first check waits for the system to settle after startup (e.g. the adapter DNS change).
+const FIRST_CHECK_DELAY: Duration = Duration::from_mins(1);
+/// How long the probe query has to reach the server.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Check every `minutes` until the server stops. The user is notified once when the queries stop
+/// reaching the server, and again only after they reached it in between.
+pub async fn check_system_resolver(
+    top_level_domain: String,
+    minutes: u64,
+    mut queries: broadcast::Receiver<QueryEvent>,
+    status_rx: watch::Receiver<ServerStatus>,
+) {
+    let period = Duration::from_secs(minutes * 60);
+    let mut ticks = interval_at(Instant::now() + FIRST_CHECK_DELAY, period);
+    let mut reported = false;
+    loop {
+        ticks.tick().await;
+        match &*status_rx.borrow() {
+            ServerStatus::Listening(_) => (),
+            ServerStatus::Stopped => return,
+            _ => continue,
+        }
+        let name = probe_name(&top_level_domain);
+        match probe(&name, &mut queries).await {
+            Some(true) => {
+                if reported {
+                    info!("Queries for {top_level_domain} reach the server again");
+                }
+                reported = false;
+            }
+            Some(false) if !reported => {
+                reported = true;
+                warn!("The system resolver didn't send the query for {name} to the server");
+                send_warning(
+                    "DNS Not Configured",
+                    &misconfiguration_message(&top_level_domain),
+                );
+            }
+            Some(false) => debug!("Queries for {top_level_domain} still don't reach the server"),
+            None => return,
+        }
+    }
+}
+
+/// A name that isn't in any cache.
+fn probe_name(top_level_domain: &str) -> String {
+    let nonce = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    format!("{RESOLVER_PROBE_PREFIX}{nonce}{top_level_domain}")
+}
+
+/// Resolve `name` through the system resolver. Returns whether the server received it, `None` if
+/// the server stopped.
+async fn probe(name: &str, queries: &mut broadcast::Receiver<QueryEvent>) -> Option<bool> {
+    let host = name.to_owned();
+    // the answer doesn't matter, only whether the query reached the server
+    tokio::spawn(async move { _ = lookup_host((host, 0)).await });
+    let received = timeout(PROBE_TIMEOUT, async {
+        loop {
+            match queries.recv().await {
+                Ok(event) if event.name.eq_ignore_ascii_case(name) => return Some(true),
+                Ok(_) | Err(RecvError::Lagged(_)) => (),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    .await;
+    received.unwrap_or(Some(false))
+}
+
+#[cfg(target_os = "windows")]
+fn misconfiguration_message(top_level_domain: &str) -> String {
+    format!(
+        "Windows doesn't send {top_level_domain} queries to {APP_NAME} (a VPN client may have \
+changed the DNS settings). Check \"Route {top_level_domain} With an NRPT Rule\" in the tray menu, \
+or set adapter_dns = true in the configuration, to route them again."
+    )
+}
+
+#[cfg(target_os = "macos")]
+fn misconfiguration_message(top_level_domain: &str) -> String {
+    format!(
+        "macOS doesn't send {top_level_domain} queries to {APP_NAME} (a VPN client may have \
+changed the DNS settings). Check \"Route {top_level_domain} With /etc/resolver\" in the menu bar \
+icon to route them again."
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{QueryType, ResultCode};
+
+    fn query(name: &str) -> QueryEvent {
+        QueryEvent {
+            name: name.to_owned(),
+            qtype: QueryType::A,
+            client: SocketAddr::from((Ipv4Addr::LOCALHOST, 5000)),
+            answer: Some(Ipv4Addr::LOCALHOST),
+            rescode: ResultCode::NOERROR,
+            received: UNIX_EPOCH,
+            duration: Duration::from_micros(50),
+        }
+    }
+
+    #[tokio::test]
+    async fn probes_pass_when_the_server_receives_the_query() {
+        let (tx, mut queries) = broadcast::channel(4);
+        let name = probe_name(".loc");
+        tx.send(query("other.loc")).unwrap();
+        tx.send(query(&name.to_uppercase())).unwrap();
+        assert_eq!(probe(&name, &mut queries).await, Some(true));
+        drop(tx);
+        assert_eq!(probe(&probe_name(".loc"), &mut queries).await, None);
+    }
+
+    #[test]
+    fn probes_are_told_apart_from_the_clients_queries() {
+        assert!(query(&probe_name(".loc").to_uppercase()).is_resolver_probe());
+        assert!(!query("dot-local-dns.loc").is_resolver_probe());
+    }
+}
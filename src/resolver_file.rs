@@ -0,0 +1,132 @@
+//! Resolver files: route the configured top level domain to the server on macOS, like the NRPT
+//! rules do on Windows. `/etc/resolver/<tld>` sends the queries for the domain to the name server
+//! and port it lists (unlike NRPT rules, any port works). Writing and removing it requires
+//! administrator rights, so those run in a shell script macOS asks the user to authorize.
+
+use crate::prelude::*;
+use std::fmt;
+use std::io::ErrorKind;
+
+const RESOLVER_DIR: &str = "/etc/resolver";
+const NAME_SERVER: &str = "127.0.0.1";
+/// The `AppleScript` error number of a canceled authorization.
+const USER_CANCELED: &str = "(-128)";
+
+/// The user canceled the authorization. Not an error worth reporting.
+#[derive(Debug)]
+pub struct AuthorizationCanceled;
+
+impl fmt::Display for AuthorizationCanceled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "administrator rights were declined")
+    }
+}
+
+impl std::error::Error for AuthorizationCanceled {}
+
+/// Does the resolver file of `tld` send it to the local server on `port`? Doesn't require rights.
+pub async fn resolver_file_exists(tld: &str, port: u16) -> Result<bool> {
+    let path = resolver_path(tld);
+    match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => Ok(routes_to_server(&contents, port)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+/// Write the resolver file sending `tld` to the local server on `port` (asks for administrator
+/// rights). It's staged in the temporary directory and installed from there.
+pub async fn add_resolver_file(tld: &str, port: u16) -> Result<()> {
+    let path = resolver_path(tld);
+    let staged = std::env::temp_dir().join(format!("{}-resolver", instance_app_name()));
+    fs::write(&staged, resolver_contents(port))
+        .with_context(|| format!("writing {}", staged.display()))?;
+    let command = format!(
+        "mkdir -p {RESOLVER_DIR} && install -m 644 {} {}",
+        shell_quote(&staged),
+        shell_quote(&path)
+    );
+    let result = run_as_admin(&format!("Routing {tld} to {APP_NAME}"), command).await;
+    _ = fs::remove_file(&staged);
+    result?;
+    info!("Wrote {}", path.display());
+    Ok(())
+}
+
+/// Remove the resolver file of `tld` (asks for administrator rights).
+pub async fn remove_resolver_file(tld: &str) -> Result<()> {
+    let path = resolver_path(tld);
+    let command = format!("rm -f {}", shell_quote(&path));
+    run_as_admin(&format!("Removing the routing of {tld}"), command).await?;
+    info!("Removed {}", path.display());
+    Ok(())
+}
+
+/// The resolver file of `tld` (`/etc/resolver/loc` for `.loc`).
+fn resolver_path(tld: &str) -> PathBuf {
+    Path::new(RESOLVER_DIR).join(tld.trim_start_matches('.'))
+}
+
+fn resolver_contents(port: u16) -> String {
+    format!("# Written by {APP_NAME}\nnameserver {NAME_SERVER}\nport {port}\n")
+}
+
+/// Do the resolver file `contents` send the queries to the local server on `port` (also when the
+/// file was written by hand)?
+fn routes_to_server(contents: &str, port: u16) -> bool {
+    let mut name_server = false;
+    let mut file_port = 53;
+    for line in contents.lines() {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("nameserver"), Some(address)) => name_server |= address == NAME_SERVER,
+            (Some("port"), Some(value)) => file_port = value.parse().unwrap_or_default(),
+            _ => (),
+        }
+    }
+    name_server && file_port == port
+}
+
+/// Run a shell command as root once the user authorizes it, macOS tells them it's for `action`.
+/// Fails with [`AuthorizationCanceled`] if the user cancels.
+async fn run_as_admin(action: &str, command: String) -> Result<()> {
+    let script = format!(
+        "do shell script {} with prompt {} with administrator privileges",
+        apple_script_string(&command),
+        apple_script_string(&format!("{action} requires administrator rights.")),
+    );
+    debug!("Running as administrator: {command}");
+    match tokio::task::spawn_blocking(move || run_apple_script(&script)).await? {
+        Err(e) if e.to_string().contains(USER_CANCELED) => Err(AuthorizationCanceled.into()),
+        result => result.map(drop),
+    }
+}
+
+/// The path as a single shell word.
+fn shell_quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolver_files_route_the_top_level_domain() {
+        assert_eq!(resolver_path(".loc"), Path::new("/etc/resolver/loc"));
+        assert!(routes_to_server(&resolver_contents(53), 53));
+        assert!(routes_to_server(&resolver_contents(5353), 5353));
+        assert!(!routes_to_server(&resolver_contents(5353), 53));
+        assert!(routes_to_server("nameserver 127.0.0.1\n", 53));
+        assert!(!routes_to_server("nameserver 192.168.1.1\nport 53\n", 53));
+        assert!(!routes_to_server("", 53));
+    }
+
+    #[test]
+    fn paths_are_quoted_for_the_shell() {
+        assert_eq!(
+            shell_quote(Path::new("/tmp/Jo's dir/resolver")),
+            r"'/tmp/Jo'\''s dir/resolver'"
+        );
+    }
+}
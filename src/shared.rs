@@ -1,30 +1,84 @@
+use crate::app_config::NotificationsConfig;
+use crate::autolaunch_manager::Registration;
+use crate::notifier::{Notifier, Severity};
 use crate::prelude::*;
-use notify_rust::Notification;
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+use std::process::Command;
+#[cfg(target_os = "windows")]
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::Duration;
+#[cfg(target_os = "windows")]
 use windows_strings::HSTRING;
+#[cfg(target_os = "windows")]
+use windows_sys::core::w;
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::Foundation::ERROR_SUCCESS;
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::System::Console::{AllocConsole, AttachConsole, ATTACH_PARENT_PROCESS};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::System::Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_SZ};
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::System::Threading::CREATE_NO_WINDOW;
+#[cfg(target_os = "windows")]
 use windows_sys::Win32::UI::WindowsAndMessaging::{
-    MessageBoxW, MB_ICONERROR, MB_ICONINFORMATION, MB_OK, MB_SYSTEMMODAL, MB_TOPMOST,
+    MessageBoxW, IDYES, MB_ICONQUESTION, MB_SYSTEMMODAL, MB_TOPMOST, MB_YESNO,
 };
+use winit::event_loop::EventLoopProxy;
 
 pub const APP_NAME: &str = "DotLocal-DNS";
 pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const DEFAULT_TOP_LEVEL_DOMAIN: &str = ".loc";
 pub const LOGS_DIR_NAME: &str = "logs";
+#[cfg(target_os = "windows")]
+pub const UPDATES_DIR_NAME: &str = "updates";
 pub const DEFAULT_RECORDS_FILE_NAME: &str = "records.txt";
+const TOOLTIP: &str = "DotLocal DNS";
+const MAX_STATUS_LABEL_LENGTH: usize = 80;
+#[cfg(target_os = "windows")]
+const FLUSHED_CACHE_MESSAGE: &str = "Flushed the Windows DNS client cache";
+#[cfg(target_os = "macos")]
+const FLUSHED_CACHE_MESSAGE: &str = "Flushed the macOS DNS cache";
+
+static NOTIFICATIONS_CONFIG: OnceLock<NotificationsConfig> = OnceLock::new();
+static HEADLESS: AtomicBool = AtomicBool::new(false);
+static INSTANCE: OnceLock<String> = OnceLock::new();
+static REPEATED_ERRORS: Mutex<RepeatedErrors> = Mutex::new(RepeatedErrors::new());
+
+/// After an error notification, identical errors are only counted for this long, then a single
+/// notification reports how many times it repeated.
+const ERROR_COOL_DOWN: Duration = Duration::from_mins(1);
+
+/// Events that may be configured to produce a notification or only be logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationEvent {
+    ReloadSuccess,
+    ReloadFailure,
+    #[cfg_attr(target_os = "macos", allow(dead_code))] // the menu bar doesn't merge records
+    Merge,
+    ServerError,
+}
 
 macro_rules! notify_error {
+    ($event:expr => $($arg:tt)+) => {
+        if should_notify($event) {
+            notify_error!($($arg)+);
+        } else {
+            error!($($arg)+);
+        }
+    };
     ($($arg:tt)+) => {
-        // todo: Can we do it without alocating strings?
-        let msg = format!($($arg)+);
-        let summary = format!("{APP_NAME} Error");
         error!($($arg)+);
-        send_notification(&summary, &msg);
+        send_error_notification(format!($($arg)+));
     };
 }
 
+/// Log the error and panic (the panic hook shows the error and writes a crash report).
 macro_rules! panic_with_error {
     ($($arg:tt)+) => {
         error!($($arg)+);
-        error_message(format!($($arg)+));
         panic!("{}", format_args!($($arg)+));
     };
 }
@@ -32,38 +86,446 @@ macro_rules! panic_with_error {
 pub(crate) use notify_error;
 pub(crate) use panic_with_error;
 
+/// Name this instance (`--instance <name>`): its configuration, records, logs, control pipe and
+/// startup entry are separate from the other instances', so several can run side by side.
+pub fn set_instance(name: &str) {
+    if INSTANCE.set(name.to_owned()).is_err() {
+        warn!("The instance is already named");
+    }
+}
+
+/// The name of this instance, `None` for the default one.
+pub fn instance() -> Option<&'static str> {
+    INSTANCE.get().map(String::as_str)
+}
+
+/// The name of what the instance owns in the system (configuration directory, startup entry,
+/// scheduled task, firewall rules): `DotLocal-DNS`, `DotLocal-DNS-<name>` for named instances.
+pub fn instance_app_name() -> String {
+    match instance() {
+        Some(name) => format!("{APP_NAME}-{name}"),
+        None => APP_NAME.to_owned(),
+    }
+}
+
+/// The arguments starting this instance again (e.g. at login or as administrator).
+pub fn instance_args() -> Vec<String> {
+    instance()
+        .map(|name| vec!["--instance".to_owned(), name.to_owned()])
+        .unwrap_or_default()
+}
+
+/// Without a desktop session (`--headless`) notifications and dialogs are only logged, and questions
+/// are answered with no.
+pub fn set_headless() {
+    HEADLESS.store(true, Ordering::Relaxed);
+}
+
+fn is_headless() -> bool {
+    HEADLESS.load(Ordering::Relaxed)
+}
+
+/// Attach to the console of the parent process (e.g. the terminal the app was started from) or open
+/// a new one, so release builds (which use the windows subsystem) can write to stderr.
+#[cfg(target_os = "windows")]
+pub fn attach_console() -> Result<()> {
+    if unsafe { AttachConsole(ATTACH_PARENT_PROCESS) } == 0 && unsafe { AllocConsole() } == 0 {
+        return Err(std::io::Error::last_os_error()).context("opening a console");
+    }
+    Ok(())
+}
+
+pub fn configure_notifications(config: NotificationsConfig) {
+    if NOTIFICATIONS_CONFIG.set(config).is_err() {
+        warn!("Notifications are already configured");
+    }
+}
+
+pub fn should_notify(event: NotificationEvent) -> bool {
+    NOTIFICATIONS_CONFIG
+        .get_or_init(NotificationsConfig::default)
+        .allows(event)
+}
+
+/// The notifier of the configured backend (see [`crate::notifier`]).
+#[cfg(not(test))]
+fn notifier() -> &'static dyn Notifier {
+    let backend = NOTIFICATIONS_CONFIG
+        .get()
+        .map(|c| c.backend)
+        .unwrap_or_default();
+    crate::notifier::notifier(backend, is_headless())
+}
+
+#[cfg(test)]
+fn notifier() -> &'static dyn Notifier {
+    &crate::notifier::Recorder
+}
+
 pub fn send_notification(summary: &str, body: &str) {
-    Notification::new()
-        .summary(summary)
-        .body(body)
-        .show()
-        .unwrap_or_else(|e| error!("{e}"));
+    notify(Severity::Info, summary, body);
 }
 
-pub fn error_message(body: String) {
-    let title = format!("{APP_NAME} Error");
-    tokio::task::spawn_blocking(move || unsafe {
-        MessageBoxW(
-            0 as _,
-            HSTRING::from(body).as_ptr(),
-            HSTRING::from(title).as_ptr(),
-            MB_OK | MB_ICONERROR | MB_TOPMOST | MB_SYSTEMMODAL,
-        );
+/// Notify a problem the user should fix, e.g. invalid records. It's shown for longer.
+pub fn send_warning(summary: &str, body: &str) {
+    notify(Severity::Warning, summary, body);
+}
+
+fn notify(severity: Severity, summary: &str, body: &str) {
+    if NOTIFICATIONS_CONFIG.get().is_some_and(|c| c.mute) {
+        debug!("Notifications muted, skipping: {summary}");
+        return;
+    }
+    notifier().notify(severity, summary, body);
+}
+
+/// Notify that an action the user asked for failed (it's expected to be logged already). It's
+/// shown even when the notifications are muted, the user is waiting for the result.
+pub fn notify_failure(message: &str) {
+    notifier().notify(Severity::Error, &format!("{APP_NAME} Error"), message);
+}
+
+/// Notify an error (it's expected to be logged already), aggregating a storm of identical errors
+/// (e.g. malformed packets) into one notification with a count (see [`ERROR_COOL_DOWN`]).
+pub fn send_error_notification(message: String) {
+    let summary = format!("{APP_NAME} Error");
+    if !lock_repeated_errors().first(&message) {
+        return;
+    }
+    notify(Severity::Error, &summary, &message);
+    tokio::spawn(async move {
+        tokio::time::sleep(ERROR_COOL_DOWN).await;
+        let repeated = lock_repeated_errors().finish(&message);
+        if repeated > 0 {
+            let times = if repeated == 1 { "time" } else { "times" };
+            let body = format!("{message} (repeated {repeated} more {times})");
+            notify(Severity::Error, &summary, &body);
+        }
     });
 }
 
+fn lock_repeated_errors() -> std::sync::MutexGuard<'static, RepeatedErrors> {
+    REPEATED_ERRORS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The errors in their cool down and how many times each repeated since it was notified.
+struct RepeatedErrors(Vec<(String, u32)>);
+
+impl RepeatedErrors {
+    const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Returns `true` if the error isn't in its cool down (it should be notified), otherwise
+    /// counts the repetition.
+    fn first(&mut self, message: &str) -> bool {
+        if let Some((_, count)) = self.0.iter_mut().find(|(m, _)| m == message) {
+            *count += 1;
+            false
+        } else {
+            self.0.push((message.to_owned(), 0));
+            true
+        }
+    }
+
+    /// End the cool down of the error, returns the number of repetitions.
+    fn finish(&mut self, message: &str) -> u32 {
+        let index = self.0.iter().position(|(m, _)| m == message);
+        index.map_or(0, |index| self.0.swap_remove(index).1)
+    }
+}
+
+/// Show a fatal error (e.g. the server stopped) in a dialog, other errors are notified (see
+/// [`notify_failure`]).
+pub fn error_message(body: String) {
+    tokio::task::spawn_blocking(move || error_message_blocking(&body));
+}
+
+/// Show a fatal error dialog and wait for the user to dismiss it.
+pub fn error_message_blocking(body: &str) {
+    notifier().fatal(&format!("{APP_NAME} Error"), body);
+}
+
 pub fn info_message(title: String, body: String) {
-    tokio::task::spawn_blocking(move || unsafe {
+    tokio::task::spawn_blocking(move || notifier().info(&title, &body));
+}
+
+/// Ask the user a yes/no question. Returns `true` if the user answered yes.
+pub async fn confirm_message(title: String, body: String) -> bool {
+    if is_headless() {
+        info!("{title}: {body} (headless, answering no)");
+        return false;
+    }
+    tokio::task::spawn_blocking(move || ask_yes_no(&title, &body))
+        .await
+        .unwrap_or(false)
+}
+
+#[cfg(target_os = "windows")]
+fn ask_yes_no(title: &str, body: &str) -> bool {
+    unsafe {
         MessageBoxW(
             0 as _,
             HSTRING::from(body).as_ptr(),
             HSTRING::from(title).as_ptr(),
-            MB_OK | MB_ICONINFORMATION | MB_TOPMOST | MB_SYSTEMMODAL,
-        );
-    });
+            MB_YESNO | MB_ICONQUESTION | MB_TOPMOST | MB_SYSTEMMODAL,
+        ) == IDYES
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn ask_yes_no(title: &str, body: &str) -> bool {
+    let script = format!(
+        r#"display dialog {} with title {} buttons {{"No", "Yes"}} default button "Yes" with icon note"#,
+        apple_script_string(body),
+        apple_script_string(title),
+    );
+    match run_apple_script(&script) {
+        Ok(answer) => answer == "button returned:Yes",
+        Err(e) => {
+            error!("Error asking {title}: {e:#}");
+            false
+        }
+    }
+}
+
+/// Run an `AppleScript` (`osascript`, e.g. a dialog), returns its result.
+#[cfg(target_os = "macos")]
+pub fn run_apple_script(script: &str) -> Result<String> {
+    let output = Command::new("osascript")
+        .arg("-e")
+        .arg(script)
+        .output()
+        .context("running osascript")?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    } else {
+        Err(anyhow!(
+            "osascript failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// The `text` as an `AppleScript` string literal.
+#[cfg(target_os = "macos")]
+pub fn apple_script_string(text: &str) -> String {
+    format!(r#""{}""#, text.replace('\\', r"\\").replace('"', r#"\""#))
+}
+
+/// The status shown at the top of the menu, shortened to a single line (clicking it in the tray
+/// copies the full status).
+pub fn status_label(status: &ServerStatus) -> String {
+    let line = status.describe();
+    let first_line = line.lines().next().unwrap_or_default();
+    if first_line.chars().count() > MAX_STATUS_LABEL_LENGTH || first_line.len() < line.len() {
+        let short = first_line
+            .chars()
+            .take(MAX_STATUS_LABEL_LENGTH)
+            .collect::<String>();
+        format!("{short}…")
+    } else {
+        line
+    }
+}
+
+/// The tooltip of the icon: the app (and instance) name and what's wrong with the server.
+pub fn status_tooltip(status: &ServerStatus) -> String {
+    let tooltip = match instance() {
+        Some(name) => format!("{TOOLTIP} [{name}]"),
+        None => TOOLTIP.to_owned(),
+    };
+    match status {
+        ServerStatus::Failed(_) => format!("{tooltip} (error - check the logs)"),
+        ServerStatus::Stopped => format!("{tooltip} (stopped)"),
+        ServerStatus::Paused(_) => format!("{tooltip} (paused)"),
+        ServerStatus::LoadingRecords => format!("{tooltip} (loading the records)"),
+        ServerStatus::Starting | ServerStatus::Listening(_) => tooltip,
+    }
+}
+
+/// Read the startup entry off the event loop (reading the scheduled task runs `PowerShell`), then
+/// send it to the loop as the event `event` makes of it.
+pub async fn read_startup_entry<E: Send + 'static>(
+    auto_launch: Arc<dyn AutoLaunchManager>,
+    proxy: EventLoopProxy<E>,
+    event: fn(Registration) -> E,
+) {
+    match tokio::task::spawn_blocking(move || auto_launch.registration()).await {
+        Ok(Ok(registration)) => {
+            if proxy.send_event(event(registration)).is_err() {
+                debug!("Event loop closed, not checking the startup entry");
+            }
+        }
+        Ok(Err(e)) => {
+            notify_error!("Error reading the startup entry: {e:#}");
+        }
+        Err(e) => error!("Reading the startup entry failed: {e}"),
+    }
+}
+
+/// The path used for backing up the supplied file before overwriting it (e.g. `records.txt.bak`).
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    backup.into()
+}
+
+#[cfg(target_os = "windows")]
+pub fn copy_to_clipboard(text: &str) -> Result<()> {
+    arboard::Clipboard::new()?.set_text(text)?;
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+pub fn read_clipboard() -> Result<String> {
+    Ok(arboard::Clipboard::new()?.get_text()?)
 }
 
 pub fn open_path(path: &PathBuf) -> Result<()> {
     open::that(path)?;
     Ok(())
 }
+
+/// Flush the Windows DNS client (resolver) cache, so changed records (and previously failed
+/// lookups, which are cached as well) take effect immediately.
+#[cfg(target_os = "windows")]
+pub async fn flush_dns_cache() -> Result<()> {
+    let output = tokio::task::spawn_blocking(|| {
+        Command::new("ipconfig")
+            .arg("/flushdns")
+            .creation_flags(CREATE_NO_WINDOW)
+            .output()
+    })
+    .await?
+    .context("running ipconfig /flushdns")?;
+    if output.status.success() {
+        info!("Flushed the DNS client cache");
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "ipconfig /flushdns failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stdout).trim()
+        ))
+    }
+}
+
+/// Flush the DNS cache from the menu, notifying how it went.
+pub async fn handle_flush_cache_request() {
+    match flush_dns_cache().await {
+        Ok(()) => send_notification("Flushed DNS Cache", FLUSHED_CACHE_MESSAGE),
+        Err(e) => {
+            error!("Error flushing DNS cache: {e:#}");
+            notify_failure(&format!("Error flushing DNS cache: {e:#}"));
+        }
+    }
+}
+
+/// The Windows version and build (e.g. `Windows 10 Pro 22H2 (build 19045)`), for bug reports.
+#[cfg(target_os = "windows")]
+pub fn os_version() -> String {
+    let product = read_os_info(w!("ProductName"));
+    let display = read_os_info(w!("DisplayVersion"));
+    let build = read_os_info(w!("CurrentBuildNumber"));
+    match (product, build) {
+        (Some(product), Some(build)) => {
+            let display = display.map(|d| format!(" {d}")).unwrap_or_default();
+            format!("{product}{display} (build {build})")
+        }
+        _ => format!("{} ({})", std::env::consts::OS, std::env::consts::ARCH),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn read_os_info(value: *const u16) -> Option<String> {
+    let mut buffer = [0u16; 128];
+    let mut size = u32::try_from(size_of_val(&buffer)).unwrap_or_default();
+    let result = unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            w!("SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion"),
+            value,
+            RRF_RT_REG_SZ,
+            null_mut(),
+            buffer.as_mut_ptr().cast(),
+            &raw mut size,
+        )
+    };
+    if result != ERROR_SUCCESS {
+        return None;
+    }
+    let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+    Some(String::from_utf16_lossy(&buffer[..len]))
+}
+
+/// Flush the macOS resolver cache, so changed records (and previously failed lookups, which are
+/// cached as well) take effect immediately.
+#[cfg(target_os = "macos")]
+pub async fn flush_dns_cache() -> Result<()> {
+    let output = tokio::process::Command::new("dscacheutil")
+        .arg("-flushcache")
+        .output()
+        .await
+        .context("running dscacheutil -flushcache")?;
+    if output.status.success() {
+        info!("Flushed the DNS client cache");
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "dscacheutil -flushcache failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// The macOS version and build (e.g. `macOS 14.5 (build 23F79)`), for bug reports.
+#[cfg(target_os = "macos")]
+pub fn os_version() -> String {
+    let read = |field: &str| {
+        let output = Command::new("sw_vers").arg(field).output().ok()?;
+        let value = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+        (output.status.success() && !value.is_empty()).then_some(value)
+    };
+    match (read("-productVersion"), read("-buildVersion")) {
+        (Some(version), Some(build)) => format!("macOS {version} (build {build})"),
+        _ => format!("{} ({})", std::env::consts::OS, std::env::consts::ARCH),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifications_and_errors_go_to_the_notifier() {
+        send_notification("Test", "Shown notification");
+        error_message_blocking("Shown error");
+        let notifications = crate::notifier::recorded_notifications();
+        assert!(notifications.contains(&("Test".to_owned(), "Shown notification".to_owned())));
+        assert!(notifications.contains(&(format!("{APP_NAME} Error"), "Shown error".to_owned())));
+    }
+
+    #[test]
+    fn repeated_errors_are_counted_during_the_cool_down() {
+        let mut errors = RepeatedErrors::new();
+        assert!(errors.first("Malformed packet"));
+        assert!(!errors.first("Malformed packet"));
+        assert!(!errors.first("Malformed packet"));
+        assert!(errors.first("Reload failed"));
+        assert_eq!(errors.finish("Malformed packet"), 2);
+        assert_eq!(errors.finish("Reload failed"), 0);
+        assert!(errors.first("Malformed packet"));
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn apple_script_strings_are_escaped() {
+        let escaped = apple_script_string(r#"Say "hi" to C:\loc"#);
+        assert_eq!(escaped, r#""Say \"hi\" to C:\\loc""#);
+    }
+}
@@ -0,0 +1,112 @@
+//! A periodic summary line of the server traffic in the application log, so reading the log after
+//! the fact shows the traffic trends without a metrics stack.
+
+use crate::dns::ResultCode;
+use crate::prelude::*;
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+use tokio::time::{interval_at, Instant};
+
+/// The queries answered since the last summary.
+#[derive(Default)]
+struct Interval {
+    queries: u64,
+    nxdomain: u64,
+    durations: Vec<Duration>,
+}
+
+impl Interval {
+    fn add(&mut self, event: &QueryEvent) {
+        self.queries += 1;
+        if event.rescode == ResultCode::NXDOMAIN {
+            self.nxdomain += 1;
+        }
+        self.durations.push(event.duration);
+    }
+
+    /// The 99th percentile of the time to build the responses, `None` without queries.
+    fn p99(&mut self) -> Option<Duration> {
+        self.durations.sort_unstable();
+        let rank = (self.durations.len() * 99).div_ceil(100);
+        self.durations.get(rank.checked_sub(1)?).copied()
+    }
+
+    /// The summary line, with the `errors` and the system wide UDP receive errors of the interval.
+    fn summary(&mut self, minutes: u64, errors: u64, system_udp_errors: u64) -> String {
+        let p99 = self
+            .p99()
+            .map_or_else(|| "-".to_owned(), |p99| format!("{}µs", p99.as_micros()));
+        format!(
+            "Stats for the last {minutes} minutes: {} queries served, {} NXDOMAIN, {errors} errors, p99 latency {p99} ({system_udp_errors} UDP receive errors system wide)",
+            self.queries, self.nxdomain,
+        )
+    }
+}
+
+/// Log a summary every `minutes` until the server stops.
+pub async fn log_stats_summaries(
+    mut queries: broadcast::Receiver<QueryEvent>,
+    stats_rx: watch::Receiver<ServerStats>,
+    minutes: u64,
+) {
+    let period = Duration::from_secs(minutes * 60);
+    let mut ticks = interval_at(Instant::now() + period, period);
+    let mut interval = Interval::default();
+    let mut last = stats_rx.borrow().clone();
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {
+                let stats = stats_rx.borrow().clone();
+                let errors = stats.errors - last.errors;
+                let system_udp_errors =
+                    stats.system_udp_receive_errors - last.system_udp_receive_errors;
+                info!("{}", interval.summary(minutes, errors, system_udp_errors));
+                last = stats;
+                interval = Interval::default();
+            }
+            event = queries.recv() => match event {
+                Ok(event) if event.is_resolver_probe() => (),
+                Ok(event) => interval.add(&event),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("The stats summary missed {skipped} queries");
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::QueryType;
+    use std::time::SystemTime;
+
+    #[test]
+    fn summary_lines() {
+        let mut interval = Interval::default();
+        for micros in 1..=200 {
+            interval.add(&QueryEvent {
+                name: "nas.loc".to_owned(),
+                qtype: QueryType::A,
+                client: "127.0.0.1:50000".parse().unwrap(),
+                answer: None,
+                rescode: if micros % 50 == 0 {
+                    ResultCode::NXDOMAIN
+                } else {
+                    ResultCode::NOERROR
+                },
+                received: SystemTime::UNIX_EPOCH,
+                duration: Duration::from_micros(micros),
+            });
+        }
+        assert_eq!(
+            interval.summary(15, 2, 7),
+            "Stats for the last 15 minutes: 200 queries served, 4 NXDOMAIN, 2 errors, p99 latency 198µs (7 UDP receive errors system wide)"
+        );
+        assert_eq!(
+            Interval::default().summary(15, 0, 0),
+            "Stats for the last 15 minutes: 0 queries served, 0 NXDOMAIN, 0 errors, p99 latency - (0 UDP receive errors system wide)"
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Register the running docker compose services as `service.project<tld>` (e.g. `web.shop.loc`).
+//! The records are refreshed whenever a container starts or stops (from `docker events`).
+//!
+//! Containers can override the names with a `dot-local-dns.hostname` label (comma separated names)
+//! and the address with a `dot-local-dns.address` label.
+
+use super::{command, host_label, output, publish};
+use crate::app_config::DockerSyncConfig;
+use crate::prelude::*;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdout};
+use tokio::time::{sleep, timeout};
+
+const SOURCE: &str = "docker";
+const PROJECT_LABEL: &str = "com.docker.compose.project";
+const SERVICE_LABEL: &str = "com.docker.compose.service";
+const HOSTNAME_LABEL: &str = "dot-local-dns.hostname";
+const ADDRESS_LABEL: &str = "dot-local-dns.address";
+/// How long to wait before retrying when docker isn't running.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// Containers often start together (`docker compose up`), so wait for the events to settle.
+const EVENTS_SETTLE_TIME: Duration = Duration::from_secs(1);
+
+/// Keep the records of the compose services in sync until the server stops.
+pub async fn sync_docker(
+    config: DockerSyncConfig,
+    top_level_domain: String,
+    notify_tx: Sender<Notification>,
+) {
+    info!("Syncing docker compose services");
+    let mut reported = false;
+    loop {
+        // subscribe before scanning, so a container starting during the scan isn't missed
+        let events = ContainerEvents::subscribe();
+        match scan(&top_level_domain, config.address).await {
+            Ok(records) => {
+                reported = false;
+                if !publish(&notify_tx, SOURCE, records).await {
+                    return;
+                }
+                if let Err(e) = async { events?.changed().await }.await {
+                    debug!("Docker events stopped: {e:#}");
+                }
+            }
+            Err(e) => {
+                // docker isn't running (or installed), report it once
+                if reported {
+                    debug!("Error listing the docker containers: {e:#}");
+                } else {
+                    warn!("Error listing the docker containers: {e:#}");
+                    reported = true;
+                }
+                if !publish(&notify_tx, SOURCE, HashMap::new()).await {
+                    return;
+                }
+                sleep(RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// The records of the running compose containers.
+async fn scan(top_level_domain: &str, address: Ipv4Addr) -> Result<HashMap<String, Ipv4Addr>> {
+    let filter = format!("label={PROJECT_LABEL}");
+    let ids = output("docker", &["ps", "--quiet", "--filter", &filter]).await?;
+    let ids: Vec<&str> = ids.split_whitespace().collect();
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let args: Vec<&str> = ["inspect"].into_iter().chain(ids).collect();
+    let containers: Vec<Value> =
+        serde_json::from_str(&output("docker", &args).await?).context("parsing docker inspect")?;
+    Ok(compose_records(&containers, top_level_domain, address))
+}
+
+/// The containers starting and stopping (`docker events`), killed when dropped.
+struct ContainerEvents {
+    process: Child,
+    lines: Lines<BufReader<ChildStdout>>,
+}
+
+impl ContainerEvents {
+    fn subscribe() -> Result<Self> {
+        let mut process = command(
+            "docker",
+            &[
+                "events",
+                "--filter",
+                "type=container",
+                "--filter",
+                "event=start",
+                "--filter",
+                "event=die",
+                "--format",
+                "{{.ID}}",
+            ],
+        )
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .context("running docker events")?;
+        let stdout = process.stdout.take().context("docker events output")?;
+        Ok(Self {
+            process,
+            lines: BufReader::new(stdout).lines(),
+        })
+    }
+
+    /// Wait until a container starts or stops (and the following events settle). Fails if the
+    /// events stream ends, e.g. when docker stops.
+    async fn changed(mut self) -> Result<()> {
+        if self.lines.next_line().await?.is_none() {
+            return Err(anyhow!(
+                "docker events exited ({:?})",
+                self.process.wait().await?
+            ));
+        }
+        while let Ok(Ok(Some(_))) = timeout(EVENTS_SETTLE_TIME, self.lines.next_line()).await {}
+        Ok(())
+    }
+}
+
+fn compose_records(
+    containers: &[Value],
+    top_level_domain: &str,
+    default_address: Ipv4Addr,
+) -> HashMap<String, Ipv4Addr> {
+    let mut records = HashMap::new();
+    for container in containers {
+        let labels = &container["Config"]["Labels"];
+        let label = |name: &str| {
+            labels[name]
+                .as_str()
+                .filter(|value| !value.trim().is_empty())
+        };
+        let address = match label(ADDRESS_LABEL).map(|value| value.trim().parse()) {
+            Some(Ok(address)) => address,
+            Some(Err(e)) => {
+                warn!(
+                    "Invalid {ADDRESS_LABEL} label on {}: {e}",
+                    container["Name"]
+                );
+                continue;
+            }
+            None => default_address,
+        };
+        let names: Vec<String> = match label(HOSTNAME_LABEL) {
+            Some(names) => names
+                .split(',')
+                .map(|name| name.trim().to_lowercase())
+                .filter(|name| !name.is_empty())
+                .collect(),
+            None => match (label(SERVICE_LABEL), label(PROJECT_LABEL)) {
+                (Some(service), Some(project)) => vec![format!(
+                    "{}.{}{top_level_domain}",
+                    host_label(service),
+                    host_label(project)
+                )],
+                _ => continue,
+            },
+        };
+        for name in names {
+            records.insert(name, address);
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn compose_services_get_records() {
+        let containers = vec![
+            json!({"Name": "/shop-web-1", "Config": {"Labels": {
+                "com.docker.compose.project": "shop",
+                "com.docker.compose.service": "web",
+            }}}),
+            json!({"Name": "/my_app-api-1", "Config": {"Labels": {
+                "com.docker.compose.project": "my_app",
+                "com.docker.compose.service": "api_v2",
+                "dot-local-dns.address": "192.168.1.10",
+            }}}),
+            json!({"Name": "/shop-db-1", "Config": {"Labels": {
+                "com.docker.compose.project": "shop",
+                "com.docker.compose.service": "db",
+                "dot-local-dns.hostname": "db.loc, Postgres.loc",
+            }}}),
+            json!({"Name": "/broken", "Config": {"Labels": {
+                "com.docker.compose.project": "shop",
+                "com.docker.compose.service": "broken",
+                "dot-local-dns.address": "nas",
+            }}}),
+        ];
+        let records = compose_records(&containers, ".loc", Ipv4Addr::LOCALHOST);
+        assert_eq!(
+            records,
+            HashMap::from([
+                ("web.shop.loc".to_owned(), Ipv4Addr::LOCALHOST),
+                (
+                    "api-v2.my-app.loc".to_owned(),
+                    Ipv4Addr::new(192, 168, 1, 10)
+                ),
+                ("db.loc".to_owned(), Ipv4Addr::LOCALHOST),
+                ("postgres.loc".to_owned(), Ipv4Addr::LOCALHOST),
+            ])
+        );
+    }
+}
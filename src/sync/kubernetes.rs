@@ -0,0 +1,237 @@
+//! Mirror the hostnames of a local cluster's (kind, k3d, minikube, Docker Desktop) Ingresses and
+//! Gateway API `HTTPRoute`s under the top level domain, pointing at the address the ingress
+//! controller (or gateway) is published on. The cluster is polled with `kubectl`.
+
+use super::{output, publish};
+use crate::app_config::KubernetesSyncConfig;
+use crate::prelude::*;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const SOURCE: &str = "kubernetes";
+
+/// Keep the records of the cluster in sync until the server stops.
+pub async fn sync_kubernetes(
+    config: KubernetesSyncConfig,
+    top_level_domain: String,
+    notify_tx: Sender<Notification>,
+) {
+    info!("Syncing the hostnames of the Kubernetes cluster");
+    let interval = Duration::from_secs(config.interval_secs.max(1));
+    let mut reported = false;
+    loop {
+        let records = match scan(&config, &top_level_domain).await {
+            Ok(records) => {
+                reported = false;
+                records
+            }
+            Err(e) => {
+                // the cluster isn't running, report it once
+                if reported {
+                    debug!("Error listing the Kubernetes ingresses: {e:#}");
+                } else {
+                    warn!("Error listing the Kubernetes ingresses: {e:#}");
+                    reported = true;
+                }
+                HashMap::new()
+            }
+        };
+        if !publish(&notify_tx, SOURCE, records).await {
+            return;
+        }
+        sleep(interval).await;
+    }
+}
+
+async fn scan(
+    config: &KubernetesSyncConfig,
+    top_level_domain: &str,
+) -> Result<HashMap<String, Ipv4Addr>> {
+    let ingresses = kubectl_get(config, "ingresses").await?;
+    // the Gateway API is optional, without its CRDs there are no routes
+    let gateway_api = match kubectl_get(config, "gateways.gateway.networking.k8s.io").await {
+        Ok(gateways) => Some((
+            gateways,
+            kubectl_get(config, "httproutes.gateway.networking.k8s.io").await?,
+        )),
+        Err(e) => {
+            trace!("Not syncing HTTPRoutes: {e:#}");
+            None
+        }
+    };
+    let mut records = ingress_records(&ingresses, top_level_domain, config.address);
+    if let Some((gateways, routes)) = gateway_api {
+        records.extend(route_records(
+            &gateways,
+            &routes,
+            top_level_domain,
+            config.address,
+        ));
+    }
+    Ok(records)
+}
+
+/// `kubectl get <resource> --all-namespaces -o json` in the configured cluster.
+async fn kubectl_get(config: &KubernetesSyncConfig, resource: &str) -> Result<Value> {
+    let mut args = vec!["get", resource, "--all-namespaces", "--output", "json"];
+    if let Some(context) = &config.context {
+        args.extend(["--context", context]);
+    }
+    let kubeconfig = config
+        .kubeconfig
+        .as_ref()
+        .map(|path| path.display().to_string());
+    if let Some(kubeconfig) = &kubeconfig {
+        args.extend(["--kubeconfig", kubeconfig]);
+    }
+    let output = output("kubectl", &args).await?;
+    serde_json::from_str(&output).with_context(|| format!("parsing the {resource}"))
+}
+
+fn ingress_records(
+    ingresses: &Value,
+    top_level_domain: &str,
+    override_address: Option<Ipv4Addr>,
+) -> HashMap<String, Ipv4Addr> {
+    let mut records = HashMap::new();
+    for ingress in items(ingresses) {
+        let address = override_address
+            .or_else(|| published_address(&ingress["status"]["loadBalancer"]["ingress"]))
+            .unwrap_or(Ipv4Addr::LOCALHOST);
+        let hosts = items_of(&ingress["spec"]["rules"]).map(|rule| &rule["host"]);
+        for name in host_names(hosts, top_level_domain) {
+            records.insert(name, address);
+        }
+    }
+    records
+}
+
+fn route_records(
+    gateways: &Value,
+    routes: &Value,
+    top_level_domain: &str,
+    override_address: Option<Ipv4Addr>,
+) -> HashMap<String, Ipv4Addr> {
+    let gateway_addresses: HashMap<(&str, &str), Ipv4Addr> = items(gateways)
+        .filter_map(|gateway| {
+            let metadata = &gateway["metadata"];
+            let address = published_address(&gateway["status"]["addresses"])?;
+            Some((
+                (metadata["namespace"].as_str()?, metadata["name"].as_str()?),
+                address,
+            ))
+        })
+        .collect();
+    let mut records = HashMap::new();
+    for route in items(routes) {
+        let namespace = route["metadata"]["namespace"].as_str().unwrap_or_default();
+        let address = override_address
+            .or_else(|| {
+                items_of(&route["spec"]["parentRefs"]).find_map(|parent| {
+                    let parent_namespace = parent["namespace"].as_str().unwrap_or(namespace);
+                    let name = parent["name"].as_str()?;
+                    gateway_addresses.get(&(parent_namespace, name)).copied()
+                })
+            })
+            .unwrap_or(Ipv4Addr::LOCALHOST);
+        for name in host_names(items_of(&route["spec"]["hostnames"]), top_level_domain) {
+            records.insert(name, address);
+        }
+    }
+    records
+}
+
+fn items(list: &Value) -> impl Iterator<Item = &Value> {
+    items_of(&list["items"])
+}
+
+fn items_of(array: &Value) -> impl Iterator<Item = &Value> {
+    array.as_array().into_iter().flatten()
+}
+
+/// The names in the top level domain. A wildcard (`*.app.loc`) becomes `app.loc`, which also
+/// answers for its subdomains.
+fn host_names<'a>(
+    hosts: impl Iterator<Item = &'a Value> + 'a,
+    top_level_domain: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    hosts
+        .filter_map(Value::as_str)
+        .map(|host| host.strip_prefix("*.").unwrap_or(host).to_lowercase())
+        .filter(move |host| host.ends_with(top_level_domain))
+}
+
+/// The first IPv4 address of a load balancer status (`ip`, `hostname` or `value` fields). A
+/// `localhost` hostname (Docker Desktop) is the loopback address.
+fn published_address(addresses: &Value) -> Option<Ipv4Addr> {
+    items_of(addresses).find_map(|address| {
+        ["ip", "value", "hostname"]
+            .iter()
+            .filter_map(|field| address[field].as_str())
+            .find_map(|value| match value {
+                "localhost" => Some(Ipv4Addr::LOCALHOST),
+                _ => value.parse().ok(),
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn ingress_hosts_point_at_the_load_balancer() {
+        let ingresses = json!({"items": [
+            {
+                "spec": {"rules": [{"host": "shop.loc"}, {"host": "*.api.loc"}, {"host": "shop.example.com"}]},
+                "status": {"loadBalancer": {"ingress": [{"ip": "172.18.0.2"}]}}
+            },
+            {
+                "spec": {"rules": [{"host": "grafana.loc"}, {}]},
+                "status": {"loadBalancer": {"ingress": [{"hostname": "localhost"}]}}
+            },
+            {"spec": {"rules": [{"host": "pending.loc"}]}, "status": {}}
+        ]});
+        let lb = Ipv4Addr::new(172, 18, 0, 2);
+        assert_eq!(
+            ingress_records(&ingresses, ".loc", None),
+            HashMap::from([
+                ("shop.loc".to_owned(), lb),
+                ("api.loc".to_owned(), lb),
+                ("grafana.loc".to_owned(), Ipv4Addr::LOCALHOST),
+                ("pending.loc".to_owned(), Ipv4Addr::LOCALHOST),
+            ])
+        );
+        let address = Ipv4Addr::new(192, 168, 1, 10);
+        assert!(ingress_records(&ingresses, ".loc", Some(address))
+            .values()
+            .all(|ip| *ip == address));
+    }
+
+    #[test]
+    fn routes_point_at_their_gateway() {
+        let gateways = json!({"items": [{
+            "metadata": {"namespace": "infra", "name": "public"},
+            "status": {"addresses": [{"type": "IPAddress", "value": "172.18.0.3"}]}
+        }]});
+        let routes = json!({"items": [
+            {
+                "metadata": {"namespace": "shop"},
+                "spec": {"hostnames": ["cart.loc"], "parentRefs": [{"name": "public", "namespace": "infra"}]}
+            },
+            {
+                "metadata": {"namespace": "shop"},
+                "spec": {"hostnames": ["admin.loc"], "parentRefs": [{"name": "public"}]}
+            }
+        ]});
+        assert_eq!(
+            route_records(&gateways, &routes, ".loc", None),
+            HashMap::from([
+                ("cart.loc".to_owned(), Ipv4Addr::new(172, 18, 0, 3)),
+                ("admin.loc".to_owned(), Ipv4Addr::LOCALHOST),
+            ])
+        );
+    }
+}
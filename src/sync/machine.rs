@@ -0,0 +1,148 @@
+//! Register the names everybody adds by hand: the machine itself (`<computer name><tld>`) and the
+//! running WSL distributions (`<distribution><tld>`, pointing at the distribution's address). WSL
+//! addresses change whenever a distribution restarts, so the running distributions are polled.
+
+use super::{command, host_label, publish};
+use crate::app_config::MachineSyncConfig;
+use crate::prelude::*;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const SOURCE: &str = "machine";
+const WSL: &str = "wsl.exe";
+
+/// Keep the machine and WSL records in sync until the server stops. The computer name points at
+/// `listen_address` in LAN mode (so other devices can use it), otherwise at localhost.
+pub async fn sync_machine(
+    config: MachineSyncConfig,
+    listen_address: Ipv4Addr,
+    top_level_domain: String,
+    notify_tx: Sender<Notification>,
+) {
+    let interval = Duration::from_secs(config.interval_secs.max(1));
+    let mut machine = HashMap::new();
+    if config.computer_name {
+        match computer_name().await {
+            Ok(name) => {
+                let address = if listen_address.is_unspecified() {
+                    Ipv4Addr::LOCALHOST
+                } else {
+                    listen_address
+                };
+                machine.insert(format!("{}{top_level_domain}", host_label(&name)), address);
+            }
+            Err(e) => warn!("Not registering the computer name: {e:#}"),
+        }
+    }
+    loop {
+        let mut records = machine.clone();
+        if config.wsl {
+            match wsl_addresses().await {
+                Ok(distributions) => {
+                    records.extend(wsl_records(&distributions, &top_level_domain));
+                }
+                Err(e) => debug!("Error listing the WSL distributions: {e:#}"),
+            }
+        }
+        if !publish(&notify_tx, SOURCE, records).await {
+            return;
+        }
+        if !config.wsl {
+            return;
+        }
+        sleep(interval).await;
+    }
+}
+
+#[cfg(target_os = "windows")]
+#[allow(clippy::unused_async)] // reading it runs a command on macOS
+async fn computer_name() -> Result<String> {
+    std::env::var("COMPUTERNAME").context("reading COMPUTERNAME")
+}
+
+/// The Bonjour name of the Mac (e.g. `Jos-MacBook-Pro`).
+#[cfg(target_os = "macos")]
+async fn computer_name() -> Result<String> {
+    let name = super::output("scutil", &["--get", "LocalHostName"]).await?;
+    Ok(name.trim().to_owned())
+}
+
+/// The running WSL distributions with their addresses.
+async fn wsl_addresses() -> Result<Vec<(String, Ipv4Addr)>> {
+    let running = wsl(&["--list", "--running", "--quiet"]).await?;
+    let mut addresses = vec![];
+    for distribution in distributions(&running) {
+        let output = wsl(&["--distribution", &distribution, "--exec", "hostname", "-I"]).await;
+        match output.map(|output| first_address(&output)) {
+            Ok(Some(address)) => addresses.push((distribution, address)),
+            Ok(None) => debug!("No IPv4 address for the WSL distribution {distribution}"),
+            Err(e) => debug!("Error getting the address of {distribution}: {e:#}"),
+        }
+    }
+    Ok(addresses)
+}
+
+/// Run `wsl.exe`, with UTF-8 output (it writes UTF-16 otherwise).
+async fn wsl(args: &[&str]) -> Result<String> {
+    let output = command(WSL, args)
+        .env("WSL_UTF8", "1")
+        .output()
+        .await
+        .context("running wsl.exe")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "wsl.exe {} failed ({})",
+            args.join(" "),
+            output.status
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The distribution names listed by `wsl --list --quiet`, without Docker Desktop's internal ones.
+/// Older versions of WSL ignore `WSL_UTF8`, so the NULs of their UTF-16 output are dropped.
+fn distributions(output: &str) -> Vec<String> {
+    output
+        .replace(['\0', '\u{feff}'], "")
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && !name.starts_with("docker-desktop"))
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// The first IPv4 address of `hostname -I`.
+fn first_address(output: &str) -> Option<Ipv4Addr> {
+    output
+        .split_whitespace()
+        .find_map(|address| address.parse().ok())
+}
+
+fn wsl_records(
+    distributions: &[(String, Ipv4Addr)],
+    top_level_domain: &str,
+) -> HashMap<String, Ipv4Addr> {
+    distributions
+        .iter()
+        .map(|(name, address)| (format!("{}{top_level_domain}", host_label(name)), *address))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn running_distributions_get_records() {
+        let output = "U\0b\0u\0n\0t\0u\0-\x002\x002\0.\x000\x004\0\r\0\n\0docker-desktop\r\n\r\n";
+        let names = distributions(output);
+        assert_eq!(names, ["Ubuntu-22.04"]);
+        let address = first_address("172.24.10.5 fd00::5 \n").unwrap();
+        let records = wsl_records(&[(names[0].clone(), address)], ".loc");
+        assert_eq!(
+            records.get("ubuntu-22-04.loc"),
+            Some(&Ipv4Addr::new(172, 24, 10, 5))
+        );
+        assert_eq!(first_address("\n"), None);
+    }
+}
@@ -0,0 +1,63 @@
+//! Record sync sources: tools that already know the hostnames of local services (e.g. docker
+//! compose) report them to the server, which serves them alongside the records file. Each report
+//! replaces all the records of the source, and the records file wins over synced records.
+
+pub mod docker;
+pub mod kubernetes;
+pub mod machine;
+pub mod proxies;
+
+use crate::prelude::*;
+use std::process::Stdio;
+use tokio::process::Command;
+#[cfg(target_os = "windows")]
+use windows_sys::Win32::System::Threading::CREATE_NO_WINDOW;
+
+/// Report all the records of `source`. Returns `false` if the server stopped.
+async fn publish(
+    notify_tx: &Sender<Notification>,
+    source: &str,
+    records: HashMap<String, Ipv4Addr>,
+) -> bool {
+    notify_tx
+        .send(SyncRecords(source.to_owned(), records))
+        .await
+        .is_ok()
+}
+
+/// A command of a sync source's CLI (e.g. `docker`), without a console window.
+fn command(program: &str, args: &[&str]) -> Command {
+    let mut command = Command::new(program);
+    command.args(args).stdin(Stdio::null()).kill_on_drop(true);
+    #[cfg(target_os = "windows")]
+    command.creation_flags(CREATE_NO_WINDOW);
+    command
+}
+
+/// Run a command and return its output.
+async fn output(program: &str, args: &[&str]) -> Result<String> {
+    let output = command(program, args)
+        .output()
+        .await
+        .with_context(|| format!("running {program}"))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{program} {} failed ({}): {}",
+            args.first().unwrap_or(&""),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// A host name label from a name that may contain characters invalid in host names (e.g. a compose
+/// project `my_app` becomes `my-app`).
+fn host_label(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect::<String>()
+        .trim_matches('-')
+        .to_owned()
+}
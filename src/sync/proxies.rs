@@ -0,0 +1,221 @@
+//! Register the hostnames routed by a local Traefik or Caddy, so adding a reverse proxy route makes
+//! its name resolvable. Their admin APIs are polled (Traefik's `/api/http/routers` and Caddy's
+//! `/config/`), a proxy that isn't running simply has no records.
+
+use super::publish;
+use crate::app_config::ProxiesSyncConfig;
+use crate::prelude::*;
+use regex::Regex;
+use reqwest::header::HeaderMap;
+use serde_json::Value;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// The header with the number of the next page of Traefik's routers (`1` on the last page).
+const NEXT_PAGE_HEADER: &str = "X-Next-Page";
+
+/// `Host(...)` and `HostSNI(...)` matchers of a Traefik rule.
+static HOST_MATCHER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\bHost(?:SNI)?\(([^)]*)\)").expect("valid regex"));
+/// The (backtick or double quoted) names in a matcher.
+static QUOTED: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"`([^`]+)`|"([^"]+)""#).expect("valid regex"));
+
+#[derive(Clone, Copy, Debug)]
+enum Proxy {
+    Traefik,
+    Caddy,
+}
+
+impl Proxy {
+    fn source(self) -> &'static str {
+        match self {
+            Proxy::Traefik => "traefik",
+            Proxy::Caddy => "caddy",
+        }
+    }
+
+    fn endpoint(self) -> &'static str {
+        match self {
+            Proxy::Traefik => "/api/http/routers",
+            Proxy::Caddy => "/config/",
+        }
+    }
+
+    fn hosts(self, response: &Value) -> Vec<String> {
+        match self {
+            Proxy::Traefik => traefik_hosts(response),
+            Proxy::Caddy => {
+                let mut hosts = vec![];
+                caddy_hosts(response, &mut hosts);
+                hosts
+            }
+        }
+    }
+}
+
+/// Keep the records of the proxies in sync until the server stops.
+pub async fn sync_proxies(
+    config: ProxiesSyncConfig,
+    top_level_domain: String,
+    notify_tx: Sender<Notification>,
+) {
+    let client = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            notify_error!("Error creating the reverse proxy HTTP client: {e}");
+            return;
+        }
+    };
+    let proxies = [
+        (Proxy::Traefik, &config.traefik_api),
+        (Proxy::Caddy, &config.caddy_api),
+    ];
+    info!("Syncing the reverse proxy routes");
+    let interval = Duration::from_secs(config.interval_secs.max(1));
+    loop {
+        for (proxy, api) in proxies {
+            let hosts = match fetch(&client, api, proxy).await {
+                Ok(hosts) => hosts,
+                Err(e) => {
+                    debug!("Error reading the {} routes: {e:#}", proxy.source());
+                    vec![]
+                }
+            };
+            let records = hosts
+                .into_iter()
+                .map(|host| host.to_lowercase())
+                .filter(|host| host.ends_with(&top_level_domain))
+                .map(|host| (host, config.address))
+                .collect();
+            if !publish(&notify_tx, proxy.source(), records).await {
+                return;
+            }
+        }
+        sleep(interval).await;
+    }
+}
+
+/// The hosts of all the routes, following Traefik's pages of routers.
+async fn fetch(client: &reqwest::Client, api: &str, proxy: Proxy) -> Result<Vec<String>> {
+    let url = format!("{}{}", api.trim_end_matches('/'), proxy.endpoint());
+    let mut hosts = vec![];
+    let mut page = 1;
+    loop {
+        let mut request = client.get(&url);
+        if matches!(proxy, Proxy::Traefik) {
+            request = request.query(&[("page", page)]);
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("querying {url}"))?
+            .error_for_status()?;
+        let next = next_page(response.headers(), page);
+        hosts.extend(proxy.hosts(&response.json().await?));
+        match next {
+            Some(next) => page = next,
+            None => return Ok(hosts),
+        }
+    }
+}
+
+/// The page after `page`, `None` on the last page (or without pagination).
+fn next_page(headers: &HeaderMap, page: u32) -> Option<u32> {
+    headers
+        .get(NEXT_PAGE_HEADER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|next| *next > page)
+}
+
+/// The names in the `Host` matchers of the routers' rules (e.g.
+/// ``Host(`shop.loc`) || Host(`api.shop.loc`)``).
+fn traefik_hosts(routers: &Value) -> Vec<String> {
+    routers
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|router| router["rule"].as_str())
+        .flat_map(|rule| HOST_MATCHER.captures_iter(rule))
+        .flat_map(|matcher| {
+            QUOTED
+                .captures_iter(matcher.get(1).map_or("", |m| m.as_str()))
+                .filter_map(|name| name.get(1).or_else(|| name.get(2)))
+                .map(|name| name.as_str().to_owned())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// The `host` matchers anywhere in the Caddy configuration (routes can be nested in subroutes).
+fn caddy_hosts(config: &Value, hosts: &mut Vec<String>) {
+    match config {
+        Value::Object(object) => {
+            // the route's own hosts before those of its subroutes (the keys are sorted)
+            let matchers = object.get("match").and_then(Value::as_array);
+            hosts.extend(
+                matchers
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|matcher| matcher["host"].as_array().into_iter().flatten())
+                    .filter_map(Value::as_str)
+                    .filter(|host| !host.contains('*'))
+                    .map(ToOwned::to_owned),
+            );
+            object
+                .iter()
+                .filter(|(key, _)| *key != "match")
+                .for_each(|(_, value)| caddy_hosts(value, hosts));
+        }
+        Value::Array(values) => values.iter().for_each(|value| caddy_hosts(value, hosts)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn traefik_router_hosts() {
+        let routers = json!([
+            {"name": "shop@docker", "rule": "Host(`shop.loc`) || Host(`api.shop.loc`)"},
+            {"name": "old@file", "rule": "Host(`a.loc`, \"b.loc\") && PathPrefix(`/api`)"},
+            {"name": "tcp@file", "rule": "HostRegexp(`{sub:[a-z]+}.loc`)"},
+            {"name": "dashboard@internal", "rule": "PathPrefix(`/dashboard`)"}
+        ]);
+        assert_eq!(
+            traefik_hosts(&routers),
+            vec!["shop.loc", "api.shop.loc", "a.loc", "b.loc"]
+        );
+    }
+
+    #[test]
+    fn traefik_pages_end_when_the_next_page_wraps() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(next_page(&headers, 1), None);
+        headers.insert(NEXT_PAGE_HEADER, "3".parse().unwrap());
+        assert_eq!(next_page(&headers, 2), Some(3));
+        headers.insert(NEXT_PAGE_HEADER, "1".parse().unwrap());
+        assert_eq!(next_page(&headers, 3), None);
+    }
+
+    #[test]
+    fn caddy_route_hosts() {
+        let config = json!({"apps": {"http": {"servers": {"srv0": {"routes": [
+            {"match": [{"host": ["shop.loc", "*.shop.loc"]}], "handle": [{"handler": "subroute", "routes": [
+                {"match": [{"host": ["api.shop.loc"]}]}
+            ]}]},
+            {"match": [{"path": ["/health"]}]}
+        ]}}}}});
+        let mut hosts = vec![];
+        caddy_hosts(&config, &mut hosts);
+        assert_eq!(hosts, vec!["shop.loc", "api.shop.loc"]);
+    }
+}
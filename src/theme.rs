@@ -0,0 +1,77 @@
+use std::ptr::null_mut;
+use windows_sys::core::w;
+use windows_sys::Win32::Foundation::ERROR_SUCCESS;
+use windows_sys::Win32::Graphics::Gdi::{GetSysColor, COLOR_WINDOWTEXT};
+use windows_sys::Win32::System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_DWORD};
+use windows_sys::Win32::UI::Accessibility::{HCF_HIGHCONTRASTON, HIGHCONTRASTW};
+use windows_sys::Win32::UI::HiDpi::{GetDpiForSystem, GetSystemMetricsForDpi};
+use windows_sys::Win32::UI::WindowsAndMessaging::{
+    SystemParametersInfoW, SM_CXSMICON, SPI_GETHIGHCONTRAST,
+};
+
+/// Fallback tray icon size (in pixels) if it can't be queried from the system.
+const DEFAULT_ICON_SIZE: u32 = 16;
+
+/// The appearance of the taskbar the tray icon is drawn on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskbarTheme {
+    Dark,
+    Light,
+    HighContrast,
+}
+
+impl TaskbarTheme {
+    pub fn current() -> Self {
+        if high_contrast_enabled() {
+            TaskbarTheme::HighContrast
+        } else if system_uses_light_theme() {
+            TaskbarTheme::Light
+        } else {
+            TaskbarTheme::Dark
+        }
+    }
+}
+
+/// The size of the tray icon in pixels, scaled to the system DPI.
+pub fn tray_icon_size() -> u32 {
+    let size = unsafe { GetSystemMetricsForDpi(SM_CXSMICON, GetDpiForSystem()) };
+    u32::try_from(size)
+        .ok()
+        .filter(|&size| size > 0)
+        .unwrap_or(DEFAULT_ICON_SIZE)
+}
+
+/// The text color of the active (high contrast) color scheme as RGB.
+pub fn text_color() -> [u8; 3] {
+    let [r, g, b, _] = unsafe { GetSysColor(COLOR_WINDOWTEXT) }.to_le_bytes();
+    [r, g, b]
+}
+
+fn high_contrast_enabled() -> bool {
+    let mut hc = HIGHCONTRASTW {
+        cbSize: u32::try_from(size_of::<HIGHCONTRASTW>()).unwrap_or_default(),
+        ..Default::default()
+    };
+    let ok =
+        unsafe { SystemParametersInfoW(SPI_GETHIGHCONTRAST, hc.cbSize, (&raw mut hc).cast(), 0) };
+    ok != 0 && hc.dwFlags & HCF_HIGHCONTRASTON != 0
+}
+
+/// The taskbar follows the "system" (not the "apps") light/dark setting. Windows versions
+/// without this setting have a dark taskbar.
+fn system_uses_light_theme() -> bool {
+    let mut value: u32 = 0;
+    let mut size = u32::try_from(size_of::<u32>()).unwrap_or_default();
+    let result = unsafe {
+        RegGetValueW(
+            HKEY_CURRENT_USER,
+            w!("Software\\Microsoft\\Windows\\CurrentVersion\\Themes\\Personalize"),
+            w!("SystemUsesLightTheme"),
+            RRF_RT_REG_DWORD,
+            null_mut(),
+            (&raw mut value).cast(),
+            &raw mut size,
+        )
+    };
+    result == ERROR_SUCCESS && value != 0
+}
@@ -1,13 +1,35 @@
+use crate::app_config::validate_host_name;
+use crate::autolaunch_manager::{
+    check_login_args, mk_auto_launch, reconcile_start_at_login, remove_other_startup_entry,
+    Registration, StartupEntry,
+};
+use crate::control::token::ApiToken;
+use crate::dialogs::{input_dialog, open_file_dialog, save_file_dialog};
+use crate::dns::{ImportFormat, ImportOutcome, ImportReport};
+use crate::drop_target::DropTarget;
+use crate::elevation::{relaunch_as_admin, ElevationDeclined};
+use crate::firewall::{add_firewall_rule, firewall_rule_exists, remove_firewall_rule};
+use crate::log_window::show_log_window;
+use crate::logging::LogTail;
+use crate::nrpt::{add_nrpt_rule, nrpt_rule_exists, remove_nrpt_rule};
 use crate::prelude::*;
-use tinyfiledialogs::input_box;
+use crate::theme::{text_color, tray_icon_size, TaskbarTheme};
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tinyfiledialogs::{message_box_yes_no, MessageBoxIcon, YesNo};
+use tokio::sync::watch;
+use tracing::Level;
 use tray_icon::menu::{
-    AboutMetadata, AboutMetadataBuilder, CheckMenuItem, Menu, MenuEvent, MenuId, MenuItem,
-    PredefinedMenuItem,
+    CheckMenuItem, Menu, MenuEvent, MenuId, MenuItem, PredefinedMenuItem, Submenu,
 };
 use tray_icon::{TrayIcon, TrayIconBuilder};
 use winit::application::ApplicationHandler;
-use winit::event::{StartCause, WindowEvent};
-use winit::event_loop::{ActiveEventLoop, EventLoop};
+use winit::event::{ElementState, MouseButton, StartCause, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, EventLoop, EventLoopProxy};
 use winit::window::WindowId;
 
 const QUIT_ID: &str = "quit";
@@ -17,28 +39,125 @@ const STARTUP_ID: &str = "startup";
 const RECORDS_ID: &str = "edit_records";
 const LOOKUP_ID: &str = "lookup";
 const MERGE_ID: &str = "merge";
+const DNSMASQ_ID: &str = "import_dnsmasq";
+const EXPORT_ID: &str = "export_settings";
+const IMPORT_ID: &str = "import_settings";
+const CONFIG_ID: &str = "config";
+const UPDATES_ID: &str = "check_for_updates";
+const VIEW_LOGS_PREFIX: &str = "view_logs:";
+const SEARCH_LOGS_ID: &str = "search_logs";
+const HISTORY_ID: &str = "config_history";
+const REVERSE_LOOKUP_ID: &str = "reverse_lookup";
+const CLIPBOARD_RECORD_ID: &str = "register_from_clipboard";
+const STATUS_ID: &str = "status";
+const ABOUT_ID: &str = "about";
+const PAUSE_ID: &str = "pause";
+const DROP_TARGET_ID: &str = "drop_target";
+const DOCS_ID: &str = "open_documentation";
+const REPORT_ISSUE_ID: &str = "report_issue";
+const DIAGNOSTICS_ID: &str = "save_diagnostics";
+const WEB_ADMIN_ID: &str = "web_admin";
+const ROTATE_TOKEN_ID: &str = "rotate_api_token";
+const LOGIN_ARGS_ID: &str = "login_args";
+const DOCS_URL: &str = "https://github.com/babysnakes/dot-local-dns/wiki";
+const NEW_ISSUE_URL: &str = "https://github.com/babysnakes/dot-local-dns/issues/new";
+const FLUSH_CACHE_ID: &str = "flush_dns_cache";
+const FLUSH_ON_RELOAD_ID: &str = "flush_dns_cache_on_reload";
+const NRPT_ID: &str = "nrpt_rule";
+const FIREWALL_ID: &str = "firewall_rule";
+const LOOKUP_MENU_ID: &str = "lookup_menu";
+const LOOKUP_HISTORY_PREFIX: &str = "lookup_history:";
+const MAX_LOOKUP_HISTORY: usize = 10;
+const RECENT_ID: &str = "recent_queries";
+const RECENT_QUERY_PREFIX: &str = "recent_query:";
+const RECENT_QUERIES_REFRESH: Duration = Duration::from_secs(1);
+const RECORDS_MENU_ID: &str = "records";
+const RECORD_TOGGLE_PREFIX: &str = "record_toggle:";
+const RECORD_REMOVE_PREFIX: &str = "record_remove:";
+const RECORD_TOGGLE_PROMPT_ID: &str = "record_toggle_prompt";
+const RECORD_REMOVE_PROMPT_ID: &str = "record_remove_prompt";
+const MAX_RECORDS_IN_MENU: usize = 25;
+const ICON_DATA: &[u8] = include_bytes!("../resources/Icon.png");
+const ERROR_BADGE: [u8; 4] = [0xE0, 0x1B, 0x24, 0xFF];
+const DARK_THEME_OUTLINE: [u8; 3] = [0xE6, 0xE6, 0xE6];
+const THEME_POLL_INTERVAL: Duration = Duration::from_secs(2);
 
 pub struct Application<'a> {
     tray_app: Option<TrayIcon>,
     notification_tx: Sender<Notification>,
+    proxy: EventLoopProxy<UserEvent>,
     app_config: &'a mut AppConfig,
+    api_token: ApiToken,
     startup_menu: CheckMenuItem,
-    auto_launch_manager: &'a dyn AutoLaunchManager,
+    recent_menu: Submenu,
+    records_menu: Submenu,
+    lookup_menu: Submenu,
+    lookup_history: Vec<String>,
+    auto_launch_manager: Arc<dyn AutoLaunchManager>,
+    server_status: ServerStatus,
+    status_item: MenuItem,
+    pause_menu: CheckMenuItem,
+    flush_on_reload_menu: CheckMenuItem,
+    nrpt_menu: CheckMenuItem,
+    firewall_menu: CheckMenuItem,
+    drop_target_menu: CheckMenuItem,
+    drop_target: Option<DropTarget>,
+    theme: TaskbarTheme,
+    stats_rx: watch::Receiver<ServerStats>,
+    started: Instant,
 }
 
 #[derive(Debug)]
 pub(crate) enum UserEvent {
     MenuEvent(MenuEvent),
-    Shutdown,
+    ServerStatus(ServerStatus),
+    RecentQueries(Vec<QueryEvent>),
+    Records(Vec<RecordEntry>),
+    ThemeChanged(TaskbarTheme),
+    /// A newer release was downloaded and verified.
+    UpdateReady {
+        version: String,
+        installer: PathBuf,
+    },
+    /// The (validated) answer to an input dialog.
+    Input(InputRequest, String),
+    /// Whether an NRPT rule routes the top level domain to the server.
+    NrptRule(bool),
+    /// Whether the firewall rule for LAN queries exists.
+    FirewallRule(bool),
+    /// The server failed with an error running as administrator would fix.
+    ElevationRequired(String),
+    /// Quit because the console was interrupted or closed (e.g. Ctrl+C with `--console`), the
+    /// update installer was launched or the app was relaunched as administrator.
+    Quit(&'static str),
+    /// Replace the startup entry starting another executable.
+    RepairStartupEntry,
+    /// The startup entry, read at startup.
+    StartupEntry(Registration),
+    /// The configuration imported from a settings archive (it applies after a restart).
+    SettingsImported(Box<AppConfig>),
+}
+
+/// What an input dialog was opened for.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum InputRequest {
+    Lookup,
+    ToggleRecord,
+    RemoveRecord,
+    SearchLogs,
+    ReverseLookup,
+    LoginArgs,
 }
 
 impl<'a> Application<'a> {
     pub fn new(
         event_loop: &EventLoop<UserEvent>,
         notification_tx: Sender<Notification>,
+        stats_rx: watch::Receiver<ServerStats>,
         app_config: &'a mut AppConfig,
-        auto_launch_manager: &'a dyn AutoLaunchManager,
-    ) -> Result<Self> {
+        auto_launch_manager: Box<dyn AutoLaunchManager>,
+        api_token: ApiToken,
+    ) -> Self {
         let proxy = event_loop.create_proxy();
         MenuEvent::set_event_handler(Some(move |event| {
             proxy
@@ -47,11 +166,17 @@ impl<'a> Application<'a> {
                     notify_error!("Failed forwarding event: {e}");
                 });
         }));
+        let theme = TaskbarTheme::current();
+        tokio::spawn(watch_taskbar_theme(theme, event_loop.create_proxy()));
         let start_flag = app_config.start_at_login;
+        let flush_flag = app_config.flush_dns_cache_on_reload;
+        let nrpt_label = format!("Route {} With an NRPT Rule", app_config.top_level_domain);
         let app = Self {
             tray_app: None,
             notification_tx,
+            proxy: event_loop.create_proxy(),
             app_config,
+            api_token,
             startup_menu: CheckMenuItem::with_id(
                 STARTUP_ID,
                 "Startup at Login",
@@ -59,25 +184,106 @@ impl<'a> Application<'a> {
                 start_flag,
                 None,
             ),
-            auto_launch_manager,
+            recent_menu: Submenu::with_id(RECENT_ID, "Recent Queries", true),
+            records_menu: Submenu::with_id(RECORDS_MENU_ID, "Manage Records", true),
+            lookup_menu: Submenu::with_id(LOOKUP_MENU_ID, "Verify Host Lookup", true),
+            lookup_history: vec![],
+            auto_launch_manager: auto_launch_manager.into(),
+            status_item: MenuItem::with_id(
+                STATUS_ID,
+                status_label(&ServerStatus::Starting),
+                true,
+                None,
+            ),
+            pause_menu: CheckMenuItem::with_id(PAUSE_ID, "Pause DNS Serving", true, false, None),
+            flush_on_reload_menu: CheckMenuItem::with_id(
+                FLUSH_ON_RELOAD_ID,
+                "Flush DNS Cache After Reload",
+                true,
+                flush_flag,
+                None,
+            ),
+            nrpt_menu: CheckMenuItem::with_id(NRPT_ID, nrpt_label, true, false, None),
+            firewall_menu: CheckMenuItem::with_id(
+                FIREWALL_ID,
+                "Allow LAN Queries Through the Firewall",
+                true,
+                false,
+                None,
+            ),
+            drop_target_menu: CheckMenuItem::with_id(
+                DROP_TARGET_ID,
+                "Show Merge Drop Target",
+                true,
+                false,
+                None,
+            ),
+            drop_target: None,
+            server_status: ServerStatus::Starting,
+            theme,
+            stats_rx,
+            started: Instant::now(),
         };
-        if start_flag != app.auto_launch_manager.is_enabled()? {
-            notify_user_about_mismatch_auto_launch(start_flag, !start_flag);
-            app.app_config.set_start_at_login(!start_flag)?;
-            app.startup_menu.set_checked(!start_flag);
+        app.update_recent_queries(&[]);
+        app.update_records(&[]);
+        app.update_lookup_menu();
+        tokio::spawn(refresh_nrpt_rule(
+            app.app_config.top_level_domain.clone(),
+            app.proxy.clone(),
+        ));
+        tokio::spawn(offer_firewall_rule(
+            app.app_config.firewall_address(),
+            app.app_config.port,
+            app.proxy.clone(),
+        ));
+        tokio::spawn(read_startup_entry(
+            app.auto_launch_manager.clone(),
+            app.proxy.clone(),
+            UserEvent::StartupEntry,
+        ));
+        app
+    }
+
+    /// Match the configuration and the menu to the startup entry read at startup.
+    fn reconcile_startup_entry(&mut self, registration: Registration) {
+        let startup_entry = std::env::current_exe()
+            .context("identifying the application path")
+            .and_then(|exe| {
+                let entry = reconcile_start_at_login(
+                    self.app_config,
+                    registration,
+                    self.auto_launch_manager.as_ref(),
+                    &exe,
+                )?;
+                Ok((entry, exe))
+            });
+        match startup_entry {
+            Ok((startup_entry, exe)) => {
+                self.publish_config();
+                self.startup_menu
+                    .set_checked(startup_entry != StartupEntry::Disabled);
+                if let StartupEntry::Stale(registered) = startup_entry {
+                    tokio::spawn(offer_startup_entry_repair(
+                        registered,
+                        exe,
+                        self.proxy.clone(),
+                    ));
+                }
+            }
+            Err(e) => {
+                notify_error!("Error checking the startup entry: {e:#}");
+            }
         }
-        Ok(app)
     }
 
     fn create_tray(&self) -> TrayIcon {
-        let icon_data = include_bytes!("../resources/Icon.png");
-        let icon = load_icon(icon_data);
+        let icon = status_icon(&self.server_status, self.theme);
         let menu = self.create_menu();
 
         TrayIconBuilder::new()
             .with_menu(Box::new(menu))
             .with_menu_on_left_click(true)
-            .with_tooltip("DotLocal DNS")
+            .with_tooltip(status_tooltip(&self.server_status))
             .with_icon(icon)
             .build()
             .unwrap_or_else(|e| {
@@ -88,20 +294,72 @@ impl<'a> Application<'a> {
     fn create_menu(&self) -> Menu {
         let quit_i = MenuItem::with_id(QUIT_ID, "Quit", true, None);
         let reload_i = MenuItem::with_id(RELOAD_ID, "Reload Records", true, None);
+        let flush_i = MenuItem::with_id(FLUSH_CACHE_ID, "Flush DNS Cache", true, None);
         let logs_i = MenuItem::with_id(LOGS_ID, "Open Logs Directory", true, None);
         let records_i = MenuItem::with_id(RECORDS_ID, "Edit Records File", true, None);
-        let lookup_i = MenuItem::with_id(LOOKUP_ID, "Verify Host Lookup", true, None);
-        let merge_i = MenuItem::with_id(MERGE_ID, "Temporarily Merge Records", true, None);
+        let merge_i = MenuItem::with_id(MERGE_ID, "Merge Records From File…", true, None);
+        let clipboard_i = MenuItem::with_id(
+            CLIPBOARD_RECORD_ID,
+            "Register Hostname From Clipboard",
+            true,
+            None,
+        );
+        let dnsmasq_i = MenuItem::with_id(DNSMASQ_ID, "Import dnsmasq Config…", true, None);
+        let export_i = MenuItem::with_id(EXPORT_ID, "Export Settings…", true, None);
+        let import_i = MenuItem::with_id(IMPORT_ID, "Import Settings…", true, None);
+        let config_i = MenuItem::with_id(CONFIG_ID, "Open Config File", true, None);
+        let history_i = MenuItem::with_id(HISTORY_ID, "View Config History", true, None);
+        let view_logs_menu = view_logs_submenu();
+        let help_menu = help_submenu();
+        let reverse_lookup_i = MenuItem::with_id(
+            REVERSE_LOOKUP_ID,
+            "Which Names Point at This IP?…",
+            true,
+            None,
+        );
+        let updates_i = MenuItem::with_id(UPDATES_ID, "Check for Updates…", true, None);
+        let web_admin_i = MenuItem::with_id(
+            WEB_ADMIN_ID,
+            "Open Web Admin",
+            self.app_config.admin_api.enabled,
+            None,
+        );
+        let rotate_token_i = MenuItem::with_id(ROTATE_TOKEN_ID, "Rotate API Token…", true, None);
+        let login_args_i =
+            MenuItem::with_id(LOGIN_ARGS_ID, "Start at Login Arguments…", true, None);
         Menu::with_items(&[
+            &self.status_item,
+            &self.pause_menu,
+            &PredefinedMenuItem::separator(),
             &records_i,
+            &self.records_menu,
+            &web_admin_i,
+            &clipboard_i,
             &merge_i,
+            &dnsmasq_i,
+            &self.drop_target_menu,
             &reload_i,
+            &flush_i,
+            &self.flush_on_reload_menu,
+            &self.nrpt_menu,
+            &self.firewall_menu,
             &PredefinedMenuItem::separator(),
-            &lookup_i,
+            &self.lookup_menu,
+            &reverse_lookup_i,
+            &self.recent_menu,
+            &view_logs_menu,
             &logs_i,
+            &export_i,
+            &import_i,
+            &config_i,
+            &history_i,
+            &rotate_token_i,
             &self.startup_menu,
+            &login_args_i,
             &PredefinedMenuItem::separator(),
-            &PredefinedMenuItem::about("About".into(), Some(about_manifest())),
+            &updates_i,
+            &help_menu,
+            &MenuItem::with_id(ABOUT_ID, "About", true, None),
             &quit_i,
         ])
         .unwrap_or_else(|e| {
@@ -109,31 +367,768 @@ impl<'a> Application<'a> {
         })
     }
 
+    fn update_server_status(&mut self, status: ServerStatus) {
+        debug!("Server status: {status:?}");
+        self.server_status = status;
+        self.status_item.set_text(status_label(&self.server_status));
+        self.pause_menu
+            .set_checked(matches!(self.server_status, ServerStatus::Paused(_)));
+        self.refresh_tray_icon();
+    }
+
+    fn update_theme(&mut self, theme: TaskbarTheme) {
+        debug!("Taskbar theme: {theme:?}");
+        self.theme = theme;
+        self.refresh_tray_icon();
+    }
+
+    fn refresh_tray_icon(&self) {
+        if let Some(tray) = &self.tray_app {
+            tray.set_icon(Some(status_icon(&self.server_status, self.theme)))
+                .and_then(|()| tray.set_tooltip(Some(status_tooltip(&self.server_status))))
+                .unwrap_or_else(|e| error!("Error updating tray icon: {e}"));
+        }
+    }
+
+    /// Rebuild the recent queries submenu, newest first and without repeating names.
+    fn update_recent_queries(&self, queries: &[QueryEvent]) {
+        while self.recent_menu.remove_at(0).is_some() {}
+        let mut seen = HashSet::new();
+        let items = queries
+            .iter()
+            .rev()
+            .filter(|q| seen.insert(&q.name))
+            .map(|q| {
+                let answer = q
+                    .answer
+                    .map_or_else(|| format!("{:?}", q.rescode), |ip| ip.to_string());
+                let label = format!("{} ({:?}) → {answer}", q.name, q.qtype);
+                MenuItem::with_id(
+                    format!("{RECENT_QUERY_PREFIX}{}", q.name),
+                    label,
+                    true,
+                    None,
+                )
+            })
+            .collect::<Vec<_>>();
+        let result = if items.is_empty() {
+            self.recent_menu
+                .append(&MenuItem::new("No queries yet", false, None))
+        } else {
+            items
+                .iter()
+                .try_for_each(|item| self.recent_menu.append(item))
+        };
+        result.unwrap_or_else(|e| error!("Error updating recent queries menu: {e}"));
+    }
+
+    fn update_lookup_menu(&self) {
+        while self.lookup_menu.remove_at(0).is_some() {}
+        let mut result =
+            self.lookup_menu
+                .append(&MenuItem::with_id(LOOKUP_ID, "New Lookup…", true, None));
+        if !self.lookup_history.is_empty() {
+            result =
+                result.and_then(|()| self.lookup_menu.append(&PredefinedMenuItem::separator()));
+        }
+        for host in &self.lookup_history {
+            let id = format!("{LOOKUP_HISTORY_PREFIX}{host}");
+            result = result.and_then(|()| {
+                self.lookup_menu
+                    .append(&MenuItem::with_id(id, host, true, None))
+            });
+        }
+        result.unwrap_or_else(|e| error!("Error updating lookup menu: {e}"));
+    }
+
+    /// Rebuild the records submenu. Large record sets are truncated and the rest of the records can
+    /// be managed by entering the hostname.
+    fn update_records(&self, records: &[RecordEntry]) {
+        while self.records_menu.remove_at(0).is_some() {}
+        let result = if records.is_empty() {
+            self.records_menu
+                .append(&MenuItem::new("No records", false, None))
+        } else {
+            records
+                .iter()
+                .take(MAX_RECORDS_IN_MENU)
+                .try_for_each(|record| self.records_menu.append(&record_submenu(record)))
+                .and_then(|()| {
+                    if records.len() > MAX_RECORDS_IN_MENU {
+                        self.records_menu.append_items(&[
+                            &PredefinedMenuItem::separator(),
+                            &MenuItem::with_id(
+                                RECORD_TOGGLE_PROMPT_ID,
+                                "Disable/Enable Record…",
+                                true,
+                                None,
+                            ),
+                            &MenuItem::with_id(
+                                RECORD_REMOVE_PROMPT_ID,
+                                "Remove Record…",
+                                true,
+                                None,
+                            ),
+                        ])
+                    } else {
+                        Ok(())
+                    }
+                })
+        };
+        result.unwrap_or_else(|e| error!("Error updating records menu: {e}"));
+    }
+
+    fn handle_record_menu_event(&self, id: &str) {
+        if let Some(name) = id.strip_prefix(RECORD_TOGGLE_PREFIX) {
+            self.handle_toggle_record(name.to_owned());
+        } else if let Some(name) = id.strip_prefix(RECORD_REMOVE_PREFIX) {
+            self.handle_remove_record(name.to_owned());
+        } else if id == RECORD_TOGGLE_PROMPT_ID {
+            self.prompt_record_name(InputRequest::ToggleRecord, "Disable/Enable Record");
+        } else if id == RECORD_REMOVE_PROMPT_ID {
+            self.prompt_record_name(InputRequest::RemoveRecord, "Remove Record");
+        }
+    }
+
+    fn prompt_record_name(&self, request: InputRequest, title: &'static str) {
+        let tld = self.app_config.top_level_domain.clone();
+        self.prompt(
+            request,
+            title,
+            "Hostname:".to_owned(),
+            String::new(),
+            move |name| validate_host_name(&name.to_lowercase(), &tld),
+        );
+    }
+
+    /// Ask for input without blocking the event loop. The answer is delivered as a
+    /// [`UserEvent::Input`].
+    fn prompt(
+        &self,
+        request: InputRequest,
+        title: &'static str,
+        prompt: String,
+        initial: String,
+        validate: impl Fn(&str) -> Result<()> + Send + 'static,
+    ) {
+        let proxy = self.proxy.clone();
+        tokio::spawn(async move {
+            if let Some(value) = input_dialog(title, &prompt, &initial, validate).await {
+                proxy
+                    .send_event(UserEvent::Input(request, value))
+                    .unwrap_or_else(|e| error!("Error forwarding input: {e}"));
+            }
+        });
+    }
+
+    fn handle_input(&mut self, request: InputRequest, value: &str) {
+        debug!("Input for {request:?}: {value}");
+        match request {
+            InputRequest::Lookup => self.lookup_host(value),
+            InputRequest::ToggleRecord => self.handle_toggle_record(value.to_lowercase()),
+            InputRequest::RemoveRecord => self.handle_remove_record(value.to_lowercase()),
+            InputRequest::SearchLogs => self.view_logs(Level::TRACE, Some(value)),
+            InputRequest::ReverseLookup => match value.parse() {
+                Ok(addr) => self.reverse_lookup(addr),
+                Err(e) => notify_failure(&format!("Invalid IPv4 address '{value}': {e}")),
+            },
+            InputRequest::LoginArgs => self.set_login_args(value),
+        }
+    }
+
+    fn handle_toggle_record(&self, name: String) {
+        let tx = self.notification_tx.clone();
+        tokio::spawn(async move {
+            match request(&tx, |reply| ToggleRecord(name.clone(), reply)).await {
+                Ok(enabled) => debug!("Record {name} enabled: {enabled}"),
+                Err(e) => {
+                    error!("Error toggling record {name}: {e:#}");
+                    notify_failure(&format!("Error toggling record {name}: {e:#}"));
+                }
+            }
+        });
+    }
+
+    fn handle_remove_record(&self, name: String) {
+        let tx = self.notification_tx.clone();
+        tokio::spawn(async move {
+            let msg = format!("Remove {name} from the records file?");
+            if !confirm_message("Remove Record".to_owned(), msg).await {
+                return;
+            }
+            match request(&tx, |reply| RemoveRecord(name.clone(), reply)).await {
+                Ok(()) => send_notification("Record Removed", &format!("Removed record: {name}")),
+                Err(e) => {
+                    error!("Error removing record {name}: {e:#}");
+                    notify_failure(&format!("Error removing record {name}: {e:#}"));
+                }
+            }
+        });
+    }
+
+    fn register_from_clipboard(&self) {
+        let record = read_clipboard()
+            .context("reading the clipboard")
+            .and_then(|text| clipboard_record(&text, &self.app_config.top_level_domain));
+        let (name, addr) = match record {
+            Ok(record) => record,
+            Err(e) => {
+                error!("Error registering from clipboard: {e:#}");
+                notify_failure(&format!("Error registering from clipboard: {e:#}"));
+                return;
+            }
+        };
+        debug!("Registering {name} ({addr}) from the clipboard");
+        let tx = self.notification_tx.clone();
+        tokio::spawn(async move {
+            match request(&tx, |reply| AddRecord(name.clone(), addr, reply)).await {
+                Ok(()) => {
+                    send_notification("Record Added", &format!("Added record: {name} → {addr}"));
+                }
+                Err(e) => {
+                    error!("Error adding record {name}: {e:#}");
+                    notify_failure(&format!("Error adding record {name}: {e:#}"));
+                }
+            }
+        });
+    }
+
+    fn view_logs(&self, max_level: Level, search: Option<&str>) {
+        debug!("View logs (level: {max_level}, search: {search:?})");
+        let search = search.filter(|s| !s.trim().is_empty());
+        show_log_window(LogTail::new(
+            &self.app_config.logging_dir,
+            max_level,
+            search,
+        ));
+    }
+
+    fn handle_reload_request(&self) {
+        debug!("Reloading Records");
+        let tx = self.notification_tx.clone();
+        tokio::spawn(async move {
+            tx.send(Reload).await.unwrap_or_else(|e| {
+                notify_error!("Error sending reload records message: {e}");
+            });
+        });
+    }
+
+    fn handle_pause_request(&self) {
+        let paused = self.pause_menu.is_checked();
+        debug!("Setting DNS serving paused: {paused}");
+        let tx = self.notification_tx.clone();
+        tokio::spawn(async move {
+            tx.send(SetPaused(paused)).await.unwrap_or_else(|e| {
+                notify_error!("Error sending pause message: {e}");
+            });
+        });
+    }
+
+    /// Version and runtime statistics, a quick health check.
+    fn about_text(&self) -> String {
+        let stats = self.stats_rx.borrow().clone();
+        format!(
+            "{APP_NAME} {APP_VERSION}{}\n\nStatus: {}\nUptime: {}\nQueries served: {}\nRecords reloads: {}\nRecords file: {}",
+            instance().map(|name| format!(" (instance {name})")).unwrap_or_default(),
+            self.server_status.describe(),
+            format_uptime(self.started.elapsed()),
+            stats.queries,
+            stats.reloads,
+            self.app_config.records_file.display(),
+        )
+    }
+
+    fn quit(&self, event_loop: &ActiveEventLoop) {
+        info!("Shutting down");
+        let tx = self.notification_tx.clone();
+        tokio::spawn(async move {
+            tx.send(Shutdown).await.unwrap_or_else(|e| {
+                notify_error!("Error sending shutdown message to application: {e}");
+            });
+        });
+        event_loop.exit();
+    }
+
+    /// Offer to install the update without blocking the event loop. Launching the installer quits
+    /// the app with a [`UserEvent::Quit`].
+    fn handle_update_ready(&self, version: String, installer: PathBuf) {
+        let proxy = self.proxy.clone();
+        tokio::spawn(async move {
+            let msg = format!(
+                "Version {version} was downloaded and verified. Install it now?\n\n{APP_NAME} will exit while the installer runs."
+            );
+            if !confirm_message("Update Ready".to_owned(), msg).await {
+                info!("Postponed installing update {version}");
+                return;
+            }
+            match tokio::task::spawn_blocking(move || launch_installer(&installer)).await {
+                Ok(Ok(())) => {
+                    if proxy
+                        .send_event(UserEvent::Quit("installing the update"))
+                        .is_err()
+                    {
+                        debug!("Event loop closed, not quitting for the installer");
+                    }
+                }
+                Ok(Err(e)) => {
+                    error!("Error installing update: {e:#}");
+                    notify_failure(&format!("Error installing update: {e:#}"));
+                }
+                Err(e) => error!("Launching the installer failed: {e}"),
+            }
+        });
+    }
+
+    /// Offer to restart as administrator without blocking the event loop (Windows asks to allow
+    /// it). The relaunched app takes over after a [`UserEvent::Quit`].
+    fn offer_relaunch_as_admin(&self, error: String) {
+        let proxy = self.proxy.clone();
+        tokio::spawn(async move {
+            let msg = format!(
+                "{error}\n\nRestart {APP_NAME} as administrator? Windows will ask you to allow it."
+            );
+            if !confirm_message("Administrator Rights Required".to_owned(), msg).await {
+                return;
+            }
+            match tokio::task::spawn_blocking(relaunch_as_admin).await {
+                Ok(Ok(())) => {
+                    if proxy
+                        .send_event(UserEvent::Quit("relaunched as administrator"))
+                        .is_err()
+                    {
+                        debug!("Event loop closed, not quitting for the relaunched app");
+                    }
+                }
+                Ok(Err(e)) if e.is::<ElevationDeclined>() => debug!("{e}"),
+                Ok(Err(e)) => {
+                    error!("Error restarting as administrator: {e:#}");
+                    notify_failure(&format!("Error restarting as administrator: {e:#}"));
+                }
+                Err(e) => error!("Restarting as administrator failed: {e}"),
+            }
+        });
+    }
+
+    fn open_config_file(&self) {
+        debug!("Open config file");
+        if let Err(e) = self
+            .app_config
+            .safe_open_config_file()
+            .context("opening config file")
+        {
+            error!("Error: {e:#}");
+            notify_failure(&format!("Error: {e:#}"));
+        }
+    }
+
+    /// Open the admin web UI, passing the token in the URL fragment so it's not sent in requests.
+    fn open_web_admin(&self) {
+        let api = &self.app_config.admin_api;
+        debug!("Opening web admin on port {}", api.port);
+        let url = reqwest::Url::parse_with_params(
+            &format!("http://127.0.0.1:{}/", api.port),
+            [("token", &self.api_token.get())],
+        )
+        .map(|mut url| {
+            let token = url.query().map(str::to_owned);
+            url.set_query(None);
+            url.set_fragment(token.as_deref());
+            url
+        });
+        if let Err(e) = url
+            .context("building web admin URL")
+            .and_then(|url| open::that(url.as_str()).context("opening browser"))
+        {
+            error!("Error: {e:#}");
+            notify_failure(&format!("Error: {e:#}"));
+        }
+    }
+
+    fn handle_menu_event(&mut self, event_loop: &ActiveEventLoop, id: &str) {
+        match id {
+            QUIT_ID => self.quit(event_loop),
+            RELOAD_ID => self.handle_reload_request(),
+            LOGS_ID => {
+                debug!("Open logs directory");
+                if let Err(e) = open_path(&self.app_config.logging_dir) {
+                    notify_error!("Error opening logs directory: {e}");
+                }
+            }
+            STARTUP_ID => self.toggle_start_at_login(),
+            LOGIN_ARGS_ID => self.prompt_login_args(),
+            RECORDS_ID => {
+                debug!("Edit records file");
+                if let Err(e) = safe_open_records_file(&self.app_config.records_file)
+                    .context("opening records file")
+                {
+                    error!("Error: {e:#}");
+                    notify_failure(&format!("Error: {e:#}"));
+                }
+            }
+            LOOKUP_ID => self.handle_lookup_request(),
+            MERGE_ID => {
+                let tx = self.notification_tx.clone();
+                tokio::spawn(handle_merge_request(tx));
+            }
+            DNSMASQ_ID => {
+                tokio::spawn(handle_dnsmasq_import_request(self.notification_tx.clone()));
+            }
+            CLIPBOARD_RECORD_ID => self.register_from_clipboard(),
+            EXPORT_ID => self.handle_export_request(),
+            IMPORT_ID => self.handle_import_request(),
+            CONFIG_ID => self.open_config_file(),
+            HISTORY_ID => {
+                debug!("Open config history");
+                if let Err(e) = open_path(&history_path(self.app_config.config_dir())) {
+                    notify_error!("Error opening config history: {e}");
+                }
+            }
+            UPDATES_ID => {
+                tokio::spawn(handle_updates_request());
+            }
+            SEARCH_LOGS_ID => {
+                let prompt = "Search for:".to_owned();
+                self.prompt(
+                    InputRequest::SearchLogs,
+                    "Search Logs",
+                    prompt,
+                    String::new(),
+                    |_| Ok(()),
+                );
+            }
+            _ if id.starts_with(VIEW_LOGS_PREFIX) => {
+                match Level::from_str(&id[VIEW_LOGS_PREFIX.len()..]) {
+                    Ok(level) => self.view_logs(level, None),
+                    Err(e) => error!("Invalid log level in menu id ({id}): {e}"),
+                }
+            }
+            STATUS_ID => {
+                let status = self.server_status.describe();
+                debug!("Copying status to clipboard: {status}");
+                if let Err(e) = copy_to_clipboard(&status) {
+                    notify_error!("Error copying to clipboard: {e}");
+                }
+            }
+            PAUSE_ID => self.handle_pause_request(),
+            FLUSH_CACHE_ID => {
+                tokio::spawn(handle_flush_cache_request());
+            }
+            FLUSH_ON_RELOAD_ID => self.set_flush_on_reload(),
+            NRPT_ID => self.toggle_nrpt_rule(),
+            FIREWALL_ID => self.toggle_firewall_rule(),
+            DROP_TARGET_ID => self.toggle_drop_target(event_loop),
+            DOCS_ID | REPORT_ISSUE_ID => open_help_page(id),
+            DIAGNOSTICS_ID => self.handle_diagnostics_request(),
+            WEB_ADMIN_ID => self.open_web_admin(),
+            ROTATE_TOKEN_ID => {
+                tokio::spawn(handle_rotate_token_request(self.api_token.clone()));
+            }
+            ABOUT_ID => info_message(format!("About {APP_NAME}"), self.about_text()),
+            REVERSE_LOOKUP_ID => self.handle_reverse_lookup_request(),
+            _ if id.starts_with(LOOKUP_HISTORY_PREFIX) => {
+                self.lookup_host(&id[LOOKUP_HISTORY_PREFIX.len()..]);
+            }
+            _ if id.starts_with(RECENT_QUERY_PREFIX) => {
+                let name = &id[RECENT_QUERY_PREFIX.len()..];
+                debug!("Copying {name} to clipboard");
+                if let Err(e) = copy_to_clipboard(name) {
+                    notify_error!("Error copying to clipboard: {e}");
+                }
+            }
+            _ if is_record_menu_id(id) => {
+                self.handle_record_menu_event(id);
+            }
+            _ => {}
+        }
+    }
+
+    fn toggle_start_at_login(&mut self) {
+        let enabled = self.startup_menu.is_checked();
+        let verb = if enabled { "setting" } else { "disabling" };
+        self.set_auto_launch(enabled).unwrap_or_else(|e| {
+            error!("Error {verb} start at login: {e}");
+            notify_failure(&format!("Error {verb} start at login: {e}"));
+        });
+    }
+
+    fn prompt_login_args(&self) {
+        self.prompt(
+            InputRequest::LoginArgs,
+            "Start at Login Arguments",
+            "Extra arguments for the app started at login (space separated, empty for none):"
+                .to_owned(),
+            self.app_config.login_start.args.join(" "),
+            |input| check_login_args(&split_login_args(input)),
+        );
+    }
+
+    /// Save the new arguments and re-register the app started at login (if enabled) with them.
+    fn set_login_args(&mut self, value: &str) {
+        let args = split_login_args(value);
+        let result = self
+            .app_config
+            .set_login_args(args, ChangeSource::Tray)
+            .and_then(|()| {
+                mk_auto_launch(
+                    self.app_config.start_at_login_method,
+                    &self.app_config.login_start.args,
+                )
+            })
+            .and_then(|manager| {
+                self.publish_config();
+                self.auto_launch_manager = manager.into();
+                if self.app_config.start_at_login {
+                    self.auto_launch_manager.enable()
+                } else {
+                    Ok(())
+                }
+            });
+        if let Err(e) = result {
+            notify_error!("Error setting the start at login arguments: {e:#}");
+        }
+    }
+
+    /// Tell the server about a configuration change that applies right away, for the diagnostics
+    /// dumps.
+    fn publish_config(&self) {
+        let tx = self.notification_tx.clone();
+        let config = Box::new(self.app_config.clone());
+        tokio::spawn(async move {
+            tx.send(SetAppConfig(config))
+                .await
+                .unwrap_or_else(|e| debug!("Not updating the server's configuration: {e}"));
+        });
+    }
+
     fn set_auto_launch(&mut self, launch: bool) -> Result<()> {
-        self.app_config.set_start_at_login(launch)?;
-        if launch {
-            self.auto_launch_manager.enable()
+        self.app_config
+            .set_start_at_login(launch, ChangeSource::Tray)?;
+        self.publish_config();
+        if !launch {
+            return self.auto_launch_manager.disable();
+        }
+        self.auto_launch_manager.enable()?;
+        let method = self.app_config.start_at_login_method;
+        let extra_args = self.app_config.login_start.args.clone();
+        tokio::task::spawn_blocking(move || {
+            remove_other_startup_entry(method, &extra_args)
+                .unwrap_or_else(|e| warn!("Error removing the other startup entry: {e:#}"));
+        });
+        Ok(())
+    }
+
+    fn toggle_drop_target(&mut self, event_loop: &ActiveEventLoop) {
+        if self.drop_target_menu.is_checked() {
+            match DropTarget::open(event_loop) {
+                Ok(target) => self.drop_target = Some(target),
+                Err(e) => {
+                    error!("Error: {e:#}");
+                    notify_failure(&format!("Error: {e:#}"));
+                    self.drop_target_menu.set_checked(false);
+                }
+            }
         } else {
-            self.auto_launch_manager.disable()
+            self.close_drop_target();
         }
     }
 
+    fn close_drop_target(&mut self) {
+        debug!("Closing merge drop target");
+        self.drop_target = None;
+        self.drop_target_menu.set_checked(false);
+    }
+
+    fn set_flush_on_reload(&mut self) {
+        let flush = self.flush_on_reload_menu.is_checked();
+        if let Err(e) = self
+            .app_config
+            .set_flush_dns_cache_on_reload(flush, ChangeSource::Tray)
+        {
+            error!("Error saving flush DNS cache setting: {e:#}");
+            notify_failure(&format!("Error saving flush DNS cache setting: {e:#}"));
+            self.flush_on_reload_menu.set_checked(!flush);
+            return;
+        }
+        self.publish_config();
+        let tx = self.notification_tx.clone();
+        tokio::spawn(async move {
+            tx.send(SetFlushCacheOnReload(flush))
+                .await
+                .unwrap_or_else(|e| {
+                    notify_error!("Error sending flush DNS cache setting: {e}");
+                });
+        });
+    }
+
+    fn toggle_nrpt_rule(&self) {
+        tokio::spawn(handle_nrpt_request(
+            self.nrpt_menu.is_checked(),
+            self.app_config.top_level_domain.clone(),
+            self.app_config.port,
+            self.proxy.clone(),
+        ));
+    }
+
+    fn toggle_firewall_rule(&self) {
+        tokio::spawn(handle_firewall_request(
+            self.firewall_menu.is_checked(),
+            self.app_config.firewall_address(),
+            self.app_config.port,
+            self.proxy.clone(),
+        ));
+    }
+
     fn handle_lookup_request(&self) {
+        let tld = self.app_config.top_level_domain.clone();
+        let msg = format!("Enter a hostname you want verify the address of (should be a valid hostname in the {tld} domain):");
+        let previous = self.lookup_history.first().cloned().unwrap_or_default();
+        self.prompt(
+            InputRequest::Lookup,
+            "Verify Host Lookup",
+            msg,
+            previous,
+            move |host| validate_host_name(&host.to_lowercase(), &tld),
+        );
+    }
+
+    fn lookup_host(&mut self, host: &str) {
+        let host = host.trim().to_lowercase();
+        if let Err(e) = validate_host_name(&host, &self.app_config.top_level_domain) {
+            notify_failure(&format!("{e}"));
+            return;
+        }
+        self.lookup_history.retain(|h| *h != host);
+        self.lookup_history.insert(0, host.clone());
+        self.lookup_history.truncate(MAX_LOOKUP_HISTORY);
+        self.update_lookup_menu();
         let notification_tx = self.notification_tx.clone();
-        let msg = format!("Enter a hostname you want verify the address of (should be a valid hostname in the {} domain):", self.app_config.top_level_domain);
-        if let Some(search_host) = input_box("Verify Host Lookup", &msg, "") {
-            tokio::spawn(async move {
-                match lookup(search_host.clone(), notification_tx).await {
-                    Ok(ip) => info_message(
-                        "Lookup Result".to_owned(),
-                        format!("Lookup resolved to: {ip}"),
+        tokio::spawn(async move {
+            match request(&notification_tx, |tx| ARecordQuery(host.clone(), tx)).await {
+                Ok(result) => info_message(
+                    "Lookup Result".to_owned(),
+                    format!(
+                        "{host} resolved to: {}\n\n{}",
+                        result.addr,
+                        result.matched.describe(&host)
                     ),
-                    Err(e) => {
-                        error_message(format!("Couldn't resolve host '{search_host}': {e:#}"));
+                ),
+                Err(e) => {
+                    notify_failure(&format!("Couldn't resolve host '{host}': {e:#}"));
+                }
+            }
+        });
+    }
+
+    fn handle_reverse_lookup_request(&self) {
+        self.prompt(
+            InputRequest::ReverseLookup,
+            "Which Names Point at This IP?",
+            "Enter an IPv4 address to find the records pointing at it:".to_owned(),
+            String::new(),
+            |input| {
+                input
+                    .parse::<Ipv4Addr>()
+                    .map(|_| ())
+                    .with_context(|| format!("'{input}' is not a valid IPv4 address"))
+            },
+        );
+    }
+
+    fn reverse_lookup(&self, addr: Ipv4Addr) {
+        let notification_tx = self.notification_tx.clone();
+        tokio::spawn(async move {
+            match request(&notification_tx, |tx| ReverseSearch(addr, tx)).await {
+                Ok(entries) => info_message(
+                    "Reverse Lookup Result".to_owned(),
+                    reverse_lookup_message(addr, &entries),
+                ),
+                Err(e) => notify_failure(&format!("Error searching records for {addr}: {e:#}")),
+            }
+        });
+    }
+
+    /// Export the settings without blocking the event loop (see [`export_settings`]).
+    fn handle_export_request(&self) {
+        let config = self.app_config.clone();
+        tokio::spawn(async move {
+            match tokio::task::spawn_blocking(move || export_settings(&config)).await {
+                Ok(Ok(Some(path))) => info_message(
+                    "Export Settings".to_owned(),
+                    format!("Settings exported to: {path}"),
+                ),
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => {
+                    error!("Error: {e:#}");
+                    notify_failure(&format!("Error: {e:#}"));
+                }
+                Err(e) => error!("Exporting settings failed: {e}"),
+            }
+        });
+    }
+
+    /// Ask where to save the diagnostics and have the server write them, without blocking the
+    /// event loop.
+    fn handle_diagnostics_request(&self) {
+        let notification_tx = self.notification_tx.clone();
+        tokio::spawn(async move {
+            let default = match home_dir() {
+                Ok(home) => home.join(format!("{APP_NAME}-diagnostics.json")),
+                Err(e) => {
+                    error!("Error: {e:#}");
+                    notify_failure(&format!("Error: {e:#}"));
+                    return;
+                }
+            };
+            let Some(path) =
+                save_file_dialog("Save Diagnostics", &default, "*.json", "JSON file").await
+            else {
+                return;
+            };
+            match request(&notification_tx, |tx| DumpDiagnostics(path.clone(), tx)).await {
+                Ok(()) => info_message(
+                    "Save Diagnostics".to_owned(),
+                    format!(
+                        "Diagnostics saved to: {}\n\nIt includes the configuration and the records, review it before attaching it to an issue.",
+                        path.display()
+                    ),
+                ),
+                Err(e) => notify_failure(&format!("Error saving the diagnostics: {e:#}")),
+            }
+        });
+    }
+
+    /// Import settings without blocking the event loop (see [`import_settings`]). The imported
+    /// configuration comes back as a [`UserEvent::SettingsImported`].
+    fn handle_import_request(&self) {
+        let mut config = self.app_config.clone();
+        let proxy = self.proxy.clone();
+        tokio::spawn(async move {
+            let imported = tokio::task::spawn_blocking(move || {
+                import_settings(&mut config).map(|imported| imported.then_some(config))
+            })
+            .await;
+            match imported {
+                Ok(Ok(Some(config))) => {
+                    if proxy
+                        .send_event(UserEvent::SettingsImported(Box::new(config)))
+                        .is_err()
+                    {
+                        debug!("Event loop closed, not updating the imported settings");
                     }
+                    info_message(
+                        "Import Settings".to_owned(),
+                        "Settings imported. Restart the application for the changes to take effect."
+                            .to_owned(),
+                    );
                 }
-            });
-        }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => {
+                    error!("Error: {e:#}");
+                    notify_failure(&format!("Error: {e:#}"));
+                }
+                Err(e) => error!("Importing settings failed: {e}"),
+            }
+        });
     }
 }
 
@@ -148,63 +1143,39 @@ impl ApplicationHandler<UserEvent> for Application<'_> {
 
     fn user_event(&mut self, event_loop: &ActiveEventLoop, event: UserEvent) {
         match event {
-            UserEvent::MenuEvent(MenuEvent { id: MenuId(id) }) if id == QUIT_ID => {
-                info!("Shutting down");
-                let tx = self.notification_tx.clone();
-                tokio::spawn(async move {
-                    tx.send(Shutdown).await.unwrap_or_else(|e| {
-                        notify_error!("Error sending shutdown message to application: {e}");
-                    });
-                });
-                event_loop.exit();
+            UserEvent::MenuEvent(MenuEvent { id: MenuId(id) }) => {
+                self.handle_menu_event(event_loop, &id);
             }
-            UserEvent::MenuEvent(MenuEvent { id: MenuId(id) }) if id == RELOAD_ID => {
-                debug!("Reloading Records");
-                let tx = self.notification_tx.clone();
-                tokio::spawn(async move {
-                    tx.send(Reload).await.unwrap_or_else(|e| {
-                        notify_error!("Error sending reload records message: {e}");
-                    });
-                });
+            UserEvent::ServerStatus(status) => {
+                self.update_server_status(status);
             }
-            UserEvent::MenuEvent(MenuEvent { id: MenuId(id) }) if id == LOGS_ID => {
-                debug!("Open logs directory");
-                if let Err(e) = open_path(&self.app_config.logging_dir) {
-                    notify_error!("Error opening logs directory: {e}");
-                }
+            UserEvent::ThemeChanged(theme) => {
+                self.update_theme(theme);
             }
-            UserEvent::MenuEvent(MenuEvent { id: MenuId(id) }) if id == STARTUP_ID => {
-                let enabled = self.startup_menu.is_checked();
-                let verb = if enabled { "setting" } else { "disabling" };
-                self.set_auto_launch(enabled).unwrap_or_else(|e| {
-                    error!("Error {verb} start at login: {e}");
-                    error_message(format!("Error {verb} start at login: {e}"));
-                });
+            UserEvent::RecentQueries(queries) => {
+                self.update_recent_queries(&queries);
             }
-            UserEvent::MenuEvent(MenuEvent { id: MenuId(id) }) if id == RECORDS_ID => {
-                debug!("Edit records file");
-                if let Err(e) = safe_open_records_file(&self.app_config.records_file)
-                    .context("opening records file")
-                {
-                    error!("Error: {e:#}");
-                    error_message(format!("Error: {e:#}"));
-                }
+            UserEvent::Records(records) => {
+                self.update_records(&records);
             }
-            UserEvent::MenuEvent(MenuEvent { id: MenuId(id) }) if id == LOOKUP_ID => {
-                self.handle_lookup_request();
+            UserEvent::UpdateReady { version, installer } => {
+                self.handle_update_ready(version, installer);
             }
-            UserEvent::MenuEvent(MenuEvent { id: MenuId(id) }) if id == MERGE_ID => {
-                let tx = self.notification_tx.clone();
-                tokio::spawn(async move {
-                    if let Err(e) = handle_merge_request(tx).await {
-                        error!("Error: {e:#}");
-                        error_message(format!("Error: {e:#}"));
-                    }
+            UserEvent::Input(request, value) => self.handle_input(request, &value),
+            UserEvent::NrptRule(exists) => self.nrpt_menu.set_checked(exists),
+            UserEvent::FirewallRule(exists) => self.firewall_menu.set_checked(exists),
+            UserEvent::ElevationRequired(error) => self.offer_relaunch_as_admin(error),
+            UserEvent::RepairStartupEntry => {
+                self.set_auto_launch(true).unwrap_or_else(|e| {
+                    error!("Error repairing the startup entry: {e:#}");
+                    notify_failure(&format!("Error repairing the startup entry: {e:#}"));
                 });
             }
-            UserEvent::MenuEvent(_) => {}
-            UserEvent::Shutdown => {
-                event_loop.exit();
+            UserEvent::StartupEntry(registration) => self.reconcile_startup_entry(registration),
+            UserEvent::SettingsImported(config) => *self.app_config = *config,
+            UserEvent::Quit(signal) => {
+                info!("Quitting ({signal})");
+                self.quit(event_loop);
             }
         }
     }
@@ -212,14 +1183,33 @@ impl ApplicationHandler<UserEvent> for Application<'_> {
     fn window_event(
         &mut self,
         _event_loop: &ActiveEventLoop,
-        _window_id: WindowId,
-        _event: WindowEvent,
+        window_id: WindowId,
+        event: WindowEvent,
     ) {
+        let Some(target) = self.drop_target.as_mut().filter(|t| t.id() == window_id) else {
+            return;
+        };
+        match event {
+            WindowEvent::RedrawRequested => target.paint(),
+            WindowEvent::HoveredFile(_) => target.set_hovered(true),
+            WindowEvent::HoveredFileCancelled => target.set_hovered(false),
+            WindowEvent::DroppedFile(path) => {
+                target.set_hovered(false);
+                tokio::spawn(handle_dropped_file(self.notification_tx.clone(), path));
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => target.start_drag(),
+            WindowEvent::CloseRequested => self.close_drop_target(),
+            _ => {}
+        }
     }
 }
 
-fn load_icon(resource: &[u8]) -> tray_icon::Icon {
-    load_rgba(resource)
+fn status_icon(status: &ServerStatus, theme: TaskbarTheme) -> tray_icon::Icon {
+    tray_rgba(status, theme)
         .and_then(|(rgba, width, height)| {
             tray_icon::Icon::from_rgba(rgba, width, height).map_err(Error::from)
         })
@@ -228,15 +1218,245 @@ fn load_icon(resource: &[u8]) -> tray_icon::Icon {
         })
 }
 
-fn load_about_icon(resource: &[u8]) -> Option<tray_icon::menu::Icon> {
-    load_rgba(resource)
-        .and_then(|(rgba, width, height)| {
-            tray_icon::menu::Icon::from_rgba(rgba, width, height).map_err(Error::from)
-        })
-        .inspect_err(|e| {
-            notify_error!("Error loading icon: {e}");
-        })
-        .ok()
+/// The tray icon adjusted to the taskbar theme, scaled to the system icon size and badged on
+/// errors.
+fn tray_rgba(status: &ServerStatus, theme: TaskbarTheme) -> Result<(Vec<u8>, u32, u32)> {
+    let (mut rgba, width, height) = load_rgba(ICON_DATA)?;
+    apply_theme(&mut rgba, theme);
+    let image = RgbaImage::from_raw(width, height, rgba).context("invalid icon data")?;
+    let size = tray_icon_size();
+    let mut rgba = imageops::resize(&image, size, size, FilterType::Lanczos3).into_raw();
+    match status {
+        ServerStatus::Failed(_) => add_badge(&mut rgba, size, size, ERROR_BADGE),
+        ServerStatus::Paused(_) => desaturate(&mut rgba),
+        _ => {}
+    }
+    Ok((rgba, size, size))
+}
+
+/// Recolor the icon for the taskbar theme: the original colors on light taskbars, a lightened
+/// (black) outline on dark ones and a single color ring in the scheme text color in high contrast
+/// mode.
+fn apply_theme(rgba: &mut [u8], theme: TaskbarTheme) {
+    let foreground = text_color();
+    for pixel in rgba.chunks_exact_mut(4) {
+        let luma = luma(pixel);
+        match theme {
+            TaskbarTheme::Light => {}
+            TaskbarTheme::Dark => {
+                if luma < 40 {
+                    pixel[..3].copy_from_slice(&DARK_THEME_OUTLINE);
+                }
+            }
+            TaskbarTheme::HighContrast => {
+                if luma < 128 {
+                    pixel[..3].copy_from_slice(&foreground);
+                } else {
+                    pixel[3] = 0;
+                }
+            }
+        }
+    }
+}
+
+/// The start at login arguments typed in the input dialog, split on whitespace.
+fn split_login_args(input: &str) -> Vec<String> {
+    input.split_whitespace().map(str::to_owned).collect()
+}
+
+/// Gray out the icon (e.g. while paused).
+fn desaturate(rgba: &mut [u8]) {
+    for pixel in rgba.chunks_exact_mut(4) {
+        let luma = luma(pixel);
+        pixel[..3].fill(u8::try_from(luma).unwrap_or(u8::MAX));
+    }
+}
+
+fn luma(pixel: &[u8]) -> u32 {
+    (u32::from(pixel[0]) * 299 + u32::from(pixel[1]) * 587 + u32::from(pixel[2]) * 114) / 1000
+}
+
+/// Draw a filled circle at the bottom right corner of the icon.
+fn add_badge(rgba: &mut [u8], width: u32, height: u32, color: [u8; 4]) {
+    let radius = width.min(height) / 4;
+    let (cx, cy) = (width - radius - 1, height - radius - 1);
+    for y in cy - radius..=cy + radius {
+        for x in cx - radius..=cx + radius {
+            let (dx, dy) = (x.abs_diff(cx), y.abs_diff(cy));
+            if dx * dx + dy * dy <= radius * radius {
+                let i = ((y * width + x) * 4) as usize;
+                rgba[i..i + 4].copy_from_slice(&color);
+            }
+        }
+    }
+}
+
+/// Windows doesn't notify windowless applications about theme changes, so poll for them.
+async fn watch_taskbar_theme(mut theme: TaskbarTheme, proxy: EventLoopProxy<UserEvent>) {
+    loop {
+        tokio::time::sleep(THEME_POLL_INTERVAL).await;
+        let current = TaskbarTheme::current();
+        if current != theme {
+            theme = current;
+            if proxy.send_event(UserEvent::ThemeChanged(theme)).is_err() {
+                return; // event loop is closed
+            }
+        }
+    }
+}
+
+/// Forward the DNS server events (status, recent queries, records) to the event loop.
+pub fn forward_server_events(dns_server: &DnsServer, event_loop: &EventLoop<UserEvent>) {
+    tokio::spawn(forward_server_status(
+        dns_server.subscribe_status(),
+        event_loop.create_proxy(),
+    ));
+    tokio::spawn(forward_changes(
+        dns_server.subscribe_recent_queries(),
+        event_loop.create_proxy(),
+        RECENT_QUERIES_REFRESH,
+        |queries| UserEvent::RecentQueries(queries.iter().cloned().collect()),
+    ));
+    tokio::spawn(forward_changes(
+        dns_server.subscribe_records(),
+        event_loop.create_proxy(),
+        Duration::ZERO,
+        |records| UserEvent::Records(records.clone()),
+    ));
+}
+
+async fn forward_server_status(
+    mut status_rx: watch::Receiver<ServerStatus>,
+    proxy: EventLoopProxy<UserEvent>,
+) {
+    loop {
+        let status = status_rx.borrow_and_update().clone();
+        if proxy.send_event(UserEvent::ServerStatus(status)).is_err() {
+            return; // event loop is closed
+        }
+        if status_rx.changed().await.is_err() {
+            // The DNS server is gone without reporting (e.g. it panicked)
+            let last = status_rx.borrow().clone();
+            if !matches!(last, ServerStatus::Stopped | ServerStatus::Failed(_)) {
+                let failed = ServerStatus::Failed("DNS server stopped unexpectedly".to_owned());
+                _ = proxy.send_event(UserEvent::ServerStatus(failed));
+            }
+            return;
+        }
+    }
+}
+
+/// Forward the changes of a watched value to the event loop (at most once per `min_interval`).
+async fn forward_changes<T>(
+    mut rx: watch::Receiver<T>,
+    proxy: EventLoopProxy<UserEvent>,
+    min_interval: Duration,
+    to_event: impl Fn(&T) -> UserEvent,
+) {
+    loop {
+        let event = to_event(&rx.borrow_and_update());
+        if proxy.send_event(event).is_err() {
+            return; // event loop is closed
+        }
+        if !min_interval.is_zero() {
+            tokio::time::sleep(min_interval).await;
+        }
+        if rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+fn view_logs_submenu() -> Submenu {
+    let item = |level: Level, label: &str| {
+        MenuItem::with_id(format!("{VIEW_LOGS_PREFIX}{level}"), label, true, None)
+    };
+    let submenu = Submenu::new("View Logs", true);
+    submenu
+        .append_items(&[
+            &item(Level::TRACE, "All Entries"),
+            &item(Level::INFO, "Info and Above"),
+            &item(Level::WARN, "Warnings and Errors"),
+            &item(Level::ERROR, "Errors Only"),
+            &PredefinedMenuItem::separator(),
+            &MenuItem::with_id(SEARCH_LOGS_ID, "Search…", true, None),
+        ])
+        .unwrap_or_else(|e| error!("Error creating view logs menu: {e}"));
+    submenu
+}
+
+fn help_submenu() -> Submenu {
+    let submenu = Submenu::new("Help", true);
+    submenu
+        .append_items(&[
+            &MenuItem::with_id(DOCS_ID, "Open Documentation", true, None),
+            &MenuItem::with_id(REPORT_ISSUE_ID, "Report an Issue…", true, None),
+            &MenuItem::with_id(DIAGNOSTICS_ID, "Save Diagnostics…", true, None),
+        ])
+        .unwrap_or_else(|e| error!("Error creating help menu: {e}"));
+    submenu
+}
+
+fn open_help_page(id: &str) {
+    let url = if id == DOCS_ID {
+        Ok(DOCS_URL.to_owned())
+    } else {
+        new_issue_url()
+    };
+    debug!("Opening help page: {url:?}");
+    if let Err(e) = url.and_then(|url| open::that(url).context("opening browser")) {
+        error!("Error: {e:#}");
+        notify_failure(&format!("Error: {e:#}"));
+    }
+}
+
+/// A new issue URL with the body pre-filled with the version and environment details.
+fn new_issue_url() -> Result<String> {
+    let body = format!(
+        "**Describe the problem**\n\n\n**Steps to reproduce**\n\n\n---\n{APP_NAME} version: {APP_VERSION}\nOS: {}\n",
+        os_version()
+    );
+    let url = reqwest::Url::parse_with_params(NEW_ISSUE_URL, &[("body", body)])?;
+    Ok(url.into())
+}
+
+fn is_record_menu_id(id: &str) -> bool {
+    id.starts_with(RECORD_TOGGLE_PREFIX)
+        || id.starts_with(RECORD_REMOVE_PREFIX)
+        || id == RECORD_TOGGLE_PROMPT_ID
+        || id == RECORD_REMOVE_PROMPT_ID
+}
+
+fn record_submenu(record: &RecordEntry) -> Submenu {
+    if let RecordSource::Sync(source) = &record.source {
+        // synced records can only be changed at their source
+        let submenu = Submenu::new(
+            format!("{} → {} ({source})", record.name, record.addr),
+            true,
+        );
+        submenu
+            .append(&MenuItem::new(format!("Synced from {source}"), false, None))
+            .unwrap_or_else(|e| error!("Error creating record menu: {e}"));
+        return submenu;
+    }
+    let (label, toggle) = if record.enabled {
+        (format!("{} → {}", record.name, record.addr), "Disable")
+    } else {
+        (
+            format!("{} → {} (disabled)", record.name, record.addr),
+            "Enable",
+        )
+    };
+    let toggle_id = format!("{RECORD_TOGGLE_PREFIX}{}", record.name);
+    let remove_id = format!("{RECORD_REMOVE_PREFIX}{}", record.name);
+    let submenu = Submenu::new(label, true);
+    submenu
+        .append_items(&[
+            &MenuItem::with_id(toggle_id, toggle, true, None),
+            &MenuItem::with_id(remove_id, "Remove…", true, None),
+        ])
+        .unwrap_or_else(|e| error!("Error creating record menu: {e}"));
+    submenu
 }
 
 fn load_rgba(resource: &[u8]) -> Result<(Vec<u8>, u32, u32)> {
@@ -246,66 +1466,440 @@ fn load_rgba(resource: &[u8]) -> Result<(Vec<u8>, u32, u32)> {
     Ok((rgb.into_raw(), width, height))
 }
 
-fn about_manifest() -> AboutMetadata {
-    let icon_data = include_bytes!("../resources/Icon.png");
-    let icon = load_about_icon(icon_data);
-    AboutMetadataBuilder::new()
-        .name(Some(APP_NAME))
-        .icon(icon)
-        .version(Some(APP_VERSION))
-        .build()
+fn format_uptime(uptime: Duration) -> String {
+    let minutes = uptime.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+    if days > 0 {
+        format!("{days}d {hours}h {minutes}m")
+    } else {
+        format!("{hours}h {minutes}m")
+    }
+}
+
+fn reverse_lookup_message(addr: Ipv4Addr, entries: &[RecordEntry]) -> String {
+    if entries.is_empty() {
+        return format!("No records point at {addr}");
+    }
+    let names = entries
+        .iter()
+        .map(|e| match &e.source {
+            RecordSource::Sync(source) => format!("{} ({source})", e.name),
+            RecordSource::RecordsFile if e.enabled => e.name.clone(),
+            RecordSource::RecordsFile => format!("{} (disabled)", e.name),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("Records pointing at {addr}:\n\n{names}")
+}
+
+/// Add (`add`) or remove the NRPT rule, then show the actual state in the menu.
+async fn handle_nrpt_request(add: bool, tld: String, port: u16, proxy: EventLoopProxy<UserEvent>) {
+    let result = if add {
+        let msg = format!(
+            "Windows sends NRPT queries to port 53, but the server listens on port {port}. Add the rule anyway?"
+        );
+        if port == 53 || confirm_message("Add NRPT Rule".to_owned(), msg).await {
+            add_nrpt_rule(&tld).await
+        } else {
+            Ok(())
+        }
+    } else {
+        remove_nrpt_rule(&tld).await
+    };
+    if let Err(e) = result.or_else(ignore_declined_elevation) {
+        error!("Error updating the NRPT rule: {e:#}");
+        notify_failure(&format!("Error updating the NRPT rule: {e:#}"));
+    }
+    refresh_nrpt_rule(tld, proxy).await;
 }
 
-fn notify_user_about_mismatch_auto_launch(app: bool, system: bool) {
-    let tr = |b: bool| {
-        if b {
-            "enabled"
+/// Add (`add`) or remove the firewall rule, then show the actual state in the menu.
+async fn handle_firewall_request(
+    add: bool,
+    address: Ipv4Addr,
+    port: u16,
+    proxy: EventLoopProxy<UserEvent>,
+) {
+    let result = if add {
+        let msg = format!(
+            "The server only listens on {address}, so other devices can't query it. Add the rule anyway?"
+        );
+        if !address.is_loopback() || confirm_message("Firewall Rule".to_owned(), msg).await {
+            add_firewall_rule(address, port).await
         } else {
-            "disabled"
+            Ok(())
         }
+    } else {
+        remove_firewall_rule().await
+    };
+    if let Err(e) = result.or_else(ignore_declined_elevation) {
+        error!("Error updating the firewall rule: {e:#}");
+        notify_failure(&format!("Error updating the firewall rule: {e:#}"));
+    }
+    refresh_firewall_rule(proxy).await;
+}
+
+/// In LAN mode, offer to add the firewall rule if it's missing.
+async fn offer_firewall_rule(address: Ipv4Addr, port: u16, proxy: EventLoopProxy<UserEvent>) {
+    if !address.is_loopback() && matches!(firewall_rule_exists().await, Ok(false)) {
+        let msg = format!(
+            "{APP_NAME} listens on {address}:{port} for other devices on the network, but Windows Firewall may block their queries.\n\nAdd a firewall rule allowing DNS queries from the local subnet?"
+        );
+        if confirm_message("Allow LAN Queries".to_owned(), msg).await {
+            handle_firewall_request(true, address, port, proxy).await;
+            return;
+        }
+    }
+    refresh_firewall_rule(proxy).await;
+}
+
+async fn offer_startup_entry_repair(
+    registered: PathBuf,
+    exe: PathBuf,
+    proxy: EventLoopProxy<UserEvent>,
+) {
+    let reason = if registered.exists() {
+        "another copy of the app"
+    } else {
+        "which doesn't exist anymore"
     };
-    let in_app = tr(app);
-    let in_system = tr(system);
     let msg = format!(
-        concat!(
-            "There is a mismatch in configured starting at login between the application ",
-            r#"({}) and the system ({})!"#,
-            "\n\nWe've set the application to match the system settings ({}). You can set it to",
-            "your liking using the menu in the system tray."
-        ),
-        in_app, in_system, in_system
+        "The startup entry starts {} ({reason}), so this copy won't start at login.\n\nStart {} at login instead?",
+        registered.display(),
+        exe.display()
     );
+    if confirm_message("Start at Login".to_owned(), msg).await
+        && proxy.send_event(UserEvent::RepairStartupEntry).is_err()
+    {
+        debug!("Event loop closed, not repairing the startup entry");
+    }
+}
 
-    error_message(msg);
+async fn refresh_firewall_rule(proxy: EventLoopProxy<UserEvent>) {
+    match firewall_rule_exists().await {
+        Ok(exists) => {
+            if proxy.send_event(UserEvent::FirewallRule(exists)).is_err() {
+                debug!("Event loop closed, not updating the firewall menu");
+            }
+        }
+        Err(e) => warn!("Error checking the firewall rules: {e:#}"),
+    }
 }
-async fn lookup(host: String, notification_tx: Sender<Notification>) -> Result<Ipv4Addr> {
-    let (tx, rx) = oneshot::channel();
-    notification_tx
-        .clone()
-        .send(ARecordQuery(host, tx))
-        .await
-        .context("sending request channel")?;
-    rx.await?
+
+/// Declining the elevation is the user's choice, not an error to report.
+fn ignore_declined_elevation(e: Error) -> Result<()> {
+    if e.is::<ElevationDeclined>() {
+        debug!("{e}");
+        Ok(())
+    } else {
+        Err(e)
+    }
+}
+
+async fn refresh_nrpt_rule(tld: String, proxy: EventLoopProxy<UserEvent>) {
+    match nrpt_rule_exists(&tld).await {
+        Ok(exists) => {
+            if proxy.send_event(UserEvent::NrptRule(exists)).is_err() {
+                debug!("Event loop closed, not updating the NRPT menu");
+            }
+        }
+        Err(e) => warn!("Error checking the NRPT rules: {e:#}"),
+    }
+}
+
+async fn handle_updates_request() {
+    if let Err(e) = check_and_report_updates().await {
+        error!("Error checking for updates: {e:#}");
+        notify_failure(&format!("Error checking for updates: {e:#}"));
+    }
+}
+
+async fn check_and_report_updates() -> Result<()> {
+    debug!("Checking for updates");
+    match check_for_updates().await? {
+        Some(release) => {
+            let msg = format!(
+                "Version {} is available (running {}).\n\nOpen the release page?",
+                release.tag_name,
+                env!("CARGO_PKG_VERSION")
+            );
+            if confirm_message("Update Available".to_owned(), msg).await {
+                open::that(&release.html_url).context("opening release page")?;
+            }
+        }
+        None => info_message(
+            "No Updates".to_owned(),
+            format!(
+                "You are running the latest version ({}).",
+                env!("CARGO_PKG_VERSION")
+            ),
+        ),
+    }
+    Ok(())
+}
+
+fn merge_summary_message(summary: &MergeSummary) -> String {
+    let overridden = if summary.conflicts.is_empty() {
+        String::new()
+    } else {
+        format!(
+            ", {} overridden ({})",
+            summary.conflicts.len(),
+            summary.conflicts.join(", ")
+        )
+    };
+    format!(
+        "Merged records: {} added{overridden}. This will hold until you Reload the records or restart the application.",
+        summary.added
+    )
+}
+
+fn home_dir() -> Result<PathBuf> {
+    dirs::home_dir().context("Couldn't get home directory")
+}
+
+/// Ask where to export the settings (and whether to include the logs), then write the archive.
+/// The dialogs and the archive I/O block, so it runs on a blocking thread. Returns the path of the
+/// archive unless cancelled.
+fn export_settings(config: &AppConfig) -> Result<Option<String>> {
+    let default = home_dir()?.join(format!("{APP_NAME}-settings.zip"));
+    let Some(path) = tinyfiledialogs::save_file_dialog_with_filter(
+        "Export Settings",
+        &default.to_string_lossy(),
+        &["*.zip"],
+        "Zip archive",
+    ) else {
+        return Ok(None);
+    };
+    let include_logs = message_box_yes_no(
+        "Export Settings",
+        "Include the log files in the archive (useful when reporting a bug)?",
+        MessageBoxIcon::Question,
+        YesNo::No,
+    ) == YesNo::Yes;
+    export_bundle(config, Path::new(&path), include_logs).context("exporting settings")?;
+    Ok(Some(path))
+}
+
+/// Ask for a settings archive and import it into `config` once confirmed, on a blocking thread like
+/// [`export_settings`]. Returns whether the settings were imported.
+fn import_settings(config: &mut AppConfig) -> Result<bool> {
+    let home = home_dir()?;
+    let Some(path) = tinyfiledialogs::open_file_dialog(
+        "Import Settings",
+        &home.to_string_lossy(),
+        Some((&["*.zip"], "Zip archive")),
+    ) else {
+        return Ok(false);
+    };
+    let msg = concat!(
+        "Importing settings overwrites the current configuration and records file ",
+        "(a backup of the records file is saved). Continue?"
+    );
+    if message_box_yes_no("Import Settings", msg, MessageBoxIcon::Warning, YesNo::No) == YesNo::No {
+        return Ok(false);
+    }
+    import_bundle(config, Path::new(&path), ChangeSource::Tray).context("importing settings")?;
+    Ok(true)
+}
+
+async fn handle_merge_request(notify_tx: Sender<Notification>) {
+    if let Err(e) = merge_records_from_file(notify_tx).await {
+        error!("Error: {e:#}");
+        notify_failure(&format!("Error: {e:#}"));
+    }
 }
 
-async fn handle_merge_request(notify_tx: Sender<Notification>) -> Result<()> {
-    let home = dirs::home_dir().context("Couldn't get home directory")?;
+async fn merge_records_from_file(notify_tx: Sender<Notification>) -> Result<()> {
+    let home = home_dir()?;
     let home_str = home
         .to_str()
         .context("Couldn't convert home directory to string")?;
     if let Some(path) = tinyfiledialogs::open_file_dialog("Open Records file", home_str, None) {
-        let (tx, rx) = oneshot::channel();
-        notify_tx
-            .send(MergeRecords(path.into(), tx))
-            .await
-            .context("Sending merge notification")?;
-        rx.await?
-            .inspect(|()| {
-                info_message(
-                    "Merge Records Succeeded".to_owned(),
-                    "Successfully merged records. This will hold until you Reload the records or restart the application.".to_owned(),
-                );
-            })?;
+        merge_records(notify_tx, path.into()).await?;
     }
     Ok(())
 }
+
+async fn handle_rotate_token_request(token: ApiToken) {
+    let msg = "Replace the API token? Tools and open web admin pages using the current token will \
+be rejected (dlocal reads the new one)."
+        .to_owned();
+    if !confirm_message("Rotate API Token".to_owned(), msg).await {
+        return;
+    }
+    match token.rotate(instance()) {
+        Ok(()) => {
+            info!("Rotated the API token");
+            info_message(
+                "Rotate API Token".to_owned(),
+                "The API token was replaced.".to_owned(),
+            );
+        }
+        Err(e) => {
+            error!("Error: {e:#}");
+            notify_failure(&format!("Error rotating the API token: {e:#}"));
+        }
+    }
+}
+
+async fn handle_dnsmasq_import_request(notify_tx: Sender<Notification>) {
+    if let Err(e) = import_dnsmasq_config(notify_tx).await {
+        error!("Error: {e:#}");
+        notify_failure(&format!("Error: {e:#}"));
+    }
+}
+
+/// Add the `address=` records of a dnsmasq configuration file to the records file.
+async fn import_dnsmasq_config(notify_tx: Sender<Notification>) -> Result<()> {
+    let home = home_dir()?;
+    let home_str = home
+        .to_str()
+        .context("Couldn't convert home directory to string")?;
+    let Some(path) = open_file_dialog("Import dnsmasq Config", home_str).await else {
+        return Ok(());
+    };
+    let config = tokio::fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("reading {path}"))?;
+    let report = request(&notify_tx, |reply| {
+        ImportRecords(config, ImportFormat::Dnsmasq, reply)
+    })
+    .await?;
+    let message = dnsmasq_import_message(&report);
+    if report.applied {
+        info_message("dnsmasq Import".to_owned(), message);
+    } else {
+        notify_failure(&message);
+    }
+    Ok(())
+}
+
+/// The record in the clipboard `text`: a hostname in the `tld` domain, optionally with its address
+/// before or after it (as in a hosts file), e.g. `app.loc` (pointing at `127.0.0.1`) or
+/// `nas.loc 192.168.1.20`.
+fn clipboard_record(text: &str, tld: &str) -> Result<(String, Ipv4Addr)> {
+    let words: Vec<_> = text.split_whitespace().collect();
+    let (host, addr) = match words[..] {
+        [host] => (host, Ipv4Addr::LOCALHOST),
+        [first, second] => match (first.parse(), second.parse()) {
+            (Ok(addr), _) => (second, addr),
+            (_, Ok(addr)) => (first, addr),
+            _ => return Err(anyhow!("'{second}' is not an IPv4 address")),
+        },
+        [] => return Err(anyhow!("The clipboard holds no text")),
+        _ => {
+            return Err(anyhow!(
+                "The clipboard should hold a hostname, optionally with its IPv4 address"
+            ))
+        }
+    };
+    let host = host.trim_end_matches('.').to_lowercase();
+    validate_host_name(&host, tld)?;
+    Ok((host, addr))
+}
+
+/// Invalid lines shown in the import error, the rest are only counted.
+const MAX_IMPORT_ERRORS: usize = 10;
+
+fn dnsmasq_import_message(report: &ImportReport) -> String {
+    if report.lines.is_empty() {
+        return "No address=/hostname/IP lines found in the file.".to_owned();
+    }
+    let count = |matches: fn(&ImportOutcome) -> bool| {
+        report
+            .lines
+            .iter()
+            .filter(|(_, outcome)| matches(outcome))
+            .count()
+    };
+    if report.applied {
+        let skipped = count(|outcome| matches!(outcome, ImportOutcome::Skipped(..)));
+        return format!(
+            "Imported {} new and {} changed record(s), {} already defined.{}",
+            count(|outcome| matches!(outcome, ImportOutcome::Added(..))),
+            count(|outcome| matches!(outcome, ImportOutcome::Updated(..))),
+            count(|outcome| matches!(outcome, ImportOutcome::Unchanged(..))),
+            if skipped > 0 {
+                format!(" Skipped {skipped} line(s) with IPv6 addresses.")
+            } else {
+                String::new()
+            }
+        );
+    }
+    let mut errors: Vec<_> = report
+        .lines
+        .iter()
+        .filter_map(|(line, outcome)| match outcome {
+            ImportOutcome::Invalid(error) => Some(format!("line {line}: {error}")),
+            _ => None,
+        })
+        .collect();
+    if errors.len() > MAX_IMPORT_ERRORS {
+        let more = errors.len() - MAX_IMPORT_ERRORS;
+        errors.truncate(MAX_IMPORT_ERRORS);
+        errors.push(format!("… and {more} more"));
+    }
+    format!(
+        "Nothing was imported, fix these lines and try again:\n\n{}",
+        errors.join("\n")
+    )
+}
+
+async fn handle_dropped_file(notify_tx: Sender<Notification>, path: PathBuf) {
+    debug!("Records file dropped: {}", path.display());
+    let msg = format!(
+        "Merge the records from {} into your records file?",
+        path.display()
+    );
+    if !confirm_message("Merge Records".to_owned(), msg).await {
+        return;
+    }
+    if let Err(e) = merge_records(notify_tx, path).await {
+        error!("Error: {e:#}");
+        notify_failure(&format!("Error: {e:#}"));
+    }
+}
+
+async fn merge_records(notify_tx: Sender<Notification>, path: PathBuf) -> Result<()> {
+    let (tx, rx) = oneshot::channel();
+    notify_tx
+        .send(MergeRecords(path, tx))
+        .await
+        .context("Sending merge notification")?;
+    let summary = rx.await??;
+    let message = merge_summary_message(&summary);
+    if should_notify(NotificationEvent::Merge) || !summary.conflicts.is_empty() {
+        info_message("Merge Records Succeeded".to_owned(), message);
+    } else {
+        info!("{message}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clipboard_records() {
+        let record = |text| clipboard_record(text, ".loc").map_err(|e| e.to_string());
+        let nas = Ok(("nas.loc".to_owned(), Ipv4Addr::new(192, 168, 1, 20)));
+        assert_eq!(
+            record(" App.loc.\n"),
+            Ok(("app.loc".to_owned(), Ipv4Addr::LOCALHOST))
+        );
+        assert_eq!(record("nas.loc 192.168.1.20"), nas);
+        assert_eq!(record("192.168.1.20\tnas.loc"), nas);
+        assert_eq!(
+            record("nas.loc 192.168.1"),
+            Err("'192.168.1' is not an IPv4 address".to_owned())
+        );
+        assert_eq!(
+            record("example.com"),
+            Err("'example.com' is not a host in the .loc domain".to_owned())
+        );
+        assert!(record("").is_err());
+        assert!(record("nas.loc is down").is_err());
+    }
+}
@@ -0,0 +1,144 @@
+//! `--uninstall-cleanup`: undo the changes the app made to the system, so nothing breaks DNS after
+//! the executable is deleted (a leftover NRPT rule or adapter DNS points at a server that's gone).
+//! Run it with `--headless` from an installer's custom action: nothing is shown, and the exit code
+//! reports failures.
+
+use crate::adapter_dns::AdapterDns;
+use crate::app_config::StartAtLoginMethod;
+use crate::elevation::ElevationDeclined;
+use crate::firewall::{firewall_rule_exists, remove_firewall_rule};
+use crate::nrpt::{nrpt_rule_exists, remove_nrpt_rule};
+use crate::perf_counters;
+use crate::prelude::*;
+use std::future::Future;
+
+/// The outcome of the cleanup steps.
+#[derive(Default)]
+struct Report {
+    lines: Vec<String>,
+    failed: bool,
+}
+
+impl Report {
+    /// Record a step: `Ok(true)` if something was removed, `Ok(false)` if there was nothing to.
+    fn step(&mut self, description: &str, result: Result<bool>) {
+        match result {
+            Ok(true) => self.lines.push(format!("✔ {description}")),
+            Ok(false) => debug!("Nothing to clean up: {description}"),
+            Err(e) if e.is::<ElevationDeclined>() => {
+                self.failed = true;
+                self.lines.push(format!("✘ {description}: skipped, {e}"));
+            }
+            Err(e) => {
+                self.failed = true;
+                self.lines.push(format!("✘ {description}: {e:#}"));
+            }
+        }
+    }
+
+    fn summary(&self) -> String {
+        if self.lines.is_empty() {
+            return "There was nothing to clean up.".to_owned();
+        }
+        self.lines.join("\n")
+    }
+}
+
+/// Remove the startup entry, the NRPT and firewall rules, the performance counters and a leftover
+/// adapter DNS change, and with `remove_data` the configuration, records and logs.
+pub async fn uninstall_cleanup(app_config: &AppConfig, remove_data: bool) -> Result<()> {
+    let mut report = Report::default();
+    report.step(
+        "Removed the startup entry",
+        disable_auto_launch(StartAtLoginMethod::Registry),
+    );
+    report.step(
+        "Removed the startup scheduled task",
+        disable_auto_launch(StartAtLoginMethod::ScheduledTask),
+    );
+    report.step(
+        "Restored the adapter DNS",
+        AdapterDns::restore_leftover(app_config.config_dir()).await,
+    );
+    let tld = &app_config.top_level_domain;
+    report.step(
+        &format!("Removed the NRPT rule for {tld}"),
+        remove_if(nrpt_rule_exists(tld).await, remove_nrpt_rule(tld)).await,
+    );
+    report.step(
+        "Removed the firewall rule",
+        remove_if(firewall_rule_exists().await, remove_firewall_rule()).await,
+    );
+    // only the default instance publishes them
+    if instance().is_none() {
+        report.step(
+            "Removed the performance counters",
+            remove_if(
+                Ok(perf_counters::is_registered()),
+                perf_counters::unregister_counters(app_config.config_dir()),
+            )
+            .await,
+        );
+    }
+    if remove_data {
+        report.step("Removed the logs", remove_dir(&app_config.logging_dir));
+        report.step(
+            "Removed the configuration and records",
+            remove_dir(app_config.config_dir()),
+        );
+    }
+    let summary = report.summary();
+    println!("{summary}");
+    if report.failed {
+        return Err(anyhow!("Cleanup failed:\n{summary}"));
+    }
+    info_message(format!("{APP_NAME} Cleanup"), summary);
+    Ok(())
+}
+
+fn disable_auto_launch(method: StartAtLoginMethod) -> Result<bool> {
+    // the arguments don't matter to remove it
+    let auto_launch = mk_auto_launch(method, &[])?;
+    if !auto_launch.is_enabled()? {
+        return Ok(false);
+    }
+    auto_launch.disable()?;
+    Ok(true)
+}
+
+/// Run `remove` if `exists`.
+async fn remove_if(exists: Result<bool>, remove: impl Future<Output = Result<()>>) -> Result<bool> {
+    if !exists? {
+        return Ok(false);
+    }
+    remove.await?;
+    Ok(true)
+}
+
+fn remove_dir(dir: &Path) -> Result<bool> {
+    match fs::remove_dir_all(dir) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("removing {}", dir.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_lists_the_removed_and_failed_steps() {
+        let mut report = Report::default();
+        assert_eq!(report.summary(), "There was nothing to clean up.");
+        report.step("Removed the startup entry", Ok(true));
+        report.step("Removed the firewall rule", Ok(false));
+        assert!(!report.failed);
+        report.step("Removed the NRPT rule", Err(ElevationDeclined.into()));
+        assert!(report.failed);
+        assert_eq!(
+            report.summary(),
+            "✔ Removed the startup entry\n✘ Removed the NRPT rule: skipped, administrator rights were declined"
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use crate::app_config::UpdatesConfig;
+use crate::prelude::*;
+use semver::Version;
+use serde::Deserialize;
+use std::process::Command;
+use std::time::Duration;
+use windows_strings::HSTRING;
+use windows_sys::Win32::Security::Cryptography::{
+    CertGetNameStringW, CERT_NAME_SIMPLE_DISPLAY_TYPE,
+};
+use windows_sys::Win32::Security::WinTrust::{
+    WTHelperGetProvSignerFromChain, WTHelperProvDataFromStateData, WinVerifyTrust,
+    WINTRUST_ACTION_GENERIC_VERIFY_V2, WINTRUST_DATA, WINTRUST_DATA_0, WINTRUST_FILE_INFO,
+    WTD_CHOICE_FILE, WTD_REVOCATION_CHECK_CHAIN_EXCLUDE_ROOT, WTD_REVOKE_WHOLECHAIN,
+    WTD_STATEACTION_CLOSE, WTD_STATEACTION_VERIFY, WTD_UI_NONE,
+};
+use winit::event_loop::EventLoopProxy;
+
+const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/babysnakes/dot-local-dns/releases/latest";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_mins(5);
+const INSTALLER_SUFFIX: &str = "-setup.exe";
+/// The subject of the code signing certificate the installers are signed with (see the
+/// `signing-command` of the packager configuration).
+const PUBLISHER: &str = "babysnakes";
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Release {
+    pub tag_name: String,
+    pub html_url: String,
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release {
+    pub fn version(&self) -> Result<Version> {
+        let version = self.tag_name.trim_start_matches('v');
+        Version::parse(version).with_context(|| format!("invalid release tag: {}", self.tag_name))
+    }
+
+    fn installer_asset(&self) -> Result<&Asset> {
+        self.assets
+            .iter()
+            .find(|a| a.name.ends_with(INSTALLER_SUFFIX))
+            .ok_or_else(|| anyhow!("Release {} has no installer", self.tag_name))
+    }
+}
+
+pub fn current_version() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION")).expect("package version is valid semver")
+}
+
+/// Query GitHub for the latest release. Returns the release if it's newer than the running version.
+pub async fn check_for_updates() -> Result<Option<Release>> {
+    let release = fetch_latest_release().await?;
+    if release.version()? > current_version() {
+        info!("New release available: {}", release.tag_name);
+        Ok(Some(release))
+    } else {
+        debug!("Running the latest release ({})", release.tag_name);
+        Ok(None)
+    }
+}
+
+/// Periodically check for new releases. When one is found, its installer is downloaded and
+/// verified, and the tray is asked to offer installing it. Each release is only offered once.
+pub async fn auto_update(config: UpdatesConfig, dir: PathBuf, proxy: EventLoopProxy<UserEvent>) {
+    let period = Duration::from_secs(config.check_interval_hours.max(1) * 60 * 60);
+    let mut interval = tokio::time::interval(period);
+    let mut offered = None;
+    loop {
+        interval.tick().await;
+        let release = match check_for_updates().await {
+            Ok(Some(release)) if Some(&release.tag_name) != offered.as_ref() => release,
+            Ok(_) => continue,
+            Err(e) => {
+                warn!("Error checking for updates: {e:#}");
+                continue;
+            }
+        };
+        match download_installer(&release, &dir).await {
+            Ok(installer) => {
+                let event = UserEvent::UpdateReady {
+                    version: release.tag_name.clone(),
+                    installer,
+                };
+                if proxy.send_event(event).is_err() {
+                    return; // event loop is closed
+                }
+                offered = Some(release.tag_name);
+            }
+            Err(e) => warn!("Error downloading update {}: {e:#}", release.tag_name),
+        }
+    }
+}
+
+/// Download the release installer into `dir` and verify its signature. An installer that fails
+/// the verification is deleted.
+pub async fn download_installer(release: &Release, dir: &Path) -> Result<PathBuf> {
+    let installer = release.installer_asset()?;
+    let client = http_client(DOWNLOAD_TIMEOUT)?;
+    let contents = client
+        .get(&installer.browser_download_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await
+        .context("downloading installer")?;
+    tokio::fs::create_dir_all(dir).await?;
+    let path = dir.join(&installer.name);
+    tokio::fs::write(&path, &contents)
+        .await
+        .with_context(|| format!("saving installer: {}", path.display()))?;
+    let verified = tokio::task::spawn_blocking({
+        let path = path.clone();
+        move || verify_signature(&path)
+    })
+    .await?;
+    if let Err(e) = verified {
+        let _ = tokio::fs::remove_file(&path).await;
+        return Err(e.context(format!("verifying {}", installer.name)));
+    }
+    info!("Downloaded and verified update: {}", path.display());
+    Ok(path)
+}
+
+/// Start the (interactive) installer once its signature is verified (again, it may have been
+/// replaced since it was downloaded). The application should exit right after so it can be
+/// replaced; the installer offers to run the new version when it's done.
+pub fn launch_installer(installer: &Path) -> Result<()> {
+    verify_signature(installer).with_context(|| format!("verifying {}", installer.display()))?;
+    info!("Launching installer: {}", installer.display());
+    Command::new(installer)
+        .spawn()
+        .with_context(|| format!("launching installer: {}", installer.display()))?;
+    Ok(())
+}
+
+/// Verify the Authenticode signature of `path` (a trusted, unrevoked certificate chain) and that
+/// it's signed by the [`PUBLISHER`].
+fn verify_signature(path: &Path) -> Result<()> {
+    let file_path = HSTRING::from(path.as_os_str());
+    let mut file = WINTRUST_FILE_INFO {
+        cbStruct: u32::try_from(size_of::<WINTRUST_FILE_INFO>()).unwrap_or_default(),
+        pcwszFilePath: file_path.as_ptr(),
+        ..Default::default()
+    };
+    let mut data = WINTRUST_DATA {
+        cbStruct: u32::try_from(size_of::<WINTRUST_DATA>()).unwrap_or_default(),
+        dwUIChoice: WTD_UI_NONE,
+        fdwRevocationChecks: WTD_REVOKE_WHOLECHAIN,
+        dwUnionChoice: WTD_CHOICE_FILE,
+        Anonymous: WINTRUST_DATA_0 {
+            pFile: &raw mut file,
+        },
+        dwStateAction: WTD_STATEACTION_VERIFY,
+        dwProvFlags: WTD_REVOCATION_CHECK_CHAIN_EXCLUDE_ROOT,
+        ..Default::default()
+    };
+    let mut action = WINTRUST_ACTION_GENERIC_VERIFY_V2;
+    let status = unsafe { WinVerifyTrust(0 as _, &raw mut action, (&raw mut data).cast()) };
+    let signer = if status == 0 {
+        unsafe { signer_name(&data) }
+    } else {
+        None
+    };
+    data.dwStateAction = WTD_STATEACTION_CLOSE;
+    unsafe { WinVerifyTrust(0 as _, &raw mut action, (&raw mut data).cast()) };
+    if status != 0 {
+        return Err(anyhow!(
+            "the signature isn't valid: {}",
+            std::io::Error::from_raw_os_error(status)
+        ));
+    }
+    match signer {
+        Some(signer) if signer == PUBLISHER => Ok(()),
+        Some(signer) => Err(anyhow!("signed by {signer} rather than {PUBLISHER}")),
+        None => Err(anyhow!("the signer isn't known")),
+    }
+}
+
+/// The name of the certificate the file verified with `data` is signed with.
+unsafe fn signer_name(data: &WINTRUST_DATA) -> Option<String> {
+    let provider = WTHelperProvDataFromStateData(data.hWVTStateData);
+    if provider.is_null() {
+        return None;
+    }
+    let signer = WTHelperGetProvSignerFromChain(provider, 0, 0, 0);
+    if signer.is_null() || (*signer).csCertChain == 0 {
+        return None;
+    }
+    let cert = (*(*signer).pasCertChain).pCert;
+    let len = CertGetNameStringW(
+        cert,
+        CERT_NAME_SIMPLE_DISPLAY_TYPE,
+        0,
+        std::ptr::null(),
+        std::ptr::null_mut(),
+        0,
+    );
+    let mut name = vec![0u16; len as usize];
+    CertGetNameStringW(
+        cert,
+        CERT_NAME_SIMPLE_DISPLAY_TYPE,
+        0,
+        std::ptr::null(),
+        name.as_mut_ptr(),
+        len,
+    );
+    Some(
+        String::from_utf16_lossy(&name)
+            .trim_end_matches('\0')
+            .to_owned(),
+    )
+}
+
+fn http_client(timeout: Duration) -> Result<reqwest::Client> {
+    let client = reqwest::Client::builder()
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .timeout(timeout)
+        .build()?;
+    Ok(client)
+}
+
+async fn fetch_latest_release() -> Result<Release> {
+    let client = http_client(REQUEST_TIMEOUT)?;
+    let release = client
+        .get(LATEST_RELEASE_URL)
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+        .send()
+        .await
+        .context("querying latest release")?
+        .error_for_status()?
+        .json::<Release>()
+        .await
+        .context("parsing latest release")?;
+    Ok(release)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(tag: &str) -> Release {
+        Release {
+            tag_name: tag.to_owned(),
+            html_url: String::new(),
+            assets: vec![],
+        }
+    }
+
+    fn asset(name: &str) -> Asset {
+        Asset {
+            name: name.to_owned(),
+            browser_download_url: format!("https://example.com/{name}"),
+        }
+    }
+
+    #[test]
+    fn release_version_accepts_v_prefix() {
+        assert_eq!(release("v1.2.3").version().unwrap(), Version::new(1, 2, 3));
+        assert_eq!(release("0.3.0").version().unwrap(), Version::new(0, 3, 0));
+        assert!(release("latest").version().is_err());
+    }
+
+    #[test]
+    fn installer_asset_test() {
+        let mut release = release("v1.0.0");
+        release.assets = vec![asset("dot-local-dns_1.0.0_x64.zip")];
+        assert!(release.installer_asset().is_err());
+        release
+            .assets
+            .push(asset("DotLocal-DNS_1.0.0_x64-setup.exe"));
+        assert_eq!(
+            release.installer_asset().unwrap().name,
+            "DotLocal-DNS_1.0.0_x64-setup.exe"
+        );
+    }
+
+    #[test]
+    fn unsigned_installers_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("setup.exe");
+        fs::write(&path, b"MZ not really an installer").unwrap();
+        assert!(verify_signature(&path).is_err());
+    }
+}